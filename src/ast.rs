@@ -1,4 +1,4 @@
-use crate::token::TokenType;
+use crate::token::{Span, TokenType};
 use crate::types::{FloatType, IntType};
 
 #[derive(Copy, Clone, PartialEq, Debug)]
@@ -21,8 +21,16 @@ impl UnOp {
         }
     }
     pub fn priority(self) -> u8 {
+        12
+    }
+
+    pub fn as_str(self) -> &'static str {
         match self {
-            _ => 12,
+            UnOp::Minus => "-",
+            UnOp::BNot => "~",
+            UnOp::Not => "not",
+            UnOp::TLen => "#",
+            UnOp::None => unreachable!(),
         }
     }
 }
@@ -112,207 +120,334 @@ impl BinOp {
             _ => unreachable!(),
         }
     }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BinOp::Or => "or",
+            BinOp::And => "and",
+            BinOp::Eq => "==",
+            BinOp::Ne => "~=",
+            BinOp::Lt => "<",
+            BinOp::Gt => ">",
+            BinOp::Le => "<=",
+            BinOp::Ge => ">=",
+            BinOp::BOr => "|",
+            BinOp::BXor => "~",
+            BinOp::BAnd => "&",
+            BinOp::Shl => "<<",
+            BinOp::Shr => ">>",
+            BinOp::Concat => "..",
+            BinOp::Add => "+",
+            BinOp::Minus => "-",
+            BinOp::Mul => "*",
+            BinOp::Mod => "%",
+            BinOp::Div => "/",
+            BinOp::IDiv => "//",
+            BinOp::Pow => "^",
+            BinOp::None => unreachable!(),
+        }
+    }
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub enum Expr {
-    Nil,
-    True,
-    False,
-    Float(FloatType),
-    Int(IntType),
-    String(String),
+    Nil(Span),
+    True(Span),
+    False(Span),
+    Float(FloatType, Span),
+    Int(IntType, Span),
+    // LuaJIT `ULL` numeral, the value keeps the bits of the unsigned one
+    UInt(IntType, Span),
+    String(String, Span),
     FuncBody(FuncBody),
     Table(Table),
     BinExpr(BinExpr),
     UnExpr(UnExpr),
-    VarArg,
+    VarArg(Span),
     SuffixedExpr(SuffixedExpr),
 }
 
-#[derive(PartialEq, Debug)]
+impl Expr {
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Nil(span)
+            | Expr::True(span)
+            | Expr::False(span)
+            | Expr::VarArg(span)
+            | Expr::Float(_, span)
+            | Expr::Int(_, span)
+            | Expr::UInt(_, span)
+            | Expr::String(_, span) => *span,
+            Expr::FuncBody(body) => body.span,
+            Expr::Table(table) => table.span,
+            Expr::BinExpr(expr) => expr.span,
+            Expr::UnExpr(expr) => expr.span,
+            Expr::SuffixedExpr(expr) => expr.span,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Name {
+    pub name: String,
+    pub span: Span,
+}
+
+#[derive(Clone, PartialEq, Debug)]
 pub struct SuffixedExpr {
     pub primary: PrimaryExpr,
     pub suffixes: Vec<Suffix>,
+    pub span: Span,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub enum PrimaryExpr {
-    Name(String),
+    Name(Name),
     ParenExpr(Box<Expr>),
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub enum Suffix {
-    Attr(String),
+    Attr(Name),
     Index(Expr),
-    Method(String),
+    Method(Name),
     FuncArgs(FuncArgs),
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub enum FuncArgs {
-    Exprs(Vec<Expr>),
+    Exprs(Vec<Expr>, Span),
     Table(Table),
-    String(String),
+    String(String, Span),
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct Table {
     pub fields: Vec<Field>,
+    pub span: Span,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub enum Field {
     ListField(Expr),
     RecFileld(RecField),
 }
 
-#[derive(PartialEq, Debug)]
+impl Field {
+    pub fn span(&self) -> Span {
+        match self {
+            Field::ListField(expr) => expr.span(),
+            Field::RecFileld(field) => field.span,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
 pub struct RecField {
     pub key: FieldKey,
     pub value: Expr,
+    pub span: Span,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub enum FieldKey {
-    Name(String),
+    Name(Name),
     Expr(Expr),
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct UnExpr {
     pub op: UnOp,
     pub expr: Box<Expr>,
+    pub span: Span,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct BinExpr {
     pub op: BinOp,
     pub left: Box<Expr>,
     pub right: Box<Expr>,
+    pub span: Span,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct IfStat {
     pub cond_blocks: Vec<CondBlock>,
     pub else_block: Block,
+    pub span: Span,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct CondBlock {
     pub cond: Expr,
     pub block: Block,
+    pub span: Span,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct WhileStat {
     pub cond: Expr,
     pub block: Block,
+    pub span: Span,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct DoBlock {
     pub block: Block,
+    pub span: Span,
 }
 
-#[derive(PartialEq, Debug)]
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, PartialEq, Debug)]
 pub enum ForStat {
     ForNum(ForNum),
     ForList(ForList),
 }
 
-#[derive(PartialEq, Debug)]
+impl ForStat {
+    pub fn span(&self) -> Span {
+        match self {
+            ForStat::ForNum(fornum) => fornum.span,
+            ForStat::ForList(forlist) => forlist.span,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
 pub struct ForNum {
-    pub var: String,
+    pub var: Name,
     pub init: Expr,
     pub limit: Expr,
     pub step: Option<Expr>,
     pub body: Block,
+    pub span: Span,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct ForList {
-    pub vars: Vec<String>,
+    pub vars: Vec<Name>,
     pub exprs: Vec<Expr>,
     pub body: Block,
+    pub span: Span,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct RepeatStat {
     pub cond: Expr,
     pub block: Block,
+    pub span: Span,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub enum FuncType {
     Global,
     Local,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct FuncStat {
     pub func_type: FuncType,
     pub func_name: FuncName,
     pub body: FuncBody,
+    pub span: Span,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct FuncName {
-    pub fields: Vec<String>,
-    pub method: Option<String>,
+    pub fields: Vec<Name>,
+    pub method: Option<Name>,
+    pub span: Span,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct FuncBody {
     pub params: Vec<Param>,
     pub block: Block,
+    pub span: Span,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub enum Param {
-    VarArg,
-    Name(String),
+    VarArg(Span),
+    Name(Name),
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum LocalAttrib {
+    Const,
+    Close,
+}
+
+impl LocalAttrib {
+    pub fn from_name(name: &str) -> Option<LocalAttrib> {
+        match name {
+            "const" => Some(LocalAttrib::Const),
+            "close" => Some(LocalAttrib::Close),
+            _ => None,
+        }
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            LocalAttrib::Const => "const",
+            LocalAttrib::Close => "close",
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct LocalName {
+    pub name: Name,
+    pub attrib: Option<LocalAttrib>,
+    pub span: Span,
+}
+
+#[derive(Clone, PartialEq, Debug)]
 pub struct LocalStat {
-    pub names: Vec<String>,
+    pub names: Vec<LocalName>,
     pub exprs: Vec<Expr>,
+    pub span: Span,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct LabelStat {
-    pub label: String,
+    pub label: Name,
+    pub span: Span,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct RetStat {
     pub exprs: Vec<Expr>,
+    pub span: Span,
 }
 
-#[derive(PartialEq, Debug)]
-pub struct BreakStat {}
+#[derive(Clone, PartialEq, Debug)]
+pub struct BreakStat {
+    pub span: Span,
+}
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct GotoStat {
-    pub label: String,
+    pub label: Name,
+    pub span: Span,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct AssignStat {
     pub left: Vec<SuffixedExpr>,
     pub right: Vec<Expr>,
+    pub span: Span,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub struct CallStat {
     pub call: SuffixedExpr,
+    pub span: Span,
 }
 
-#[derive(PartialEq, Debug)]
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, PartialEq, Debug)]
 pub enum Stat {
-    Empty,
+    Empty(Span),
     IfStat(IfStat),
     WhileStat(WhileStat),
     DoBlock(DoBlock),
@@ -326,15 +461,280 @@ pub enum Stat {
     GotoStat(GotoStat),
     AssignStat(AssignStat),
     CallStat(CallStat),
+    // statement skipped by `Parser::run_recovering` after a syntax error
+    Error(Span),
 }
 
-#[derive(PartialEq, Debug)]
+impl Stat {
+    pub fn span(&self) -> Span {
+        match self {
+            Stat::Empty(span) => *span,
+            Stat::IfStat(stat) => stat.span,
+            Stat::WhileStat(stat) => stat.span,
+            Stat::DoBlock(stat) => stat.span,
+            Stat::ForStat(stat) => stat.span(),
+            Stat::RepeatStat(stat) => stat.span,
+            Stat::FuncStat(stat) => stat.span,
+            Stat::LocalStat(stat) => stat.span,
+            Stat::LabelStat(stat) => stat.span,
+            Stat::RetStat(stat) => stat.span,
+            Stat::BreakStat(stat) => stat.span,
+            Stat::GotoStat(stat) => stat.span,
+            Stat::AssignStat(stat) => stat.span,
+            Stat::CallStat(stat) => stat.span,
+            Stat::Error(span) => *span,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
 pub struct Block {
     pub stats: Vec<Stat>,
+    pub span: Span,
 }
 
 impl Block {
     pub fn empty() -> Self {
-        Block { stats: Vec::new() }
+        Block {
+            stats: Vec::new(),
+            span: Span::default(),
+        }
+    }
+}
+
+// resets every span to `Span::default()`, so trees parsed from differently formatted sources
+// compare equal with `==` afterwards
+pub trait ClearSpans {
+    fn clear_spans(&mut self);
+}
+
+// whether two nodes are the same apart from their spans
+pub fn same_structure<T: ClearSpans + Clone + PartialEq>(a: &T, b: &T) -> bool {
+    let (mut a, mut b) = (a.clone(), b.clone());
+    a.clear_spans();
+    b.clear_spans();
+    a == b
+}
+
+impl<T: ClearSpans> ClearSpans for Vec<T> {
+    fn clear_spans(&mut self) {
+        self.iter_mut().for_each(ClearSpans::clear_spans);
+    }
+}
+
+impl<T: ClearSpans> ClearSpans for Option<T> {
+    fn clear_spans(&mut self) {
+        if let Some(node) = self {
+            node.clear_spans();
+        }
+    }
+}
+
+impl<T: ClearSpans> ClearSpans for Box<T> {
+    fn clear_spans(&mut self) {
+        self.as_mut().clear_spans();
+    }
+}
+
+impl ClearSpans for Span {
+    fn clear_spans(&mut self) {
+        *self = Span::default();
+    }
+}
+
+impl ClearSpans for Expr {
+    fn clear_spans(&mut self) {
+        match self {
+            Expr::Nil(span)
+            | Expr::True(span)
+            | Expr::False(span)
+            | Expr::VarArg(span)
+            | Expr::Float(_, span)
+            | Expr::Int(_, span)
+            | Expr::UInt(_, span)
+            | Expr::String(_, span) => span.clear_spans(),
+            Expr::FuncBody(body) => body.clear_spans(),
+            Expr::Table(table) => table.clear_spans(),
+            Expr::BinExpr(expr) => expr.clear_spans(),
+            Expr::UnExpr(expr) => expr.clear_spans(),
+            Expr::SuffixedExpr(expr) => expr.clear_spans(),
+        }
+    }
+}
+
+impl ClearSpans for Name {
+    fn clear_spans(&mut self) {
+        self.span.clear_spans();
+    }
+}
+
+impl ClearSpans for SuffixedExpr {
+    fn clear_spans(&mut self) {
+        match &mut self.primary {
+            PrimaryExpr::Name(name) => name.clear_spans(),
+            PrimaryExpr::ParenExpr(expr) => expr.clear_spans(),
+        }
+        self.suffixes.clear_spans();
+        self.span.clear_spans();
+    }
+}
+
+impl ClearSpans for Suffix {
+    fn clear_spans(&mut self) {
+        match self {
+            Suffix::Attr(name) | Suffix::Method(name) => name.clear_spans(),
+            Suffix::Index(expr) => expr.clear_spans(),
+            Suffix::FuncArgs(args) => args.clear_spans(),
+        }
+    }
+}
+
+impl ClearSpans for FuncArgs {
+    fn clear_spans(&mut self) {
+        match self {
+            FuncArgs::Exprs(exprs, span) => {
+                exprs.clear_spans();
+                span.clear_spans();
+            }
+            FuncArgs::Table(table) => table.clear_spans(),
+            FuncArgs::String(_, span) => span.clear_spans(),
+        }
+    }
+}
+
+impl ClearSpans for Table {
+    fn clear_spans(&mut self) {
+        self.fields.clear_spans();
+        self.span.clear_spans();
+    }
+}
+
+impl ClearSpans for Field {
+    fn clear_spans(&mut self) {
+        match self {
+            Field::ListField(expr) => expr.clear_spans(),
+            Field::RecFileld(field) => {
+                match &mut field.key {
+                    FieldKey::Name(name) => name.clear_spans(),
+                    FieldKey::Expr(expr) => expr.clear_spans(),
+                }
+                field.value.clear_spans();
+                field.span.clear_spans();
+            }
+        }
+    }
+}
+
+impl ClearSpans for UnExpr {
+    fn clear_spans(&mut self) {
+        self.expr.clear_spans();
+        self.span.clear_spans();
+    }
+}
+
+impl ClearSpans for BinExpr {
+    fn clear_spans(&mut self) {
+        self.left.clear_spans();
+        self.right.clear_spans();
+        self.span.clear_spans();
+    }
+}
+
+impl ClearSpans for FuncBody {
+    fn clear_spans(&mut self) {
+        for param in self.params.iter_mut() {
+            match param {
+                Param::VarArg(span) => span.clear_spans(),
+                Param::Name(name) => name.clear_spans(),
+            }
+        }
+        self.block.clear_spans();
+        self.span.clear_spans();
+    }
+}
+
+impl ClearSpans for Stat {
+    fn clear_spans(&mut self) {
+        match self {
+            Stat::Empty(span) | Stat::Error(span) => span.clear_spans(),
+            Stat::IfStat(stat) => {
+                for cond_block in stat.cond_blocks.iter_mut() {
+                    cond_block.cond.clear_spans();
+                    cond_block.block.clear_spans();
+                    cond_block.span.clear_spans();
+                }
+                stat.else_block.clear_spans();
+                stat.span.clear_spans();
+            }
+            Stat::WhileStat(stat) => {
+                stat.cond.clear_spans();
+                stat.block.clear_spans();
+                stat.span.clear_spans();
+            }
+            Stat::DoBlock(stat) => {
+                stat.block.clear_spans();
+                stat.span.clear_spans();
+            }
+            Stat::ForStat(ForStat::ForNum(fornum)) => {
+                fornum.var.clear_spans();
+                fornum.init.clear_spans();
+                fornum.limit.clear_spans();
+                fornum.step.clear_spans();
+                fornum.body.clear_spans();
+                fornum.span.clear_spans();
+            }
+            Stat::ForStat(ForStat::ForList(forlist)) => {
+                forlist.vars.clear_spans();
+                forlist.exprs.clear_spans();
+                forlist.body.clear_spans();
+                forlist.span.clear_spans();
+            }
+            Stat::RepeatStat(stat) => {
+                stat.cond.clear_spans();
+                stat.block.clear_spans();
+                stat.span.clear_spans();
+            }
+            Stat::FuncStat(stat) => {
+                stat.func_name.fields.clear_spans();
+                stat.func_name.method.clear_spans();
+                stat.func_name.span.clear_spans();
+                stat.body.clear_spans();
+                stat.span.clear_spans();
+            }
+            Stat::LocalStat(stat) => {
+                for name in stat.names.iter_mut() {
+                    name.name.clear_spans();
+                    name.span.clear_spans();
+                }
+                stat.exprs.clear_spans();
+                stat.span.clear_spans();
+            }
+            Stat::LabelStat(LabelStat { label, span })
+            | Stat::GotoStat(GotoStat { label, span }) => {
+                label.clear_spans();
+                span.clear_spans();
+            }
+            Stat::RetStat(stat) => {
+                stat.exprs.clear_spans();
+                stat.span.clear_spans();
+            }
+            Stat::BreakStat(stat) => stat.span.clear_spans(),
+            Stat::AssignStat(stat) => {
+                stat.left.clear_spans();
+                stat.right.clear_spans();
+                stat.span.clear_spans();
+            }
+            Stat::CallStat(stat) => {
+                stat.call.clear_spans();
+                stat.span.clear_spans();
+            }
+        }
+    }
+}
+
+impl ClearSpans for Block {
+    fn clear_spans(&mut self) {
+        self.stats.clear_spans();
+        self.span.clear_spans();
     }
 }
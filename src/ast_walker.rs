@@ -1,98 +1,335 @@
 use crate::ast::*;
 use crate::types::*;
 
-// if visitor return `true`, walker will not travel its child nodes
+// if visitor return `true`, walker will not travel its child nodes,
+// every hook does nothing by default so a visitor only implements the ones it needs.
 pub trait AstVisitor {
-    fn stat_sep(&mut self);
+    fn stat_sep(&mut self) {}
 
-    fn begin_if(&mut self, cond: &Expr) -> bool;
-    fn then(&mut self, block: &Block) -> bool;
-    fn begin_else_if(&mut self, cond: &Expr) -> bool;
-    fn begin_else(&mut self, block: &Block) -> bool;
-    fn end_if(&mut self);
+    fn begin_if(&mut self, _cond: &Expr) -> bool {
+        false
+    }
+    fn then(&mut self, _block: &Block) -> bool {
+        false
+    }
+    fn begin_else_if(&mut self, _cond: &Expr) -> bool {
+        false
+    }
+    fn begin_else(&mut self, _block: &Block) -> bool {
+        false
+    }
+    fn end_if(&mut self) {}
+
+    fn begin_while(&mut self, _cond: &Expr) -> bool {
+        false
+    }
+    fn begin_while_block(&mut self, _block: &Block) -> bool {
+        false
+    }
+    fn end_while(&mut self) {}
+
+    fn begin_do_block(&mut self, _block: &Block) -> bool {
+        false
+    }
+    fn end_do_block(&mut self) {}
+
+    fn for_num(&mut self, _fornum: &ForNum) -> bool {
+        false
+    }
+    fn for_list(&mut self, _forlist: &ForList) -> bool {
+        false
+    }
+    fn begin_for_block(&mut self, _block: &Block) -> bool {
+        false
+    }
+    fn end_for(&mut self) {}
+
+    fn begin_repeat(&mut self, _block: &Block) -> bool {
+        false
+    }
+    fn until(&mut self) {}
+    fn end_repeat(&mut self) {}
+
+    fn func(&mut self, _funcstat: &FuncStat) {}
+
+    fn begin_local_stat(&mut self, _stat: &LocalStat) -> bool {
+        false
+    }
+    fn attrib(&mut self, _attrib: LocalAttrib) {}
+    fn end_local_stat(&mut self) {}
+
+    fn label_stat(&mut self, _stat: &LabelStat) {}
+
+    fn begin_ret_stat(&mut self, _stat: &RetStat) -> bool {
+        false
+    }
+    fn end_ret_stat(&mut self) {}
+
+    fn break_stat(&mut self, _stat: &BreakStat) {}
+    fn goto_stat(&mut self, _stat: &GotoStat) {}
+
+    fn begin_assign_stat(&mut self, _stat: &AssignStat) -> bool {
+        false
+    }
+    // '=' between targets and values of local and assign statements
+    fn assign_sep(&mut self) {}
+    fn end_assign_stat(&mut self) {}
+
+    fn begin_call_stat(&mut self, _stat: &CallStat) -> bool {
+        false
+    }
+    fn end_call_stat(&mut self) {}
+
+    fn expr(&mut self, _stat: &Expr) -> bool {
+        false
+    }
+    fn expr_sep(&mut self) {}
+
+    fn nil(&mut self) {}
+    fn true_(&mut self) {}
+    fn false_(&mut self) {}
+    fn float(&mut self, _f: FloatType) {}
+    fn int(&mut self, _i: IntType) {}
+    fn uint(&mut self, _i: IntType) {}
+    fn string(&mut self, _s: &str) {}
+    fn vararg(&mut self) {}
+
+    fn anonymous_func(&mut self) {}
+    fn begin_func_body(&mut self, _body: &FuncBody) -> bool {
+        false
+    }
+    fn end_func_body(&mut self) {}
+
+    fn begin_table(&mut self, _t: &Table) -> bool {
+        false
+    }
+    fn end_table(&mut self, _t: &Table) {}
+
+    fn field_sep(&mut self) {}
+
+    fn begin_rec_field(&mut self, _field: &RecField) -> bool {
+        false
+    }
+    fn field_kv_sep(&mut self) {}
+    fn begin_field_key(&mut self, _key: &FieldKey) -> bool {
+        false
+    }
+    fn end_field_key(&mut self, _key: &FieldKey) {}
+    fn end_rec_field(&mut self) {}
+
+    fn begin_bin_expr(&mut self, _expr: &BinExpr) -> bool {
+        false
+    }
+    fn binop(&mut self, _op: BinOp) {}
+    fn end_bin_expr(&mut self) {}
+
+    fn begin_un_expr(&mut self, _expr: &UnExpr) -> bool {
+        false
+    }
+    fn unop(&mut self, _op: UnOp) {}
+    fn end_un_expr(&mut self) {}
+
+    fn begin_suffixed_expr(&mut self, _expr: &SuffixedExpr) -> bool {
+        false
+    }
+    fn end_suffixed_expr(&mut self) {}
+
+    fn begin_primary_expr(&mut self, _expr: &PrimaryExpr) -> bool {
+        false
+    }
+    fn end_primary_expr(&mut self) {}
+
+    fn name(&mut self, _name: &str) {}
+    fn attr(&mut self, _attr: &str) {}
+    fn method(&mut self, _method: &str) {}
+
+    fn begin_index(&mut self, _expr: &Expr) -> bool {
+        false
+    }
+    fn end_index(&mut self) {}
+
+    fn begin_func_args(&mut self, _args: &FuncArgs) -> bool {
+        false
+    }
+    fn end_func_args(&mut self) {}
+
+    fn begin_paren_expr(&mut self, _expr: &Expr) -> bool {
+        false
+    }
+    fn end_paren_expr(&mut self) {}
+
+    fn suffix(&mut self, _suf: &Suffix) -> bool {
+        false
+    }
+}
+
+// same as `AstVisitor` but walks a mutable AST, nodes can be rewritten in place
+pub trait AstVisitorMut {
+    // called before walking a statement, e.g. to replace it with a desugared one
+    fn stat(&mut self, _stat: &mut Stat) -> bool {
+        false
+    }
+
+    fn stat_sep(&mut self) {}
+
+    fn begin_if(&mut self, _cond: &mut Expr) -> bool {
+        false
+    }
+    fn then(&mut self, _block: &mut Block) -> bool {
+        false
+    }
+    fn begin_else_if(&mut self, _cond: &mut Expr) -> bool {
+        false
+    }
+    fn begin_else(&mut self, _block: &mut Block) -> bool {
+        false
+    }
+    fn end_if(&mut self) {}
+
+    fn begin_while(&mut self, _cond: &mut Expr) -> bool {
+        false
+    }
+    fn begin_while_block(&mut self, _block: &mut Block) -> bool {
+        false
+    }
+    fn end_while(&mut self) {}
+
+    fn begin_do_block(&mut self, _block: &mut Block) -> bool {
+        false
+    }
+    fn end_do_block(&mut self) {}
+
+    fn for_num(&mut self, _fornum: &mut ForNum) -> bool {
+        false
+    }
+    fn for_list(&mut self, _forlist: &mut ForList) -> bool {
+        false
+    }
+    fn begin_for_block(&mut self, _block: &mut Block) -> bool {
+        false
+    }
+    fn end_for(&mut self) {}
+
+    fn begin_repeat(&mut self, _block: &mut Block) -> bool {
+        false
+    }
+    fn until(&mut self) {}
+    fn end_repeat(&mut self) {}
 
-    fn begin_while(&mut self, cond: &Expr) -> bool;
-    fn begin_while_block(&mut self, block: &Block) -> bool;
-    fn end_while(&mut self);
+    fn func(&mut self, _funcstat: &mut FuncStat) {}
 
-    fn begin_do_block(&mut self, block: &Block) -> bool;
-    fn end_do_block(&mut self);
+    fn begin_local_stat(&mut self, _stat: &mut LocalStat) -> bool {
+        false
+    }
+    fn attrib(&mut self, _attrib: LocalAttrib) {}
+    fn end_local_stat(&mut self) {}
 
-    fn for_num(&mut self, fornum: &ForNum) -> bool;
-    fn for_list(&mut self, forlist: &ForList) -> bool;
-    fn begin_for_block(&mut self, block: &Block) -> bool;
-    fn end_for(&mut self);
+    fn label_stat(&mut self, _stat: &mut LabelStat) {}
 
-    fn begin_repeat(&mut self, block: &Block) -> bool;
-    fn until(&mut self);
-    fn end_repeat(&mut self);
+    fn begin_ret_stat(&mut self, _stat: &mut RetStat) -> bool {
+        false
+    }
+    fn end_ret_stat(&mut self) {}
 
-    fn func(&mut self, funcstat: &FuncStat);
+    fn break_stat(&mut self, _stat: &mut BreakStat) {}
+    fn goto_stat(&mut self, _stat: &mut GotoStat) {}
 
-    fn local_stat(&mut self, stat: &LocalStat);
-    fn label_stat(&mut self, stat: &LabelStat);
-    fn ret_stat(&mut self, stat: &RetStat);
-    fn break_stat(&mut self, stat: &BreakStat);
-    fn goto_stat(&mut self, stat: &GotoStat);
-    fn assign_stat(&mut self, stat: &AssignStat);
-    fn call_stat(&mut self, stat: &CallStat);
+    fn begin_assign_stat(&mut self, _stat: &mut AssignStat) -> bool {
+        false
+    }
+    // '=' between targets and values of local and assign statements
+    fn assign_sep(&mut self) {}
+    fn end_assign_stat(&mut self) {}
 
-    fn expr(&mut self, stat: &Expr) -> bool;
-    fn expr_sep(&mut self);
+    fn begin_call_stat(&mut self, _stat: &mut CallStat) -> bool {
+        false
+    }
+    fn end_call_stat(&mut self) {}
 
-    fn nil(&mut self);
-    fn true_(&mut self);
-    fn false_(&mut self);
-    fn float(&mut self, f: FloatType);
-    fn int(&mut self, i: IntType);
-    fn string(&mut self, s: &str);
-    fn vararg(&mut self);
+    fn expr(&mut self, _stat: &mut Expr) -> bool {
+        false
+    }
+    fn expr_sep(&mut self) {}
 
-    fn anonymous_func(&mut self);
-    fn begin_func_body(&mut self, body: &FuncBody) -> bool;
-    fn end_func_body(&mut self);
+    fn nil(&mut self) {}
+    fn true_(&mut self) {}
+    fn false_(&mut self) {}
+    fn float(&mut self, _f: &mut FloatType) {}
+    fn int(&mut self, _i: &mut IntType) {}
+    fn uint(&mut self, _i: &mut IntType) {}
+    fn string(&mut self, _s: &mut String) {}
+    fn vararg(&mut self) {}
 
-    fn begin_table(&mut self, t: &Table) -> bool;
-    fn end_table(&mut self, t: &Table);
+    fn anonymous_func(&mut self) {}
+    fn begin_func_body(&mut self, _body: &mut FuncBody) -> bool {
+        false
+    }
+    fn end_func_body(&mut self) {}
 
-    fn field_sep(&mut self);
+    fn begin_table(&mut self, _t: &mut Table) -> bool {
+        false
+    }
+    fn end_table(&mut self, _t: &mut Table) {}
 
-    fn begin_rec_field(&mut self, field: &RecField) -> bool;
-    fn field_kv_sep(&mut self);
-    fn begin_field_key(&mut self, key: &FieldKey) -> bool;
-    fn end_field_key(&mut self, key: &FieldKey);
-    fn end_rec_field(&mut self);
+    fn field_sep(&mut self) {}
 
-    fn begin_bin_expr(&mut self, expr: &BinExpr) -> bool;
-    fn binop(&mut self, op: BinOp);
-    fn end_bin_expr(&mut self);
+    fn begin_rec_field(&mut self, _field: &mut RecField) -> bool {
+        false
+    }
+    fn field_kv_sep(&mut self) {}
+    fn begin_field_key(&mut self, _key: &mut FieldKey) -> bool {
+        false
+    }
+    fn end_field_key(&mut self, _key: &mut FieldKey) {}
+    fn end_rec_field(&mut self) {}
 
-    fn begin_un_expr(&mut self, expr: &UnExpr) -> bool;
-    fn unop(&mut self, op: UnOp);
-    fn end_un_expr(&mut self);
+    fn begin_bin_expr(&mut self, _expr: &mut BinExpr) -> bool {
+        false
+    }
+    fn binop(&mut self, _op: BinOp) {}
+    fn end_bin_expr(&mut self) {}
 
-    fn begin_suffixed_expr(&mut self, expr: &SuffixedExpr) -> bool;
-    fn end_suffixed_expr(&mut self);
+    fn begin_un_expr(&mut self, _expr: &mut UnExpr) -> bool {
+        false
+    }
+    fn unop(&mut self, _op: UnOp) {}
+    fn end_un_expr(&mut self) {}
 
-    fn begin_primary_expr(&mut self, expr: &PrimaryExpr) -> bool;
-    fn end_primary_expr(&mut self);
+    fn begin_suffixed_expr(&mut self, _expr: &mut SuffixedExpr) -> bool {
+        false
+    }
+    fn end_suffixed_expr(&mut self) {}
 
-    fn name(&mut self, name: &str);
-    fn attr(&mut self, attr: &str);
-    fn method(&mut self, method: &str);
+    fn begin_primary_expr(&mut self, _expr: &mut PrimaryExpr) -> bool {
+        false
+    }
+    fn end_primary_expr(&mut self) {}
 
-    fn begin_index(&mut self, expr: &Expr) -> bool;
-    fn end_index(&mut self);
+    fn name(&mut self, _name: &mut Name) {}
+    fn attr(&mut self, _attr: &mut Name) {}
+    fn method(&mut self, _method: &mut Name) {}
 
-    fn begin_func_args(&mut self, args: &FuncArgs) -> bool;
-    fn end_func_args(&mut self);
+    fn begin_index(&mut self, _expr: &mut Expr) -> bool {
+        false
+    }
+    fn end_index(&mut self) {}
 
-    fn begin_paren_expr(&mut self, expr: &Expr) -> bool;
-    fn end_paren_expr(&mut self);
+    fn begin_func_args(&mut self, _args: &mut FuncArgs) -> bool {
+        false
+    }
+    fn end_func_args(&mut self) {}
 
-    fn suffix(&mut self, suf: &Suffix) -> bool;
+    fn begin_paren_expr(&mut self, _expr: &mut Expr) -> bool {
+        false
+    }
+    fn end_paren_expr(&mut self) {}
+
+    fn suffix(&mut self, _suf: &mut Suffix) -> bool {
+        false
+    }
 }
 
+#[allow(clippy::module_inception)]
 pub mod ast_walker {
     use super::AstVisitor;
     use crate::ast::*;
@@ -106,7 +343,7 @@ pub mod ast_walker {
 
     pub fn walk_stat<T: AstVisitor>(stat: &Stat, visitor: &mut T) {
         match stat {
-            Stat::Empty => (),
+            Stat::Empty(_) => (),
             Stat::IfStat(ifstat) => walk_ifstat(ifstat, visitor),
             Stat::WhileStat(whilestat) => walk_whilestat(whilestat, visitor),
             Stat::DoBlock(doblock) => walk_doblockstat(doblock, visitor),
@@ -120,6 +357,7 @@ pub mod ast_walker {
             Stat::GotoStat(gotostat) => walk_gotostat(gotostat, visitor),
             Stat::AssignStat(assignstat) => walk_assignstat(assignstat, visitor),
             Stat::CallStat(callstat) => walk_callstat(callstat, visitor),
+            Stat::Error(_) => (),
         };
     }
 
@@ -132,7 +370,7 @@ pub mod ast_walker {
             if !visitor.then(&if_block.block) {
                 walk_block(&if_block.block, visitor);
             }
-            while let Some(else_if_block) = if_blocks.next() {
+            for else_if_block in if_blocks {
                 if !visitor.begin_else_if(&else_if_block.cond) {
                     walk_expr(&else_if_block.cond, visitor);
                 }
@@ -140,10 +378,8 @@ pub mod ast_walker {
                     walk_block(&else_if_block.block, visitor);
                 }
             }
-            if stat.else_block.stats.len() > 0 {
-                if !visitor.begin_else(&stat.else_block) {
-                    walk_block(&stat.else_block, visitor);
-                }
+            if !stat.else_block.stats.is_empty() && !visitor.begin_else(&stat.else_block) {
+                walk_block(&stat.else_block, visitor);
             }
             visitor.end_if();
         }
@@ -214,7 +450,26 @@ pub mod ast_walker {
     }
 
     pub fn walk_localstat<T: AstVisitor>(stat: &LocalStat, visitor: &mut T) {
-        visitor.local_stat(stat);
+        if !visitor.begin_local_stat(stat) {
+            walk_localnames(&stat.names, visitor);
+            if !stat.exprs.is_empty() {
+                visitor.assign_sep();
+                walk_exprlist(&stat.exprs, visitor);
+            }
+        }
+        visitor.end_local_stat();
+    }
+
+    pub fn walk_localnames<T: AstVisitor>(names: &[LocalName], visitor: &mut T) {
+        for (n, name) in names.iter().enumerate() {
+            visitor.name(&name.name.name);
+            if let Some(attrib) = name.attrib {
+                visitor.attrib(attrib);
+            }
+            if n < names.len() - 1 {
+                visitor.expr_sep();
+            }
+        }
     }
 
     pub fn walk_labelstat<T: AstVisitor>(stat: &LabelStat, visitor: &mut T) {
@@ -222,7 +477,10 @@ pub mod ast_walker {
     }
 
     pub fn walk_retstat<T: AstVisitor>(stat: &RetStat, visitor: &mut T) {
-        visitor.ret_stat(stat);
+        if !visitor.begin_ret_stat(stat) {
+            walk_exprlist(&stat.exprs, visitor);
+        }
+        visitor.end_ret_stat();
     }
 
     pub fn walk_breakstat<T: AstVisitor>(stat: &BreakStat, visitor: &mut T) {
@@ -234,23 +492,38 @@ pub mod ast_walker {
     }
 
     pub fn walk_assignstat<T: AstVisitor>(stat: &AssignStat, visitor: &mut T) {
-        visitor.assign_stat(stat);
+        if !visitor.begin_assign_stat(stat) {
+            let len = stat.left.len();
+            for (n, expr) in stat.left.iter().enumerate() {
+                walk_suffixedexpr(expr, visitor);
+                if n < len - 1 {
+                    visitor.expr_sep();
+                }
+            }
+            visitor.assign_sep();
+            walk_exprlist(&stat.right, visitor);
+        }
+        visitor.end_assign_stat();
     }
 
     pub fn walk_callstat<T: AstVisitor>(stat: &CallStat, visitor: &mut T) {
-        visitor.call_stat(stat);
+        if !visitor.begin_call_stat(stat) {
+            walk_suffixedexpr(&stat.call, visitor);
+        }
+        visitor.end_call_stat();
     }
 
     pub fn walk_expr<T: AstVisitor>(expr: &Expr, visitor: &mut T) {
         if !visitor.expr(expr) {
             match expr {
-                Expr::Nil => visitor.nil(),
-                Expr::True => visitor.true_(),
-                Expr::False => visitor.false_(),
-                Expr::Float(f) => visitor.float(*f),
-                Expr::Int(i) => visitor.int(*i),
-                Expr::String(string) => visitor.string(string),
-                Expr::VarArg => visitor.vararg(),
+                Expr::Nil(_) => visitor.nil(),
+                Expr::True(_) => visitor.true_(),
+                Expr::False(_) => visitor.false_(),
+                Expr::Float(f, _) => visitor.float(*f),
+                Expr::Int(i, _) => visitor.int(*i),
+                Expr::UInt(i, _) => visitor.uint(*i),
+                Expr::String(string, _) => visitor.string(string),
+                Expr::VarArg(_) => visitor.vararg(),
                 Expr::FuncBody(body) => {
                     visitor.anonymous_func();
                     walk_funcbody(body, visitor)
@@ -293,8 +566,8 @@ pub mod ast_walker {
             for suf in expr.suffixes.iter() {
                 if !visitor.suffix(suf) {
                     match suf {
-                        Suffix::Attr(attr) => visitor.attr(attr),
-                        Suffix::Method(method) => visitor.method(method),
+                        Suffix::Attr(attr) => visitor.attr(&attr.name),
+                        Suffix::Method(method) => visitor.method(&method.name),
                         Suffix::Index(index) => walk_index(index, visitor),
                         Suffix::FuncArgs(args) => walk_funcargs(args, visitor),
                     }
@@ -314,9 +587,9 @@ pub mod ast_walker {
     pub fn walk_funcargs<T: AstVisitor>(args: &FuncArgs, visitor: &mut T) {
         if !visitor.begin_func_args(args) {
             match args {
-                FuncArgs::String(s) => visitor.string(s),
+                FuncArgs::String(s, _) => visitor.string(s),
                 FuncArgs::Table(t) => walk_table(t, visitor),
-                FuncArgs::Exprs(exprs) => walk_exprlist(exprs, visitor),
+                FuncArgs::Exprs(exprs, _) => walk_exprlist(exprs, visitor),
             }
         }
         visitor.end_func_args();
@@ -325,8 +598,8 @@ pub mod ast_walker {
     pub fn walk_primaryexpr<T: AstVisitor>(expr: &PrimaryExpr, visitor: &mut T) {
         if !visitor.begin_primary_expr(expr) {
             match expr {
-                PrimaryExpr::Name(n) => visitor.name(n),
-                PrimaryExpr::ParenExpr(expr) => walk_parenexpr(&expr, visitor),
+                PrimaryExpr::Name(n) => visitor.name(&n.name),
+                PrimaryExpr::ParenExpr(expr) => walk_parenexpr(expr, visitor),
             }
         }
         visitor.end_primary_expr();
@@ -346,7 +619,7 @@ pub mod ast_walker {
         visitor.end_table(table);
     }
 
-    pub fn walk_fields<T: AstVisitor>(fields: &Vec<Field>, visitor: &mut T) {
+    pub fn walk_fields<T: AstVisitor>(fields: &[Field], visitor: &mut T) {
         for field in fields.iter() {
             walk_field(field, visitor);
             visitor.field_sep();
@@ -372,14 +645,14 @@ pub mod ast_walker {
     pub fn walk_fieldkey<T: AstVisitor>(key: &FieldKey, visitor: &mut T) {
         if !visitor.begin_field_key(key) {
             match key {
-                FieldKey::Name(s) => visitor.name(s),
+                FieldKey::Name(s) => visitor.name(&s.name),
                 FieldKey::Expr(expr) => walk_expr(expr, visitor),
             };
         }
         visitor.end_field_key(key);
     }
 
-    pub fn walk_exprlist<T: AstVisitor>(exprlist: &Vec<Expr>, visitor: &mut T) {
+    pub fn walk_exprlist<T: AstVisitor>(exprlist: &[Expr], visitor: &mut T) {
         for (n, expr) in exprlist.iter().enumerate() {
             walk_expr(expr, visitor);
             if n < exprlist.len() - 1 {
@@ -388,3 +661,340 @@ pub mod ast_walker {
         }
     }
 }
+
+pub mod ast_walker_mut {
+    use super::AstVisitorMut;
+    use crate::ast::*;
+
+    pub fn walk_block_mut<T: AstVisitorMut>(block: &mut Block, visitor: &mut T) {
+        for stat in block.stats.iter_mut() {
+            walk_stat_mut(stat, visitor);
+            visitor.stat_sep();
+        }
+    }
+
+    pub fn walk_stat_mut<T: AstVisitorMut>(stat: &mut Stat, visitor: &mut T) {
+        if visitor.stat(stat) {
+            return;
+        }
+        match stat {
+            Stat::Empty(_) => (),
+            Stat::IfStat(ifstat) => walk_ifstat_mut(ifstat, visitor),
+            Stat::WhileStat(whilestat) => walk_whilestat_mut(whilestat, visitor),
+            Stat::DoBlock(doblock) => walk_doblockstat_mut(doblock, visitor),
+            Stat::ForStat(forstat) => walk_forstat_mut(forstat, visitor),
+            Stat::RepeatStat(repeatstat) => walk_repeatstat_mut(repeatstat, visitor),
+            Stat::FuncStat(funcstat) => walk_funcstat_mut(funcstat, visitor),
+            Stat::LocalStat(localstat) => walk_localstat_mut(localstat, visitor),
+            Stat::LabelStat(labelstat) => walk_labelstat_mut(labelstat, visitor),
+            Stat::RetStat(retstat) => walk_retstat_mut(retstat, visitor),
+            Stat::BreakStat(breakstat) => walk_breakstat_mut(breakstat, visitor),
+            Stat::GotoStat(gotostat) => walk_gotostat_mut(gotostat, visitor),
+            Stat::AssignStat(assignstat) => walk_assignstat_mut(assignstat, visitor),
+            Stat::CallStat(callstat) => walk_callstat_mut(callstat, visitor),
+            Stat::Error(_) => (),
+        };
+    }
+
+    pub fn walk_ifstat_mut<T: AstVisitorMut>(stat: &mut IfStat, visitor: &mut T) {
+        let mut if_blocks = stat.cond_blocks.iter_mut();
+        if let Some(if_block) = if_blocks.next() {
+            if !visitor.begin_if(&mut if_block.cond) {
+                walk_expr_mut(&mut if_block.cond, visitor);
+            }
+            if !visitor.then(&mut if_block.block) {
+                walk_block_mut(&mut if_block.block, visitor);
+            }
+            for else_if_block in if_blocks {
+                if !visitor.begin_else_if(&mut else_if_block.cond) {
+                    walk_expr_mut(&mut else_if_block.cond, visitor);
+                }
+                if !visitor.then(&mut else_if_block.block) {
+                    walk_block_mut(&mut else_if_block.block, visitor);
+                }
+            }
+            if !stat.else_block.stats.is_empty() && !visitor.begin_else(&mut stat.else_block) {
+                walk_block_mut(&mut stat.else_block, visitor);
+            }
+            visitor.end_if();
+        }
+    }
+
+    pub fn walk_whilestat_mut<T: AstVisitorMut>(stat: &mut WhileStat, visitor: &mut T) {
+        if !visitor.begin_while(&mut stat.cond) {
+            walk_expr_mut(&mut stat.cond, visitor);
+        }
+        if !visitor.begin_while_block(&mut stat.block) {
+            walk_block_mut(&mut stat.block, visitor);
+        }
+        visitor.end_while();
+    }
+
+    pub fn walk_doblockstat_mut<T: AstVisitorMut>(stat: &mut DoBlock, visitor: &mut T) {
+        if !visitor.begin_do_block(&mut stat.block) {
+            walk_block_mut(&mut stat.block, visitor);
+        }
+        visitor.end_do_block();
+    }
+
+    pub fn walk_forstat_mut<T: AstVisitorMut>(stat: &mut ForStat, visitor: &mut T) {
+        match stat {
+            ForStat::ForNum(fornum) => walk_forenum_mut(fornum, visitor),
+            ForStat::ForList(forlist) => walk_forlist_mut(forlist, visitor),
+        };
+    }
+
+    pub fn walk_forenum_mut<T: AstVisitorMut>(stat: &mut ForNum, visitor: &mut T) {
+        if !visitor.for_num(stat) {
+            walk_expr_mut(&mut stat.init, visitor);
+            visitor.expr_sep();
+            walk_expr_mut(&mut stat.limit, visitor);
+            if let Some(expr) = &mut stat.step {
+                visitor.expr_sep();
+                walk_expr_mut(expr, visitor);
+            }
+        }
+        if !visitor.begin_for_block(&mut stat.body) {
+            walk_block_mut(&mut stat.body, visitor);
+        }
+        visitor.end_for();
+    }
+
+    pub fn walk_forlist_mut<T: AstVisitorMut>(stat: &mut ForList, visitor: &mut T) {
+        if !visitor.for_list(stat) {
+            walk_exprlist_mut(&mut stat.exprs, visitor);
+        }
+        if !visitor.begin_for_block(&mut stat.body) {
+            walk_block_mut(&mut stat.body, visitor);
+        }
+        visitor.end_for();
+    }
+
+    pub fn walk_repeatstat_mut<T: AstVisitorMut>(stat: &mut RepeatStat, visitor: &mut T) {
+        if !visitor.begin_repeat(&mut stat.block) {
+            walk_block_mut(&mut stat.block, visitor);
+            visitor.until();
+            walk_expr_mut(&mut stat.cond, visitor);
+        }
+        visitor.end_repeat();
+    }
+
+    pub fn walk_funcstat_mut<T: AstVisitorMut>(stat: &mut FuncStat, visitor: &mut T) {
+        visitor.func(stat);
+        walk_funcbody_mut(&mut stat.body, visitor);
+    }
+
+    pub fn walk_localstat_mut<T: AstVisitorMut>(stat: &mut LocalStat, visitor: &mut T) {
+        if !visitor.begin_local_stat(stat) {
+            walk_localnames_mut(&mut stat.names, visitor);
+            if !stat.exprs.is_empty() {
+                visitor.assign_sep();
+                walk_exprlist_mut(&mut stat.exprs, visitor);
+            }
+        }
+        visitor.end_local_stat();
+    }
+
+    pub fn walk_localnames_mut<T: AstVisitorMut>(names: &mut [LocalName], visitor: &mut T) {
+        let len = names.len();
+        for (n, name) in names.iter_mut().enumerate() {
+            visitor.name(&mut name.name);
+            if let Some(attrib) = name.attrib {
+                visitor.attrib(attrib);
+            }
+            if n < len - 1 {
+                visitor.expr_sep();
+            }
+        }
+    }
+
+    pub fn walk_labelstat_mut<T: AstVisitorMut>(stat: &mut LabelStat, visitor: &mut T) {
+        visitor.label_stat(stat);
+    }
+
+    pub fn walk_retstat_mut<T: AstVisitorMut>(stat: &mut RetStat, visitor: &mut T) {
+        if !visitor.begin_ret_stat(stat) {
+            walk_exprlist_mut(&mut stat.exprs, visitor);
+        }
+        visitor.end_ret_stat();
+    }
+
+    pub fn walk_breakstat_mut<T: AstVisitorMut>(stat: &mut BreakStat, visitor: &mut T) {
+        visitor.break_stat(stat);
+    }
+
+    pub fn walk_gotostat_mut<T: AstVisitorMut>(stat: &mut GotoStat, visitor: &mut T) {
+        visitor.goto_stat(stat);
+    }
+
+    pub fn walk_assignstat_mut<T: AstVisitorMut>(stat: &mut AssignStat, visitor: &mut T) {
+        if !visitor.begin_assign_stat(stat) {
+            let len = stat.left.len();
+            for (n, expr) in stat.left.iter_mut().enumerate() {
+                walk_suffixedexpr_mut(expr, visitor);
+                if n < len - 1 {
+                    visitor.expr_sep();
+                }
+            }
+            visitor.assign_sep();
+            walk_exprlist_mut(&mut stat.right, visitor);
+        }
+        visitor.end_assign_stat();
+    }
+
+    pub fn walk_callstat_mut<T: AstVisitorMut>(stat: &mut CallStat, visitor: &mut T) {
+        if !visitor.begin_call_stat(stat) {
+            walk_suffixedexpr_mut(&mut stat.call, visitor);
+        }
+        visitor.end_call_stat();
+    }
+
+    pub fn walk_expr_mut<T: AstVisitorMut>(expr: &mut Expr, visitor: &mut T) {
+        if !visitor.expr(expr) {
+            match expr {
+                Expr::Nil(_) => visitor.nil(),
+                Expr::True(_) => visitor.true_(),
+                Expr::False(_) => visitor.false_(),
+                Expr::Float(f, _) => visitor.float(f),
+                Expr::Int(i, _) => visitor.int(i),
+                Expr::UInt(i, _) => visitor.uint(i),
+                Expr::String(string, _) => visitor.string(string),
+                Expr::VarArg(_) => visitor.vararg(),
+                Expr::FuncBody(body) => {
+                    visitor.anonymous_func();
+                    walk_funcbody_mut(body, visitor)
+                }
+                Expr::Table(t) => walk_table_mut(t, visitor),
+                Expr::BinExpr(expr) => walk_binexpr_mut(expr, visitor),
+                Expr::UnExpr(expr) => walk_unexpr_mut(expr, visitor),
+                Expr::SuffixedExpr(expr) => walk_suffixedexpr_mut(expr, visitor),
+            };
+        }
+    }
+
+    pub fn walk_funcbody_mut<T: AstVisitorMut>(body: &mut FuncBody, visitor: &mut T) {
+        if !visitor.begin_func_body(body) {
+            walk_block_mut(&mut body.block, visitor);
+        }
+        visitor.end_func_body();
+    }
+
+    pub fn walk_binexpr_mut<T: AstVisitorMut>(expr: &mut BinExpr, visitor: &mut T) {
+        if !visitor.begin_bin_expr(expr) {
+            walk_expr_mut(&mut expr.left, visitor);
+            visitor.binop(expr.op);
+            walk_expr_mut(&mut expr.right, visitor);
+        }
+        visitor.end_bin_expr();
+    }
+
+    pub fn walk_unexpr_mut<T: AstVisitorMut>(expr: &mut UnExpr, visitor: &mut T) {
+        if !visitor.begin_un_expr(expr) {
+            visitor.unop(expr.op);
+            walk_expr_mut(&mut expr.expr, visitor);
+        }
+        visitor.end_un_expr();
+    }
+
+    pub fn walk_suffixedexpr_mut<T: AstVisitorMut>(expr: &mut SuffixedExpr, visitor: &mut T) {
+        if !visitor.begin_suffixed_expr(expr) {
+            walk_primaryexpr_mut(&mut expr.primary, visitor);
+            for suf in expr.suffixes.iter_mut() {
+                if !visitor.suffix(suf) {
+                    match suf {
+                        Suffix::Attr(attr) => visitor.attr(attr),
+                        Suffix::Method(method) => visitor.method(method),
+                        Suffix::Index(index) => walk_index_mut(index, visitor),
+                        Suffix::FuncArgs(args) => walk_funcargs_mut(args, visitor),
+                    }
+                }
+            }
+        }
+        visitor.end_suffixed_expr();
+    }
+
+    pub fn walk_index_mut<T: AstVisitorMut>(expr: &mut Expr, visitor: &mut T) {
+        if !visitor.begin_index(expr) {
+            walk_expr_mut(expr, visitor);
+        }
+        visitor.end_index();
+    }
+
+    pub fn walk_funcargs_mut<T: AstVisitorMut>(args: &mut FuncArgs, visitor: &mut T) {
+        if !visitor.begin_func_args(args) {
+            match args {
+                FuncArgs::String(s, _) => visitor.string(s),
+                FuncArgs::Table(t) => walk_table_mut(t, visitor),
+                FuncArgs::Exprs(exprs, _) => walk_exprlist_mut(exprs, visitor),
+            }
+        }
+        visitor.end_func_args();
+    }
+
+    pub fn walk_primaryexpr_mut<T: AstVisitorMut>(expr: &mut PrimaryExpr, visitor: &mut T) {
+        if !visitor.begin_primary_expr(expr) {
+            match expr {
+                PrimaryExpr::Name(n) => visitor.name(n),
+                PrimaryExpr::ParenExpr(expr) => walk_parenexpr_mut(expr, visitor),
+            }
+        }
+        visitor.end_primary_expr();
+    }
+
+    pub fn walk_parenexpr_mut<T: AstVisitorMut>(expr: &mut Expr, visitor: &mut T) {
+        if !visitor.begin_paren_expr(expr) {
+            walk_expr_mut(expr, visitor);
+        }
+        visitor.end_paren_expr();
+    }
+
+    pub fn walk_table_mut<T: AstVisitorMut>(table: &mut Table, visitor: &mut T) {
+        if !visitor.begin_table(table) {
+            walk_fields_mut(&mut table.fields, visitor);
+        }
+        visitor.end_table(table);
+    }
+
+    pub fn walk_fields_mut<T: AstVisitorMut>(fields: &mut [Field], visitor: &mut T) {
+        for field in fields.iter_mut() {
+            walk_field_mut(field, visitor);
+            visitor.field_sep();
+        }
+    }
+
+    pub fn walk_field_mut<T: AstVisitorMut>(field: &mut Field, visitor: &mut T) {
+        match field {
+            Field::RecFileld(field) => walk_recfield_mut(field, visitor),
+            Field::ListField(field) => walk_expr_mut(field, visitor),
+        };
+    }
+
+    pub fn walk_recfield_mut<T: AstVisitorMut>(field: &mut RecField, visitor: &mut T) {
+        if !visitor.begin_rec_field(field) {
+            walk_fieldkey_mut(&mut field.key, visitor);
+            visitor.field_kv_sep();
+            walk_expr_mut(&mut field.value, visitor);
+        }
+        visitor.end_rec_field();
+    }
+
+    pub fn walk_fieldkey_mut<T: AstVisitorMut>(key: &mut FieldKey, visitor: &mut T) {
+        if !visitor.begin_field_key(key) {
+            match key {
+                FieldKey::Name(s) => visitor.name(s),
+                FieldKey::Expr(expr) => walk_expr_mut(expr, visitor),
+            };
+        }
+        visitor.end_field_key(key);
+    }
+
+    pub fn walk_exprlist_mut<T: AstVisitorMut>(exprlist: &mut [Expr], visitor: &mut T) {
+        let len = exprlist.len();
+        for (n, expr) in exprlist.iter_mut().enumerate() {
+            walk_expr_mut(expr, visitor);
+            if n < len - 1 {
+                visitor.expr_sep();
+            }
+        }
+    }
+}
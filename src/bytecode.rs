@@ -0,0 +1,581 @@
+use std::fmt;
+
+use crate::types::{FloatType, IntType};
+
+// instructions of Lua 5.3 are 32 bits: the opcode in the low 6 bits, then A in 8 bits and
+// C and B in 9 bits each, Bx in place of B and C or Ax in place of A, B and C
+const POS_A: u32 = 6;
+const POS_C: u32 = 14;
+const POS_B: u32 = 23;
+const POS_BX: u32 = POS_C;
+const POS_AX: u32 = POS_A;
+
+pub const MAXARG_A: u32 = (1 << 8) - 1;
+pub const MAXARG_B: u32 = (1 << 9) - 1;
+pub const MAXARG_C: u32 = (1 << 9) - 1;
+pub const MAXARG_BX: u32 = (1 << 18) - 1;
+pub const MAXARG_SBX: i32 = (MAXARG_BX >> 1) as i32;
+pub const MAXARG_AX: u32 = (1 << 26) - 1;
+
+// a B or C argument with this bit set is a constant index rather than a register
+pub const BITRK: u32 = 1 << 8;
+pub const MAXINDEXRK: u32 = BITRK - 1;
+
+// list items a single SETLIST stores
+pub const LFIELDS_PER_FLUSH: u32 = 50;
+
+pub(crate) const SIGNATURE: &[u8] = b"\x1bLua";
+const LUAC_VERSION: u8 = 0x53;
+const LUAC_FORMAT: u8 = 0;
+// catches conversions of line endings and such
+pub(crate) const LUAC_DATA: &[u8] = b"\x19\x93\r\n\x1a\n";
+pub(crate) const LUAC_INT: IntType = 0x5678;
+pub(crate) const LUAC_NUM: FloatType = 370.5;
+// longer strings are stored with another type tag
+const MAX_SHORT_LEN: usize = 40;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum OpCode {
+    Move,
+    LoadK,
+    LoadKX,
+    LoadBool,
+    LoadNil,
+    GetUpval,
+    GetTabUp,
+    GetTable,
+    SetTabUp,
+    SetUpval,
+    SetTable,
+    NewTable,
+    Self_,
+    Add,
+    Sub,
+    Mul,
+    Mod,
+    Pow,
+    Div,
+    IDiv,
+    BAnd,
+    BOr,
+    BXor,
+    Shl,
+    Shr,
+    Unm,
+    BNot,
+    Not,
+    Len,
+    Concat,
+    Jmp,
+    Eq,
+    Lt,
+    Le,
+    Test,
+    TestSet,
+    Call,
+    TailCall,
+    Return,
+    ForLoop,
+    ForPrep,
+    TForCall,
+    TForLoop,
+    SetList,
+    Closure,
+    VarArg,
+    ExtraArg,
+}
+
+// how the bits after A are split
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum OpMode {
+    ABC,
+    ABx,
+    AsBx,
+    Ax,
+}
+
+// what a B or C argument holds
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum OpArg {
+    // unused
+    N,
+    // a plain number
+    U,
+    // a register or a jump offset
+    R,
+    // a constant, or a register or a constant (RK)
+    K,
+}
+
+impl OpCode {
+    pub const ALL: [OpCode; 47] = [
+        OpCode::Move,
+        OpCode::LoadK,
+        OpCode::LoadKX,
+        OpCode::LoadBool,
+        OpCode::LoadNil,
+        OpCode::GetUpval,
+        OpCode::GetTabUp,
+        OpCode::GetTable,
+        OpCode::SetTabUp,
+        OpCode::SetUpval,
+        OpCode::SetTable,
+        OpCode::NewTable,
+        OpCode::Self_,
+        OpCode::Add,
+        OpCode::Sub,
+        OpCode::Mul,
+        OpCode::Mod,
+        OpCode::Pow,
+        OpCode::Div,
+        OpCode::IDiv,
+        OpCode::BAnd,
+        OpCode::BOr,
+        OpCode::BXor,
+        OpCode::Shl,
+        OpCode::Shr,
+        OpCode::Unm,
+        OpCode::BNot,
+        OpCode::Not,
+        OpCode::Len,
+        OpCode::Concat,
+        OpCode::Jmp,
+        OpCode::Eq,
+        OpCode::Lt,
+        OpCode::Le,
+        OpCode::Test,
+        OpCode::TestSet,
+        OpCode::Call,
+        OpCode::TailCall,
+        OpCode::Return,
+        OpCode::ForLoop,
+        OpCode::ForPrep,
+        OpCode::TForCall,
+        OpCode::TForLoop,
+        OpCode::SetList,
+        OpCode::Closure,
+        OpCode::VarArg,
+        OpCode::ExtraArg,
+    ];
+
+    pub fn from_u8(op: u8) -> Option<OpCode> {
+        OpCode::ALL.get(op as usize).copied()
+    }
+
+    // the name `luac -l` lists
+    pub fn name(self) -> &'static str {
+        match self {
+            OpCode::Move => "MOVE",
+            OpCode::LoadK => "LOADK",
+            OpCode::LoadKX => "LOADKX",
+            OpCode::LoadBool => "LOADBOOL",
+            OpCode::LoadNil => "LOADNIL",
+            OpCode::GetUpval => "GETUPVAL",
+            OpCode::GetTabUp => "GETTABUP",
+            OpCode::GetTable => "GETTABLE",
+            OpCode::SetTabUp => "SETTABUP",
+            OpCode::SetUpval => "SETUPVAL",
+            OpCode::SetTable => "SETTABLE",
+            OpCode::NewTable => "NEWTABLE",
+            OpCode::Self_ => "SELF",
+            OpCode::Add => "ADD",
+            OpCode::Sub => "SUB",
+            OpCode::Mul => "MUL",
+            OpCode::Mod => "MOD",
+            OpCode::Pow => "POW",
+            OpCode::Div => "DIV",
+            OpCode::IDiv => "IDIV",
+            OpCode::BAnd => "BAND",
+            OpCode::BOr => "BOR",
+            OpCode::BXor => "BXOR",
+            OpCode::Shl => "SHL",
+            OpCode::Shr => "SHR",
+            OpCode::Unm => "UNM",
+            OpCode::BNot => "BNOT",
+            OpCode::Not => "NOT",
+            OpCode::Len => "LEN",
+            OpCode::Concat => "CONCAT",
+            OpCode::Jmp => "JMP",
+            OpCode::Eq => "EQ",
+            OpCode::Lt => "LT",
+            OpCode::Le => "LE",
+            OpCode::Test => "TEST",
+            OpCode::TestSet => "TESTSET",
+            OpCode::Call => "CALL",
+            OpCode::TailCall => "TAILCALL",
+            OpCode::Return => "RETURN",
+            OpCode::ForLoop => "FORLOOP",
+            OpCode::ForPrep => "FORPREP",
+            OpCode::TForCall => "TFORCALL",
+            OpCode::TForLoop => "TFORLOOP",
+            OpCode::SetList => "SETLIST",
+            OpCode::Closure => "CLOSURE",
+            OpCode::VarArg => "VARARG",
+            OpCode::ExtraArg => "EXTRAARG",
+        }
+    }
+
+    pub fn mode(self) -> OpMode {
+        match self {
+            OpCode::LoadK | OpCode::LoadKX | OpCode::Closure => OpMode::ABx,
+            OpCode::Jmp | OpCode::ForLoop | OpCode::ForPrep | OpCode::TForLoop => OpMode::AsBx,
+            OpCode::ExtraArg => OpMode::Ax,
+            _ => OpMode::ABC,
+        }
+    }
+
+    // modes of the B (or Bx) and C arguments
+    pub fn arg_modes(self) -> (OpArg, OpArg) {
+        match self {
+            OpCode::Move
+            | OpCode::Unm
+            | OpCode::BNot
+            | OpCode::Not
+            | OpCode::Len
+            | OpCode::Jmp
+            | OpCode::ForLoop
+            | OpCode::ForPrep
+            | OpCode::TForLoop => (OpArg::R, OpArg::N),
+            OpCode::LoadK => (OpArg::K, OpArg::N),
+            OpCode::LoadKX => (OpArg::N, OpArg::N),
+            OpCode::LoadNil
+            | OpCode::GetUpval
+            | OpCode::SetUpval
+            | OpCode::Return
+            | OpCode::Closure
+            | OpCode::VarArg => (OpArg::U, OpArg::N),
+            OpCode::GetTabUp => (OpArg::U, OpArg::K),
+            OpCode::GetTable | OpCode::Self_ => (OpArg::R, OpArg::K),
+            OpCode::Concat => (OpArg::R, OpArg::R),
+            OpCode::Test | OpCode::TForCall => (OpArg::N, OpArg::U),
+            OpCode::TestSet => (OpArg::R, OpArg::U),
+            OpCode::LoadBool
+            | OpCode::NewTable
+            | OpCode::Call
+            | OpCode::TailCall
+            | OpCode::SetList
+            | OpCode::ExtraArg => (OpArg::U, OpArg::U),
+            _ => (OpArg::K, OpArg::K),
+        }
+    }
+
+    // a test, the next instruction is the jump it skips or not
+    pub fn is_test(self) -> bool {
+        matches!(
+            self,
+            OpCode::Eq | OpCode::Lt | OpCode::Le | OpCode::Test | OpCode::TestSet
+        )
+    }
+}
+
+impl fmt::Display for OpCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Instruction(pub u32);
+
+impl Instruction {
+    pub fn abc(op: OpCode, a: u32, b: u32, c: u32) -> Self {
+        Instruction(op as u32 | a << POS_A | b << POS_B | c << POS_C)
+    }
+
+    pub fn abx(op: OpCode, a: u32, bx: u32) -> Self {
+        Instruction(op as u32 | a << POS_A | bx << POS_BX)
+    }
+
+    pub fn asbx(op: OpCode, a: u32, sbx: i32) -> Self {
+        Instruction::abx(op, a, (sbx + MAXARG_SBX) as u32)
+    }
+
+    pub fn ax(op: OpCode, ax: u32) -> Self {
+        Instruction(op as u32 | ax << POS_AX)
+    }
+
+    // none for the unused opcode numbers
+    pub fn opcode(self) -> Option<OpCode> {
+        OpCode::from_u8((self.0 & 0x3f) as u8)
+    }
+
+    pub fn a(self) -> u32 {
+        self.0 >> POS_A & MAXARG_A
+    }
+
+    pub fn b(self) -> u32 {
+        self.0 >> POS_B & MAXARG_B
+    }
+
+    pub fn c(self) -> u32 {
+        self.0 >> POS_C & MAXARG_C
+    }
+
+    pub fn bx(self) -> u32 {
+        self.0 >> POS_BX & MAXARG_BX
+    }
+
+    pub fn sbx(self) -> i32 {
+        self.bx() as i32 - MAXARG_SBX
+    }
+
+    pub fn ax_arg(self) -> u32 {
+        self.0 >> POS_AX & MAXARG_AX
+    }
+
+    pub(crate) fn set_opcode(&mut self, op: OpCode) {
+        self.0 = self.0 & !0x3f | op as u32;
+    }
+
+    pub(crate) fn set_a(&mut self, a: u32) {
+        self.0 = self.0 & !(MAXARG_A << POS_A) | a << POS_A;
+    }
+
+    pub(crate) fn set_b(&mut self, b: u32) {
+        self.0 = self.0 & !(MAXARG_B << POS_B) | b << POS_B;
+    }
+
+    pub(crate) fn set_c(&mut self, c: u32) {
+        self.0 = self.0 & !(MAXARG_C << POS_C) | c << POS_C;
+    }
+
+    pub(crate) fn set_sbx(&mut self, sbx: i32) {
+        let bx = (sbx + MAXARG_SBX) as u32;
+        self.0 = self.0 & !(MAXARG_BX << POS_BX) | bx << POS_BX;
+    }
+}
+
+// the opcode and arguments as `luac -l` lists them, constants as -1 - index
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = match self.opcode() {
+            Some(op) => op,
+            None => return write!(f, "{:#010x}", self.0),
+        };
+        let rk = |arg: u32| {
+            if is_k(arg) {
+                -1 - index_k(arg) as i64
+            } else {
+                arg as i64
+            }
+        };
+        write!(f, "{:<9}\t", op.name())?;
+        let (b, c) = op.arg_modes();
+        match op.mode() {
+            OpMode::ABC => {
+                write!(f, "{}", self.a())?;
+                if b != OpArg::N {
+                    write!(f, " {}", rk(self.b()))?;
+                }
+                if c != OpArg::N {
+                    write!(f, " {}", rk(self.c()))?;
+                }
+                Ok(())
+            }
+            OpMode::ABx => match b {
+                OpArg::K => write!(f, "{} {}", self.a(), -1 - self.bx() as i64),
+                OpArg::U => write!(f, "{} {}", self.a(), self.bx()),
+                _ => write!(f, "{}", self.a()),
+            },
+            OpMode::AsBx => write!(f, "{} {}", self.a(), self.sbx()),
+            OpMode::Ax => write!(f, "{}", -1 - self.ax_arg() as i64),
+        }
+    }
+}
+
+// whether an RK argument is a constant, and its index then
+pub fn is_k(rk: u32) -> bool {
+    rk & BITRK != 0
+}
+
+pub fn index_k(rk: u32) -> u32 {
+    rk & !BITRK
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Constant {
+    Nil,
+    Boolean(bool),
+    Int(IntType),
+    Float(FloatType),
+    Str(Vec<u8>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct UpvalueDesc {
+    // empty when stripped
+    pub name: String,
+    // a local of the enclosing function, otherwise one of its upvalues
+    pub in_stack: bool,
+    pub index: u8,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LocalVar {
+    pub name: String,
+    // the instructions the local is active for
+    pub start_pc: usize,
+    pub end_pc: usize,
+}
+
+// a compiled function, the main chunk is a vararg function with the upvalue `_ENV`
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct Proto {
+    // chunk name like `@file.lua`, that of the parent in nested functions
+    pub source: Option<String>,
+    pub line_defined: usize,
+    pub last_line_defined: usize,
+    pub num_params: u8,
+    pub is_vararg: bool,
+    pub max_stack_size: u8,
+    pub code: Vec<Instruction>,
+    pub constants: Vec<Constant>,
+    pub upvalues: Vec<UpvalueDesc>,
+    pub protos: Vec<Proto>,
+    // debug info, empty when stripped
+    pub line_info: Vec<usize>,
+    pub local_vars: Vec<LocalVar>,
+}
+
+impl Proto {
+    // binary chunk the stock `lua` 5.3 loads, as `luac` writes it for 64-bit targets
+    pub fn dump(&self, strip: bool) -> Vec<u8> {
+        let mut dumper = Dumper {
+            output: Vec::new(),
+            strip,
+        };
+        dumper.header();
+        dumper.byte(self.upvalues.len() as u8);
+        dumper.function(self, None);
+        dumper.output
+    }
+}
+
+struct Dumper {
+    output: Vec<u8>,
+    strip: bool,
+}
+
+impl Dumper {
+    fn byte(&mut self, b: u8) {
+        self.output.push(b);
+    }
+
+    fn int(&mut self, i: usize) {
+        self.output.extend_from_slice(&(i as u32).to_le_bytes());
+    }
+
+    fn size(&mut self, size: usize) {
+        self.output.extend_from_slice(&(size as u64).to_le_bytes());
+    }
+
+    fn integer(&mut self, i: IntType) {
+        self.output.extend_from_slice(&i.to_le_bytes());
+    }
+
+    fn number(&mut self, f: FloatType) {
+        self.output.extend_from_slice(&f.to_le_bytes());
+    }
+
+    // length plus one, 0 is the null string
+    fn string(&mut self, s: Option<&[u8]>) {
+        match s {
+            None => self.byte(0),
+            Some(s) => {
+                let size = s.len() + 1;
+                if size < 0xff {
+                    self.byte(size as u8);
+                } else {
+                    self.byte(0xff);
+                    self.size(size);
+                }
+                self.output.extend_from_slice(s);
+            }
+        }
+    }
+
+    fn header(&mut self) {
+        self.output.extend_from_slice(SIGNATURE);
+        self.byte(LUAC_VERSION);
+        self.byte(LUAC_FORMAT);
+        self.output.extend_from_slice(LUAC_DATA);
+        // sizes of int, size_t, Instruction, lua_Integer and lua_Number
+        for size in [4, 8, 4, 8, 8].iter() {
+            self.byte(*size);
+        }
+        self.integer(LUAC_INT);
+        self.number(LUAC_NUM);
+    }
+
+    fn function(&mut self, proto: &Proto, parent_source: Option<&str>) {
+        if self.strip || proto.source.as_deref() == parent_source {
+            self.string(None);
+        } else {
+            self.string(proto.source.as_deref().map(str::as_bytes));
+        }
+        self.int(proto.line_defined);
+        self.int(proto.last_line_defined);
+        self.byte(proto.num_params);
+        self.byte(proto.is_vararg as u8);
+        self.byte(proto.max_stack_size);
+        self.int(proto.code.len());
+        for instruction in proto.code.iter() {
+            self.output.extend_from_slice(&instruction.0.to_le_bytes());
+        }
+        self.int(proto.constants.len());
+        for constant in proto.constants.iter() {
+            match constant {
+                Constant::Nil => self.byte(0),
+                Constant::Boolean(b) => {
+                    self.byte(1);
+                    self.byte(*b as u8);
+                }
+                Constant::Float(f) => {
+                    self.byte(3);
+                    self.number(*f);
+                }
+                Constant::Int(i) => {
+                    self.byte(3 | 1 << 4);
+                    self.integer(*i);
+                }
+                Constant::Str(s) => {
+                    self.byte(if s.len() <= MAX_SHORT_LEN {
+                        4
+                    } else {
+                        4 | 1 << 4
+                    });
+                    self.string(Some(s));
+                }
+            }
+        }
+        self.int(proto.upvalues.len());
+        for upvalue in proto.upvalues.iter() {
+            self.byte(upvalue.in_stack as u8);
+            self.byte(upvalue.index);
+        }
+        self.int(proto.protos.len());
+        for child in proto.protos.iter() {
+            self.function(child, proto.source.as_deref());
+        }
+        if self.strip {
+            self.int(0);
+            self.int(0);
+            self.int(0);
+            return;
+        }
+        self.int(proto.line_info.len());
+        for line in proto.line_info.iter() {
+            self.int(*line);
+        }
+        self.int(proto.local_vars.len());
+        for local in proto.local_vars.iter() {
+            self.string(Some(local.name.as_bytes()));
+            self.int(local.start_pc);
+            self.int(local.end_pc);
+        }
+        self.int(proto.upvalues.len());
+        for upvalue in proto.upvalues.iter() {
+            self.string(Some(upvalue.name.as_bytes()));
+        }
+    }
+}
@@ -0,0 +1,1960 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::ast::*;
+use crate::bytecode::*;
+use crate::resolver::Resolver;
+use crate::token::Span;
+use crate::types::{FloatType, IntType, Number};
+use crate::value::{self, float_to_int, Value};
+
+// end of a jump list
+const NO_JUMP: i32 = -1;
+// TESTSET target meaning the value is not needed
+const NO_REG: u32 = MAXARG_A;
+// results of a call or `...` when all of them are wanted
+const MULTRET: i32 = -1;
+const MAX_REGS: u32 = 255;
+const MAX_VARS: usize = 200;
+const MAX_UPVALUES: usize = 255;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompileError {
+    pub message: String,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl CompileError {
+    fn new(message: String, span: Span) -> Self {
+        CompileError {
+            message,
+            line: span.line,
+            col: span.col,
+        }
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[compile error] {} at line [{}:{}].",
+            self.message, self.line, self.col
+        )
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+// where the value of an expression is, `expdesc` of lparser.c
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum ExpKind {
+    // no value, an empty argument list
+    Void,
+    Nil,
+    True,
+    False,
+    // index in the constants
+    K(u32),
+    KFlt(FloatType),
+    KInt(IntType),
+    // in a fixed register
+    NonReloc(u32),
+    // register of a local
+    Local(u32),
+    // index in the upvalues
+    Upval(u32),
+    // table in a register or an upvalue, key as an RK argument
+    Indexed { t: u32, idx: u32, upval: bool },
+    // pc of the jump of a comparison
+    Jmp(i32),
+    // pc of an instruction whose target register is still to be set
+    Relocable(i32),
+    Call(i32),
+    VarArg(i32),
+}
+
+#[derive(Copy, Clone, Debug)]
+struct ExpDesc {
+    k: ExpKind,
+    // jump lists to patch when the value is true or false
+    t: i32,
+    f: i32,
+}
+
+impl ExpDesc {
+    fn new(k: ExpKind) -> Self {
+        ExpDesc {
+            k,
+            t: NO_JUMP,
+            f: NO_JUMP,
+        }
+    }
+
+    fn has_jumps(&self) -> bool {
+        self.t != self.f
+    }
+
+    fn has_multret(&self) -> bool {
+        matches!(self.k, ExpKind::Call(_) | ExpKind::VarArg(_))
+    }
+
+    // the register, pc or constant index the kind carries
+    fn info(&self) -> u32 {
+        match self.k {
+            ExpKind::K(i) | ExpKind::NonReloc(i) | ExpKind::Local(i) | ExpKind::Upval(i) => i,
+            ExpKind::Jmp(pc) | ExpKind::Relocable(pc) | ExpKind::Call(pc) | ExpKind::VarArg(pc) => {
+                pc as u32
+            }
+            _ => 0,
+        }
+    }
+
+    fn numeral(&self) -> Option<Number> {
+        if self.has_jumps() {
+            return None;
+        }
+        match self.k {
+            ExpKind::KInt(i) => Some(Number::Int(i)),
+            ExpKind::KFlt(f) => Some(Number::Float(f)),
+            _ => None,
+        }
+    }
+}
+
+// constants are looked up by value, integers and floats apart
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum ConstKey {
+    Nil,
+    Boolean(bool),
+    Int(IntType),
+    Float(u64),
+    Str(Vec<u8>),
+}
+
+#[derive(Copy, Clone)]
+struct BlockCnt {
+    // labels and pending gotos of the block start at these
+    first_label: usize,
+    first_goto: usize,
+    // active locals outside the block
+    nactvar: usize,
+    // some local of the block is captured by a closure
+    upval: bool,
+    is_loop: bool,
+}
+
+struct LabelDesc {
+    name: String,
+    pc: i32,
+    span: Span,
+    // active locals where it appears
+    nactvar: usize,
+}
+
+// a function being compiled, the `FuncState` of lparser.c with the code generation of lcode.c
+struct FuncState {
+    proto: Proto,
+    max_stack: u32,
+    constants: HashMap<ConstKey, u32>,
+    blocks: Vec<BlockCnt>,
+    // pc of the last jump target
+    last_target: usize,
+    // jumps to the next instruction, patched when it is coded
+    jpc: i32,
+    nactvar: usize,
+    freereg: u32,
+    // declared locals as indexes in `proto.local_vars`, the active ones first
+    actvar: Vec<usize>,
+    labels: Vec<LabelDesc>,
+    gotos: Vec<LabelDesc>,
+    // node being compiled, for line info and errors
+    span: Span,
+    // last line seen, stands in for the line of `end`
+    last_line: usize,
+    error: Option<CompileError>,
+}
+
+impl FuncState {
+    fn new(source: &str, line_defined: usize) -> Self {
+        FuncState {
+            proto: Proto {
+                source: Some(source.to_string()),
+                line_defined,
+                ..Proto::default()
+            },
+            max_stack: 2,
+            constants: HashMap::new(),
+            blocks: Vec::new(),
+            last_target: 0,
+            jpc: NO_JUMP,
+            nactvar: 0,
+            freereg: 0,
+            actvar: Vec::new(),
+            labels: Vec::new(),
+            gotos: Vec::new(),
+            span: Span::default(),
+            last_line: line_defined,
+            error: None,
+        }
+    }
+
+    fn fail(&mut self, message: String) {
+        self.fail_at(message, self.span);
+    }
+
+    fn fail_at(&mut self, message: String, span: Span) {
+        if self.error.is_none() {
+            self.error = Some(CompileError::new(message, span));
+        }
+    }
+
+    fn limit_error(&mut self, what: &str, limit: usize) {
+        let place = match self.proto.line_defined {
+            0 => "main function".to_string(),
+            line => format!("function at line {}", line),
+        };
+        self.fail(format!(
+            "too many {} (limit is {}) in {}",
+            what, limit, place
+        ));
+    }
+
+    fn pc(&self) -> usize {
+        self.proto.code.len()
+    }
+
+    fn code(&mut self, i: Instruction) -> i32 {
+        self.discharge_jpc();
+        self.proto.code.push(i);
+        self.proto.line_info.push(self.span.line);
+        self.pc() as i32 - 1
+    }
+
+    fn code_abc(&mut self, op: OpCode, a: u32, b: u32, c: u32) -> i32 {
+        self.code(Instruction::abc(op, a, b, c))
+    }
+
+    fn code_abx(&mut self, op: OpCode, a: u32, bx: u32) -> i32 {
+        self.code(Instruction::abx(op, a, bx))
+    }
+
+    fn code_asbx(&mut self, op: OpCode, a: u32, sbx: i32) -> i32 {
+        self.code(Instruction::asbx(op, a, sbx))
+    }
+
+    fn code_k(&mut self, reg: u32, k: u32) -> i32 {
+        if k <= MAXARG_BX {
+            self.code_abx(OpCode::LoadK, reg, k)
+        } else {
+            let pc = self.code_abx(OpCode::LoadKX, reg, 0);
+            self.code(Instruction::ax(OpCode::ExtraArg, k));
+            pc
+        }
+    }
+
+    fn fix_line(&mut self, line: usize) {
+        if let Some(last) = self.proto.line_info.last_mut() {
+            *last = line;
+        }
+    }
+
+    fn instruction(&mut self, pc: i32) -> &mut Instruction {
+        &mut self.proto.code[pc as usize]
+    }
+
+    // LOADNIL, merged with a previous one setting adjacent registers
+    fn nil(&mut self, from: u32, n: u32) {
+        let mut from = from;
+        let mut last = from + n - 1;
+        if self.pc() > self.last_target && self.pc() > 0 {
+            let previous = self.proto.code[self.pc() - 1];
+            if previous.opcode() == Some(OpCode::LoadNil) {
+                let pfrom = previous.a();
+                let plast = pfrom + previous.b();
+                if (pfrom <= from && from <= plast + 1) || (from <= pfrom && pfrom <= last + 1) {
+                    from = from.min(pfrom);
+                    last = last.max(plast);
+                    let previous = self.instruction(self.pc() as i32 - 1);
+                    previous.set_a(from);
+                    previous.set_b(last - from);
+                    return;
+                }
+            }
+        }
+        self.code_abc(OpCode::LoadNil, from, n - 1, 0);
+    }
+
+    fn jump(&mut self) -> i32 {
+        let jpc = self.jpc;
+        self.jpc = NO_JUMP;
+        let mut j = self.code_asbx(OpCode::Jmp, 0, NO_JUMP);
+        self.concat(&mut j, jpc);
+        j
+    }
+
+    fn ret(&mut self, first: u32, nret: i32) {
+        self.code_abc(OpCode::Return, first, (nret + 1) as u32, 0);
+    }
+
+    fn cond_jump(&mut self, op: OpCode, a: u32, b: u32, c: u32) -> i32 {
+        self.code_abc(op, a, b, c);
+        self.jump()
+    }
+
+    fn fix_jump(&mut self, pc: i32, dest: i32) {
+        let offset = dest - (pc + 1);
+        if offset.abs() > MAXARG_SBX {
+            self.fail("control structure too long".to_string());
+            return;
+        }
+        self.instruction(pc).set_sbx(offset);
+    }
+
+    // marks the current pc as a jump target
+    fn get_label(&mut self) -> i32 {
+        self.last_target = self.pc();
+        self.pc() as i32
+    }
+
+    fn get_jump(&self, pc: i32) -> i32 {
+        match self.proto.code[pc as usize].sbx() {
+            NO_JUMP => NO_JUMP,
+            offset => pc + 1 + offset,
+        }
+    }
+
+    // the test a jump belongs to, or the jump itself
+    fn jump_control(&self, pc: i32) -> i32 {
+        let pc = pc as usize;
+        match pc.checked_sub(1).and_then(|p| self.proto.code[p].opcode()) {
+            Some(op) if op.is_test() => pc as i32 - 1,
+            _ => pc as i32,
+        }
+    }
+
+    // some jump of the list needs a boolean value produced
+    fn need_value(&self, mut list: i32) -> bool {
+        while list != NO_JUMP {
+            let i = self.proto.code[self.jump_control(list) as usize];
+            if i.opcode() != Some(OpCode::TestSet) {
+                return true;
+            }
+            list = self.get_jump(list);
+        }
+        false
+    }
+
+    // a TESTSET copies into `reg`, or becomes a TEST without a register
+    fn patch_test_reg(&mut self, node: i32, reg: u32) -> bool {
+        let pc = self.jump_control(node);
+        let i = self.proto.code[pc as usize];
+        if i.opcode() != Some(OpCode::TestSet) {
+            return false;
+        }
+        if reg != NO_REG && reg != i.b() {
+            self.instruction(pc).set_a(reg);
+        } else {
+            *self.instruction(pc) = Instruction::abc(OpCode::Test, i.b(), 0, i.c());
+        }
+        true
+    }
+
+    fn remove_values(&mut self, mut list: i32) {
+        while list != NO_JUMP {
+            self.patch_test_reg(list, NO_REG);
+            list = self.get_jump(list);
+        }
+    }
+
+    fn patch_list_aux(&mut self, mut list: i32, vtarget: i32, reg: u32, dtarget: i32) {
+        while list != NO_JUMP {
+            let next = self.get_jump(list);
+            if self.patch_test_reg(list, reg) {
+                self.fix_jump(list, vtarget);
+            } else {
+                self.fix_jump(list, dtarget);
+            }
+            list = next;
+        }
+    }
+
+    fn discharge_jpc(&mut self) {
+        let pc = self.pc() as i32;
+        self.patch_list_aux(self.jpc, pc, NO_REG, pc);
+        self.jpc = NO_JUMP;
+    }
+
+    fn patch_to_here(&mut self, list: i32) {
+        self.get_label();
+        let mut jpc = self.jpc;
+        self.concat(&mut jpc, list);
+        self.jpc = jpc;
+    }
+
+    fn patch_list(&mut self, list: i32, target: i32) {
+        if target == self.pc() as i32 {
+            self.patch_to_here(list);
+        } else {
+            self.patch_list_aux(list, target, NO_REG, target);
+        }
+    }
+
+    // the jumps also close the upvalues from `level` up
+    fn patch_close(&mut self, mut list: i32, level: usize) {
+        while list != NO_JUMP {
+            self.instruction(list).set_a(level as u32 + 1);
+            list = self.get_jump(list);
+        }
+    }
+
+    fn concat(&mut self, l1: &mut i32, l2: i32) {
+        if l2 == NO_JUMP {
+            return;
+        }
+        if *l1 == NO_JUMP {
+            *l1 = l2;
+            return;
+        }
+        let mut list = *l1;
+        loop {
+            let next = self.get_jump(list);
+            if next == NO_JUMP {
+                break;
+            }
+            list = next;
+        }
+        self.fix_jump(list, l2);
+    }
+
+    fn check_stack(&mut self, n: u32) {
+        let size = self.freereg + n;
+        if size > self.max_stack {
+            if size >= MAX_REGS {
+                self.fail("function or expression needs too many registers".to_string());
+                return;
+            }
+            self.max_stack = size;
+        }
+    }
+
+    fn reserve_regs(&mut self, n: u32) {
+        self.check_stack(n);
+        self.freereg += n;
+    }
+
+    fn free_reg(&mut self, reg: u32) {
+        if !is_k(reg) && reg as usize >= self.nactvar && self.freereg > 0 {
+            self.freereg -= 1;
+        }
+    }
+
+    fn free_exp(&mut self, e: &ExpDesc) {
+        if let ExpKind::NonReloc(reg) = e.k {
+            self.free_reg(reg);
+        }
+    }
+
+    // the higher register first, as registers are freed in stack order
+    fn free_exps(&mut self, e1: &ExpDesc, e2: &ExpDesc) {
+        let reg = |e: &ExpDesc| match e.k {
+            ExpKind::NonReloc(reg) => Some(reg),
+            _ => None,
+        };
+        let (r1, r2) = (reg(e1), reg(e2));
+        let (first, second) = if r1 > r2 { (r1, r2) } else { (r2, r1) };
+        for reg in first.into_iter().chain(second) {
+            self.free_reg(reg);
+        }
+    }
+
+    fn add_k(&mut self, key: ConstKey, constant: Constant) -> u32 {
+        if let Some(index) = self.constants.get(&key) {
+            return *index;
+        }
+        let index = self.proto.constants.len() as u32;
+        self.proto.constants.push(constant);
+        self.constants.insert(key, index);
+        index
+    }
+
+    fn string_k(&mut self, s: &str) -> u32 {
+        let s = s.as_bytes().to_vec();
+        self.add_k(ConstKey::Str(s.clone()), Constant::Str(s))
+    }
+
+    fn int_k(&mut self, i: IntType) -> u32 {
+        self.add_k(ConstKey::Int(i), Constant::Int(i))
+    }
+
+    fn number_k(&mut self, f: FloatType) -> u32 {
+        self.add_k(ConstKey::Float(f.to_bits()), Constant::Float(f))
+    }
+
+    fn bool_k(&mut self, b: bool) -> u32 {
+        self.add_k(ConstKey::Boolean(b), Constant::Boolean(b))
+    }
+
+    fn nil_k(&mut self) -> u32 {
+        self.add_k(ConstKey::Nil, Constant::Nil)
+    }
+
+    fn set_returns(&mut self, e: &mut ExpDesc, nresults: i32) {
+        match e.k {
+            ExpKind::Call(pc) => self.instruction(pc).set_c((nresults + 1) as u32),
+            ExpKind::VarArg(pc) => {
+                let freereg = self.freereg;
+                let i = self.instruction(pc);
+                i.set_b((nresults + 1) as u32);
+                i.set_a(freereg);
+                self.reserve_regs(1);
+            }
+            _ => (),
+        }
+    }
+
+    fn set_one_ret(&mut self, e: &mut ExpDesc) {
+        match e.k {
+            ExpKind::Call(pc) => e.k = ExpKind::NonReloc(self.proto.code[pc as usize].a()),
+            ExpKind::VarArg(pc) => {
+                self.instruction(pc).set_b(2);
+                e.k = ExpKind::Relocable(pc);
+            }
+            _ => (),
+        }
+    }
+
+    // variables become values
+    fn discharge_vars(&mut self, e: &mut ExpDesc) {
+        match e.k {
+            ExpKind::Local(reg) => e.k = ExpKind::NonReloc(reg),
+            ExpKind::Upval(index) => {
+                e.k = ExpKind::Relocable(self.code_abc(OpCode::GetUpval, 0, index, 0));
+            }
+            ExpKind::Indexed { t, idx, upval } => {
+                self.free_reg(idx);
+                let op = if upval {
+                    OpCode::GetTabUp
+                } else {
+                    self.free_reg(t);
+                    OpCode::GetTable
+                };
+                e.k = ExpKind::Relocable(self.code_abc(op, 0, t, idx));
+            }
+            ExpKind::Call(_) | ExpKind::VarArg(_) => self.set_one_ret(e),
+            _ => (),
+        }
+    }
+
+    fn discharge_to_reg(&mut self, e: &mut ExpDesc, reg: u32) {
+        self.discharge_vars(e);
+        match e.k {
+            ExpKind::Nil => self.nil(reg, 1),
+            ExpKind::False | ExpKind::True => {
+                self.code_abc(OpCode::LoadBool, reg, (e.k == ExpKind::True) as u32, 0);
+            }
+            ExpKind::K(k) => {
+                self.code_k(reg, k);
+            }
+            ExpKind::KFlt(f) => {
+                let k = self.number_k(f);
+                self.code_k(reg, k);
+            }
+            ExpKind::KInt(i) => {
+                let k = self.int_k(i);
+                self.code_k(reg, k);
+            }
+            ExpKind::Relocable(pc) => self.instruction(pc).set_a(reg),
+            ExpKind::NonReloc(r) => {
+                if r != reg {
+                    self.code_abc(OpCode::Move, reg, r, 0);
+                }
+            }
+            _ => return,
+        }
+        e.k = ExpKind::NonReloc(reg);
+    }
+
+    fn discharge_to_any_reg(&mut self, e: &mut ExpDesc) {
+        if let ExpKind::NonReloc(_) = e.k {
+            return;
+        }
+        self.reserve_regs(1);
+        let reg = self.freereg - 1;
+        self.discharge_to_reg(e, reg);
+    }
+
+    fn code_load_bool(&mut self, a: u32, b: u32, jump: u32) -> i32 {
+        self.get_label();
+        self.code_abc(OpCode::LoadBool, a, b, jump)
+    }
+
+    // the value in `reg`, jumps of a condition produce booleans there
+    fn exp_to_reg(&mut self, e: &mut ExpDesc, reg: u32) {
+        self.discharge_to_reg(e, reg);
+        if let ExpKind::Jmp(pc) = e.k {
+            let mut t = e.t;
+            self.concat(&mut t, pc);
+            e.t = t;
+        }
+        if e.has_jumps() {
+            let mut load_false = NO_JUMP;
+            let mut load_true = NO_JUMP;
+            if self.need_value(e.t) || self.need_value(e.f) {
+                let fj = match e.k {
+                    ExpKind::Jmp(_) => NO_JUMP,
+                    _ => self.jump(),
+                };
+                load_false = self.code_load_bool(reg, 0, 1);
+                load_true = self.code_load_bool(reg, 1, 0);
+                self.patch_to_here(fj);
+            }
+            let end = self.get_label();
+            self.patch_list_aux(e.f, end, reg, load_false);
+            self.patch_list_aux(e.t, end, reg, load_true);
+        }
+        e.t = NO_JUMP;
+        e.f = NO_JUMP;
+        e.k = ExpKind::NonReloc(reg);
+    }
+
+    fn exp_to_next_reg(&mut self, e: &mut ExpDesc) {
+        self.discharge_vars(e);
+        self.free_exp(e);
+        self.reserve_regs(1);
+        let reg = self.freereg - 1;
+        self.exp_to_reg(e, reg);
+    }
+
+    fn exp_to_any_reg(&mut self, e: &mut ExpDesc) -> u32 {
+        self.discharge_vars(e);
+        if let ExpKind::NonReloc(reg) = e.k {
+            if !e.has_jumps() {
+                return reg;
+            }
+            // a temporary can hold the boolean
+            if reg as usize >= self.nactvar {
+                self.exp_to_reg(e, reg);
+                return reg;
+            }
+        }
+        self.exp_to_next_reg(e);
+        e.info()
+    }
+
+    fn exp_to_any_reg_up(&mut self, e: &mut ExpDesc) {
+        if !matches!(e.k, ExpKind::Upval(_)) || e.has_jumps() {
+            self.exp_to_any_reg(e);
+        }
+    }
+
+    fn exp_to_val(&mut self, e: &mut ExpDesc) {
+        if e.has_jumps() {
+            self.exp_to_any_reg(e);
+        } else {
+            self.discharge_vars(e);
+        }
+    }
+
+    // a register or a constant as an RK argument
+    fn exp_to_rk(&mut self, e: &mut ExpDesc) -> u32 {
+        self.exp_to_val(e);
+        let k = match e.k {
+            ExpKind::True => Some(self.bool_k(true)),
+            ExpKind::False => Some(self.bool_k(false)),
+            ExpKind::Nil => Some(self.nil_k()),
+            ExpKind::KInt(i) => Some(self.int_k(i)),
+            ExpKind::KFlt(f) => Some(self.number_k(f)),
+            ExpKind::K(k) => Some(k),
+            _ => None,
+        };
+        if let Some(k) = k {
+            e.k = ExpKind::K(k);
+            if k <= MAXINDEXRK {
+                return k | BITRK;
+            }
+        }
+        self.exp_to_any_reg(e)
+    }
+
+    fn store_var(&mut self, var: &ExpDesc, ex: &mut ExpDesc) {
+        match var.k {
+            ExpKind::Local(reg) => {
+                self.free_exp(ex);
+                self.exp_to_reg(ex, reg);
+                return;
+            }
+            ExpKind::Upval(index) => {
+                let e = self.exp_to_any_reg(ex);
+                self.code_abc(OpCode::SetUpval, e, index, 0);
+            }
+            ExpKind::Indexed { t, idx, upval } => {
+                let op = if upval {
+                    OpCode::SetTabUp
+                } else {
+                    OpCode::SetTable
+                };
+                let e = self.exp_to_rk(ex);
+                self.code_abc(op, t, idx, e);
+            }
+            _ => unreachable!("store to a value"),
+        }
+        self.free_exp(ex);
+    }
+
+    // SELF: the method and its object in two consecutive registers
+    fn self_(&mut self, e: &mut ExpDesc, key: &mut ExpDesc) {
+        self.exp_to_any_reg(e);
+        let reg = e.info();
+        self.free_exp(e);
+        let base = self.freereg;
+        e.k = ExpKind::NonReloc(base);
+        self.reserve_regs(2);
+        let k = self.exp_to_rk(key);
+        self.code_abc(OpCode::Self_, base, reg, k);
+        self.free_exp(key);
+    }
+
+    fn negate_condition(&mut self, pc: i32) {
+        let pc = self.jump_control(pc);
+        let i = self.instruction(pc);
+        let a = i.a();
+        i.set_a((a == 0) as u32);
+    }
+
+    fn jump_on_cond(&mut self, e: &mut ExpDesc, cond: bool) -> i32 {
+        if let ExpKind::Relocable(pc) = e.k {
+            let i = self.proto.code[pc as usize];
+            // `not x` tests `x` the other way
+            if i.opcode() == Some(OpCode::Not) {
+                self.proto.code.pop();
+                self.proto.line_info.pop();
+                return self.cond_jump(OpCode::Test, i.b(), 0, !cond as u32);
+            }
+        }
+        self.discharge_to_any_reg(e);
+        self.free_exp(e);
+        self.cond_jump(OpCode::TestSet, NO_REG, e.info(), cond as u32)
+    }
+
+    // falls through when true, jumps on the false list otherwise
+    fn go_if_true(&mut self, e: &mut ExpDesc) {
+        self.discharge_vars(e);
+        let pc = match e.k {
+            ExpKind::Jmp(pc) => {
+                self.negate_condition(pc);
+                pc
+            }
+            ExpKind::K(_) | ExpKind::KFlt(_) | ExpKind::KInt(_) | ExpKind::True => NO_JUMP,
+            _ => self.jump_on_cond(e, false),
+        };
+        let mut f = e.f;
+        self.concat(&mut f, pc);
+        e.f = f;
+        self.patch_to_here(e.t);
+        e.t = NO_JUMP;
+    }
+
+    fn go_if_false(&mut self, e: &mut ExpDesc) {
+        self.discharge_vars(e);
+        let pc = match e.k {
+            ExpKind::Jmp(pc) => pc,
+            ExpKind::Nil | ExpKind::False => NO_JUMP,
+            _ => self.jump_on_cond(e, true),
+        };
+        let mut t = e.t;
+        self.concat(&mut t, pc);
+        e.t = t;
+        self.patch_to_here(e.f);
+        e.f = NO_JUMP;
+    }
+
+    fn code_not(&mut self, e: &mut ExpDesc) {
+        self.discharge_vars(e);
+        match e.k {
+            ExpKind::Nil | ExpKind::False => e.k = ExpKind::True,
+            ExpKind::K(_) | ExpKind::KFlt(_) | ExpKind::KInt(_) | ExpKind::True => {
+                e.k = ExpKind::False
+            }
+            ExpKind::Jmp(pc) => self.negate_condition(pc),
+            ExpKind::Relocable(_) | ExpKind::NonReloc(_) => {
+                self.discharge_to_any_reg(e);
+                self.free_exp(e);
+                e.k = ExpKind::Relocable(self.code_abc(OpCode::Not, 0, e.info(), 0));
+            }
+            _ => unreachable!("not of a variable"),
+        }
+        std::mem::swap(&mut e.f, &mut e.t);
+        self.remove_values(e.f);
+        self.remove_values(e.t);
+    }
+
+    fn indexed(&mut self, t: &mut ExpDesc, key: &mut ExpDesc) {
+        let table = t.info();
+        let upval = matches!(t.k, ExpKind::Upval(_));
+        let idx = self.exp_to_rk(key);
+        t.k = ExpKind::Indexed {
+            t: table,
+            idx,
+            upval,
+        };
+    }
+
+    // folds operators on numerals, except those raising errors or giving NaN or 0.0
+    fn const_folding(&mut self, op: BinOp, e1: &mut ExpDesc, e2: &ExpDesc) -> bool {
+        let (x, y) = match (e1.numeral(), e2.numeral()) {
+            (Some(x), Some(y)) => (x, y),
+            _ => return false,
+        };
+        let int = |n: Number| match n {
+            Number::Int(i) => Some(i),
+            Number::Float(f) => float_to_int(f),
+            Number::None => None,
+        };
+        let valid = match op {
+            BinOp::BAnd | BinOp::BOr | BinOp::BXor | BinOp::Shl | BinOp::Shr => {
+                int(x).is_some() && int(y).is_some()
+            }
+            BinOp::Div | BinOp::IDiv | BinOp::Mod => match y {
+                Number::Int(i) => i != 0,
+                Number::Float(f) => f != 0.0,
+                Number::None => false,
+            },
+            _ => true,
+        };
+        if !valid {
+            return false;
+        }
+        match value::arith(op, x, y) {
+            Some(Value::Int(i)) => e1.k = ExpKind::KInt(i),
+            Some(Value::Float(f)) if !f.is_nan() && f != 0.0 => e1.k = ExpKind::KFlt(f),
+            _ => return false,
+        }
+        true
+    }
+
+    fn code_unary(&mut self, op: OpCode, e: &mut ExpDesc, line: usize) {
+        let reg = self.exp_to_any_reg(e);
+        self.free_exp(e);
+        e.k = ExpKind::Relocable(self.code_abc(op, 0, reg, 0));
+        self.fix_line(line);
+    }
+
+    fn prefix(&mut self, op: UnOp, e: &mut ExpDesc, line: usize) {
+        match op {
+            UnOp::Minus | UnOp::BNot => {
+                let folded = match e.numeral() {
+                    Some(Number::Int(i)) if op == UnOp::Minus => {
+                        Some(ExpKind::KInt(i.wrapping_neg()))
+                    }
+                    Some(Number::Float(f)) if op == UnOp::Minus && f != 0.0 => {
+                        Some(ExpKind::KFlt(-f))
+                    }
+                    Some(Number::Int(i)) if op == UnOp::BNot => Some(ExpKind::KInt(!i)),
+                    Some(Number::Float(f)) if op == UnOp::BNot => {
+                        float_to_int(f).map(|i| ExpKind::KInt(!i))
+                    }
+                    _ => None,
+                };
+                match folded {
+                    Some(k) => e.k = k,
+                    None if op == UnOp::Minus => self.code_unary(OpCode::Unm, e, line),
+                    None => self.code_unary(OpCode::BNot, e, line),
+                }
+            }
+            UnOp::TLen => self.code_unary(OpCode::Len, e, line),
+            UnOp::Not => self.code_not(e),
+            UnOp::None => unreachable!(),
+        }
+    }
+
+    // the left operand before the right one is compiled
+    fn infix(&mut self, op: BinOp, e: &mut ExpDesc) {
+        match op {
+            BinOp::And => self.go_if_true(e),
+            BinOp::Or => self.go_if_false(e),
+            BinOp::Concat => self.exp_to_next_reg(e),
+            BinOp::Add
+            | BinOp::Minus
+            | BinOp::Mul
+            | BinOp::Div
+            | BinOp::IDiv
+            | BinOp::Mod
+            | BinOp::Pow
+            | BinOp::BAnd
+            | BinOp::BOr
+            | BinOp::BXor
+            | BinOp::Shl
+            | BinOp::Shr => {
+                // numerals are kept for folding
+                if e.numeral().is_none() {
+                    self.exp_to_rk(e);
+                }
+            }
+            _ => {
+                self.exp_to_rk(e);
+            }
+        }
+    }
+
+    fn code_binary(&mut self, op: OpCode, e1: &mut ExpDesc, e2: &mut ExpDesc, line: usize) {
+        let rk2 = self.exp_to_rk(e2);
+        let rk1 = self.exp_to_rk(e1);
+        self.free_exps(e1, e2);
+        e1.k = ExpKind::Relocable(self.code_abc(op, 0, rk1, rk2));
+        self.fix_line(line);
+    }
+
+    fn code_comp(&mut self, op: BinOp, e1: &mut ExpDesc, e2: &mut ExpDesc) {
+        let rk1 = match e1.k {
+            ExpKind::K(k) => k | BITRK,
+            _ => e1.info(),
+        };
+        let rk2 = self.exp_to_rk(e2);
+        self.free_exps(e1, e2);
+        let pc = match op {
+            BinOp::Ne => self.cond_jump(OpCode::Eq, 0, rk1, rk2),
+            // `a > b` is `b < a`
+            BinOp::Gt => self.cond_jump(OpCode::Lt, 1, rk2, rk1),
+            BinOp::Ge => self.cond_jump(OpCode::Le, 1, rk2, rk1),
+            BinOp::Eq => self.cond_jump(OpCode::Eq, 1, rk1, rk2),
+            BinOp::Lt => self.cond_jump(OpCode::Lt, 1, rk1, rk2),
+            _ => self.cond_jump(OpCode::Le, 1, rk1, rk2),
+        };
+        e1.k = ExpKind::Jmp(pc);
+    }
+
+    fn posfix(&mut self, op: BinOp, e1: &mut ExpDesc, e2: &mut ExpDesc, line: usize) {
+        match op {
+            BinOp::And => {
+                self.discharge_vars(e2);
+                let mut f = e2.f;
+                self.concat(&mut f, e1.f);
+                e2.f = f;
+                *e1 = *e2;
+            }
+            BinOp::Or => {
+                self.discharge_vars(e2);
+                let mut t = e2.t;
+                self.concat(&mut t, e1.t);
+                e2.t = t;
+                *e1 = *e2;
+            }
+            BinOp::Concat => {
+                self.exp_to_val(e2);
+                // `a .. b .. c` is a single CONCAT of consecutive registers
+                if let ExpKind::Relocable(pc) = e2.k {
+                    if self.proto.code[pc as usize].opcode() == Some(OpCode::Concat) {
+                        self.free_exp(e1);
+                        let reg = e1.info();
+                        self.instruction(pc).set_b(reg);
+                        e1.k = ExpKind::Relocable(pc);
+                        return;
+                    }
+                }
+                self.exp_to_next_reg(e2);
+                self.code_binary(OpCode::Concat, e1, e2, line);
+            }
+            BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => {
+                self.code_comp(op, e1, e2)
+            }
+            _ => {
+                if !self.const_folding(op, e1, e2) {
+                    let opcode = match op {
+                        BinOp::Add => OpCode::Add,
+                        BinOp::Minus => OpCode::Sub,
+                        BinOp::Mul => OpCode::Mul,
+                        BinOp::Mod => OpCode::Mod,
+                        BinOp::Pow => OpCode::Pow,
+                        BinOp::Div => OpCode::Div,
+                        BinOp::IDiv => OpCode::IDiv,
+                        BinOp::BAnd => OpCode::BAnd,
+                        BinOp::BOr => OpCode::BOr,
+                        BinOp::BXor => OpCode::BXor,
+                        BinOp::Shl => OpCode::Shl,
+                        BinOp::Shr => OpCode::Shr,
+                        _ => unreachable!(),
+                    };
+                    self.code_binary(opcode, e1, e2, line);
+                }
+            }
+        }
+    }
+
+    fn set_list(&mut self, base: u32, nelems: u32, tostore: i32) {
+        let c = (nelems.saturating_sub(1)) / LFIELDS_PER_FLUSH + 1;
+        let b = if tostore == MULTRET {
+            0
+        } else {
+            tostore as u32
+        };
+        if c <= MAXARG_C {
+            self.code_abc(OpCode::SetList, base, b, c);
+        } else if c <= MAXARG_AX {
+            self.code_abc(OpCode::SetList, base, b, 0);
+            self.code(Instruction::ax(OpCode::ExtraArg, c));
+        } else {
+            self.fail("constructor too long".to_string());
+        }
+        self.freereg = base + 1;
+    }
+
+    fn local_var(&mut self, i: usize) -> &mut LocalVar {
+        &mut self.proto.local_vars[self.actvar[i]]
+    }
+
+    fn new_local_var(&mut self, name: &str) {
+        if self.actvar.len() + 1 > MAX_VARS {
+            self.limit_error("local variables", MAX_VARS);
+        }
+        self.actvar.push(self.proto.local_vars.len());
+        self.proto.local_vars.push(LocalVar {
+            name: name.to_string(),
+            start_pc: 0,
+            end_pc: 0,
+        });
+    }
+
+    fn adjust_local_vars(&mut self, n: usize) {
+        self.nactvar += n;
+        let pc = self.pc();
+        for i in self.nactvar - n..self.nactvar {
+            self.local_var(i).start_pc = pc;
+        }
+    }
+
+    fn remove_vars(&mut self, level: usize) {
+        let pc = self.pc();
+        while self.nactvar > level {
+            self.nactvar -= 1;
+            self.local_var(self.nactvar).end_pc = pc;
+        }
+        self.actvar.truncate(level);
+    }
+
+    fn search_var(&self, name: &str) -> Option<usize> {
+        (0..self.nactvar)
+            .rev()
+            .find(|i| self.proto.local_vars[self.actvar[*i]].name == name)
+    }
+
+    // the block declaring the local of `level` must close upvalues
+    fn mark_upval(&mut self, level: usize) {
+        if let Some(block) = self.blocks.iter_mut().rev().find(|b| b.nactvar <= level) {
+            block.upval = true;
+        }
+    }
+
+    fn search_upvalue(&self, name: &str) -> Option<usize> {
+        self.proto.upvalues.iter().position(|u| u.name == name)
+    }
+
+    fn new_upvalue(&mut self, name: &str, var: ExpKind) -> u32 {
+        if self.proto.upvalues.len() + 1 > MAX_UPVALUES {
+            self.limit_error("upvalues", MAX_UPVALUES);
+        }
+        let (in_stack, index) = match var {
+            ExpKind::Local(reg) => (true, reg),
+            other => (false, ExpDesc::new(other).info()),
+        };
+        self.proto.upvalues.push(UpvalueDesc {
+            name: name.to_string(),
+            in_stack,
+            index: index as u8,
+        });
+        self.proto.upvalues.len() as u32 - 1
+    }
+
+    fn enter_block(&mut self, is_loop: bool) {
+        self.blocks.push(BlockCnt {
+            first_label: self.labels.len(),
+            first_goto: self.gotos.len(),
+            nactvar: self.nactvar,
+            upval: false,
+            is_loop,
+        });
+    }
+
+    fn leave_block(&mut self) {
+        let block = *self.blocks.last().unwrap();
+        let inner = self.blocks.len() > 1;
+        if inner && block.upval {
+            // a jump to here that closes the upvalues
+            let j = self.jump();
+            self.patch_close(j, block.nactvar);
+            self.patch_to_here(j);
+        }
+        if block.is_loop {
+            // pending breaks jump to the end of the loop
+            let pc = self.pc() as i32;
+            let label = self.new_label(true, "break", Span::default(), pc);
+            self.find_gotos(label);
+        }
+        self.blocks.pop();
+        self.remove_vars(block.nactvar);
+        self.freereg = self.nactvar as u32;
+        self.labels.truncate(block.first_label);
+        if inner {
+            self.move_gotos_out(block);
+        } else if let Some(goto) = self.gotos.get(block.first_goto) {
+            let message = if goto.name == "break" {
+                format!("<break> at line {} not inside a loop", goto.span.line)
+            } else {
+                format!(
+                    "no visible label '{}' for <goto> at line {}",
+                    goto.name, goto.span.line
+                )
+            };
+            let span = goto.span;
+            self.fail_at(message, span);
+        }
+    }
+
+    fn new_label(&mut self, label: bool, name: &str, span: Span, pc: i32) -> usize {
+        let list = if label {
+            &mut self.labels
+        } else {
+            &mut self.gotos
+        };
+        list.push(LabelDesc {
+            name: name.to_string(),
+            pc,
+            span,
+            nactvar: self.nactvar,
+        });
+        list.len() - 1
+    }
+
+    fn close_goto(&mut self, g: usize, label: usize) {
+        let (goto_nactvar, label_nactvar) = (self.gotos[g].nactvar, self.labels[label].nactvar);
+        if goto_nactvar < label_nactvar {
+            let local = self.local_var(goto_nactvar).name.clone();
+            let goto = &self.gotos[g];
+            let message = format!(
+                "<goto {}> at line {} jumps into the scope of local '{}'",
+                goto.name, goto.span.line, local
+            );
+            self.fail(message);
+        }
+        let (pc, target) = (self.gotos[g].pc, self.labels[label].pc);
+        self.patch_list(pc, target);
+        self.gotos.remove(g);
+    }
+
+    // closes the goto with a visible label of the current block
+    fn find_label(&mut self, g: usize) -> bool {
+        let block = *self.blocks.last().unwrap();
+        for i in block.first_label..self.labels.len() {
+            if self.labels[i].name == self.gotos[g].name {
+                let (goto, label) = (&self.gotos[g], &self.labels[i]);
+                if goto.nactvar > label.nactvar
+                    && (block.upval || self.labels.len() > block.first_label)
+                {
+                    let (pc, level) = (goto.pc, label.nactvar);
+                    self.patch_close(pc, level);
+                }
+                self.close_goto(g, i);
+                return true;
+            }
+        }
+        false
+    }
+
+    // closes the pending gotos of the current block a new label matches
+    fn find_gotos(&mut self, label: usize) {
+        let mut i = self.blocks.last().unwrap().first_goto;
+        while i < self.gotos.len() {
+            if self.gotos[i].name == self.labels[label].name {
+                self.close_goto(i, label);
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    // pending gotos of a block left now belong to the enclosing one
+    fn move_gotos_out(&mut self, block: BlockCnt) {
+        let mut i = block.first_goto;
+        while i < self.gotos.len() {
+            if self.gotos[i].nactvar > block.nactvar {
+                if block.upval {
+                    let pc = self.gotos[i].pc;
+                    self.patch_close(pc, block.nactvar);
+                }
+                self.gotos[i].nactvar = block.nactvar;
+            }
+            if !self.find_label(i) {
+                i += 1;
+            }
+        }
+    }
+}
+
+// lowers an AST into the function prototypes of the Lua 5.3 virtual machine, as the reference
+// compiler does: same registers, constants, jumps and folding. the tree comes from any dialect
+// but only what 5.3 supports is compiled, `<close>` locals are rejected
+pub struct Compiler {
+    chunk_name: String,
+    // functions being compiled, innermost last
+    funcs: Vec<FuncState>,
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Compiler::new()
+    }
+}
+
+impl Compiler {
+    pub fn new() -> Self {
+        Compiler {
+            chunk_name: "=?".to_string(),
+            funcs: Vec::new(),
+        }
+    }
+
+    // source of the chunk in the debug info, `@file.lua` for a file like `luac` does
+    pub fn set_chunk_name(&mut self, name: &str) {
+        self.chunk_name = name.to_string();
+    }
+
+    pub fn run(&mut self, block: &Block) -> Result<Proto, CompileError> {
+        self.funcs.clear();
+        self.open_func(0);
+        let fs = self.fs();
+        fs.proto.is_vararg = true;
+        fs.new_upvalue("_ENV", ExpKind::Local(0));
+        self.stat_list(&block.stats, false);
+        let (proto, error) = self.close_func();
+        // `<const>` locals compile to plain ones, assigning them is still an error
+        let error = error.or_else(|| {
+            let resolution = Resolver::new().run(block);
+            let u = resolution.const_writes().next()?;
+            let message = format!("attempt to assign to const variable '{}'", u.name);
+            Some(CompileError::new(message, u.span))
+        });
+        match error {
+            Some(error) => Err(error),
+            None => Ok(proto),
+        }
+    }
+
+    fn fs(&mut self) -> &mut FuncState {
+        self.funcs.last_mut().unwrap()
+    }
+
+    fn set_span(&mut self, span: Span) {
+        let fs = self.fs();
+        fs.span = span;
+        fs.last_line = fs.last_line.max(span.line);
+    }
+
+    fn fail(&mut self, message: String, span: Span) {
+        self.set_span(span);
+        self.fs().fail(message);
+    }
+
+    fn open_func(&mut self, line: usize) {
+        let mut fs = FuncState::new(&self.chunk_name, line);
+        fs.span.line = line;
+        fs.enter_block(false);
+        self.funcs.push(fs);
+    }
+
+    // the prototype and the first error of the function or a nested one
+    fn close_func(&mut self) -> (Proto, Option<CompileError>) {
+        let fs = self.fs();
+        fs.span.line = fs.last_line;
+        fs.ret(0, 0);
+        fs.leave_block();
+        if fs.proto.line_defined > 0 {
+            fs.proto.last_line_defined = fs.last_line;
+        }
+        let mut fs = self.funcs.pop().unwrap();
+        fs.proto.max_stack_size = fs.max_stack as u8;
+        (fs.proto, fs.error)
+    }
+
+    fn stat_list(&mut self, stats: &[Stat], until: bool) {
+        for (i, stat) in stats.iter().enumerate() {
+            // a label followed by nothing but labels and `;` ends the block, where its locals
+            // are out of scope already, except in a `repeat` whose condition still sees them
+            let follow = !until
+                && stats[i + 1..]
+                    .iter()
+                    .all(|stat| matches!(stat, Stat::Empty(_) | Stat::LabelStat(_)));
+            self.statement(stat, follow);
+        }
+    }
+
+    fn block(&mut self, block: &Block) {
+        self.fs().enter_block(false);
+        self.stat_list(&block.stats, false);
+        self.fs().leave_block();
+    }
+
+    fn statement(&mut self, stat: &Stat, follow: bool) {
+        self.set_span(stat.span());
+        match stat {
+            Stat::Empty(_) => (),
+            Stat::IfStat(stat) => self.if_stat(stat),
+            Stat::WhileStat(stat) => self.while_stat(stat),
+            Stat::DoBlock(stat) => self.block(&stat.block),
+            Stat::ForStat(stat) => self.for_stat(stat),
+            Stat::RepeatStat(stat) => self.repeat_stat(stat),
+            Stat::FuncStat(stat) => self.func_stat(stat),
+            Stat::LocalStat(stat) => self.local_stat(stat),
+            Stat::LabelStat(stat) => self.label_stat(stat, follow),
+            Stat::RetStat(stat) => self.ret_stat(stat),
+            Stat::BreakStat(stat) => {
+                let pc = self.fs().jump();
+                self.goto_stat("break", stat.span, pc);
+            }
+            Stat::GotoStat(stat) => {
+                let pc = self.fs().jump();
+                self.goto_stat(&stat.label.name, stat.span, pc);
+            }
+            Stat::AssignStat(stat) => self.assign_stat(stat),
+            Stat::CallStat(stat) => {
+                let e = self.suffixed_expr(&stat.call);
+                // a call statement uses no results
+                if let ExpKind::Call(pc) = e.k {
+                    self.fs().instruction(pc).set_c(1);
+                }
+            }
+            Stat::Error(span) => self.fail("syntax error".to_string(), *span),
+        }
+        let fs = self.fs();
+        fs.freereg = fs.nactvar as u32;
+    }
+
+    fn goto_stat(&mut self, name: &str, span: Span, pc: i32) {
+        let g = self.fs().new_label(false, name, span, pc);
+        self.fs().find_label(g);
+    }
+
+    fn label_stat(&mut self, stat: &LabelStat, follow: bool) {
+        let fs = self.fs();
+        let first = fs.blocks.last().unwrap().first_label;
+        let name = &stat.label.name;
+        if let Some(label) = fs.labels[first..].iter().find(|l| &l.name == name) {
+            let message = format!(
+                "label '{}' already defined on line {}",
+                name, label.span.line
+            );
+            fs.fail(message);
+        }
+        let pc = fs.get_label();
+        let label = fs.new_label(true, name, stat.span, pc);
+        if follow {
+            fs.labels[label].nactvar = fs.blocks.last().unwrap().nactvar;
+        }
+        fs.find_gotos(label);
+    }
+
+    // the false exit of the condition
+    fn cond(&mut self, expr: &Expr) -> i32 {
+        let mut v = self.expr(expr);
+        if v.k == ExpKind::Nil {
+            v.k = ExpKind::False;
+        }
+        self.fs().go_if_true(&mut v);
+        v.f
+    }
+
+    fn test_then_block(&mut self, cond_block: &CondBlock, escapes: &mut i32, more: bool) {
+        self.set_span(cond_block.span);
+        let mut v = self.expr(&cond_block.cond);
+        let stats = &cond_block.block.stats;
+        let jf = match stats.first() {
+            // `if cond then break end` jumps straight out when the condition is true
+            Some(Stat::GotoStat(_)) | Some(Stat::BreakStat(_)) => {
+                let fs = self.fs();
+                fs.go_if_false(&mut v);
+                fs.enter_block(false);
+                let (name, span) = match &stats[0] {
+                    Stat::GotoStat(stat) => (stat.label.name.as_str(), stat.span),
+                    stat => ("break", stat.span()),
+                };
+                self.goto_stat(name, span, v.t);
+                let rest = stats[1..]
+                    .iter()
+                    .position(|stat| !matches!(stat, Stat::Empty(_)))
+                    .map(|i| &stats[1 + i..]);
+                match rest {
+                    None => {
+                        self.fs().leave_block();
+                        return;
+                    }
+                    Some(rest) => {
+                        let jf = self.fs().jump();
+                        self.stat_list(rest, false);
+                        jf
+                    }
+                }
+            }
+            _ => {
+                let fs = self.fs();
+                fs.go_if_true(&mut v);
+                fs.enter_block(false);
+                self.stat_list(stats, false);
+                v.f
+            }
+        };
+        let fs = self.fs();
+        fs.leave_block();
+        if more {
+            let j = fs.jump();
+            fs.concat(escapes, j);
+        }
+        fs.patch_to_here(jf);
+    }
+
+    fn if_stat(&mut self, stat: &IfStat) {
+        let mut escapes = NO_JUMP;
+        let has_else = !stat.else_block.stats.is_empty();
+        for (i, cond_block) in stat.cond_blocks.iter().enumerate() {
+            let more = i + 1 < stat.cond_blocks.len() || has_else;
+            self.test_then_block(cond_block, &mut escapes, more);
+        }
+        if has_else {
+            self.block(&stat.else_block);
+        }
+        self.fs().patch_to_here(escapes);
+    }
+
+    fn while_stat(&mut self, stat: &WhileStat) {
+        let init = self.fs().get_label();
+        let exit = self.cond(&stat.cond);
+        self.fs().enter_block(true);
+        self.block(&stat.block);
+        let fs = self.fs();
+        let j = fs.jump();
+        fs.patch_list(j, init);
+        fs.leave_block();
+        fs.patch_to_here(exit);
+    }
+
+    fn repeat_stat(&mut self, stat: &RepeatStat) {
+        let init = self.fs().get_label();
+        let fs = self.fs();
+        // the loop and the scope of its body, which the condition still sees
+        fs.enter_block(true);
+        fs.enter_block(false);
+        self.stat_list(&stat.block.stats, true);
+        self.set_span(stat.cond.span());
+        let exit = self.cond(&stat.cond);
+        let fs = self.fs();
+        let scope = *fs.blocks.last().unwrap();
+        if scope.upval {
+            fs.patch_close(exit, scope.nactvar);
+        }
+        fs.leave_block();
+        fs.patch_list(exit, init);
+        fs.leave_block();
+    }
+
+    fn exp1(&mut self, expr: &Expr) {
+        let mut e = self.expr(expr);
+        self.fs().exp_to_next_reg(&mut e);
+    }
+
+    fn for_body(&mut self, base: u32, line: usize, nvars: usize, numeric: bool, body: &Block) {
+        let fs = self.fs();
+        fs.adjust_local_vars(3);
+        let prep = if numeric {
+            fs.code_asbx(OpCode::ForPrep, base, NO_JUMP)
+        } else {
+            fs.jump()
+        };
+        fs.enter_block(false);
+        fs.adjust_local_vars(nvars);
+        fs.reserve_regs(nvars as u32);
+        self.block(body);
+        self.set_span(Span { line, ..body.span });
+        let fs = self.fs();
+        fs.leave_block();
+        fs.patch_to_here(prep);
+        let end = if numeric {
+            fs.code_asbx(OpCode::ForLoop, base, NO_JUMP)
+        } else {
+            fs.code_abc(OpCode::TForCall, base, 0, nvars as u32);
+            fs.fix_line(line);
+            fs.code_asbx(OpCode::TForLoop, base + 2, NO_JUMP)
+        };
+        fs.patch_list(end, prep + 1);
+        fs.fix_line(line);
+    }
+
+    fn for_stat(&mut self, stat: &ForStat) {
+        self.fs().enter_block(true);
+        match stat {
+            ForStat::ForNum(stat) => {
+                let fs = self.fs();
+                let base = fs.freereg;
+                for name in ["(for index)", "(for limit)", "(for step)"].iter() {
+                    fs.new_local_var(name);
+                }
+                fs.new_local_var(&stat.var.name);
+                self.exp1(&stat.init);
+                self.exp1(&stat.limit);
+                match &stat.step {
+                    Some(step) => self.exp1(step),
+                    None => {
+                        let fs = self.fs();
+                        let k = fs.int_k(1);
+                        fs.code_k(fs.freereg, k);
+                        fs.reserve_regs(1);
+                    }
+                }
+                self.for_body(base, stat.span.line, 1, true, &stat.body);
+            }
+            ForStat::ForList(stat) => {
+                let fs = self.fs();
+                let base = fs.freereg;
+                for name in ["(for generator)", "(for state)", "(for control)"].iter() {
+                    fs.new_local_var(name);
+                }
+                for var in stat.vars.iter() {
+                    fs.new_local_var(&var.name);
+                }
+                let line = stat.exprs[0].span().line;
+                let (n, mut e) = self.explist(&stat.exprs);
+                self.adjust_assign(3, n, &mut e);
+                // room to call the generator
+                self.fs().check_stack(3);
+                self.for_body(base, line, stat.vars.len(), false, &stat.body);
+            }
+        }
+        self.fs().leave_block();
+    }
+
+    fn func_stat(&mut self, stat: &FuncStat) {
+        let line = stat.span.line;
+        let name = &stat.func_name.fields[0];
+        if let FuncType::Local = stat.func_type {
+            let fs = self.fs();
+            fs.new_local_var(&name.name);
+            fs.adjust_local_vars(1);
+            let b = self.body(&stat.body, false, line);
+            // the debug info only sees the local after its closure is made
+            let fs = self.fs();
+            let pc = fs.pc();
+            fs.local_var(b.info() as usize).start_pc = pc;
+            return;
+        }
+        let mut v = self.single_var(name);
+        for field in stat.func_name.fields[1..].iter() {
+            self.field_sel(&mut v, field);
+        }
+        if let Some(method) = &stat.func_name.method {
+            self.field_sel(&mut v, method);
+        }
+        let mut b = self.body(&stat.body, stat.func_name.method.is_some(), line);
+        let fs = self.fs();
+        fs.store_var(&v, &mut b);
+        fs.fix_line(line);
+    }
+
+    fn local_stat(&mut self, stat: &LocalStat) {
+        for name in stat.names.iter() {
+            if name.attrib == Some(LocalAttrib::Close) {
+                let message = format!("to-be-closed local '{}' needs Lua 5.4", name.name.name);
+                self.fail(message, name.span);
+            }
+            self.fs().new_local_var(&name.name.name);
+        }
+        let (n, mut e) = self.explist(&stat.exprs);
+        self.adjust_assign(stat.names.len(), n, &mut e);
+        self.fs().adjust_local_vars(stat.names.len());
+    }
+
+    fn ret_stat(&mut self, stat: &RetStat) {
+        let (first, nret) = if stat.exprs.is_empty() {
+            (0, 0)
+        } else {
+            let (n, mut e) = self.explist(&stat.exprs);
+            let fs = self.fs();
+            if e.has_multret() {
+                fs.set_returns(&mut e, MULTRET);
+                if let (ExpKind::Call(pc), 1) = (e.k, n) {
+                    fs.instruction(pc).set_opcode(OpCode::TailCall);
+                }
+                (fs.nactvar as u32, MULTRET)
+            } else if n == 1 {
+                (fs.exp_to_any_reg(&mut e), 1)
+            } else {
+                fs.exp_to_next_reg(&mut e);
+                (fs.nactvar as u32, n as i32)
+            }
+        };
+        self.fs().ret(first, nret);
+    }
+
+    fn assign_stat(&mut self, stat: &AssignStat) {
+        let mut targets: Vec<ExpDesc> = Vec::new();
+        for (i, target) in stat.left.iter().enumerate() {
+            let v = self.suffixed_expr(target);
+            if i > 0 && !matches!(v.k, ExpKind::Indexed { .. }) {
+                self.check_conflict(&mut targets, &v);
+            }
+            targets.push(v);
+        }
+        let (n, mut e) = self.explist(&stat.right);
+        let last = targets.pop().unwrap();
+        if n != stat.left.len() {
+            self.adjust_assign(stat.left.len(), n, &mut e);
+            e = ExpDesc::new(ExpKind::NonReloc(self.fs().freereg - 1));
+        } else {
+            self.fs().set_one_ret(&mut e);
+        }
+        self.fs().store_var(&last, &mut e);
+        // the other values are on the stack, the last target is stored first
+        while let Some(target) = targets.pop() {
+            let fs = self.fs();
+            let mut e = ExpDesc::new(ExpKind::NonReloc(fs.freereg - 1));
+            fs.store_var(&target, &mut e);
+        }
+    }
+
+    // an earlier target indexes the local or upvalue assigned now, it gets a copy
+    fn check_conflict(&mut self, targets: &mut [ExpDesc], v: &ExpDesc) {
+        let fs = self.fs();
+        let extra = fs.freereg;
+        let mut conflict = false;
+        for target in targets.iter_mut() {
+            if let ExpKind::Indexed { t, idx, upval } = &mut target.k {
+                let same_table = match v.k {
+                    ExpKind::Local(reg) => !*upval && *t == reg,
+                    ExpKind::Upval(index) => *upval && *t == index,
+                    _ => false,
+                };
+                if same_table {
+                    conflict = true;
+                    *upval = false;
+                    *t = extra;
+                }
+                if let ExpKind::Local(reg) = v.k {
+                    if *idx == reg {
+                        conflict = true;
+                        *idx = extra;
+                    }
+                }
+            }
+        }
+        if conflict {
+            let op = match v.k {
+                ExpKind::Local(_) => OpCode::Move,
+                _ => OpCode::GetUpval,
+            };
+            fs.code_abc(op, extra, v.info(), 0);
+            fs.reserve_regs(1);
+        }
+    }
+
+    fn adjust_assign(&mut self, nvars: usize, nexps: usize, e: &mut ExpDesc) {
+        let fs = self.fs();
+        let extra = nvars as i32 - nexps as i32;
+        if e.has_multret() {
+            // the call or `...` provides the missing values
+            let extra = (extra + 1).max(0);
+            fs.set_returns(e, extra);
+            if extra > 1 {
+                fs.reserve_regs(extra as u32 - 1);
+            }
+        } else {
+            if e.k != ExpKind::Void {
+                fs.exp_to_next_reg(e);
+            }
+            if extra > 0 {
+                let reg = fs.freereg;
+                fs.reserve_regs(extra as u32);
+                fs.nil(reg, extra as u32);
+            }
+        }
+        if nexps > nvars {
+            fs.freereg -= (nexps - nvars) as u32;
+        }
+    }
+
+    // all but the last expression go to consecutive registers
+    fn explist(&mut self, exprs: &[Expr]) -> (usize, ExpDesc) {
+        let mut e = ExpDesc::new(ExpKind::Void);
+        for (i, expr) in exprs.iter().enumerate() {
+            if i > 0 {
+                self.fs().exp_to_next_reg(&mut e);
+            }
+            e = self.expr(expr);
+        }
+        (exprs.len(), e)
+    }
+
+    fn single_var_aux(&mut self, level: usize, name: &str, base: bool) -> Option<ExpKind> {
+        let fs = &mut self.funcs[level];
+        if let Some(reg) = fs.search_var(name) {
+            if !base {
+                fs.mark_upval(reg);
+            }
+            return Some(ExpKind::Local(reg as u32));
+        }
+        if let Some(index) = fs.search_upvalue(name) {
+            return Some(ExpKind::Upval(index as u32));
+        }
+        if level == 0 {
+            return None;
+        }
+        let var = self.single_var_aux(level - 1, name, false)?;
+        Some(ExpKind::Upval(self.funcs[level].new_upvalue(name, var)))
+    }
+
+    // a local, an upvalue or a field of `_ENV`
+    fn single_var(&mut self, name: &Name) -> ExpDesc {
+        let level = self.funcs.len() - 1;
+        if let Some(k) = self.single_var_aux(level, &name.name, true) {
+            return ExpDesc::new(k);
+        }
+        let env = self.single_var_aux(level, "_ENV", true).unwrap();
+        let mut var = ExpDesc::new(env);
+        let fs = self.fs();
+        let mut key = ExpDesc::new(ExpKind::K(fs.string_k(&name.name)));
+        fs.indexed(&mut var, &mut key);
+        var
+    }
+
+    fn field_sel(&mut self, v: &mut ExpDesc, name: &Name) {
+        let fs = self.fs();
+        fs.exp_to_any_reg_up(v);
+        let mut key = ExpDesc::new(ExpKind::K(fs.string_k(&name.name)));
+        fs.indexed(v, &mut key);
+    }
+
+    fn expr(&mut self, expr: &Expr) -> ExpDesc {
+        self.set_span(expr.span());
+        let k = match expr {
+            Expr::Nil(_) => ExpKind::Nil,
+            Expr::True(_) => ExpKind::True,
+            Expr::False(_) => ExpKind::False,
+            Expr::Float(f, _) => ExpKind::KFlt(*f),
+            Expr::Int(i, _) | Expr::UInt(i, _) => ExpKind::KInt(*i),
+            Expr::String(s, _) => ExpKind::K(self.fs().string_k(s)),
+            Expr::VarArg(span) => {
+                let fs = self.fs();
+                if !fs.proto.is_vararg {
+                    let message = "cannot use '...' outside a vararg function".to_string();
+                    self.fail(message, *span);
+                }
+                ExpKind::VarArg(self.fs().code_abc(OpCode::VarArg, 0, 1, 0))
+            }
+            Expr::Table(table) => return self.constructor(table),
+            Expr::FuncBody(body) => return self.body(body, false, body.span.line),
+            Expr::BinExpr(expr) => {
+                let mut e1 = self.expr(&expr.left);
+                self.fs().infix(expr.op, &mut e1);
+                let mut e2 = self.expr(&expr.right);
+                self.set_span(expr.span);
+                self.fs().posfix(expr.op, &mut e1, &mut e2, expr.span.line);
+                return e1;
+            }
+            Expr::UnExpr(expr) => {
+                let mut e = self.expr(&expr.expr);
+                self.set_span(expr.span);
+                self.fs().prefix(expr.op, &mut e, expr.span.line);
+                return e;
+            }
+            Expr::SuffixedExpr(expr) => return self.suffixed_expr(expr),
+        };
+        ExpDesc::new(k)
+    }
+
+    fn suffixed_expr(&mut self, expr: &SuffixedExpr) -> ExpDesc {
+        let line = expr.span.line;
+        let mut v = match &expr.primary {
+            PrimaryExpr::Name(name) => self.single_var(name),
+            PrimaryExpr::ParenExpr(inner) => {
+                // parentheses keep a single value
+                let mut v = self.expr(inner);
+                self.fs().discharge_vars(&mut v);
+                v
+            }
+        };
+        // the object of a method call is in place already
+        let mut method = false;
+        for suffix in expr.suffixes.iter() {
+            match suffix {
+                Suffix::Attr(name) => self.field_sel(&mut v, name),
+                Suffix::Index(index) => {
+                    self.fs().exp_to_any_reg_up(&mut v);
+                    let mut key = self.expr(index);
+                    let fs = self.fs();
+                    fs.exp_to_val(&mut key);
+                    fs.indexed(&mut v, &mut key);
+                }
+                Suffix::Method(name) => {
+                    let fs = self.fs();
+                    let mut key = ExpDesc::new(ExpKind::K(fs.string_k(&name.name)));
+                    fs.self_(&mut v, &mut key);
+                    method = true;
+                }
+                Suffix::FuncArgs(args) => {
+                    if !method {
+                        self.fs().exp_to_next_reg(&mut v);
+                    }
+                    method = false;
+                    self.func_args(&mut v, args, line);
+                }
+            }
+        }
+        v
+    }
+
+    fn func_args(&mut self, f: &mut ExpDesc, args: &FuncArgs, line: usize) {
+        let mut args = match args {
+            FuncArgs::Exprs(exprs, _) if exprs.is_empty() => ExpDesc::new(ExpKind::Void),
+            FuncArgs::Exprs(exprs, _) => {
+                let (_, mut e) = self.explist(exprs);
+                self.fs().set_returns(&mut e, MULTRET);
+                e
+            }
+            FuncArgs::Table(table) => self.constructor(table),
+            FuncArgs::String(s, _) => ExpDesc::new(ExpKind::K(self.fs().string_k(s))),
+        };
+        let fs = self.fs();
+        let base = f.info();
+        let nparams = if args.has_multret() {
+            MULTRET
+        } else {
+            if args.k != ExpKind::Void {
+                fs.exp_to_next_reg(&mut args);
+            }
+            (fs.freereg - (base + 1)) as i32
+        };
+        f.k = ExpKind::Call(fs.code_abc(OpCode::Call, base, (nparams + 1) as u32, 2));
+        fs.fix_line(line);
+        fs.freereg = base + 1;
+    }
+
+    fn constructor(&mut self, table: &Table) -> ExpDesc {
+        self.set_span(table.span);
+        let fs = self.fs();
+        let pc = fs.code_abc(OpCode::NewTable, 0, 0, 0);
+        let mut t = ExpDesc::new(ExpKind::Relocable(pc));
+        fs.exp_to_next_reg(&mut t);
+        let reg = t.info();
+        // the pending list item, stored in batches
+        let mut item = ExpDesc::new(ExpKind::Void);
+        let (mut na, mut nh, mut tostore) = (0, 0, 0);
+        for field in table.fields.iter() {
+            if item.k != ExpKind::Void {
+                let fs = self.fs();
+                fs.exp_to_next_reg(&mut item);
+                item = ExpDesc::new(ExpKind::Void);
+                if tostore == LFIELDS_PER_FLUSH {
+                    fs.set_list(reg, na, tostore as i32);
+                    tostore = 0;
+                }
+            }
+            match field {
+                Field::ListField(expr) => {
+                    item = self.expr(expr);
+                    na += 1;
+                    tostore += 1;
+                }
+                Field::RecFileld(field) => {
+                    let free = self.fs().freereg;
+                    let mut key = match &field.key {
+                        FieldKey::Name(name) => {
+                            ExpDesc::new(ExpKind::K(self.fs().string_k(&name.name)))
+                        }
+                        FieldKey::Expr(expr) => {
+                            let mut key = self.expr(expr);
+                            self.fs().exp_to_val(&mut key);
+                            key
+                        }
+                    };
+                    nh += 1;
+                    let rk_key = self.fs().exp_to_rk(&mut key);
+                    let mut value = self.expr(&field.value);
+                    let fs = self.fs();
+                    let rk_value = fs.exp_to_rk(&mut value);
+                    fs.code_abc(OpCode::SetTable, reg, rk_key, rk_value);
+                    fs.freereg = free;
+                }
+            }
+        }
+        let fs = self.fs();
+        if tostore > 0 {
+            if item.has_multret() {
+                fs.set_returns(&mut item, MULTRET);
+                fs.set_list(reg, na, MULTRET);
+                // the values of the last call or `...` are not counted
+                na -= 1;
+            } else {
+                if item.k != ExpKind::Void {
+                    fs.exp_to_next_reg(&mut item);
+                }
+                fs.set_list(reg, na, tostore as i32);
+            }
+        }
+        let i = fs.instruction(pc);
+        i.set_b(int_to_fb(na));
+        i.set_c(int_to_fb(nh));
+        t
+    }
+
+    // compiles a function into a closure in the next register of the enclosing one
+    fn body(&mut self, body: &FuncBody, method: bool, line: usize) -> ExpDesc {
+        self.open_func(line);
+        let fs = self.fs();
+        if method {
+            fs.new_local_var("self");
+            fs.adjust_local_vars(1);
+        }
+        let mut params = 0;
+        for param in body.params.iter() {
+            match param {
+                Param::Name(name) => {
+                    fs.new_local_var(&name.name);
+                    params += 1;
+                }
+                Param::VarArg(_) => fs.proto.is_vararg = true,
+            }
+        }
+        fs.adjust_local_vars(params);
+        fs.proto.num_params = fs.nactvar as u8;
+        fs.reserve_regs(fs.nactvar as u32);
+        self.stat_list(&body.block.stats, false);
+        let (proto, error) = self.close_func();
+        let last_line = proto.last_line_defined;
+        let fs = self.fs();
+        if let Some(error) = error {
+            if fs.error.is_none() {
+                fs.error = Some(error);
+            }
+        }
+        fs.proto.protos.push(proto);
+        fs.span.line = last_line;
+        fs.last_line = fs.last_line.max(last_line);
+        let index = fs.proto.protos.len() as u32 - 1;
+        let mut e = ExpDesc::new(ExpKind::Relocable(fs.code_abx(OpCode::Closure, 0, index)));
+        fs.exp_to_next_reg(&mut e);
+        e
+    }
+}
+
+// the "floating point byte" of NEWTABLE sizes, `eeeeexxx` for (1xxx) * 2^(eeeee-1)
+fn int_to_fb(mut x: u32) -> u32 {
+    let mut e = 0;
+    if x < 8 {
+        return x;
+    }
+    while x >= 8 << 4 {
+        x = (x + 0xf) >> 4;
+        e += 4;
+    }
+    while x >= 8 << 1 {
+        x = (x + 1) >> 1;
+        e += 1;
+    }
+    ((e + 1) << 3) | (x - 8)
+}
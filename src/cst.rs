@@ -0,0 +1,189 @@
+use crate::token::{Source, Token, TokenType, Trivia};
+use std::fmt;
+
+// kinds of syntax nodes, single token constructs like names and literals are kept as plain tokens.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NodeKind {
+    // root of the tree, the main block followed by eos
+    Chunk,
+    Block,
+    EmptyStat,
+    IfStat,
+    CondBlock,
+    WhileStat,
+    DoBlock,
+    ForNum,
+    ForList,
+    RepeatStat,
+    FuncStat,
+    FuncName,
+    FuncBody,
+    LocalStat,
+    LocalName,
+    LabelStat,
+    RetStat,
+    BreakStat,
+    GotoStat,
+    AssignStat,
+    CallStat,
+    BinExpr,
+    UnExpr,
+    SuffixedExpr,
+    ParenExpr,
+    Index,
+    FuncArgs,
+    Table,
+    RecField,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxToken {
+    pub t: TokenType,
+    // the token exactly as written in the source
+    pub text: String,
+    pub leading: Vec<Trivia>,
+    pub trailing: Vec<Trivia>,
+    pub source: Source,
+}
+
+impl SyntaxToken {
+    fn new(token: Token, input: &str) -> Self {
+        let Token {
+            t,
+            source,
+            leading,
+            trailing,
+            ..
+        } = token;
+        let text = String::from(&input[source.pos..source.pos + source.length]);
+        SyntaxToken {
+            t,
+            text,
+            leading,
+            trailing,
+            source,
+        }
+    }
+}
+
+impl fmt::Display for SyntaxToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for trivia in self.leading.iter() {
+            write!(f, "{}", trivia.text)?;
+        }
+        write!(f, "{}", self.text)?;
+        for trivia in self.trailing.iter() {
+            write!(f, "{}", trivia.text)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyntaxElement {
+    Node(SyntaxNode),
+    Token(SyntaxToken),
+}
+
+// lossless syntax tree, printing it reproduces the parsed source byte-for-byte
+// if the tokens were lexed with trivia kept.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxNode {
+    pub kind: NodeKind,
+    pub children: Vec<SyntaxElement>,
+}
+
+impl SyntaxNode {
+    pub fn new(kind: NodeKind) -> Self {
+        SyntaxNode {
+            kind,
+            children: Vec::new(),
+        }
+    }
+
+    // all tokens of this node in source order
+    pub fn tokens(&self) -> Vec<&SyntaxToken> {
+        let mut tokens = Vec::new();
+        self.collect_tokens(&mut tokens);
+        tokens
+    }
+
+    fn collect_tokens<'a>(&'a self, tokens: &mut Vec<&'a SyntaxToken>) {
+        for child in self.children.iter() {
+            match child {
+                SyntaxElement::Node(node) => node.collect_tokens(tokens),
+                SyntaxElement::Token(token) => tokens.push(token),
+            }
+        }
+    }
+
+    // direct child nodes
+    pub fn nodes(&self) -> impl Iterator<Item = &SyntaxNode> {
+        self.children.iter().filter_map(|child| match child {
+            SyntaxElement::Node(node) => Some(node),
+            _ => None,
+        })
+    }
+}
+
+impl fmt::Display for SyntaxNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for child in self.children.iter() {
+            match child {
+                SyntaxElement::Node(node) => write!(f, "{}", node)?,
+                SyntaxElement::Token(token) => write!(f, "{}", token)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// a node covering tokens in [start, end), recorded by the parser
+#[derive(Debug)]
+pub(crate) struct NodeRange {
+    pub kind: NodeKind,
+    pub start: usize,
+    pub end: usize,
+}
+
+// nest recorded node ranges into a tree over `tokens`, each token goes to the innermost node covering it.
+pub(crate) fn build(input: &str, tokens: Vec<Token>, ranges: Vec<NodeRange>) -> SyntaxNode {
+    let mut ranges: Vec<(usize, NodeRange)> = ranges.into_iter().enumerate().collect();
+    // outer nodes first, nodes with the same bounds are recorded from inner to outer
+    ranges.sort_by(|(i, a), (j, b)| a.start.cmp(&b.start).then(b.end.cmp(&a.end)).then(j.cmp(i)));
+
+    let mut tokens = tokens
+        .into_iter()
+        .map(|token| SyntaxToken::new(token, input))
+        .enumerate()
+        .peekable();
+    let mut flush = |node: &mut SyntaxNode, end: usize| {
+        while let Some((_, token)) = tokens.next_if(|(i, _)| *i < end) {
+            node.children.push(SyntaxElement::Token(token));
+        }
+    };
+
+    let mut stack: Vec<(SyntaxNode, usize)> = vec![(SyntaxNode::new(NodeKind::Chunk), usize::MAX)];
+    for (_, range) in ranges {
+        while range.end > stack.last().unwrap().1 {
+            let (mut node, end) = stack.pop().unwrap();
+            flush(&mut node, end);
+            stack
+                .last_mut()
+                .unwrap()
+                .0
+                .children
+                .push(SyntaxElement::Node(node));
+        }
+        flush(&mut stack.last_mut().unwrap().0, range.start);
+        stack.push((SyntaxNode::new(range.kind), range.end));
+    }
+    while let Some((mut node, end)) = stack.pop() {
+        flush(&mut node, end);
+        match stack.last_mut() {
+            Some((parent, _)) => parent.children.push(SyntaxElement::Node(node)),
+            None => return node,
+        }
+    }
+    unreachable!()
+}
@@ -0,0 +1,2495 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+
+use crate::ast::*;
+use crate::bytecode::{index_k, is_k, Constant, Instruction, OpCode, Proto};
+use crate::token::{Span, TokenType};
+use crate::types::{FloatType, IntType, LuaVersion};
+use crate::undump::Chunk;
+
+// reads the bytecode of Lua 5.1 and 5.3 back into an AST. registers hold pending
+// expressions until an instruction consumes them, statements come from stores and calls.
+// jumps are matched against the shapes `luac` emits for conditions and loops, whatever is
+// left over becomes a goto. names of locals come from the debug info, stripped functions
+// get `l<register>` variables declared at their top.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecompileError {
+    pub message: String,
+    // index of the offending instruction
+    pub pc: usize,
+}
+
+impl DecompileError {
+    fn new(message: String, pc: usize) -> Self {
+        DecompileError { message, pc }
+    }
+}
+
+impl fmt::Display for DecompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[decompile error] {} at instruction {}.",
+            self.message,
+            self.pc + 1
+        )
+    }
+}
+
+impl std::error::Error for DecompileError {}
+
+type DecompileResult<T> = Result<T, DecompileError>;
+
+// a B or C argument
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Rk {
+    R(u32),
+    K(usize),
+}
+
+// instructions of both versions brought to one shape, jumps hold absolute targets
+#[derive(Clone, PartialEq, Debug)]
+enum Op {
+    Move(u32, u32),
+    LoadK(u32, usize),
+    // register, value, skip the next instruction
+    LoadBool(u32, bool, bool),
+    // first register and count
+    LoadNil(u32, u32),
+    GetUpval(u32, u32),
+    // the key is a constant name looked up in the globals
+    GetGlobal(u32, usize),
+    GetTabUp(u32, u32, Rk),
+    GetTable(u32, u32, Rk),
+    SetGlobal(usize, Rk),
+    SetTabUp(u32, Rk, Rk),
+    // register of the value, upvalue
+    SetUpval(u32, u32),
+    SetTable(u32, Rk, Rk),
+    // register, sizes of the array and the hash part the constructor asked for
+    NewTable(u32, u32, u32),
+    Self_(u32, u32, Rk),
+    Binary(u32, BinOp, Rk, Rk),
+    Unary(u32, UnOp, u32),
+    Concat(u32, u32, u32),
+    Jmp(usize),
+    // the following jump is taken when the comparison gives the flag
+    Compare(BinOp, bool, Rk, Rk),
+    // ... when the truth of the register is the flag
+    Test(u32, bool),
+    TestSet(u32, u32, bool),
+    Call(u32, u32, u32),
+    TailCall(u32, u32),
+    Return(u32, u32),
+    ForLoop(u32, usize),
+    ForPrep(u32, usize),
+    TForCall(u32, u32),
+    TForLoop(u32, usize),
+    SetList(u32, u32),
+    // register, prototype, captured locals (true) or upvalues of the enclosing function
+    Closure(u32, usize, Vec<(bool, u32)>),
+    VarArg(u32, u32),
+    // arguments of the instruction before
+    Nop,
+}
+
+impl Op {
+    fn is_test(&self) -> bool {
+        matches!(self, Op::Compare(..) | Op::Test(..) | Op::TestSet(..))
+    }
+
+    // the register the instruction leaves its single value in
+    fn dest(&self) -> Option<u32> {
+        match *self {
+            Op::Move(a, _)
+            | Op::LoadK(a, _)
+            | Op::LoadBool(a, _, _)
+            | Op::LoadNil(a, 1)
+            | Op::GetUpval(a, _)
+            | Op::GetGlobal(a, _)
+            | Op::GetTabUp(a, _, _)
+            | Op::GetTable(a, _, _)
+            | Op::NewTable(a, _, _)
+            | Op::Binary(a, _, _, _)
+            | Op::Unary(a, _, _)
+            | Op::Concat(a, _, _)
+            | Op::Call(a, _, 2)
+            | Op::VarArg(a, 2)
+            | Op::Closure(a, _, _)
+            | Op::SetTable(a, _, _)
+            | Op::SetList(a, _) => Some(a),
+            _ => None,
+        }
+    }
+}
+
+fn rk(arg: u32) -> Rk {
+    if is_k(arg) {
+        Rk::K(index_k(arg) as usize)
+    } else {
+        Rk::R(arg)
+    }
+}
+
+// table sizes are stored as a "floating point byte" eeeeexxx
+fn fb2int(x: u32, pc: usize) -> DecompileResult<u32> {
+    match x >> 3 {
+        0 => Ok(x),
+        // larger exponents overflow, only a corrupted chunk has them
+        e if e < 30 => Ok(((x & 7) + 8) << (e - 1)),
+        _ => Err(DecompileError::new(
+            "table size out of range".to_string(),
+            pc,
+        )),
+    }
+}
+
+fn target(pc: usize, sbx: i32, len: usize) -> DecompileResult<usize> {
+    let target = pc as i64 + 1 + sbx as i64;
+    if target < 0 || target >= len as i64 {
+        return Err(DecompileError::new("jump out of bounds".to_string(), pc));
+    }
+    Ok(target as usize)
+}
+
+// upvalue `env` of the function in 5.3 terms: globals are its fields
+fn normalize(proto: &Proto, version: LuaVersion, env: &[bool]) -> DecompileResult<Vec<Op>> {
+    let code = &proto.code;
+    let mut ops = Vec::with_capacity(code.len());
+    let mut pc = 0;
+    while pc < code.len() {
+        let i = code[pc];
+        let op = if version == LuaVersion::Lua51 {
+            normalize_51(proto, pc, &ops)?
+        } else {
+            normalize_53(proto, pc, env)?
+        };
+        ops.push(op);
+        // instructions that carry arguments in the next ones
+        let extra = match ops[pc] {
+            Op::Closure(_, _, ref captures) if version == LuaVersion::Lua51 => captures.len(),
+            Op::SetList(..) if i.c() == 0 => 1,
+            Op::LoadK(..) if version != LuaVersion::Lua51 && i.opcode() == Some(OpCode::LoadKX) => {
+                1
+            }
+            _ => 0,
+        };
+        if pc + extra >= code.len() {
+            return Err(DecompileError::new(
+                "missing arguments of the instruction".to_string(),
+                pc,
+            ));
+        }
+        for _ in 0..extra {
+            ops.push(Op::Nop);
+        }
+        pc += 1 + extra;
+    }
+    Ok(ops)
+}
+
+fn normalize_53(proto: &Proto, pc: usize, env: &[bool]) -> DecompileResult<Op> {
+    let code = &proto.code;
+    let i = code[pc];
+    let len = code.len();
+    let (a, b, c) = (i.a(), i.b(), i.c());
+    let op = match i.opcode() {
+        Some(op) => op,
+        None => {
+            return Err(DecompileError::new(
+                format!("invalid opcode {}", i.0 & 0x3f),
+                pc,
+            ))
+        }
+    };
+    let is_env = |up: u32| env.get(up as usize).copied().unwrap_or(false);
+    let name_k = |arg: u32| match rk(arg) {
+        Rk::K(k) if proto.constants.get(k).is_some_and(is_name) => Some(k),
+        _ => None,
+    };
+    Ok(match op {
+        OpCode::Move => Op::Move(a, b),
+        OpCode::LoadK => Op::LoadK(a, i.bx() as usize),
+        OpCode::LoadKX => match code.get(pc + 1) {
+            Some(extra) => Op::LoadK(a, extra.ax_arg() as usize),
+            None => {
+                return Err(DecompileError::new(
+                    "missing arguments of the instruction".to_string(),
+                    pc,
+                ))
+            }
+        },
+        OpCode::LoadBool => Op::LoadBool(a, b != 0, c != 0),
+        OpCode::LoadNil => Op::LoadNil(a, b + 1),
+        OpCode::GetUpval => Op::GetUpval(a, b),
+        OpCode::GetTabUp => match name_k(c) {
+            Some(k) if is_env(b) => Op::GetGlobal(a, k),
+            _ => Op::GetTabUp(a, b, rk(c)),
+        },
+        OpCode::GetTable => Op::GetTable(a, b, rk(c)),
+        OpCode::SetTabUp => match name_k(b) {
+            Some(k) if is_env(a) => Op::SetGlobal(k, rk(c)),
+            _ => Op::SetTabUp(a, rk(b), rk(c)),
+        },
+        OpCode::SetUpval => Op::SetUpval(a, b),
+        OpCode::SetTable => Op::SetTable(a, rk(b), rk(c)),
+        OpCode::NewTable => Op::NewTable(a, fb2int(b, pc)?, fb2int(c, pc)?),
+        OpCode::Self_ => Op::Self_(a, b, rk(c)),
+        OpCode::Add => Op::Binary(a, BinOp::Add, rk(b), rk(c)),
+        OpCode::Sub => Op::Binary(a, BinOp::Minus, rk(b), rk(c)),
+        OpCode::Mul => Op::Binary(a, BinOp::Mul, rk(b), rk(c)),
+        OpCode::Mod => Op::Binary(a, BinOp::Mod, rk(b), rk(c)),
+        OpCode::Pow => Op::Binary(a, BinOp::Pow, rk(b), rk(c)),
+        OpCode::Div => Op::Binary(a, BinOp::Div, rk(b), rk(c)),
+        OpCode::IDiv => Op::Binary(a, BinOp::IDiv, rk(b), rk(c)),
+        OpCode::BAnd => Op::Binary(a, BinOp::BAnd, rk(b), rk(c)),
+        OpCode::BOr => Op::Binary(a, BinOp::BOr, rk(b), rk(c)),
+        OpCode::BXor => Op::Binary(a, BinOp::BXor, rk(b), rk(c)),
+        OpCode::Shl => Op::Binary(a, BinOp::Shl, rk(b), rk(c)),
+        OpCode::Shr => Op::Binary(a, BinOp::Shr, rk(b), rk(c)),
+        OpCode::Unm => Op::Unary(a, UnOp::Minus, b),
+        OpCode::BNot => Op::Unary(a, UnOp::BNot, b),
+        OpCode::Not => Op::Unary(a, UnOp::Not, b),
+        OpCode::Len => Op::Unary(a, UnOp::TLen, b),
+        OpCode::Concat => Op::Concat(a, b, c),
+        OpCode::Jmp => Op::Jmp(target(pc, i.sbx(), len)?),
+        OpCode::Eq => Op::Compare(BinOp::Eq, a != 0, rk(b), rk(c)),
+        OpCode::Lt => Op::Compare(BinOp::Lt, a != 0, rk(b), rk(c)),
+        OpCode::Le => Op::Compare(BinOp::Le, a != 0, rk(b), rk(c)),
+        OpCode::Test => Op::Test(a, c != 0),
+        OpCode::TestSet => Op::TestSet(a, b, c != 0),
+        OpCode::Call => Op::Call(a, b, c),
+        OpCode::TailCall => Op::TailCall(a, b),
+        OpCode::Return => Op::Return(a, b),
+        OpCode::ForLoop => Op::ForLoop(a, target(pc, i.sbx(), len)?),
+        OpCode::ForPrep => Op::ForPrep(a, target(pc, i.sbx(), len)?),
+        OpCode::TForCall => Op::TForCall(a, c),
+        OpCode::TForLoop => Op::TForLoop(a, target(pc, i.sbx(), len)?),
+        OpCode::SetList => Op::SetList(a, b),
+        OpCode::Closure => {
+            let child = proto_at(proto, i.bx() as usize, pc)?;
+            let captures = child
+                .upvalues
+                .iter()
+                .map(|upvalue| (upvalue.in_stack, upvalue.index as u32))
+                .collect();
+            Op::Closure(a, i.bx() as usize, captures)
+        }
+        OpCode::VarArg => Op::VarArg(a, b),
+        OpCode::ExtraArg => Op::Nop,
+    })
+}
+
+// the numbering of `OPCODES_51` in the disassembler
+fn normalize_51(proto: &Proto, pc: usize, ops: &[Op]) -> DecompileResult<Op> {
+    let code = &proto.code;
+    let i = code[pc];
+    let len = code.len();
+    let (a, b, c) = (i.a(), i.b(), i.c());
+    let arith = |op| Op::Binary(a, op, rk(b), rk(c));
+    Ok(match i.0 & 0x3f {
+        0 => Op::Move(a, b),
+        1 => Op::LoadK(a, i.bx() as usize),
+        2 => Op::LoadBool(a, b != 0, c != 0),
+        3 => Op::LoadNil(a, (b + 1).saturating_sub(a)),
+        4 => Op::GetUpval(a, b),
+        5 => Op::GetGlobal(a, i.bx() as usize),
+        6 => Op::GetTable(a, b, rk(c)),
+        7 => Op::SetGlobal(i.bx() as usize, Rk::R(a)),
+        8 => Op::SetUpval(a, b),
+        9 => Op::SetTable(a, rk(b), rk(c)),
+        10 => Op::NewTable(a, fb2int(b, pc)?, fb2int(c, pc)?),
+        11 => Op::Self_(a, b, rk(c)),
+        12 => arith(BinOp::Add),
+        13 => arith(BinOp::Minus),
+        14 => arith(BinOp::Mul),
+        15 => arith(BinOp::Div),
+        16 => arith(BinOp::Mod),
+        17 => arith(BinOp::Pow),
+        18 => Op::Unary(a, UnOp::Minus, b),
+        19 => Op::Unary(a, UnOp::Not, b),
+        20 => Op::Unary(a, UnOp::TLen, b),
+        21 => Op::Concat(a, b, c),
+        22 => {
+            let target = target(pc, i.sbx(), len)?;
+            // the jump back of a generic for, the call before it is split like in 5.3
+            match ops.last() {
+                Some(Op::TForCall(base, _)) => Op::TForLoop(base + 2, target),
+                _ => Op::Jmp(target),
+            }
+        }
+        23 => Op::Compare(BinOp::Eq, a != 0, rk(b), rk(c)),
+        24 => Op::Compare(BinOp::Lt, a != 0, rk(b), rk(c)),
+        25 => Op::Compare(BinOp::Le, a != 0, rk(b), rk(c)),
+        26 => Op::Test(a, c != 0),
+        27 => Op::TestSet(a, b, c != 0),
+        28 => Op::Call(a, b, c),
+        29 => Op::TailCall(a, b),
+        30 => Op::Return(a, b),
+        31 => Op::ForLoop(a, target(pc, i.sbx(), len)?),
+        32 => Op::ForPrep(a, target(pc, i.sbx(), len)?),
+        33 => {
+            if !matches!(code.get(pc + 1).map(|i| i.0 & 0x3f), Some(22)) {
+                return Err(DecompileError::new(
+                    "TFORLOOP without a jump back".to_string(),
+                    pc,
+                ));
+            }
+            Op::TForCall(a, c)
+        }
+        34 => Op::SetList(a, b),
+        35 => Op::Nop,
+        36 => {
+            let child = proto_at(proto, i.bx() as usize, pc)?;
+            let mut captures = Vec::new();
+            for n in 0..child.upvalues.len() {
+                let pseudo = code.get(pc + 1 + n).copied().unwrap_or(Instruction(0));
+                // MOVE takes a local, GETUPVAL an upvalue
+                captures.push((pseudo.0 & 0x3f == 0, pseudo.b()));
+            }
+            Op::Closure(a, i.bx() as usize, captures)
+        }
+        37 => Op::VarArg(a, b),
+        op => {
+            return Err(DecompileError::new(format!("invalid opcode {}", op), pc));
+        }
+    })
+}
+
+fn proto_at(proto: &Proto, index: usize, pc: usize) -> DecompileResult<&Proto> {
+    proto
+        .protos
+        .get(index)
+        .ok_or_else(|| DecompileError::new("missing function prototype".to_string(), pc))
+}
+
+fn is_name_str(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_') && TokenType::from_keyword(s).is_none()
+}
+
+fn is_name(constant: &Constant) -> bool {
+    match constant {
+        Constant::Str(s) => std::str::from_utf8(s).is_ok_and(is_name_str),
+        _ => false,
+    }
+}
+
+fn name(name: &str) -> Name {
+    Name {
+        name: name.to_string(),
+        span: Span::default(),
+    }
+}
+
+fn name_expr(s: &str) -> Expr {
+    Expr::SuffixedExpr(SuffixedExpr {
+        primary: PrimaryExpr::Name(name(s)),
+        suffixes: Vec::new(),
+        span: Span::default(),
+    })
+}
+
+// expressions other than names and suffixed ones are parenthesized to take a suffix
+fn suffixed(expr: Expr) -> SuffixedExpr {
+    match expr {
+        Expr::SuffixedExpr(expr) => expr,
+        expr => SuffixedExpr {
+            primary: PrimaryExpr::ParenExpr(Box::new(expr)),
+            suffixes: Vec::new(),
+            span: Span::default(),
+        },
+    }
+}
+
+fn with_suffix(expr: Expr, suffix: Suffix) -> Expr {
+    let mut expr = suffixed(expr);
+    expr.suffixes.push(suffix);
+    Expr::SuffixedExpr(expr)
+}
+
+fn key_name(key: &Expr) -> Option<&str> {
+    match key {
+        Expr::String(s, _) if is_name_str(s) => Some(s),
+        _ => None,
+    }
+}
+
+fn index(table: Expr, key: Expr) -> Expr {
+    let suffix = match key_name(&key) {
+        Some(s) => Suffix::Attr(name(s)),
+        None => Suffix::Index(key),
+    };
+    with_suffix(table, suffix)
+}
+
+fn binary(op: BinOp, left: Expr, right: Expr) -> Expr {
+    Expr::BinExpr(BinExpr {
+        op,
+        left: Box::new(left),
+        right: Box::new(right),
+        span: Span::default(),
+    })
+}
+
+fn unary(op: UnOp, expr: Expr) -> Expr {
+    Expr::UnExpr(UnExpr {
+        op,
+        expr: Box::new(expr),
+        span: Span::default(),
+    })
+}
+
+fn is_boolean(expr: &Expr) -> bool {
+    match expr {
+        Expr::True(_) | Expr::False(_) => true,
+        Expr::UnExpr(expr) => expr.op == UnOp::Not,
+        Expr::BinExpr(expr) => matches!(
+            expr.op,
+            BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge
+        ),
+        _ => false,
+    }
+}
+
+// `not expr`, when only the truth of the result counts `not not x` is just `x`
+fn negate(expr: Expr, truthy: bool) -> Expr {
+    match expr {
+        Expr::True(span) => Expr::False(span),
+        Expr::False(span) => Expr::True(span),
+        Expr::UnExpr(UnExpr {
+            op: UnOp::Not,
+            expr,
+            ..
+        }) if truthy || is_boolean(&expr) => *expr,
+        Expr::BinExpr(BinExpr {
+            op: op @ (BinOp::Eq | BinOp::Ne),
+            left,
+            right,
+            span,
+        }) => Expr::BinExpr(BinExpr {
+            op: if op == BinOp::Eq {
+                BinOp::Ne
+            } else {
+                BinOp::Eq
+            },
+            left,
+            right,
+            span,
+        }),
+        expr => unary(UnOp::Not, expr),
+    }
+}
+
+// calls and `...` give all their values at the end of a list
+fn is_multi(expr: &Expr) -> bool {
+    match expr {
+        Expr::VarArg(_) => true,
+        Expr::SuffixedExpr(expr) => matches!(expr.suffixes.last(), Some(Suffix::FuncArgs(_))),
+        _ => false,
+    }
+}
+
+fn paren(expr: Expr) -> Expr {
+    Expr::SuffixedExpr(SuffixedExpr {
+        primary: PrimaryExpr::ParenExpr(Box::new(expr)),
+        suffixes: Vec::new(),
+        span: Span::default(),
+    })
+}
+
+fn trim_nils(exprs: &mut Vec<Expr>) {
+    while let Some(Expr::Nil(_)) = exprs.last() {
+        exprs.pop();
+    }
+}
+
+fn block(stats: Vec<Stat>) -> Block {
+    // `return` ends a block, unreachable code after one needs its own
+    let n = stats.len();
+    let stats = stats
+        .into_iter()
+        .enumerate()
+        .map(|(i, stat)| match stat {
+            Stat::RetStat(ret) if i + 1 < n => Stat::DoBlock(DoBlock {
+                block: Block {
+                    stats: vec![Stat::RetStat(ret)],
+                    span: Span::default(),
+                },
+                span: Span::default(),
+            }),
+            stat => stat,
+        })
+        .collect();
+    Block {
+        stats,
+        span: Span::default(),
+    }
+}
+
+fn if_stat(cond: Expr, then: Block, mut else_block: Block) -> Stat {
+    let mut cond_blocks = vec![CondBlock {
+        cond,
+        block: then,
+        span: Span::default(),
+    }];
+    // an `if` alone in the else part is an elseif
+    if let [Stat::IfStat(_)] = else_block.stats[..] {
+        if let Some(Stat::IfStat(inner)) = else_block.stats.pop() {
+            cond_blocks.extend(inner.cond_blocks);
+            else_block = inner.else_block;
+        }
+    }
+    Stat::IfStat(IfStat {
+        cond_blocks,
+        else_block,
+        span: Span::default(),
+    })
+}
+
+// `name = function` as a function statement, `self` as first parameter makes a method
+fn function_stat(target: &SuffixedExpr, body: &FuncBody) -> Option<Stat> {
+    let mut fields = match &target.primary {
+        PrimaryExpr::Name(name) => vec![name.clone()],
+        _ => return None,
+    };
+    for suffix in target.suffixes.iter() {
+        match suffix {
+            Suffix::Attr(name) => fields.push(name.clone()),
+            _ => return None,
+        }
+    }
+    let mut body = body.clone();
+    let method = match body.params.first() {
+        Some(Param::Name(param)) if param.name == "self" && fields.len() > 1 => {
+            body.params.remove(0);
+            fields.pop()
+        }
+        _ => None,
+    };
+    Some(Stat::FuncStat(FuncStat {
+        func_type: FuncType::Global,
+        func_name: FuncName {
+            fields,
+            method,
+            span: Span::default(),
+        },
+        body,
+        span: Span::default(),
+    }))
+}
+
+fn label(pc: usize) -> Name {
+    name(&format!("L{}", pc + 1))
+}
+
+// what a register holds until an instruction consumes it
+#[derive(Clone, Debug)]
+enum Slot {
+    // the flag marks a call or `...` cut to one value
+    Expr(Expr, bool),
+    // a call or `...` with all its values, they end the stack
+    Open(Expr),
+    // one of the values of a call or `...` below
+    Extra,
+    // object and key of a method, the register above holds the object
+    Method(Expr, Expr),
+}
+
+// a test and its jump, the operands start at `start`
+#[derive(Copy, Clone, Debug)]
+struct Unit {
+    start: usize,
+    test: usize,
+    target: usize,
+}
+
+// tests and jumps that leave their value in a register, `a and b or c` and comparisons
+// turned into booleans. the last operand runs from `last` to `last_end`, `bools` is the
+// LOADBOOL pair false and true values jump to.
+#[derive(Clone, Debug)]
+struct Region {
+    units: Vec<Unit>,
+    last: usize,
+    last_end: usize,
+    bools: Option<usize>,
+    end: usize,
+    reg: u32,
+}
+
+// tests of a statement, they end up at `next` or at `other`
+#[derive(Clone, Debug)]
+struct Chain {
+    units: Vec<Unit>,
+    next: usize,
+    other: usize,
+}
+
+// a test turned into an expression: the jump is taken when it is true, or the value
+// goes to the end of the region when its truth is the flag
+enum Cond {
+    Jump(Expr),
+    Value(Expr, bool),
+}
+
+#[derive(Copy, Clone)]
+struct Ctx {
+    // where a `break` goes
+    exit: Option<usize>,
+    // jump back of the enclosing `repeat`
+    until: Option<usize>,
+}
+
+enum Flow {
+    Next(usize),
+    Until(Expr),
+}
+
+#[derive(Default)]
+pub struct Decompiler {}
+
+impl Decompiler {
+    pub fn new() -> Self {
+        Decompiler {}
+    }
+
+    pub fn run(&mut self, chunk: &Chunk) -> DecompileResult<Block> {
+        let env = match chunk.version {
+            LuaVersion::Lua51 => Vec::new(),
+            LuaVersion::Lua53 => vec![true],
+            version => {
+                return Err(DecompileError::new(
+                    format!("unsupported version {}", version),
+                    0,
+                ))
+            }
+        };
+        let upvalues = chunk
+            .main
+            .upvalues
+            .iter()
+            .map(|upvalue| match upvalue.name.as_str() {
+                "" => "_ENV".to_string(),
+                name => name.to_string(),
+            })
+            .collect();
+        let body = Function::new(&chunk.main, chunk.version, env, upvalues, 0)?.run()?;
+        Ok(body.block)
+    }
+}
+
+struct Function<'a> {
+    proto: &'a Proto,
+    version: LuaVersion,
+    ops: Vec<Op>,
+    // nesting of the function, part of the names in stripped ones
+    depth: usize,
+    // upvalues that are `_ENV`, and the names of all of them
+    env: Vec<bool>,
+    upvalues: Vec<String>,
+    stripped: bool,
+    // writes of stripped functions that go to a variable instead of a pending value
+    var_writes: HashSet<(usize, u32)>,
+    // registers declared at the top of stripped functions
+    vars: BTreeSet<u32>,
+    // jumps back by their target
+    back_jumps: HashMap<usize, Vec<usize>>,
+    // targets of gotos found by the last pass, and by this one
+    labels: BTreeSet<usize>,
+    gotos: BTreeSet<usize>,
+    placed: HashSet<usize>,
+    regs: Vec<Option<Slot>>,
+    stats: Vec<Stat>,
+    // stores of a multiple assignment in the order they run, the last target first
+    assign: Vec<(SuffixedExpr, Option<Expr>)>,
+    declared: HashSet<usize>,
+    loops: HashSet<(usize, usize)>,
+    // inside an expression, nothing is stored
+    capture: usize,
+    // RETURN after a tail call
+    tail: Option<usize>,
+}
+
+impl<'a> Function<'a> {
+    fn new(
+        proto: &'a Proto,
+        version: LuaVersion,
+        env: Vec<bool>,
+        upvalues: Vec<String>,
+        depth: usize,
+    ) -> DecompileResult<Self> {
+        let ops = normalize(proto, version, &env)?;
+        let mut back_jumps: HashMap<usize, Vec<usize>> = HashMap::new();
+        for (pc, op) in ops.iter().enumerate() {
+            if let Op::Jmp(target) = *op {
+                if target <= pc {
+                    back_jumps.entry(target).or_default().push(pc);
+                }
+            }
+        }
+        let mut function = Function {
+            proto,
+            version,
+            ops,
+            depth,
+            env,
+            upvalues,
+            stripped: proto.line_info.is_empty() && proto.local_vars.is_empty(),
+            var_writes: HashSet::new(),
+            vars: BTreeSet::new(),
+            back_jumps,
+            labels: BTreeSet::new(),
+            gotos: BTreeSet::new(),
+            placed: HashSet::new(),
+            regs: Vec::new(),
+            stats: Vec::new(),
+            assign: Vec::new(),
+            declared: HashSet::new(),
+            loops: HashSet::new(),
+            capture: 0,
+            tail: None,
+        };
+        if function.stripped {
+            function.classify();
+        }
+        Ok(function)
+    }
+
+    // gotos are only known once their jumps are read, another pass places their labels
+    fn run(mut self) -> DecompileResult<FuncBody> {
+        let mut params: Vec<Param> = (0..self.proto.num_params as u32)
+            .map(|reg| Param::Name(name(&self.reg_name(0, reg))))
+            .collect();
+        if self.proto.is_vararg {
+            params.push(Param::VarArg(Span::default()));
+        }
+        loop {
+            self.gotos.clear();
+            self.placed.clear();
+            self.regs.clear();
+            self.stats.clear();
+            self.assign.clear();
+            self.loops.clear();
+            self.declared = (0..self.proto.local_vars.len())
+                .filter(|&i| self.proto.local_vars[i].start_pc == 0)
+                .collect();
+            let ctx = Ctx {
+                exit: None,
+                until: None,
+            };
+            let mut body = self.body(0, self.ops.len(), ctx)?;
+            if self.gotos.is_subset(&self.labels) {
+                let names: Vec<LocalName> = self
+                    .vars
+                    .iter()
+                    .filter(|&&reg| reg >= self.proto.num_params as u32)
+                    .map(|&reg| LocalName {
+                        name: name(&self.stripped_name(reg)),
+                        attrib: None,
+                        span: Span::default(),
+                    })
+                    .collect();
+                if !names.is_empty() {
+                    let stat = Stat::LocalStat(LocalStat {
+                        names,
+                        exprs: Vec::new(),
+                        span: Span::default(),
+                    });
+                    body.stats.insert(0, stat);
+                }
+                return Ok(FuncBody {
+                    params,
+                    block: body,
+                    span: Span::default(),
+                });
+            }
+            let gotos = std::mem::take(&mut self.gotos);
+            self.labels.extend(gotos);
+        }
+    }
+
+    // locals active at `pc` in the order of their registers
+    fn active(&self, pc: usize) -> impl Iterator<Item = usize> + '_ {
+        let vars = &self.proto.local_vars;
+        (0..vars.len()).filter(move |&i| vars[i].start_pc <= pc && pc < vars[i].end_pc)
+    }
+
+    fn local_at(&self, pc: usize, reg: u32) -> Option<usize> {
+        self.active(pc).nth(reg as usize)
+    }
+
+    fn is_var(&self, pc: usize, reg: u32) -> bool {
+        if self.stripped {
+            self.var_writes.contains(&(pc, reg))
+        } else {
+            self.local_at(pc, reg).is_some()
+        }
+    }
+
+    fn stripped_name(&self, reg: u32) -> String {
+        match self.depth {
+            0 => format!("l{}", reg),
+            depth => format!("l{}_{}", depth, reg),
+        }
+    }
+
+    fn reg_name(&self, pc: usize, reg: u32) -> String {
+        if self.stripped {
+            return self.stripped_name(reg);
+        }
+        match self.local_at(pc, reg) {
+            Some(i) => self.proto.local_vars[i].name.clone(),
+            None => format!("r{}", reg),
+        }
+    }
+
+    fn upvalue_name(&self, index: u32) -> String {
+        match self.upvalues.get(index as usize) {
+            Some(name) => name.clone(),
+            None => format!("u{}", index),
+        }
+    }
+
+    fn constant(&self, k: usize) -> Expr {
+        let span = Span::default();
+        let expr = match self.proto.constants.get(k) {
+            Some(Constant::Boolean(true)) => Expr::True(span),
+            Some(Constant::Boolean(false)) => Expr::False(span),
+            Some(Constant::Int(i)) => Expr::Int(*i, span),
+            // 5.1 has floats only, most of them were written as integers
+            Some(Constant::Float(f))
+                if self.version == LuaVersion::Lua51
+                    && f.fract() == 0.0
+                    && f.abs() < (1u64 << 53) as FloatType
+                    && !(*f == 0.0 && f.is_sign_negative()) =>
+            {
+                Expr::Int(*f as IntType, span)
+            }
+            Some(Constant::Float(f)) => Expr::Float(*f, span),
+            Some(Constant::Str(s)) => Expr::String(String::from_utf8_lossy(s).into_owned(), span),
+            Some(Constant::Nil) | None => Expr::Nil(span),
+        };
+        // negative numbers come from folded constants, they are written with a minus
+        match expr {
+            Expr::Int(i, span) if i < 0 && i != IntType::MIN => {
+                unary(UnOp::Minus, Expr::Int(-i, span))
+            }
+            Expr::Float(f, span) if f < 0.0 => unary(UnOp::Minus, Expr::Float(-f, span)),
+            expr => expr,
+        }
+    }
+
+    fn set(&mut self, reg: u32, slot: Slot) {
+        let reg = reg as usize;
+        if self.regs.len() <= reg {
+            self.regs.resize(reg + 1, None);
+        }
+        self.regs[reg] = Some(slot);
+    }
+
+    fn take_slot(&mut self, reg: u32) -> Option<Slot> {
+        self.regs.get_mut(reg as usize).and_then(Option::take)
+    }
+
+    fn is_pending(&self) -> bool {
+        self.regs.iter().any(Option::is_some)
+    }
+
+    // the value of a register, the name of its variable when nothing is pending in it
+    fn take(&mut self, pc: usize, reg: u32) -> Expr {
+        match self.take_slot(reg) {
+            Some(Slot::Expr(expr, _)) | Some(Slot::Open(expr)) | Some(Slot::Method(expr, _)) => {
+                expr
+            }
+            Some(Slot::Extra) | None => name_expr(&self.reg_name(pc, reg)),
+        }
+    }
+
+    // the value a store takes, none for one of several values of a call
+    fn stored(&mut self, pc: usize, arg: Rk) -> Option<Expr> {
+        match arg {
+            Rk::R(reg) if matches!(self.regs.get(reg as usize), Some(Some(Slot::Extra))) => {
+                self.take_slot(reg);
+                None
+            }
+            arg => Some(self.rk(pc, arg)),
+        }
+    }
+
+    fn rk(&mut self, pc: usize, arg: Rk) -> Expr {
+        match arg {
+            Rk::R(reg) => self.take(pc, reg),
+            Rk::K(k) => self.constant(k),
+        }
+    }
+
+    // values of consecutive registers, up to the open call or `...` without a count. the
+    // flag marks the ones cut to a single value
+    fn values(&mut self, pc: usize, from: u32, count: Option<u32>) -> Vec<(Expr, bool)> {
+        let end = match count {
+            Some(n) => from + n,
+            None => (from as usize..self.regs.len())
+                .find(|&reg| matches!(self.regs[reg], Some(Slot::Open(_))))
+                .map_or(self.regs.len(), |reg| reg + 1) as u32,
+        };
+        let mut values = Vec::new();
+        for reg in from..end {
+            match self.take_slot(reg) {
+                Some(Slot::Expr(expr, cut)) => values.push((expr, cut)),
+                Some(Slot::Open(expr)) | Some(Slot::Method(expr, _)) => values.push((expr, false)),
+                Some(Slot::Extra) => {}
+                None => values.push((name_expr(&self.reg_name(pc, reg)), false)),
+            }
+        }
+        values
+    }
+
+    // a list that ends with a call cut to one value needs it in parentheses
+    fn list(&mut self, pc: usize, from: u32, count: Option<u32>) -> Vec<Expr> {
+        let values = self.values(pc, from, count);
+        let n = values.len();
+        values
+            .into_iter()
+            .enumerate()
+            .map(
+                |(i, (expr, cut))| match cut && i + 1 == n && is_multi(&expr) {
+                    true => paren(expr),
+                    false => expr,
+                },
+            )
+            .collect()
+    }
+
+    fn statement(&self, pc: usize) -> DecompileResult<()> {
+        if self.capture > 0 {
+            return Err(DecompileError::new(
+                "statement inside an expression".to_string(),
+                pc,
+            ));
+        }
+        Ok(())
+    }
+
+    fn emit(&mut self, stat: Stat) {
+        self.flush_assign();
+        self.stats.push(stat);
+    }
+
+    fn flush_assign(&mut self) {
+        if self.assign.is_empty() {
+            return;
+        }
+        let mut left = Vec::new();
+        let mut right = Vec::new();
+        for (target, value) in self.assign.drain(..).rev() {
+            left.push(target);
+            right.extend(value);
+        }
+        if right.is_empty() {
+            right.push(Expr::Nil(Span::default()));
+        }
+        if let ([target], [Expr::FuncBody(body)]) = (&left[..], &right[..]) {
+            if let Some(stat) = function_stat(target, body) {
+                self.stats.push(stat);
+                return;
+            }
+        }
+        self.stats.push(Stat::AssignStat(AssignStat {
+            left,
+            right,
+            span: Span::default(),
+        }));
+    }
+
+    // the stores of a multiple assignment follow each other, its values are all pending
+    // until the last one
+    fn store(&mut self, pc: usize, target: Expr, value: Option<Expr>) -> DecompileResult<()> {
+        self.statement(pc)?;
+        self.assign.push((suffixed(target), value));
+        let next_store = match self.ops.get(pc + 1) {
+            Some(Op::Move(a, _)) => self.is_var(pc + 1, *a),
+            Some(Op::SetGlobal(..) | Op::SetTabUp(..) | Op::SetUpval(..) | Op::SetTable(..)) => {
+                true
+            }
+            _ => false,
+        };
+        if !self.is_pending() || !next_store {
+            self.flush_assign();
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, pc: usize, reg: u32, slot: Slot) -> DecompileResult<()> {
+        if self.capture > 0 || !self.is_var(pc, reg) {
+            self.set(reg, slot);
+            return Ok(());
+        }
+        let value = match slot {
+            Slot::Expr(expr, _) | Slot::Open(expr) | Slot::Method(expr, _) => Some(expr),
+            Slot::Extra => None,
+        };
+        let target = name_expr(&self.reg_name(pc, reg));
+        self.store(pc, target, value)
+    }
+
+    fn write_expr(&mut self, pc: usize, reg: u32, expr: Expr) -> DecompileResult<()> {
+        self.write(pc, reg, Slot::Expr(expr, false))
+    }
+
+    // `n` values of a call or `...` starting at `reg`
+    fn write_values(&mut self, pc: usize, reg: u32, n: u32, expr: Expr) -> DecompileResult<()> {
+        match n {
+            0 => self.set(reg, Slot::Open(expr)),
+            1 => self.write(pc, reg, Slot::Expr(expr, true))?,
+            _ if self.capture == 0 && self.is_var(pc, reg) => {
+                self.statement(pc)?;
+                let left = (reg..reg + n)
+                    .map(|reg| suffixed(name_expr(&self.reg_name(pc, reg))))
+                    .collect();
+                self.emit(Stat::AssignStat(AssignStat {
+                    left,
+                    right: vec![expr],
+                    span: Span::default(),
+                }));
+            }
+            _ => {
+                self.set(reg, Slot::Expr(expr, false));
+                for extra in reg + 1..reg + n {
+                    self.set(extra, Slot::Extra);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn call(&mut self, pc: usize, a: u32, b: u32) -> Expr {
+        let count = |first: u32| {
+            if b == 0 {
+                None
+            } else {
+                Some((a + b).saturating_sub(first))
+            }
+        };
+        match self.take_slot(a) {
+            Some(Slot::Method(object, key)) => {
+                self.take_slot(a + 1);
+                let mut args = self.list(pc, a + 2, count(a + 2));
+                match key_name(&key) {
+                    Some(method) => {
+                        let object = with_suffix(object, Suffix::Method(name(method)));
+                        with_suffix(
+                            object,
+                            Suffix::FuncArgs(FuncArgs::Exprs(args, Span::default())),
+                        )
+                    }
+                    None => {
+                        args.insert(0, object.clone());
+                        let func = index(object, key);
+                        with_suffix(
+                            func,
+                            Suffix::FuncArgs(FuncArgs::Exprs(args, Span::default())),
+                        )
+                    }
+                }
+            }
+            slot => {
+                let func = match slot {
+                    Some(Slot::Expr(expr, _)) | Some(Slot::Open(expr)) => expr,
+                    _ => name_expr(&self.reg_name(pc, a)),
+                };
+                let args = self.list(pc, a + 1, count(a + 1));
+                with_suffix(
+                    func,
+                    Suffix::FuncArgs(FuncArgs::Exprs(args, Span::default())),
+                )
+            }
+        }
+    }
+
+    fn closure(
+        &mut self,
+        pc: usize,
+        index: usize,
+        captures: &[(bool, u32)],
+    ) -> DecompileResult<FuncBody> {
+        let child = proto_at(self.proto, index, pc)?;
+        let mut env = Vec::new();
+        let mut names = Vec::new();
+        for (i, &(in_stack, reg)) in captures.iter().enumerate() {
+            let debug_name = child
+                .upvalues
+                .get(i)
+                .map_or("", |upvalue| upvalue.name.as_str());
+            let name = match debug_name {
+                "" if in_stack => self.reg_name(pc, reg),
+                "" => self.upvalue_name(reg),
+                name => name.to_string(),
+            };
+            let inherited = !in_stack && self.env.get(reg as usize).copied().unwrap_or(false);
+            env.push(self.version != LuaVersion::Lua51 && (name == "_ENV" || inherited));
+            names.push(name);
+        }
+        Function::new(child, self.version, env, names, self.depth + 1)?.run()
+    }
+
+    // fields set on a table that is still being built go into its constructor
+    fn field(&mut self, pc: usize, a: u32, key: Rk, value: Rk) -> bool {
+        let mut table = match self.take_slot(a) {
+            Some(Slot::Expr(Expr::Table(table), _)) => table,
+            slot => {
+                if let Some(slot) = slot {
+                    self.set(a, slot);
+                }
+                return false;
+            }
+        };
+        // list items before the field are waiting in the registers below its operands
+        let above = |arg: Rk| match arg {
+            Rk::R(reg) if reg > a => reg,
+            _ => u32::MAX,
+        };
+        let end = above(key).min(above(value)).min(self.regs.len() as u32);
+        for reg in a + 1..end {
+            match self.take_slot(reg) {
+                Some(Slot::Expr(expr, _)) | Some(Slot::Open(expr)) => {
+                    table.fields.push(Field::ListField(expr));
+                    // SETLIST still counts the register
+                    self.set(reg, Slot::Extra);
+                }
+                Some(slot) => self.set(reg, slot),
+                None => {}
+            }
+        }
+        let value = self.rk(pc, value);
+        let key = self.rk(pc, key);
+        let key = match key_name(&key) {
+            Some(s) => FieldKey::Name(name(s)),
+            None => FieldKey::Expr(key),
+        };
+        table.fields.push(Field::RecFileld(RecField {
+            key,
+            value,
+            span: Span::default(),
+        }));
+        self.set(a, Slot::Expr(Expr::Table(table), false));
+        true
+    }
+
+    // register of a local, the number of locals still active where it starts
+    fn local_reg(&self, i: usize) -> u32 {
+        let vars = &self.proto.local_vars;
+        let start = vars[i].start_pc;
+        (0..i)
+            .filter(|&j| vars[j].start_pc <= start && start < vars[j].end_pc)
+            .count() as u32
+    }
+
+    // locals that start at `pc` take the pending values, only those that end there too
+    // when `pc` closes a block
+    fn declare(&mut self, pc: usize, closing: bool) -> DecompileResult<()> {
+        if self.stripped || self.capture > 0 {
+            return Ok(());
+        }
+        let vars = &self.proto.local_vars;
+        let new: Vec<usize> = (0..vars.len())
+            .filter(|&i| vars[i].start_pc == pc && !self.declared.contains(&i))
+            .filter(|&i| !closing || vars[i].end_pc <= pc)
+            .collect();
+        self.declared.extend(new.iter().copied());
+        let new: Vec<usize> = new
+            .into_iter()
+            .filter(|&i| !vars[i].name.starts_with('('))
+            .collect();
+        let first = match new.first() {
+            Some(&i) => self.local_reg(i),
+            None => return Ok(()),
+        };
+        if new.len() == 1 {
+            if let Some(Some(Slot::Expr(Expr::FuncBody(_), _))) = self.regs.get(first as usize) {
+                if let Some(Slot::Expr(Expr::FuncBody(body), _)) = self.take_slot(first) {
+                    self.emit(Stat::FuncStat(FuncStat {
+                        func_type: FuncType::Local,
+                        func_name: FuncName {
+                            fields: vec![name(&vars[new[0]].name)],
+                            method: None,
+                            span: Span::default(),
+                        },
+                        body,
+                        span: Span::default(),
+                    }));
+                }
+                return Ok(());
+            }
+        }
+        let names: Vec<LocalName> = new
+            .iter()
+            .map(|&i| LocalName {
+                name: name(&vars[i].name),
+                attrib: None,
+                span: Span::default(),
+            })
+            .collect();
+        let mut values = self.values(pc, first, Some(names.len() as u32));
+        while let Some((Expr::Nil(_), _)) = values.last() {
+            values.pop();
+        }
+        // `local a, b = (f())` leaves `b` nil
+        let short = values.len() < names.len();
+        let n = values.len();
+        let exprs = values
+            .into_iter()
+            .enumerate()
+            .map(
+                |(i, (expr, cut))| match cut && short && i + 1 == n && is_multi(&expr) {
+                    true => paren(expr),
+                    false => expr,
+                },
+            )
+            .collect();
+        self.emit(Stat::LocalStat(LocalStat {
+            names,
+            exprs,
+            span: Span::default(),
+        }));
+        Ok(())
+    }
+
+    // a stripped function stores a constructor in its variable after the last field
+    fn finish_table(&mut self, pc: usize, a: u32) -> DecompileResult<()> {
+        if self.stripped && self.capture == 0 && self.is_var(pc, a) {
+            if let Some(slot) = self.take_slot(a) {
+                self.write(pc, a, slot)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn global(&self, k: usize) -> Expr {
+        match self.constant(k) {
+            Expr::String(s, _) if is_name_str(&s) => name_expr(&s),
+            key => index(name_expr("_G"), key),
+        }
+    }
+
+    fn unexpected(&self, pc: usize) -> DecompileError {
+        DecompileError::new(format!("unexpected {:?}", self.ops[pc]), pc)
+    }
+
+    // one instruction that is neither a jump nor a test
+    fn step(&mut self, pc: usize) -> DecompileResult<()> {
+        match self.ops[pc].clone() {
+            Op::Move(a, b) => match self.take_slot(b) {
+                Some(Slot::Expr(expr, cut)) => self.write(pc, a, Slot::Expr(expr, cut))?,
+                Some(Slot::Extra) => self.write(pc, a, Slot::Extra)?,
+                Some(slot) => {
+                    self.set(b, slot);
+                    let value = self.take(pc, b);
+                    self.write_expr(pc, a, value)?
+                }
+                None => self.write_expr(pc, a, name_expr(&self.reg_name(pc, b)))?,
+            },
+            Op::LoadK(a, k) => self.write_expr(pc, a, self.constant(k))?,
+            Op::LoadBool(a, value, _) => {
+                let value = if value {
+                    Expr::True(Span::default())
+                } else {
+                    Expr::False(Span::default())
+                };
+                self.write_expr(pc, a, value)?
+            }
+            Op::LoadNil(a, n) => {
+                for reg in a..a + n {
+                    self.write_expr(pc, reg, Expr::Nil(Span::default()))?;
+                }
+            }
+            Op::GetUpval(a, b) => self.write_expr(pc, a, name_expr(&self.upvalue_name(b)))?,
+            Op::GetGlobal(a, k) => self.write_expr(pc, a, self.global(k))?,
+            Op::GetTabUp(a, up, key) => {
+                let table = name_expr(&self.upvalue_name(up));
+                let key = self.rk(pc, key);
+                self.write_expr(pc, a, index(table, key))?
+            }
+            Op::GetTable(a, b, key) => {
+                let table = self.take(pc, b);
+                let key = self.rk(pc, key);
+                self.write_expr(pc, a, index(table, key))?
+            }
+            Op::SetGlobal(k, value) => {
+                let value = self.stored(pc, value);
+                self.store(pc, self.global(k), value)?
+            }
+            Op::SetTabUp(up, key, value) => {
+                let table = name_expr(&self.upvalue_name(up));
+                let key = self.rk(pc, key);
+                let value = self.stored(pc, value);
+                self.store(pc, index(table, key), value)?
+            }
+            Op::SetUpval(a, up) => {
+                let value = self.stored(pc, Rk::R(a));
+                self.store(pc, name_expr(&self.upvalue_name(up)), value)?
+            }
+            Op::SetTable(a, key, value) => {
+                if self.field(pc, a, key, value) {
+                    self.finish_table(pc, a)?;
+                } else {
+                    let table = self.take(pc, a);
+                    let key = self.rk(pc, key);
+                    let value = self.stored(pc, value);
+                    self.store(pc, index(table, key), value)?
+                }
+            }
+            Op::NewTable(a, _, _) => {
+                let table = Expr::Table(Table {
+                    fields: Vec::new(),
+                    span: Span::default(),
+                });
+                self.write_expr(pc, a, table)?
+            }
+            Op::Self_(a, b, key) => {
+                let object = self.take(pc, b);
+                let key = self.rk(pc, key);
+                self.set(a, Slot::Method(object, key));
+                self.set(a + 1, Slot::Extra);
+            }
+            Op::Binary(a, op, left, right) => {
+                let left = self.rk(pc, left);
+                let right = self.rk(pc, right);
+                self.write_expr(pc, a, binary(op, left, right))?
+            }
+            Op::Unary(a, op, b) => {
+                let expr = self.take(pc, b);
+                self.write_expr(pc, a, unary(op, expr))?
+            }
+            Op::Concat(a, b, c) => {
+                let mut exprs: Vec<Expr> = (b..=c).map(|reg| self.take(pc, reg)).collect();
+                let mut expr = exprs.pop().unwrap_or(Expr::Nil(Span::default()));
+                while let Some(left) = exprs.pop() {
+                    expr = binary(BinOp::Concat, left, expr);
+                }
+                self.write_expr(pc, a, expr)?
+            }
+            Op::Call(a, b, c) => {
+                let call = self.call(pc, a, b);
+                match c {
+                    0 => self.set(a, Slot::Open(call)),
+                    1 => {
+                        self.statement(pc)?;
+                        self.emit(Stat::CallStat(CallStat {
+                            call: suffixed(call),
+                            span: Span::default(),
+                        }));
+                    }
+                    _ => self.write_values(pc, a, c - 1, call)?,
+                }
+            }
+            Op::TailCall(a, b) => {
+                self.statement(pc)?;
+                let call = self.call(pc, a, b);
+                self.emit(Stat::RetStat(RetStat {
+                    exprs: vec![call],
+                    span: Span::default(),
+                }));
+                self.tail = Some(pc + 1);
+            }
+            Op::Return(a, b) => {
+                self.statement(pc)?;
+                // the RETURN every function ends with
+                let last = pc + 1 == self.ops.len() && b == 1;
+                if !last && self.tail != Some(pc) {
+                    let count = if b == 0 { None } else { Some(b - 1) };
+                    let exprs = self.list(pc, a, count);
+                    self.emit(Stat::RetStat(RetStat {
+                        exprs,
+                        span: Span::default(),
+                    }));
+                }
+            }
+            Op::SetList(a, b) => {
+                let count = if b == 0 { None } else { Some(b) };
+                let items = self.list(pc, a + 1, count);
+                match self.take_slot(a) {
+                    Some(Slot::Expr(Expr::Table(mut table), _)) => {
+                        table.fields.extend(items.into_iter().map(Field::ListField));
+                        self.set(a, Slot::Expr(Expr::Table(table), false));
+                        self.finish_table(pc, a)?;
+                    }
+                    _ => {
+                        return Err(DecompileError::new(
+                            "SETLIST without a table".to_string(),
+                            pc,
+                        ))
+                    }
+                }
+            }
+            Op::Closure(a, index, captures) => {
+                let body = self.closure(pc, index, &captures)?;
+                self.write_expr(pc, a, Expr::FuncBody(body))?
+            }
+            Op::VarArg(a, b) => match b {
+                0 => self.set(a, Slot::Open(Expr::VarArg(Span::default()))),
+                _ => self.write_values(pc, a, b - 1, Expr::VarArg(Span::default()))?,
+            },
+            Op::Nop => {}
+            Op::Jmp(_)
+            | Op::Compare(..)
+            | Op::Test(..)
+            | Op::TestSet(..)
+            | Op::ForLoop(..)
+            | Op::ForPrep(..)
+            | Op::TForCall(..)
+            | Op::TForLoop(..) => return Err(self.unexpected(pc)),
+        }
+        Ok(())
+    }
+
+    fn is_test_at(&self, pc: usize) -> bool {
+        self.ops.get(pc).is_some_and(Op::is_test)
+            && matches!(self.ops.get(pc + 1), Some(Op::Jmp(_)))
+    }
+
+    // the register a LOADBOOL pair at `pc` sets
+    fn bools_at(&self, pc: usize) -> Option<u32> {
+        match (self.ops.get(pc), self.ops.get(pc + 1)) {
+            (Some(Op::LoadBool(a, false, true)), Some(Op::LoadBool(b, true, false))) if a == b => {
+                Some(*a)
+            }
+            _ => None,
+        }
+    }
+
+    // the tests from `q` on that compute a value, their operands are pure expressions
+    fn value_region(&self, q: usize) -> Option<Region> {
+        let mut units: Vec<Unit> = Vec::new();
+        let mut jumps = Vec::new();
+        let (mut start, mut pc, mut max) = (q, q, q);
+        'units: loop {
+            let target = match self.ops.get(pc + 1) {
+                Some(Op::Jmp(target)) if *target > pc + 1 => *target,
+                _ => return None,
+            };
+            units.push(Unit {
+                start,
+                test: pc,
+                target,
+            });
+            max = max.max(target);
+            pc += 2;
+            start = pc;
+            loop {
+                if pc >= max {
+                    break 'units;
+                }
+                if self.is_test_at(pc) {
+                    // a region of its own inside an operand
+                    if let Some(inner) = self.value_region(pc) {
+                        let crossed = units
+                            .iter()
+                            .any(|unit| unit.target > pc && unit.target < inner.end);
+                        if inner.end < max && !crossed {
+                            pc = inner.end;
+                            continue;
+                        }
+                    }
+                    break;
+                }
+                match self.ops[pc] {
+                    Op::Jmp(target) if target > pc + 1 => {
+                        jumps.push(pc);
+                        max = max.max(target);
+                    }
+                    Op::Jmp(_)
+                    | Op::Return(..)
+                    | Op::TailCall(..)
+                    | Op::Call(_, _, 1)
+                    | Op::SetGlobal(..)
+                    | Op::SetTabUp(..)
+                    | Op::SetUpval(..)
+                    | Op::ForLoop(..)
+                    | Op::ForPrep(..)
+                    | Op::TForCall(..)
+                    | Op::TForLoop(..) => return None,
+                    _ => {}
+                }
+                pc += 1;
+            }
+        }
+        let last = units.last()?.test + 2;
+        let (bools, end) = match (self.bools_at(max - 1), self.bools_at(max.checked_sub(2)?)) {
+            (Some(_), _) if max > last => (Some(max - 1), max + 1),
+            (_, Some(_)) if max >= last + 2 => (Some(max - 2), max),
+            _ => (None, max),
+        };
+        let reg = match bools {
+            Some(bools) => self.bools_at(bools)?,
+            None => self.ops[end - 1].dest()?,
+        };
+        let last_end = match bools {
+            Some(bools) if bools > last && self.ops[bools - 1] == Op::Jmp(end) => bools - 1,
+            Some(bools) => bools,
+            None => end,
+        };
+        if last < last_end {
+            if self.ops[last_end - 1].dest() != Some(reg) {
+                return None;
+            }
+        } else if bools != Some(last) {
+            return None;
+        }
+        if jumps
+            .iter()
+            .any(|&pc| pc != last_end || self.ops[pc] != Op::Jmp(end))
+        {
+            return None;
+        }
+        for (i, unit) in units.iter().enumerate() {
+            let valid = match self.ops[unit.test] {
+                Op::TestSet(a, _, _) => a == reg && unit.target == end,
+                Op::Test(a, _) if unit.target == end => a == reg,
+                _ => {
+                    units[i + 1..]
+                        .iter()
+                        .any(|later| later.start == unit.target)
+                        || unit.target == last
+                        || bools.is_some_and(|b| unit.target == b || unit.target == b + 1)
+                }
+            };
+            if !valid {
+                return None;
+            }
+        }
+        Some(Region {
+            units,
+            last,
+            last_end,
+            bools,
+            end,
+            reg,
+        })
+    }
+
+    // the value of a region, the operands of its first test are pending already
+    fn region(&mut self, region: &Region) -> DecompileResult<()> {
+        self.capture += 1;
+        let mut conds = Vec::new();
+        for (i, unit) in region.units.iter().enumerate() {
+            if i > 0 {
+                self.exprs(unit.start, unit.test)?;
+            }
+            conds.push(self.unit_cond(unit, Some((region.reg, region.end)))?);
+        }
+        let mut terminals = HashMap::new();
+        if region.last < region.last_end {
+            self.exprs(region.last, region.last_end)?;
+            terminals.insert(region.last, self.take(region.last_end - 1, region.reg));
+        }
+        if let Some(bools) = region.bools {
+            terminals.insert(bools, Expr::False(Span::default()));
+            terminals.insert(bools + 1, Expr::True(Span::default()));
+        }
+        self.capture -= 1;
+        let start = region.units[0].start;
+        let value = value_at(start, &region.units, &conds, &terminals, false);
+        self.write_expr(region.end - 1, region.reg, value)
+    }
+
+    // instructions of an operand, they only leave values in registers
+    fn exprs(&mut self, from: usize, to: usize) -> DecompileResult<()> {
+        self.capture += 1;
+        let mut pc = from;
+        while pc < to {
+            if self.is_test_at(pc) {
+                match self.value_region(pc) {
+                    Some(region) if region.end <= to => {
+                        self.region(&region)?;
+                        pc = region.end;
+                        continue;
+                    }
+                    _ => return Err(DecompileError::new("unexpected condition".to_string(), pc)),
+                }
+            }
+            self.step(pc)?;
+            pc += 1;
+        }
+        self.capture -= 1;
+        Ok(())
+    }
+
+    // `value` is the register and end of the region a test may leave its operand for
+    fn unit_cond(&mut self, unit: &Unit, value: Option<(u32, usize)>) -> DecompileResult<Cond> {
+        let pc = unit.test;
+        Ok(match self.ops[pc] {
+            Op::Compare(op, flag, left, right) => {
+                let l = self.rk(pc, left);
+                let r = self.rk(pc, right);
+                // `a > 1` is compiled as `1 < a`
+                let cmp = match (left, right, op) {
+                    (Rk::K(_), Rk::R(_), BinOp::Lt) => binary(BinOp::Gt, r, l),
+                    (Rk::K(_), Rk::R(_), BinOp::Le) => binary(BinOp::Ge, r, l),
+                    (Rk::K(_), Rk::R(_), op) => binary(op, r, l),
+                    (_, _, op) => binary(op, l, r),
+                };
+                Cond::Jump(if flag { cmp } else { negate(cmp, false) })
+            }
+            Op::Test(a, flag) => {
+                let x = self.take(pc, a);
+                match value {
+                    Some((reg, end)) if reg == a && unit.target == end => Cond::Value(x, flag),
+                    _ => Cond::Jump(if flag { x } else { negate(x, false) }),
+                }
+            }
+            Op::TestSet(_, b, flag) => Cond::Value(self.take(pc, b), flag),
+            _ => return Err(self.unexpected(pc)),
+        })
+    }
+}
+
+// `x and y` / `x or y`, a constant that cannot change the truth is dropped when only that
+// counts
+fn logical(op: BinOp, x: Expr, y: Expr, truthy: bool) -> Expr {
+    match (op, &y) {
+        (BinOp::And, Expr::True(_)) | (BinOp::Or, Expr::False(_)) if truthy || is_boolean(&x) => x,
+        _ => binary(op, x, y),
+    }
+}
+
+// the value of tests that go to `x` when `c` holds and to `y` otherwise
+fn choose(c: Expr, x: Expr, y: Expr, truthy: bool) -> Expr {
+    let exact = truthy || is_boolean(&c);
+    match (x, y) {
+        (Expr::True(_), Expr::False(_)) if exact => c,
+        (Expr::False(_), Expr::True(_)) => negate(c, truthy),
+        (Expr::True(_), y) if exact => logical(BinOp::Or, c, y, truthy),
+        (Expr::False(_), y) => logical(BinOp::And, negate(c, truthy), y, truthy),
+        (x, Expr::True(_)) => logical(BinOp::Or, negate(c, truthy), x, truthy),
+        (x, Expr::False(_)) if exact => logical(BinOp::And, c, x, truthy),
+        (x, Expr::BinExpr(y)) if *y.right == x && matches!(y.op, BinOp::And | BinOp::Or) => {
+            // `not c and b or x` and `(c or b) and x` share the jump to `x`, only the truth
+            // of `c` counts there
+            let left = match y.op {
+                BinOp::Or => binary(BinOp::And, negate(c, true), *y.left),
+                _ => binary(BinOp::Or, c, *y.left),
+            };
+            binary(y.op, left, x)
+        }
+        (x, y) => binary(BinOp::Or, binary(BinOp::And, c, x), y),
+    }
+}
+
+// the expression of the tests from the one starting at `pos`
+fn value_at(
+    pos: usize,
+    units: &[Unit],
+    conds: &[Cond],
+    terminals: &HashMap<usize, Expr>,
+    truthy: bool,
+) -> Expr {
+    if let Some(expr) = terminals.get(&pos) {
+        return expr.clone();
+    }
+    let i = match units.iter().position(|unit| unit.start == pos) {
+        Some(i) => i,
+        None => return Expr::Nil(Span::default()),
+    };
+    let next = value_at(units[i].test + 2, units, conds, terminals, truthy);
+    match &conds[i] {
+        Cond::Value(x, true) => logical(BinOp::Or, x.clone(), next, truthy),
+        Cond::Value(x, false) => logical(BinOp::And, x.clone(), next, truthy),
+        Cond::Jump(c) => {
+            let taken = value_at(units[i].target, units, conds, terminals, truthy);
+            choose(c.clone(), taken, next, truthy)
+        }
+    }
+}
+
+impl<'a> Function<'a> {
+    // where a chain of plain jumps ends up
+    fn follow(&self, mut target: usize) -> usize {
+        let mut seen = HashSet::new();
+        while let Some(Op::Jmp(next)) = self.ops.get(target) {
+            if (target > 0 && self.is_test_at(target - 1)) || !seen.insert(target) {
+                break;
+            }
+            target = *next;
+        }
+        target
+    }
+
+    fn same_target(&self, a: usize, b: usize) -> bool {
+        a == b || self.follow(a) == self.follow(b)
+    }
+
+    fn starts_local(&self, pc: usize) -> bool {
+        self.proto.local_vars.iter().any(|var| var.start_pc == pc)
+    }
+
+    // the next test from `from` when all before it only compute its operands. `head` is a
+    // loop or label that belongs to the test already.
+    fn pure_until_test(&self, from: usize, to: usize, head: bool) -> Option<usize> {
+        let mut pc = from;
+        while pc + 1 < to {
+            if pc > from || !head {
+                let loop_head = self.back_jumps.contains_key(&pc);
+                if loop_head || self.labels.contains(&pc) || self.starts_local(pc) {
+                    return None;
+                }
+            }
+            if self.is_test_at(pc) {
+                match self.value_region(pc) {
+                    Some(region) if region.end < to && !self.is_test_at(region.end - 1) => {
+                        pc = region.end;
+                        continue;
+                    }
+                    _ => return Some(pc),
+                }
+            }
+            let pure = match self.ops[pc] {
+                Op::Call(_, _, c) => c != 1,
+                Op::SetTable(a, ..) => !self.is_var(pc, a),
+                ref op => match op.dest() {
+                    Some(reg) => !self.is_var(pc, reg),
+                    None => matches!(
+                        op,
+                        Op::Nop | Op::Self_(..) | Op::LoadNil(..) | Op::VarArg(..)
+                    ),
+                },
+            };
+            if !pure {
+                return None;
+            }
+            pc += 1;
+        }
+        None
+    }
+
+    // the tests from `q` on that decide where a statement goes
+    fn cond_chain(&self, q: usize, to: usize) -> Chain {
+        let mut units = Vec::new();
+        let (mut start, mut pc) = (q, q);
+        while let Some(Op::Jmp(target)) = self.ops.get(pc + 1) {
+            units.push(Unit {
+                start,
+                test: pc,
+                target: *target,
+            });
+            start = pc + 2;
+            match self.pure_until_test(start, to, false) {
+                Some(test) => pc = test,
+                None => break,
+            }
+        }
+        // the longest run of tests with one way out besides falling through
+        for n in (1..=units.len()).rev() {
+            let next = units[n - 1].test + 2;
+            let mut others = BTreeSet::new();
+            for (i, unit) in units[..n].iter().enumerate() {
+                let inner = units[i + 1..n]
+                    .iter()
+                    .any(|later| later.start == unit.target);
+                if unit.target != next && !inner {
+                    others.insert(unit.target);
+                }
+            }
+            if others.len() <= 1 {
+                units.truncate(n);
+                return Chain {
+                    units,
+                    next,
+                    other: others.into_iter().next().unwrap_or(next),
+                };
+            }
+        }
+        unreachable!("a chain has a test")
+    }
+
+    // the condition of a chain that holds when it goes to `t` rather than to `f`
+    fn chain_expr(&mut self, chain: &Chain, t: usize, f: usize) -> DecompileResult<Expr> {
+        self.capture += 1;
+        let mut conds = Vec::new();
+        for (i, unit) in chain.units.iter().enumerate() {
+            if i > 0 {
+                self.exprs(unit.start, unit.test)?;
+            }
+            conds.push(self.unit_cond(unit, None)?);
+        }
+        self.capture -= 1;
+        let mut terminals = HashMap::new();
+        terminals.insert(f, Expr::False(Span::default()));
+        terminals.insert(t, Expr::True(Span::default()));
+        let start = chain.units[0].start;
+        Ok(value_at(start, &chain.units, &conds, &terminals, true))
+    }
+
+    fn body(&mut self, from: usize, to: usize, ctx: Ctx) -> DecompileResult<Block> {
+        Ok(self.body_until(from, to, ctx)?.0)
+    }
+
+    // statements of `from..to` in a block of their own, and the condition that ends a
+    // `repeat` there
+    fn body_until(
+        &mut self,
+        from: usize,
+        to: usize,
+        ctx: Ctx,
+    ) -> DecompileResult<(Block, Option<Expr>)> {
+        self.flush_assign();
+        let outer = std::mem::take(&mut self.stats);
+        let until = self.stats_in(from, to, ctx);
+        self.flush_assign();
+        let stats = std::mem::replace(&mut self.stats, outer);
+        Ok((block(stats), until?))
+    }
+
+    fn stats_in(&mut self, from: usize, to: usize, ctx: Ctx) -> DecompileResult<Option<Expr>> {
+        let mut pc = from;
+        while pc < to {
+            if self.labels.contains(&pc) && self.placed.insert(pc) {
+                self.emit(Stat::LabelStat(LabelStat {
+                    label: label(pc),
+                    span: Span::default(),
+                }));
+            }
+            self.declare(pc, false)?;
+            let back = self.back_jumps.get(&pc).and_then(|jumps| {
+                jumps
+                    .iter()
+                    .copied()
+                    .filter(|&j| j < to && !self.loops.contains(&(pc, j)))
+                    // a test at the end of a loop body jumps straight back for an `if`
+                    .filter(|&j| {
+                        !self.ops[j - 1].is_test()
+                            || !self.loops.iter().any(|&(head, end)| head == pc && end > j)
+                    })
+                    .max()
+            });
+            if let Some(j) = back {
+                pc = self.loop_stat(pc, j)?;
+                continue;
+            }
+            match self.ops[pc] {
+                Op::ForPrep(a, q) if q < to => pc = self.for_num(pc, a, q)?,
+                Op::Jmp(q) if q + 1 < to && self.is_for_list(pc, q) => pc = self.for_list(pc, q)?,
+                Op::Jmp(target) => {
+                    self.jump(pc, target, to, ctx)?;
+                    pc += 1;
+                }
+                _ if self.is_test_at(pc) => {
+                    if let Some(region) = self.value_region(pc).filter(|r| r.end <= to) {
+                        self.region(&region)?;
+                        pc = region.end;
+                        continue;
+                    }
+                    match self.conditional(pc, to, ctx)? {
+                        Flow::Next(next) => pc = next,
+                        Flow::Until(cond) => return Ok(Some(cond)),
+                    }
+                }
+                _ => {
+                    self.step(pc)?;
+                    pc += 1;
+                }
+            }
+        }
+        // locals of the block that end with it
+        self.declare(to, true)?;
+        Ok(None)
+    }
+
+    fn is_for_list(&self, pc: usize, q: usize) -> bool {
+        matches!(self.ops[q], Op::TForCall(..))
+            && matches!(self.ops.get(q + 1), Some(Op::TForLoop(_, back)) if *back == pc + 1)
+    }
+
+    // a loop from `pc` to the jump back at `j`, gives where the code goes on
+    fn loop_stat(&mut self, pc: usize, j: usize) -> DecompileResult<usize> {
+        self.loops.insert((pc, j));
+        let exit = j + 1;
+        if self.ops[j - 1].is_test() {
+            let ctx = Ctx {
+                exit: Some(exit),
+                until: Some(j),
+            };
+            let (block, until) = self.body_until(pc, exit, ctx)?;
+            // the test went to a goto, the loop ends where the code falls out of it
+            let cond = until.unwrap_or(Expr::True(Span::default()));
+            self.emit(Stat::RepeatStat(RepeatStat {
+                cond,
+                block,
+                span: Span::default(),
+            }));
+            return Ok(exit);
+        }
+        let ctx = Ctx {
+            exit: Some(exit),
+            until: None,
+        };
+        let chain = self
+            .pure_until_test(pc, j, true)
+            .map(|test| (test, self.cond_chain(test, j)))
+            .filter(|(_, chain)| chain.next <= j && self.same_target(chain.other, exit));
+        let (cond, start) = match chain {
+            Some((test, chain)) => {
+                self.exprs(pc, test)?;
+                (
+                    self.chain_expr(&chain, chain.next, chain.other)?,
+                    chain.next,
+                )
+            }
+            None => (Expr::True(Span::default()), pc),
+        };
+        let block = self.body(start, j, ctx)?;
+        self.emit(Stat::WhileStat(WhileStat {
+            cond,
+            block,
+            span: Span::default(),
+        }));
+        Ok(exit)
+    }
+
+    // locals that start at `pc` belong to a loop
+    fn declare_loop(&mut self, pc: usize) {
+        let vars = &self.proto.local_vars;
+        let new: Vec<usize> = (0..vars.len())
+            .filter(|&i| vars[i].start_pc == pc)
+            .collect();
+        self.declared.extend(new);
+    }
+
+    fn for_num(&mut self, p: usize, a: u32, q: usize) -> DecompileResult<usize> {
+        self.statement(p)?;
+        let init = self.take(p, a);
+        let limit = self.take(p, a + 1);
+        let step = match self.take(p, a + 2) {
+            Expr::Int(1, _) => None,
+            step => Some(step),
+        };
+        let var = name(&self.reg_name(p + 1, a + 3));
+        self.declare_loop(p + 1);
+        let ctx = Ctx {
+            exit: Some(q + 1),
+            until: None,
+        };
+        let body = self.body(p + 1, q, ctx)?;
+        self.emit(Stat::ForStat(ForStat::ForNum(ForNum {
+            var,
+            init,
+            limit,
+            step,
+            body,
+            span: Span::default(),
+        })));
+        Ok(q + 1)
+    }
+
+    fn for_list(&mut self, p: usize, q: usize) -> DecompileResult<usize> {
+        self.statement(p)?;
+        let (a, c) = match self.ops[q] {
+            Op::TForCall(a, c) => (a, c),
+            _ => return Err(self.unexpected(q)),
+        };
+        let mut exprs = self.list(p, a, Some(3));
+        trim_nils(&mut exprs);
+        let vars = (0..c.max(1))
+            .map(|i| name(&self.reg_name(p + 1, a + 3 + i)))
+            .collect();
+        self.declare_loop(p + 1);
+        let ctx = Ctx {
+            exit: Some(q + 2),
+            until: None,
+        };
+        let body = self.body(p + 1, q, ctx)?;
+        self.emit(Stat::ForStat(ForStat::ForList(ForList {
+            vars,
+            exprs,
+            body,
+            span: Span::default(),
+        })));
+        Ok(q + 2)
+    }
+
+    // whether `target` is where control ends up anyway from `from`, past nothing but jumps
+    // that go there too
+    fn falls_to_end(&self, from: usize, target: usize, to: usize) -> bool {
+        self.same_target(target, to)
+            && (from..to)
+                .all(|q| matches!(self.ops[q], Op::Jmp(next) if self.same_target(next, to)))
+    }
+
+    fn goto(&mut self, target: usize) {
+        self.gotos.insert(target);
+        self.emit(Stat::GotoStat(GotoStat {
+            label: label(target),
+            span: Span::default(),
+        }));
+    }
+
+    fn jump(&mut self, pc: usize, target: usize, to: usize, ctx: Ctx) -> DecompileResult<()> {
+        self.statement(pc)?;
+        if target == pc + 1 {
+            return Ok(());
+        }
+        if ctx.exit.is_some_and(|exit| self.same_target(target, exit)) {
+            self.emit(Stat::BreakStat(BreakStat {
+                span: Span::default(),
+            }));
+        } else if !self.falls_to_end(pc + 1, target, to) {
+            self.goto(target);
+        }
+        Ok(())
+    }
+
+    // an `if`, a `break` or a `goto` under a condition, or the end of a `repeat`
+    fn conditional(&mut self, pc: usize, to: usize, ctx: Ctx) -> DecompileResult<Flow> {
+        self.statement(pc)?;
+        let chain = self.cond_chain(pc, to);
+        let (n, x) = (chain.next, chain.other);
+        if ctx.until == Some(n - 1) {
+            return Ok(Flow::Until(self.chain_expr(&chain, n, x)?));
+        }
+        let span = Span::default();
+        if ctx
+            .exit
+            .is_some_and(|exit| x != n && self.same_target(x, exit))
+        {
+            let cond = self.chain_expr(&chain, x, n)?;
+            let then = block(vec![Stat::BreakStat(BreakStat { span })]);
+            self.emit(if_stat(cond, then, Block::empty()));
+            return Ok(Flow::Next(n));
+        }
+        if n <= x && x <= to {
+            let cond = self.chain_expr(&chain, n, x)?;
+            let else_end = match self.ops[x - 1] {
+                Op::Jmp(y)
+                    if x > n
+                        && !self.is_test_at(x - 2)
+                        && y > x
+                        && !ctx.exit.is_some_and(|exit| self.same_target(y, exit)) =>
+                {
+                    if y <= to {
+                        Some(y)
+                    } else if self.same_target(y, to) {
+                        Some(to)
+                    } else {
+                        None
+                    }
+                }
+                _ => None,
+            };
+            let stat = match else_end {
+                Some(end) => {
+                    let then = self.body(n, x - 1, ctx)?;
+                    let else_block = self.body(x, end, ctx)?;
+                    self.emit(if_stat(cond, then, else_block));
+                    return Ok(Flow::Next(end));
+                }
+                None => if_stat(cond, self.body(n, x, ctx)?, Block::empty()),
+            };
+            self.emit(stat);
+            return Ok(Flow::Next(x));
+        }
+        if self.same_target(x, to) {
+            let cond = self.chain_expr(&chain, n, x)?;
+            let then = self.body(n, to, ctx)?;
+            self.emit(if_stat(cond, then, Block::empty()));
+            return Ok(Flow::Next(to));
+        }
+        let cond = self.chain_expr(&chain, x, n)?;
+        self.gotos.insert(x);
+        let then = block(vec![Stat::GotoStat(GotoStat {
+            label: label(x),
+            span,
+        })]);
+        self.emit(if_stat(cond, then, Block::empty()));
+        Ok(Flow::Next(n))
+    }
+
+    // value regions from `from` on, with those in their operands
+    fn regions_in(&self, from: usize, to: usize, out: &mut Vec<Region>) {
+        let mut pc = from;
+        while pc < to {
+            if self.is_test_at(pc) {
+                if let Some(region) = self.value_region(pc).filter(|r| r.end <= to) {
+                    for unit in region.units.iter().skip(1) {
+                        self.regions_in(unit.start, unit.test, out);
+                    }
+                    self.regions_in(region.last, region.last_end, out);
+                    pc = region.end;
+                    out.push(region);
+                    continue;
+                }
+            }
+            pc += 1;
+        }
+    }
+
+    fn is_store(&self, pc: usize) -> bool {
+        matches!(
+            self.ops.get(pc),
+            Some(Op::SetGlobal(..) | Op::SetTabUp(..) | Op::SetUpval(..) | Op::SetTable(..))
+        )
+    }
+
+    // without debug info a register is a variable when its value is not used exactly once,
+    // or used after the statement that computes it ends
+    fn classify(&mut self) {
+        let len = self.ops.len();
+        let mut regions = Vec::new();
+        self.regions_in(0, len, &mut regions);
+        let mut in_region = vec![false; len];
+        let mut ignored = HashSet::new();
+        for region in regions.iter() {
+            let start = region.units[0].test;
+            for (pc, op) in self.ops.iter().enumerate().take(region.end).skip(start) {
+                in_region[pc] = true;
+                if let Op::TestSet(a, _, _) = *op {
+                    ignored.insert((pc, a));
+                }
+            }
+            if region.bools.is_some() && region.last < region.last_end {
+                ignored.insert((region.last_end - 1, region.reg));
+            }
+        }
+        let mut boundary_before = vec![false; len + 1];
+        for (pc, op) in self.ops.iter().enumerate() {
+            match *op {
+                Op::Jmp(target)
+                | Op::ForLoop(_, target)
+                | Op::ForPrep(_, target)
+                | Op::TForLoop(_, target)
+                    if !in_region[pc] =>
+                {
+                    boundary_before[target] = true
+                }
+                _ => {}
+            }
+        }
+        for &head in self.back_jumps.keys() {
+            boundary_before[head] = true;
+        }
+        let mut live: HashMap<u32, Write> = HashMap::new();
+        let mut captured = HashSet::new();
+        let mut open: Option<u32> = None;
+        let mut groups = Vec::new();
+        let mut moves = Vec::new();
+        for pc in 0..len {
+            if boundary_before[pc] {
+                live.values_mut().for_each(|write| write.crossed = true);
+            }
+            let mut reads = Vec::new();
+            let mut writes = Vec::new();
+            let mut loop_vars = false;
+            let mut boundary = false;
+            // the register whose value the instruction passes on as it is
+            let mut passed = None;
+            let mut multi = false;
+            let push_rk = |reads: &mut Vec<u32>, arg: Rk| {
+                if let Rk::R(reg) = arg {
+                    reads.push(reg);
+                }
+            };
+            // registers up to the open values when there is no count
+            let range = |from: u32, count: Option<u32>, open: &mut Option<u32>| match count {
+                Some(n) => (from..from + n).collect::<Vec<u32>>(),
+                None => (from..open.take().map_or(from, |open| (open + 1).max(from))).collect(),
+            };
+            // a count of 0 is taken as all values up to the open ones
+            let count = |n: u32, less: u32| if n == 0 { None } else { Some(n - less) };
+            match self.ops[pc] {
+                Op::Move(a, b) => {
+                    reads.push(b);
+                    writes.push(a);
+                    passed = Some(Rk::R(b));
+                }
+                Op::Unary(a, _, b) => {
+                    reads.push(b);
+                    writes.push(a);
+                }
+                Op::LoadK(a, _)
+                | Op::GetUpval(a, _)
+                | Op::GetGlobal(a, _)
+                | Op::NewTable(a, ..) => writes.push(a),
+                Op::LoadBool(a, _, skip) => {
+                    if !skip {
+                        writes.push(a);
+                    }
+                }
+                Op::LoadNil(a, n) => writes.extend(a..a + n),
+                Op::GetTabUp(a, _, key) => {
+                    push_rk(&mut reads, key);
+                    writes.push(a);
+                }
+                Op::GetTable(a, b, key) => {
+                    reads.push(b);
+                    push_rk(&mut reads, key);
+                    writes.push(a);
+                }
+                Op::SetGlobal(_, value) => {
+                    push_rk(&mut reads, value);
+                    passed = Some(value);
+                    boundary = !self.is_store(pc + 1);
+                }
+                Op::SetTabUp(_, key, value) => {
+                    push_rk(&mut reads, key);
+                    push_rk(&mut reads, value);
+                    passed = Some(value);
+                    boundary = !self.is_store(pc + 1);
+                }
+                Op::SetUpval(a, _) => {
+                    reads.push(a);
+                    passed = Some(Rk::R(a));
+                    boundary = !self.is_store(pc + 1);
+                }
+                Op::SetTable(a, key, value) => {
+                    push_rk(&mut reads, key);
+                    push_rk(&mut reads, value);
+                    match live.get_mut(&a) {
+                        // a field of a constructor, up to the size it asked for
+                        Some(Write {
+                            pc: at,
+                            reads: 0,
+                            fields: Some((_, hash)),
+                            ..
+                        }) if *hash > 0 => {
+                            *hash -= 1;
+                            *at = pc;
+                        }
+                        _ => {
+                            reads.push(a);
+                            passed = Some(value);
+                            boundary = !self.is_store(pc + 1);
+                        }
+                    }
+                }
+                Op::SetList(a, b) => {
+                    reads.extend(range(a + 1, count(b, 0), &mut open));
+                    match live.get_mut(&a) {
+                        // only constructors have SETLIST, `{...}` asks for no size
+                        Some(Write {
+                            pc: at,
+                            reads: 0,
+                            fields: Some(_),
+                            ..
+                        }) => *at = pc,
+                        _ => reads.push(a),
+                    }
+                }
+                Op::Self_(a, b, key) => {
+                    reads.push(b);
+                    push_rk(&mut reads, key);
+                    writes.extend([a, a + 1].iter());
+                }
+                Op::Binary(a, _, left, right) => {
+                    push_rk(&mut reads, left);
+                    push_rk(&mut reads, right);
+                    writes.push(a);
+                }
+                Op::Concat(a, b, c) => {
+                    reads.extend(b..=c);
+                    writes.push(a);
+                }
+                Op::Jmp(q) => {
+                    if let (Op::TForCall(a, c), false) = (&self.ops[q], in_region[pc]) {
+                        if self.is_for_list(pc, q) {
+                            reads.extend(*a..*a + 3);
+                            writes.extend(*a + 3..*a + 3 + (*c).max(1));
+                            loop_vars = true;
+                        }
+                    }
+                    boundary = !in_region[pc];
+                }
+                Op::Compare(_, _, left, right) => {
+                    push_rk(&mut reads, left);
+                    push_rk(&mut reads, right);
+                }
+                Op::Test(a, _) => reads.push(a),
+                Op::TestSet(a, b, _) => {
+                    reads.push(b);
+                    writes.push(a);
+                }
+                Op::Call(a, b, c) => {
+                    reads.extend(range(a, count(b, 0), &mut open));
+                    match c {
+                        0 => {
+                            writes.push(a);
+                            open = Some(a);
+                        }
+                        1 => boundary = true,
+                        2 => writes.push(a),
+                        c => {
+                            writes.extend(a..a + c - 1);
+                            multi = true;
+                        }
+                    }
+                }
+                Op::TailCall(a, b) => {
+                    reads.extend(range(a, count(b, 0), &mut open));
+                    boundary = true;
+                }
+                Op::Return(a, b) => {
+                    reads.extend(range(a, count(b, 1), &mut open));
+                    boundary = true;
+                }
+                Op::ForPrep(a, _) => {
+                    reads.extend(a..a + 3);
+                    writes.push(a + 3);
+                    loop_vars = true;
+                    boundary = true;
+                }
+                Op::ForLoop(..) | Op::TForCall(..) | Op::TForLoop(..) => boundary = true,
+                Op::Closure(a, _, ref captures) => {
+                    for &(in_stack, reg) in captures.iter() {
+                        if in_stack {
+                            reads.push(reg);
+                            captured.insert(reg);
+                            if let Some(write) = live.get_mut(&reg) {
+                                write.var = true;
+                            }
+                        }
+                    }
+                    writes.push(a);
+                }
+                Op::VarArg(a, b) => match b {
+                    0 => {
+                        writes.push(a);
+                        open = Some(a);
+                    }
+                    1 => {}
+                    2 => writes.push(a),
+                    b => {
+                        writes.extend(a..a + b - 1);
+                        multi = true;
+                    }
+                },
+                Op::Nop => {}
+            }
+            // the registers the written values are computed from, through the temporaries read
+            let mut sources = Vec::new();
+            for reg in reads.iter() {
+                match live.get(reg) {
+                    Some(write) if write.reads == 0 && !write.var && !write.crossed => {
+                        sources.extend(write.sources.iter())
+                    }
+                    _ => sources.push(*reg),
+                }
+            }
+            // a value after the first of a call only goes on through a store or a move
+            for reg in reads {
+                match live.get_mut(&reg) {
+                    Some(write) => {
+                        write.reads += 1;
+                        write.var |= write.crossed;
+                        // a register it was computed from changed, unless as part of the
+                        // same multiple assignment
+                        write.var |= write.clobbered.is_some_and(|at| {
+                            (at..=pc)
+                                .any(|q| !matches!(self.ops[q], Op::Move(..)) && !self.is_store(q))
+                        });
+                        if write.multi {
+                            write.var |= passed != Some(Rk::R(reg));
+                            if let Op::Move(a, _) = self.ops[pc] {
+                                moves.push((pc, a, write.pc, reg));
+                            }
+                        }
+                    }
+                    None if reg >= self.proto.num_params as u32 => {
+                        self.vars.insert(reg);
+                    }
+                    None => {}
+                }
+            }
+            for reg in writes {
+                if ignored.contains(&(pc, reg)) {
+                    continue;
+                }
+                for write in live.values_mut() {
+                    if write.reads == 0 && write.clobbered.is_none() && write.sources.contains(&reg)
+                    {
+                        write.clobbered = Some(pc);
+                    }
+                }
+                let write = Write {
+                    pc,
+                    reads: 0,
+                    crossed: false,
+                    // parameters stay variables all along
+                    var: captured.contains(&reg) || reg < self.proto.num_params as u32,
+                    multi,
+                    fields: match self.ops[pc] {
+                        Op::NewTable(_, array, hash) => Some((array, hash)),
+                        _ => None,
+                    },
+                    loop_var: loop_vars,
+                    sources: sources.clone(),
+                    clobbered: None,
+                };
+                if let Some(old) = live.insert(reg, write) {
+                    self.finish_write(reg, old);
+                }
+            }
+            if boundary {
+                live.values_mut().for_each(|write| write.crossed = true);
+            }
+            if multi {
+                groups.push(pc);
+            }
+        }
+        for (reg, write) in live {
+            self.finish_write(reg, write);
+        }
+        // the values of a call are assigned together
+        for pc in groups {
+            let (a, n) = match self.ops[pc] {
+                Op::Call(a, _, c) => (a, c - 1),
+                Op::VarArg(a, b) => (a, b - 1),
+                _ => continue,
+            };
+            if (a..a + n).any(|reg| self.var_writes.contains(&(pc, reg))) {
+                for reg in a..a + n {
+                    self.var_writes.insert((pc, reg));
+                    if reg >= self.proto.num_params as u32 {
+                        self.vars.insert(reg);
+                    }
+                }
+            }
+        }
+        // a value moved on as it is ends up in a variable
+        for (pc, a, from, reg) in moves {
+            if !self.var_writes.contains(&(from, reg)) {
+                self.var_writes.insert((pc, a));
+                if a >= self.proto.num_params as u32 {
+                    self.vars.insert(a);
+                }
+            }
+        }
+    }
+
+    fn finish_write(&mut self, reg: u32, write: Write) {
+        if !write.loop_var && (write.var || write.reads != 1) {
+            self.var_writes.insert((write.pc, reg));
+            if reg >= self.proto.num_params as u32 {
+                self.vars.insert(reg);
+            }
+        }
+    }
+}
+
+// a value written to a register while classifying a stripped function
+struct Write {
+    pc: usize,
+    reads: usize,
+    // a statement ended since
+    crossed: bool,
+    var: bool,
+    // one of several values of a call or `...`
+    multi: bool,
+    // a new table and the fields its constructor has left, the constructor moves `pc` along
+    fields: Option<(u32, u32)>,
+    loop_var: bool,
+    // the registers its value is computed from
+    sources: Vec<u32>,
+    // where one of them was written again before the value was used
+    clobbered: Option<usize>,
+}
@@ -0,0 +1,66 @@
+use crate::token::{Source, TriviaKind};
+
+const PREFIX: &str = "rslua:";
+
+// how far an `ignore` directive reaches
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum DirectiveKind {
+    // `-- rslua: ignore`, its own line, or the next line with code if the comment stands alone
+    Line,
+    // `-- rslua: ignore-begin` up to the matching `-- rslua: ignore-end` or the end of the file
+    Block,
+    // `-- rslua: ignore-file`
+    File,
+}
+
+// a comment silencing findings of some rules, `-- rslua: ignore unused-local, shadowed-local`.
+// without rule names every rule is silenced.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Directive {
+    pub kind: DirectiveKind,
+    pub rules: Vec<String>,
+    // lines covered, inclusive
+    pub first_line: usize,
+    pub last_line: usize,
+    // the comment itself
+    pub source: Source,
+}
+
+// what a directive comment asks for
+pub(crate) enum Command {
+    Ignore(DirectiveKind, Vec<String>),
+    End,
+}
+
+impl Directive {
+    pub fn covers(&self, rule: &str, line: usize) -> bool {
+        (self.first_line..=self.last_line).contains(&line)
+            && (self.rules.is_empty() || self.rules.iter().any(|r| r == rule))
+    }
+
+    // the command in a comment, `text` is the comment as written
+    pub(crate) fn parse(text: &str, kind: TriviaKind) -> Option<Command> {
+        let body = &text[2..];
+        let body = match kind {
+            TriviaKind::LongComment(sep) => {
+                let close = 2 + sep;
+                body.get(close..body.len().checked_sub(close)?)?
+            }
+            _ => body,
+        };
+        let body = body.trim().strip_prefix(PREFIX)?.trim_start();
+        let (command, rules) = body.split_once(char::is_whitespace).unwrap_or((body, ""));
+        let rules = rules
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|rule| !rule.is_empty())
+            .map(str::to_string)
+            .collect();
+        match command {
+            "ignore" => Some(Command::Ignore(DirectiveKind::Line, rules)),
+            "ignore-begin" => Some(Command::Ignore(DirectiveKind::Block, rules)),
+            "ignore-file" => Some(Command::Ignore(DirectiveKind::File, rules)),
+            "ignore-end" => Some(Command::End),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,726 @@
+use std::fmt::Write;
+
+use crate::bytecode::{index_k, is_k, Constant, Instruction, OpArg, OpCode, OpMode, Proto};
+use crate::types::LuaVersion;
+use crate::undump::Chunk;
+use crate::value::float_to_string;
+
+// instruction names of Lua 5.1, fields are laid out like those of 5.3
+const OPCODES_51: [(&str, OpMode, OpArg, OpArg); 38] = [
+    ("MOVE", OpMode::ABC, OpArg::R, OpArg::N),
+    ("LOADK", OpMode::ABx, OpArg::K, OpArg::N),
+    ("LOADBOOL", OpMode::ABC, OpArg::U, OpArg::U),
+    ("LOADNIL", OpMode::ABC, OpArg::R, OpArg::N),
+    ("GETUPVAL", OpMode::ABC, OpArg::U, OpArg::N),
+    ("GETGLOBAL", OpMode::ABx, OpArg::K, OpArg::N),
+    ("GETTABLE", OpMode::ABC, OpArg::R, OpArg::K),
+    ("SETGLOBAL", OpMode::ABx, OpArg::K, OpArg::N),
+    ("SETUPVAL", OpMode::ABC, OpArg::U, OpArg::N),
+    ("SETTABLE", OpMode::ABC, OpArg::K, OpArg::K),
+    ("NEWTABLE", OpMode::ABC, OpArg::U, OpArg::U),
+    ("SELF", OpMode::ABC, OpArg::R, OpArg::K),
+    ("ADD", OpMode::ABC, OpArg::K, OpArg::K),
+    ("SUB", OpMode::ABC, OpArg::K, OpArg::K),
+    ("MUL", OpMode::ABC, OpArg::K, OpArg::K),
+    ("DIV", OpMode::ABC, OpArg::K, OpArg::K),
+    ("MOD", OpMode::ABC, OpArg::K, OpArg::K),
+    ("POW", OpMode::ABC, OpArg::K, OpArg::K),
+    ("UNM", OpMode::ABC, OpArg::R, OpArg::N),
+    ("NOT", OpMode::ABC, OpArg::R, OpArg::N),
+    ("LEN", OpMode::ABC, OpArg::R, OpArg::N),
+    ("CONCAT", OpMode::ABC, OpArg::R, OpArg::R),
+    ("JMP", OpMode::AsBx, OpArg::R, OpArg::N),
+    ("EQ", OpMode::ABC, OpArg::K, OpArg::K),
+    ("LT", OpMode::ABC, OpArg::K, OpArg::K),
+    ("LE", OpMode::ABC, OpArg::K, OpArg::K),
+    ("TEST", OpMode::ABC, OpArg::R, OpArg::U),
+    ("TESTSET", OpMode::ABC, OpArg::R, OpArg::U),
+    ("CALL", OpMode::ABC, OpArg::U, OpArg::U),
+    ("TAILCALL", OpMode::ABC, OpArg::U, OpArg::U),
+    ("RETURN", OpMode::ABC, OpArg::U, OpArg::N),
+    ("FORLOOP", OpMode::AsBx, OpArg::R, OpArg::N),
+    ("FORPREP", OpMode::AsBx, OpArg::R, OpArg::N),
+    ("TFORLOOP", OpMode::ABC, OpArg::N, OpArg::U),
+    ("SETLIST", OpMode::ABC, OpArg::U, OpArg::U),
+    ("CLOSE", OpMode::ABC, OpArg::N, OpArg::N),
+    ("CLOSURE", OpMode::ABx, OpArg::U, OpArg::N),
+    ("VARARG", OpMode::ABC, OpArg::U, OpArg::N),
+];
+
+const OPCODES_54: [&str; 83] = [
+    "MOVE",
+    "LOADI",
+    "LOADF",
+    "LOADK",
+    "LOADKX",
+    "LOADFALSE",
+    "LFALSESKIP",
+    "LOADTRUE",
+    "LOADNIL",
+    "GETUPVAL",
+    "SETUPVAL",
+    "GETTABUP",
+    "GETTABLE",
+    "GETI",
+    "GETFIELD",
+    "SETTABUP",
+    "SETTABLE",
+    "SETI",
+    "SETFIELD",
+    "NEWTABLE",
+    "SELF",
+    "ADDI",
+    "ADDK",
+    "SUBK",
+    "MULK",
+    "MODK",
+    "POWK",
+    "DIVK",
+    "IDIVK",
+    "BANDK",
+    "BORK",
+    "BXORK",
+    "SHRI",
+    "SHLI",
+    "ADD",
+    "SUB",
+    "MUL",
+    "MOD",
+    "POW",
+    "DIV",
+    "IDIV",
+    "BAND",
+    "BOR",
+    "BXOR",
+    "SHL",
+    "SHR",
+    "MMBIN",
+    "MMBINI",
+    "MMBINK",
+    "UNM",
+    "BNOT",
+    "NOT",
+    "LEN",
+    "CONCAT",
+    "CLOSE",
+    "TBC",
+    "JMP",
+    "EQ",
+    "LT",
+    "LE",
+    "EQK",
+    "EQI",
+    "LTI",
+    "LEI",
+    "GTI",
+    "GEI",
+    "TEST",
+    "TESTSET",
+    "CALL",
+    "TAILCALL",
+    "RETURN",
+    "RETURN0",
+    "RETURN1",
+    "FORLOOP",
+    "FORPREP",
+    "TFORPREP",
+    "TFORCALL",
+    "TFORLOOP",
+    "SETLIST",
+    "CLOSURE",
+    "VARARG",
+    "VARARGPREP",
+    "EXTRAARG",
+];
+
+// metamethods by the event number of 5.4 MMBIN instructions
+const EVENTS_54: [&str; 25] = [
+    "__index",
+    "__newindex",
+    "__gc",
+    "__mode",
+    "__len",
+    "__eq",
+    "__add",
+    "__sub",
+    "__mul",
+    "__mod",
+    "__pow",
+    "__div",
+    "__idiv",
+    "__band",
+    "__bor",
+    "__bxor",
+    "__shl",
+    "__shr",
+    "__unm",
+    "__bnot",
+    "__lt",
+    "__le",
+    "__concat",
+    "__call",
+    "__close",
+];
+
+// the listing of `luac -l`, or of `luac -l -l` with constants, locals and upvalues when full.
+// functions are named by their lines instead of their addresses
+pub fn disassemble(chunk: &Chunk, full: bool) -> String {
+    let mut disassembler = Disassembler {
+        version: chunk.version,
+        out: String::new(),
+    };
+    disassembler.function(&chunk.main, full);
+    disassembler.out
+}
+
+struct Disassembler {
+    version: LuaVersion,
+    out: String,
+}
+
+// 5.4 instruction fields
+struct Args54 {
+    a: u32,
+    b: u32,
+    c: u32,
+    k: bool,
+    bx: u32,
+    sb: i32,
+    sc: i32,
+    sbx: i32,
+    sj: i32,
+}
+
+impl Args54 {
+    fn new(i: u32) -> Self {
+        let b = i >> 16 & 0xff;
+        let c = i >> 24;
+        let bx = i >> 15;
+        Args54 {
+            a: i >> 7 & 0xff,
+            b,
+            c,
+            k: i >> 15 & 1 != 0,
+            bx,
+            sb: b as i32 - 127,
+            sc: c as i32 - 127,
+            sbx: bx as i32 - 65535,
+            sj: (i >> 7) as i32 - 16_777_215,
+        }
+    }
+}
+
+impl Disassembler {
+    fn function(&mut self, proto: &Proto, full: bool) {
+        self.header(proto);
+        self.code(proto);
+        if full {
+            self.debug(proto);
+        }
+        for child in &proto.protos {
+            self.function(child, full);
+        }
+    }
+
+    fn header(&mut self, proto: &Proto) {
+        let plural = |n: usize| if n == 1 { "" } else { "s" };
+        let n = proto.code.len();
+        write!(
+            self.out,
+            "\n{} {} ({} instruction{}",
+            if proto.line_defined == 0 {
+                "main"
+            } else {
+                "function"
+            },
+            identity(proto),
+            n,
+            plural(n)
+        )
+        .unwrap();
+        if self.version == LuaVersion::Lua51 {
+            write!(self.out, ", {} bytes", n * 4).unwrap();
+        }
+        self.out.push_str(")\n");
+        let params = proto.num_params as usize;
+        let slots = proto.max_stack_size as usize;
+        let upvalues = proto.upvalues.len();
+        let locals = proto.local_vars.len();
+        let constants = proto.constants.len();
+        let functions = proto.protos.len();
+        writeln!(
+            self.out,
+            "{}{} param{}, {} slot{}, {} upvalue{}, {} local{}, {} constant{}, {} function{}",
+            params,
+            if proto.is_vararg { "+" } else { "" },
+            plural(params),
+            slots,
+            plural(slots),
+            upvalues,
+            plural(upvalues),
+            locals,
+            plural(locals),
+            constants,
+            plural(constants),
+            functions,
+            plural(functions)
+        )
+        .unwrap();
+    }
+
+    fn code(&mut self, proto: &Proto) {
+        let mut pc = 0;
+        while pc < proto.code.len() {
+            write!(self.out, "\t{}\t", pc + 1).unwrap();
+            match proto.line_info.get(pc) {
+                Some(line) if *line > 0 => write!(self.out, "[{}]\t", line).unwrap(),
+                _ => self.out.push_str("[-]\t"),
+            }
+            pc = match self.version {
+                LuaVersion::Lua51 => self.instruction51(proto, pc),
+                LuaVersion::Lua54 => self.instruction54(proto, pc),
+                _ => self.instruction53(proto, pc),
+            };
+            self.out.push('\n');
+            pc += 1;
+        }
+    }
+
+    fn debug(&mut self, proto: &Proto) {
+        let numbered_from = if self.version == LuaVersion::Lua54 {
+            0
+        } else {
+            1
+        };
+        writeln!(self.out, "constants ({}):", proto.constants.len()).unwrap();
+        for (i, constant) in proto.constants.iter().enumerate() {
+            write!(self.out, "\t{}\t", i + numbered_from).unwrap();
+            if self.version == LuaVersion::Lua54 {
+                self.out.push_str(match constant {
+                    Constant::Nil => "N\t",
+                    Constant::Boolean(_) => "B\t",
+                    Constant::Float(_) => "F\t",
+                    Constant::Int(_) => "I\t",
+                    Constant::Str(_) => "S\t",
+                });
+            }
+            self.constant(proto, i as u32);
+            self.out.push('\n');
+        }
+        writeln!(self.out, "locals ({}):", proto.local_vars.len()).unwrap();
+        for (i, var) in proto.local_vars.iter().enumerate() {
+            writeln!(
+                self.out,
+                "\t{}\t{}\t{}\t{}",
+                i,
+                var.name,
+                var.start_pc + 1,
+                var.end_pc + 1
+            )
+            .unwrap();
+        }
+        writeln!(self.out, "upvalues ({}):", proto.upvalues.len()).unwrap();
+        for (i, upvalue) in proto.upvalues.iter().enumerate() {
+            write!(self.out, "\t{}\t{}", i, upvalue_name(proto, i as u32)).unwrap();
+            if self.version != LuaVersion::Lua51 {
+                write!(self.out, "\t{}\t{}", upvalue.in_stack as u8, upvalue.index).unwrap();
+            }
+            self.out.push('\n');
+        }
+    }
+
+    fn constant(&mut self, proto: &Proto, i: u32) {
+        match proto.constants.get(i as usize) {
+            Some(Constant::Nil) => self.out.push_str("nil"),
+            Some(Constant::Boolean(b)) => write!(self.out, "{}", b).unwrap(),
+            Some(Constant::Int(i)) => write!(self.out, "{}", i).unwrap(),
+            Some(Constant::Float(f)) => self
+                .out
+                .push_str(&float_to_string(*f, self.version != LuaVersion::Lua51)),
+            Some(Constant::Str(s)) => self.string(s),
+            None => write!(self.out, "?{}", i).unwrap(),
+        }
+    }
+
+    fn string(&mut self, s: &[u8]) {
+        self.out.push('"');
+        for &c in s {
+            match c {
+                b'"' => self.out.push_str("\\\""),
+                b'\\' => self.out.push_str("\\\\"),
+                0x07 => self.out.push_str("\\a"),
+                0x08 => self.out.push_str("\\b"),
+                0x0c => self.out.push_str("\\f"),
+                b'\n' => self.out.push_str("\\n"),
+                b'\r' => self.out.push_str("\\r"),
+                b'\t' => self.out.push_str("\\t"),
+                0x0b => self.out.push_str("\\v"),
+                0x20..=0x7e => self.out.push(c as char),
+                _ => write!(self.out, "\\{:03}", c).unwrap(),
+            }
+        }
+        self.out.push('"');
+    }
+
+    // the two operands of a binary instruction when either is a constant
+    fn rk_pair(&mut self, proto: &Proto, b: u32, c: u32) {
+        if is_k(b) || is_k(c) {
+            self.out.push_str("\t; ");
+            self.rk(proto, b);
+            self.out.push(' ');
+            self.rk(proto, c);
+        }
+    }
+
+    fn rk(&mut self, proto: &Proto, arg: u32) {
+        if is_k(arg) {
+            self.constant(proto, index_k(arg));
+        } else {
+            self.out.push('-');
+        }
+    }
+
+    fn closure(&mut self, proto: &Proto, bx: u32) {
+        self.out.push_str("\t; ");
+        match proto.protos.get(bx as usize) {
+            Some(child) => write!(self.out, "function {}", identity(child)).unwrap(),
+            None => self.out.push('?'),
+        }
+    }
+
+    // returns the pc of the last word the instruction takes
+    fn instruction53(&mut self, proto: &Proto, mut pc: usize) -> usize {
+        let i = proto.code[pc];
+        write!(self.out, "{}", i).unwrap();
+        let op = match i.opcode() {
+            Some(op) => op,
+            None => return pc,
+        };
+        let (a, b, c, bx) = (i.a(), i.b(), i.c(), i.bx());
+        match op {
+            OpCode::LoadK => {
+                self.out.push_str("\t; ");
+                self.constant(proto, bx);
+            }
+            OpCode::GetUpval | OpCode::SetUpval => {
+                write!(self.out, "\t; {}", upvalue_name(proto, b)).unwrap();
+            }
+            OpCode::GetTabUp => {
+                write!(self.out, "\t; {}", upvalue_name(proto, b)).unwrap();
+                if is_k(c) {
+                    self.out.push(' ');
+                    self.constant(proto, index_k(c));
+                }
+            }
+            OpCode::SetTabUp => {
+                write!(self.out, "\t; {}", upvalue_name(proto, a)).unwrap();
+                for arg in [b, c].iter().filter(|arg| is_k(**arg)) {
+                    self.out.push(' ');
+                    self.constant(proto, index_k(*arg));
+                }
+            }
+            OpCode::GetTable | OpCode::Self_ if is_k(c) => {
+                self.out.push_str("\t; ");
+                self.constant(proto, index_k(c));
+            }
+            OpCode::SetTable
+            | OpCode::Add
+            | OpCode::Sub
+            | OpCode::Mul
+            | OpCode::Mod
+            | OpCode::Pow
+            | OpCode::Div
+            | OpCode::IDiv
+            | OpCode::BAnd
+            | OpCode::BOr
+            | OpCode::BXor
+            | OpCode::Shl
+            | OpCode::Shr
+            | OpCode::Eq
+            | OpCode::Lt
+            | OpCode::Le => self.rk_pair(proto, b, c),
+            OpCode::Jmp | OpCode::ForLoop | OpCode::ForPrep | OpCode::TForLoop => {
+                write!(self.out, "\t; to {}", i.sbx() + pc as i32 + 2).unwrap();
+            }
+            OpCode::Closure => self.closure(proto, bx),
+            OpCode::SetList => {
+                // the count is in the next word when it does not fit C
+                if c == 0 {
+                    pc += 1;
+                    let n = proto.code.get(pc).map_or(0, |i| i.0);
+                    write!(self.out, "\t; {}", n).unwrap();
+                } else {
+                    write!(self.out, "\t; {}", c).unwrap();
+                }
+            }
+            OpCode::ExtraArg => {
+                self.out.push_str("\t; ");
+                self.constant(proto, i.ax_arg());
+            }
+            _ => (),
+        }
+        pc
+    }
+
+    fn instruction51(&mut self, proto: &Proto, mut pc: usize) -> usize {
+        let i = proto.code[pc];
+        let (name, mode, b_mode, c_mode) = match OPCODES_51.get((i.0 & 0x3f) as usize) {
+            Some(op) => *op,
+            None => {
+                write!(self.out, "{:#010x}", i.0).unwrap();
+                return pc;
+            }
+        };
+        let (a, b, c, bx, sbx) = (i.a(), i.b(), i.c(), i.bx(), i.sbx());
+        let rk = |arg: u32| {
+            if is_k(arg) {
+                -1 - index_k(arg) as i64
+            } else {
+                arg as i64
+            }
+        };
+        write!(self.out, "{:<9}\t", name).unwrap();
+        match mode {
+            OpMode::ABx if b_mode == OpArg::K => {
+                write!(self.out, "{} {}", a, -1 - bx as i64).unwrap()
+            }
+            OpMode::ABx => write!(self.out, "{} {}", a, bx).unwrap(),
+            OpMode::AsBx if name == "JMP" => write!(self.out, "{}", sbx).unwrap(),
+            OpMode::AsBx => write!(self.out, "{} {}", a, sbx).unwrap(),
+            _ => {
+                write!(self.out, "{}", a).unwrap();
+                if b_mode != OpArg::N {
+                    write!(self.out, " {}", rk(b)).unwrap();
+                }
+                if c_mode != OpArg::N {
+                    write!(self.out, " {}", rk(c)).unwrap();
+                }
+            }
+        }
+        match name {
+            "LOADK" => {
+                self.out.push_str("\t; ");
+                self.constant(proto, bx);
+            }
+            "GETUPVAL" | "SETUPVAL" => {
+                write!(self.out, "\t; {}", upvalue_name(proto, b)).unwrap();
+            }
+            "GETGLOBAL" | "SETGLOBAL" => {
+                self.out.push_str("\t; ");
+                match proto.constants.get(bx as usize) {
+                    Some(Constant::Str(s)) => self.out.push_str(&String::from_utf8_lossy(s)),
+                    _ => self.constant(proto, bx),
+                }
+            }
+            "GETTABLE" | "SELF" if is_k(c) => {
+                self.out.push_str("\t; ");
+                self.constant(proto, index_k(c));
+            }
+            "SETTABLE" | "ADD" | "SUB" | "MUL" | "DIV" | "MOD" | "POW" | "EQ" | "LT" | "LE" => {
+                self.rk_pair(proto, b, c)
+            }
+            "JMP" | "FORLOOP" | "FORPREP" => {
+                write!(self.out, "\t; to {}", sbx + pc as i32 + 2).unwrap();
+            }
+            "CLOSURE" => self.closure(proto, bx),
+            "SETLIST" => {
+                if c == 0 {
+                    pc += 1;
+                    let n = proto.code.get(pc).map_or(0, |i| i.0);
+                    write!(self.out, "\t; {}", n).unwrap();
+                } else {
+                    write!(self.out, "\t; {}", c).unwrap();
+                }
+            }
+            _ => (),
+        }
+        pc
+    }
+
+    fn instruction54(&mut self, proto: &Proto, pc: usize) -> usize {
+        let i = proto.code[pc].0;
+        let name = match OPCODES_54.get((i & 0x7f) as usize) {
+            Some(name) => *name,
+            None => {
+                write!(self.out, "{:#010x}", i).unwrap();
+                return pc;
+            }
+        };
+        let Args54 {
+            a,
+            b,
+            c,
+            k,
+            bx,
+            sb,
+            sc,
+            sbx,
+            sj,
+        } = Args54::new(i);
+        let isk = if k { "k" } else { "" };
+        // the argument of a following EXTRAARG
+        let extra = proto.code.get(pc + 1).map_or(0, |i: &Instruction| i.0 >> 7);
+        let out = &mut self.out;
+        write!(out, "{:<9}\t", name).unwrap();
+        match name {
+            "MOVE" | "UNM" | "BNOT" | "NOT" | "LEN" | "CONCAT" => {
+                write!(out, "{} {}", a, b).unwrap()
+            }
+            "LOADI" | "LOADF" => write!(out, "{} {}", a, sbx).unwrap(),
+            "LOADK" => {
+                write!(out, "{} {}\t; ", a, bx).unwrap();
+                self.constant(proto, bx);
+            }
+            "LOADKX" => {
+                write!(out, "{}\t; ", a).unwrap();
+                self.constant(proto, extra);
+            }
+            "LOADFALSE" | "LFALSESKIP" | "LOADTRUE" | "CLOSE" | "TBC" | "RETURN1"
+            | "VARARGPREP" => write!(out, "{}", a).unwrap(),
+            "LOADNIL" => write!(out, "{} {}\t; {} out", a, b, b + 1).unwrap(),
+            "GETUPVAL" | "SETUPVAL" => {
+                write!(out, "{} {}\t; {}", a, b, upvalue_name(proto, b)).unwrap()
+            }
+            "GETTABUP" => {
+                write!(out, "{} {} {}\t; {} ", a, b, c, upvalue_name(proto, b)).unwrap();
+                self.constant(proto, c);
+            }
+            "GETTABLE" | "GETI" | "ADD" | "SUB" | "MUL" | "MOD" | "POW" | "DIV" | "IDIV"
+            | "BAND" | "BOR" | "BXOR" | "SHL" | "SHR" => write!(out, "{} {} {}", a, b, c).unwrap(),
+            "GETFIELD" | "ADDK" | "SUBK" | "MULK" | "MODK" | "POWK" | "DIVK" | "IDIVK"
+            | "BANDK" | "BORK" | "BXORK" => {
+                write!(out, "{} {} {}\t; ", a, b, c).unwrap();
+                self.constant(proto, c);
+            }
+            "SETTABUP" => {
+                write!(
+                    out,
+                    "{} {} {}{}\t; {} ",
+                    a,
+                    b,
+                    c,
+                    isk,
+                    upvalue_name(proto, a)
+                )
+                .unwrap();
+                self.constant(proto, b);
+                if k {
+                    self.out.push(' ');
+                    self.constant(proto, c);
+                }
+            }
+            "SETTABLE" | "SETI" | "SELF" => {
+                write!(out, "{} {} {}{}", a, b, c, isk).unwrap();
+                if k {
+                    self.out.push_str("\t; ");
+                    self.constant(proto, c);
+                }
+            }
+            "SETFIELD" => {
+                write!(out, "{} {} {}{}\t; ", a, b, c, isk).unwrap();
+                self.constant(proto, b);
+                if k {
+                    self.out.push(' ');
+                    self.constant(proto, c);
+                }
+            }
+            "NEWTABLE" => write!(out, "{} {} {}\t; {}", a, b, c, c + extra * 256).unwrap(),
+            "ADDI" | "SHRI" | "SHLI" => write!(out, "{} {} {}", a, b, sc).unwrap(),
+            "MMBIN" => write!(out, "{} {} {}\t; {}", a, b, c, event(c)).unwrap(),
+            "MMBINI" | "MMBINK" => {
+                let first = if name == "MMBINI" { sb } else { b as i32 };
+                write!(out, "{} {} {} {}\t; {}", a, first, c, k as u8, event(c)).unwrap();
+                if name == "MMBINK" {
+                    self.out.push(' ');
+                    self.constant(proto, b);
+                }
+                if k {
+                    self.out.push_str(" flip");
+                }
+            }
+            "JMP" => write!(out, "{}\t; to {}", sj, sj + pc as i32 + 2).unwrap(),
+            "EQ" | "LT" | "LE" => write!(out, "{} {} {}", a, b, k as u8).unwrap(),
+            "EQK" => {
+                write!(out, "{} {} {}\t; ", a, b, k as u8).unwrap();
+                self.constant(proto, b);
+            }
+            "EQI" | "LTI" | "LEI" | "GTI" | "GEI" => {
+                write!(out, "{} {} {}", a, sb, k as u8).unwrap()
+            }
+            "TEST" => write!(out, "{} {}", a, k as u8).unwrap(),
+            "TESTSET" => write!(out, "{} {} {}", a, b, k as u8).unwrap(),
+            "CALL" => {
+                write!(out, "{} {} {}\t; ", a, b, c).unwrap();
+                match b {
+                    0 => out.push_str("all in "),
+                    _ => write!(out, "{} in ", b - 1).unwrap(),
+                }
+                match c {
+                    0 => out.push_str("all out"),
+                    _ => write!(out, "{} out", c - 1).unwrap(),
+                }
+            }
+            "TAILCALL" => write!(out, "{} {} {}{}\t; {} in", a, b, c, isk, b as i32 - 1).unwrap(),
+            "RETURN" => {
+                write!(out, "{} {} {}{}\t; ", a, b, c, isk).unwrap();
+                match b {
+                    0 => out.push_str("all out"),
+                    _ => write!(out, "{} out", b - 1).unwrap(),
+                }
+            }
+            "RETURN0" => (),
+            "FORLOOP" | "TFORLOOP" => {
+                write!(out, "{} {}\t; to {}", a, bx, pc as i32 - bx as i32 + 2).unwrap()
+            }
+            "FORPREP" => write!(out, "{} {}\t; exit to {}", a, bx, pc as u32 + bx + 3).unwrap(),
+            "TFORPREP" => write!(out, "{} {}\t; to {}", a, bx, pc as u32 + bx + 2).unwrap(),
+            "TFORCALL" => write!(out, "{} {}", a, c).unwrap(),
+            "SETLIST" => {
+                write!(out, "{} {} {}", a, b, c).unwrap();
+                if k {
+                    write!(out, "\t; {}", c + extra * 256).unwrap();
+                }
+            }
+            "CLOSURE" => {
+                write!(out, "{} {}", a, bx).unwrap();
+                self.closure(proto, bx);
+            }
+            "VARARG" => {
+                write!(out, "{} {}\t; ", a, c).unwrap();
+                match c {
+                    0 => out.push_str("all out"),
+                    _ => write!(out, "{} out", c - 1).unwrap(),
+                }
+            }
+            _ => write!(out, "{}", i >> 7).unwrap(),
+        }
+        pc
+    }
+}
+
+// `<file.lua:1,3>` like the headers of luac, sources in binary or from strings are not named
+fn identity(proto: &Proto) -> String {
+    let source = proto.source.as_deref().unwrap_or("=?");
+    let name = if source.starts_with('@') || source.starts_with('=') {
+        &source[1..]
+    } else if source.starts_with('\x1b') {
+        "(bstring)"
+    } else {
+        "(string)"
+    };
+    format!(
+        "<{}:{},{}>",
+        name, proto.line_defined, proto.last_line_defined
+    )
+}
+
+fn upvalue_name(proto: &Proto, i: u32) -> &str {
+    match proto.upvalues.get(i as usize) {
+        Some(upvalue) if !upvalue.name.is_empty() => &upvalue.name,
+        _ => "-",
+    }
+}
+
+fn event(c: u32) -> &'static str {
+    EVENTS_54.get(c as usize).copied().unwrap_or("?")
+}
@@ -0,0 +1,794 @@
+use std::fmt;
+
+use crate::ast::*;
+use crate::lexer::{LexError, Lexer};
+use crate::parser::{Parser, SyntaxError};
+use crate::printer::{ends_with_expr, starts_with_paren};
+use crate::token::{Span, TokenType, TriviaKind};
+use crate::types::LuaVersion;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum QuoteStyle {
+    // double quotes, unless the string holds more double quotes than single ones
+    Double,
+    // single quotes, unless the string holds more single quotes than double ones
+    Single,
+    // quotes of the source
+    Keep,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum CallParens {
+    Keep,
+    // `f "s"` and `f {}` become `f("s")` and `f({})`
+    Always,
+    // `f("s")` and `f({})` become `f "s"` and `f {}`
+    Omit,
+}
+
+#[derive(Clone, Debug)]
+pub struct FormatConfig {
+    // spaces per indent level, unused with tabs
+    pub indent_width: usize,
+    pub use_tabs: bool,
+    // argument lists and tables longer than this are split over several lines
+    pub max_width: usize,
+    pub quote_style: QuoteStyle,
+    // add a separator after the last field of tables split over several lines
+    pub trailing_separator: bool,
+    pub call_parens: CallParens,
+    // dialect of the source
+    pub version: LuaVersion,
+}
+
+impl Default for FormatConfig {
+    fn default() -> Self {
+        FormatConfig {
+            indent_width: 2,
+            use_tabs: false,
+            max_width: 100,
+            quote_style: QuoteStyle::Double,
+            trailing_separator: true,
+            call_parens: CallParens::Keep,
+            version: LuaVersion::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FormatError {
+    Lex(LexError),
+    Syntax(SyntaxError),
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FormatError::Lex(e) => e.fmt(f),
+            FormatError::Syntax(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+impl From<LexError> for FormatError {
+    fn from(e: LexError) -> Self {
+        FormatError::Lex(e)
+    }
+}
+
+impl From<SyntaxError> for FormatError {
+    fn from(e: SyntaxError) -> Self {
+        FormatError::Syntax(e)
+    }
+}
+
+// format Lua source, comments are kept and blank lines between statements are collapsed to one.
+pub fn format(source: &str, config: &FormatConfig) -> Result<String, FormatError> {
+    let mut lexer = Lexer::new();
+    lexer.set_version(config.version);
+    lexer.set_keep_trivia(true);
+    let tokens = lexer.run(source)?;
+
+    let mut comments = Vec::new();
+    let mut keywords = Vec::new();
+    for token in tokens.iter() {
+        for trivia in token.leading.iter().chain(token.trailing.iter()) {
+            if trivia.is_comment() {
+                let start = trivia.source.pos;
+                let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+                comments.push(Comment {
+                    text: trivia.text.trim_end().to_string(),
+                    short: trivia.kind == TriviaKind::ShortComment,
+                    start,
+                    end: start + trivia.source.length,
+                    trailing: !source[line_start..start].trim().is_empty(),
+                });
+            }
+        }
+        keywords.push((token.t, token.source.pos));
+    }
+
+    let mut parser = Parser::new();
+    parser.set_version(config.version);
+    let block = parser.run(tokens)?;
+
+    let mut formatter = Formatter {
+        source,
+        config,
+        comments,
+        keywords,
+        output: String::new(),
+        depth: 0,
+        state: State {
+            next_comment: 0,
+            last_end: 0,
+            block_start: true,
+            short_comment_end: None,
+        },
+        flat: false,
+        flat_failed: false,
+    };
+    formatter.block(&block);
+    formatter.flush_comments(usize::MAX);
+    Ok(formatter.output)
+}
+
+struct Comment {
+    text: String,
+    short: bool,
+    start: usize,
+    end: usize,
+    // code precedes it on its line
+    trailing: bool,
+}
+
+// everything besides the output that a failed attempt to fit a list on one line must restore
+#[derive(Clone, Copy)]
+struct State {
+    // first comment not written yet
+    next_comment: usize,
+    // source offset of the end of the last statement, list item or comment written
+    last_end: usize,
+    // nothing has been written in the current block or list yet
+    block_start: bool,
+    // output length right after a short comment, nothing may follow it on that line
+    short_comment_end: Option<usize>,
+}
+
+struct Formatter<'a> {
+    source: &'a str,
+    config: &'a FormatConfig,
+    comments: Vec<Comment>,
+    // type and offset of every token, to find keywords the AST keeps no span for
+    keywords: Vec<(TokenType, usize)>,
+    output: String,
+    depth: usize,
+    state: State,
+    // writing on one line, lists and expressions must not be split
+    flat: bool,
+    // a list had to be split while writing on one line
+    flat_failed: bool,
+}
+
+impl<'a> Formatter<'a> {
+    fn append(&mut self, content: &str) {
+        self.output.push_str(content);
+    }
+
+    fn indent(&mut self) {
+        if self.config.use_tabs {
+            self.output.push_str(&"\t".repeat(self.depth));
+        } else {
+            let spaces = self.depth * self.config.indent_width;
+            self.output.push_str(&" ".repeat(spaces));
+        }
+    }
+
+    // offset of the first `t` token at or after `pos`
+    fn find_token(&self, t: TokenType, pos: usize) -> usize {
+        let first = self.keywords.partition_point(|(_, p)| *p < pos);
+        self.keywords[first..]
+            .iter()
+            .find(|(token, _)| *token == t)
+            .map_or(self.source.len(), |(_, p)| *p)
+    }
+
+    // offset of the token closing `block`, which follows `start`
+    fn block_close(&self, t: TokenType, block: &Block, start: usize) -> usize {
+        let end = block.stats.last().map_or(start, |stat| stat.span().end);
+        self.find_token(t, end)
+    }
+
+    // write the comments found before `pos`, the output must be at the start of a line.
+    // comments that followed code keep trailing the line written before them.
+    fn flush_comments(&mut self, pos: usize) {
+        while let Some(comment) = self.comments.get(self.state.next_comment) {
+            if comment.start >= pos {
+                break;
+            }
+            let (text, short, start, end) = (
+                comment.text.clone(),
+                comment.short,
+                comment.start,
+                comment.end,
+            );
+            let can_trail = self.output.ends_with('\n')
+                && self.state.short_comment_end != Some(self.output.len() - 1);
+            if comment.trailing && can_trail {
+                self.output.pop();
+                self.append(" ");
+            } else {
+                self.blank_line(start);
+                self.indent();
+            }
+            self.append(&text);
+            if short {
+                self.state.short_comment_end = Some(self.output.len());
+            }
+            self.append("\n");
+            self.state.next_comment += 1;
+            // a comment moved past code written after it does not move the end back
+            self.state.last_end = self.state.last_end.max(end);
+            self.state.block_start = false;
+        }
+    }
+
+    // keep one blank line if the source had any before `pos`
+    fn blank_line(&mut self, pos: usize) {
+        let last_end = self.state.last_end.min(pos);
+        if !self.state.block_start && self.source[last_end..pos].matches('\n').count() > 1 {
+            self.append("\n");
+        }
+    }
+
+    // start a line holding the item found at `pos`
+    fn line_item(&mut self, pos: usize) {
+        self.flush_comments(pos);
+        self.blank_line(pos);
+        self.indent();
+        self.state.block_start = false;
+    }
+
+    fn item_end(&mut self, pos: usize) {
+        self.state.last_end = pos;
+    }
+
+    fn block(&mut self, block: &Block) {
+        let mut prev: Option<&Stat> = None;
+        for stat in block.stats.iter() {
+            if let Stat::Empty(_) = stat {
+                continue;
+            }
+            self.line_item(stat.span().start);
+            // `a = b (f)()` would be a call of `b`
+            if starts_with_paren(stat) && prev.is_some_and(ends_with_expr) {
+                self.append(";");
+            }
+            self.stat(stat);
+            self.append("\n");
+            self.item_end(stat.span().end);
+            prev = Some(stat);
+        }
+    }
+
+    // block one level deeper up to the token at `close`, followed by the indent of that token
+    // statements are laid out on their own even inside an expression written on one line
+    fn nested_block(&mut self, block: &Block, close: usize) {
+        let (flat, flat_failed) = (self.flat, self.flat_failed);
+        self.flat = false;
+        self.append("\n");
+        self.depth += 1;
+        self.state.block_start = true;
+        self.block(block);
+        self.flush_comments(close);
+        self.depth -= 1;
+        self.indent();
+        self.state.block_start = false;
+        self.flat = flat;
+        self.flat_failed = flat_failed;
+    }
+
+    fn is_empty(block: &Block) -> bool {
+        block
+            .stats
+            .iter()
+            .all(|stat| matches!(stat, Stat::Empty(_)))
+    }
+
+    fn has_comment_before(&self, pos: usize) -> bool {
+        self.comments
+            .get(self.state.next_comment)
+            .is_some_and(|c| c.start < pos)
+    }
+
+    fn stat(&mut self, stat: &Stat) {
+        match stat {
+            Stat::IfStat(stat) => self.ifstat(stat),
+            Stat::WhileStat(stat) => {
+                self.append("while ");
+                self.expr(&stat.cond);
+                self.append(" do");
+                let close = self.block_close(TokenType::End, &stat.block, stat.cond.span().end);
+                self.nested_block(&stat.block, close);
+                self.append("end");
+            }
+            Stat::DoBlock(stat) => {
+                self.append("do");
+                let close = self.block_close(TokenType::End, &stat.block, stat.span.start);
+                self.nested_block(&stat.block, close);
+                self.append("end");
+            }
+            Stat::ForStat(ForStat::ForNum(stat)) => {
+                self.append(&format!("for {} = ", stat.var.name));
+                self.expr(&stat.init);
+                self.append(", ");
+                self.expr(&stat.limit);
+                if let Some(step) = &stat.step {
+                    self.append(", ");
+                    self.expr(step);
+                }
+                self.append(" do");
+                let close = self.block_close(TokenType::End, &stat.body, stat.limit.span().end);
+                self.nested_block(&stat.body, close);
+                self.append("end");
+            }
+            Stat::ForStat(ForStat::ForList(stat)) => {
+                let vars: Vec<&str> = stat.vars.iter().map(|v| v.name.as_str()).collect();
+                self.append(&format!("for {} in ", vars.join(", ")));
+                self.exprlist(&stat.exprs);
+                self.append(" do");
+                let exprs_end = stat.exprs.last().map_or(stat.span.start, |e| e.span().end);
+                let close = self.block_close(TokenType::End, &stat.body, exprs_end);
+                self.nested_block(&stat.body, close);
+                self.append("end");
+            }
+            Stat::RepeatStat(stat) => {
+                self.append("repeat");
+                let close = self.block_close(TokenType::Until, &stat.block, stat.span.start);
+                self.nested_block(&stat.block, close);
+                self.append("until ");
+                self.expr(&stat.cond);
+            }
+            Stat::FuncStat(stat) => {
+                if stat.func_type == FuncType::Local {
+                    self.append("local ");
+                }
+                self.append("function ");
+                let fields: Vec<&str> = stat
+                    .func_name
+                    .fields
+                    .iter()
+                    .map(|f| f.name.as_str())
+                    .collect();
+                self.append(&fields.join("."));
+                if let Some(method) = &stat.func_name.method {
+                    self.append(":");
+                    self.append(&method.name);
+                }
+                self.funcbody(&stat.body);
+            }
+            Stat::LocalStat(stat) => {
+                self.append("local ");
+                for (n, name) in stat.names.iter().enumerate() {
+                    if n > 0 {
+                        self.append(", ");
+                    }
+                    self.append(&name.name.name);
+                    if let Some(attrib) = name.attrib {
+                        self.append(&format!(" <{}>", attrib.name()));
+                    }
+                }
+                if !stat.exprs.is_empty() {
+                    self.append(" = ");
+                    self.exprlist(&stat.exprs);
+                }
+            }
+            Stat::LabelStat(stat) => self.append(&format!("::{}::", stat.label.name)),
+            Stat::RetStat(stat) => {
+                self.append("return");
+                if !stat.exprs.is_empty() {
+                    self.append(" ");
+                    self.exprlist(&stat.exprs);
+                }
+            }
+            Stat::BreakStat(_) => self.append("break"),
+            Stat::GotoStat(stat) => self.append(&format!("goto {}", stat.label.name)),
+            Stat::AssignStat(stat) => {
+                for (n, expr) in stat.left.iter().enumerate() {
+                    if n > 0 {
+                        self.append(", ");
+                    }
+                    self.suffixedexpr(expr);
+                }
+                self.append(" = ");
+                self.exprlist(&stat.right);
+            }
+            Stat::CallStat(stat) => self.suffixedexpr(&stat.call),
+            Stat::Empty(_) | Stat::Error(_) => (),
+        }
+    }
+
+    fn ifstat(&mut self, stat: &IfStat) {
+        let count = stat.cond_blocks.len();
+        let has_else = !Formatter::is_empty(&stat.else_block) || self.has_commented_else(stat);
+        for (n, cond_block) in stat.cond_blocks.iter().enumerate() {
+            self.append(if n == 0 { "if " } else { "elseif " });
+            self.expr(&cond_block.cond);
+            self.append(" then");
+            let t = if n + 1 < count {
+                TokenType::ElseIf
+            } else if has_else {
+                TokenType::Else
+            } else {
+                TokenType::End
+            };
+            let close = self.block_close(t, &cond_block.block, cond_block.cond.span().end);
+            self.nested_block(&cond_block.block, close);
+        }
+        if has_else {
+            self.append("else");
+            let close = self.block_close(TokenType::End, &stat.else_block, stat.span.start);
+            self.nested_block(&stat.else_block, close);
+        }
+        self.append("end");
+    }
+
+    // an `else` branch holding nothing but comments, the AST does not tell it from no `else`
+    fn has_commented_else(&self, stat: &IfStat) -> bool {
+        let last = &stat.cond_blocks[stat.cond_blocks.len() - 1];
+        let end = last
+            .block
+            .stats
+            .last()
+            .map_or(last.cond.span().end, |stat| stat.span().end);
+        let first = self.keywords.partition_point(|(_, p)| *p < end);
+        let next = self.keywords[first..]
+            .iter()
+            .find(|(token, _)| *token != TokenType::Then);
+        match next {
+            Some((TokenType::Else, pos)) => {
+                let close = self.find_token(TokenType::End, *pos);
+                self.comments
+                    .iter()
+                    .any(|c| c.start > *pos && c.start < close)
+            }
+            _ => false,
+        }
+    }
+
+    fn funcbody(&mut self, body: &FuncBody) {
+        let params: Vec<&str> = body
+            .params
+            .iter()
+            .map(|param| match param {
+                Param::VarArg(_) => "...",
+                Param::Name(name) => name.name.as_str(),
+            })
+            .collect();
+        self.append(&format!("({})", params.join(", ")));
+        let close = self.block_close(TokenType::End, &body.block, body.span.start);
+        if Formatter::is_empty(&body.block) && !self.has_comment_before(close) {
+            self.append(" end");
+            return;
+        }
+        self.nested_block(&body.block, close);
+        self.append("end");
+    }
+
+    fn exprlist(&mut self, exprs: &[Expr]) {
+        for (n, expr) in exprs.iter().enumerate() {
+            if n > 0 {
+                self.append(", ");
+            }
+            self.expr(expr);
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Nil(_) => self.append("nil"),
+            Expr::True(_) => self.append("true"),
+            Expr::False(_) => self.append("false"),
+            Expr::VarArg(_) => self.append("..."),
+            Expr::Float(_, span) | Expr::Int(_, span) | Expr::UInt(_, span) => {
+                self.append(&self.source[span.start..span.end]);
+            }
+            Expr::String(s, span) => self.string(s, *span),
+            Expr::FuncBody(body) => {
+                self.append("function");
+                self.funcbody(body);
+            }
+            Expr::Table(table) => self.table(table),
+            Expr::BinExpr(expr) => self.binexpr(expr),
+            Expr::UnExpr(expr) => {
+                self.append(expr.op.as_str());
+                let space = match &*expr.expr {
+                    // `- -a` must not become a comment
+                    Expr::UnExpr(operand) => expr.op == UnOp::Minus && operand.op == UnOp::Minus,
+                    _ => false,
+                };
+                if space || expr.op == UnOp::Not {
+                    self.append(" ");
+                }
+                self.expr(&expr.expr);
+            }
+            Expr::SuffixedExpr(expr) => self.suffixedexpr(expr),
+        }
+    }
+
+    // too long chains of `or`, `and` and `..` continue on the next lines, one operand each
+    fn binexpr(&mut self, expr: &BinExpr) {
+        let flat = |f: &mut Self| {
+            f.expr(&expr.left);
+            f.append(&format!(" {} ", expr.op.as_str()));
+            f.expr(&expr.right);
+        };
+        if !matches!(expr.op, BinOp::Or | BinOp::And | BinOp::Concat) {
+            flat(self);
+            return;
+        }
+        if self.try_flat(flat) {
+            return;
+        }
+        let mut operands = Vec::new();
+        Formatter::chain(&expr.left, expr.op, &mut operands);
+        Formatter::chain(&expr.right, expr.op, &mut operands);
+        self.expr(operands[0]);
+        self.depth += 1;
+        for operand in operands[1..].iter() {
+            self.append("\n");
+            self.indent();
+            self.append(&format!("{} ", expr.op.as_str()));
+            self.expr(operand);
+        }
+        self.depth -= 1;
+    }
+
+    fn chain<'e>(expr: &'e Expr, op: BinOp, operands: &mut Vec<&'e Expr>) {
+        match expr {
+            Expr::BinExpr(expr) if expr.op == op => {
+                Formatter::chain(&expr.left, op, operands);
+                Formatter::chain(&expr.right, op, operands);
+            }
+            _ => operands.push(expr),
+        }
+    }
+
+    fn suffixedexpr(&mut self, expr: &SuffixedExpr) {
+        match &expr.primary {
+            PrimaryExpr::Name(name) => self.append(&name.name),
+            PrimaryExpr::ParenExpr(expr) => {
+                self.append("(");
+                self.expr(expr);
+                self.append(")");
+            }
+        }
+        for suffix in expr.suffixes.iter() {
+            match suffix {
+                Suffix::Attr(name) => {
+                    self.append(".");
+                    self.append(&name.name);
+                }
+                Suffix::Method(name) => {
+                    self.append(":");
+                    self.append(&name.name);
+                }
+                Suffix::Index(index) => self.bracketed(index),
+                Suffix::FuncArgs(args) => self.funcargs(args),
+            }
+        }
+    }
+
+    // `[[[` would open a long string
+    fn bracketed(&mut self, expr: &Expr) {
+        self.append("[");
+        let mark = self.output.len();
+        self.expr(expr);
+        if self.output[mark..].starts_with('[') {
+            self.output.insert(mark, ' ');
+            self.append(" ]");
+        } else {
+            self.append("]");
+        }
+    }
+
+    fn funcargs(&mut self, args: &FuncArgs) {
+        let parens = self.config.call_parens;
+        match args {
+            FuncArgs::Exprs(exprs, _)
+                if parens == CallParens::Omit
+                    && exprs.len() == 1
+                    && matches!(exprs[0], Expr::String(..) | Expr::Table(_)) =>
+            {
+                self.append(" ");
+                self.expr(&exprs[0]);
+            }
+            FuncArgs::Exprs(exprs, span) => {
+                let spans: Vec<Span> = exprs.iter().map(|e| e.span()).collect();
+                self.list(("(", ")"), *span, &spans, false, |f, n| f.expr(&exprs[n]));
+            }
+            FuncArgs::Table(table) if parens == CallParens::Always => {
+                self.append("(");
+                self.table(table);
+                self.append(")");
+            }
+            FuncArgs::String(s, span) if parens == CallParens::Always => {
+                self.append("(");
+                self.string(s, *span);
+                self.append(")");
+            }
+            FuncArgs::Table(table) => {
+                self.append(" ");
+                self.table(table);
+            }
+            FuncArgs::String(s, span) => {
+                self.append(" ");
+                self.string(s, *span);
+            }
+        }
+    }
+
+    fn table(&mut self, table: &Table) {
+        let spans: Vec<Span> = table.fields.iter().map(|f| f.span()).collect();
+        self.list(("{", "}"), table.span, &spans, true, |f, n| {
+            match &table.fields[n] {
+                Field::ListField(expr) => f.expr(expr),
+                Field::RecFileld(field) => {
+                    match &field.key {
+                        FieldKey::Name(name) => f.append(&name.name),
+                        FieldKey::Expr(key) => f.bracketed(key),
+                    }
+                    f.append(" = ");
+                    f.expr(&field.value);
+                }
+            }
+        });
+    }
+
+    // items between `delims` on one line if that fits and no comment sits between them,
+    // otherwise one item per line. tables get spaces inside the braces and the trailing separator.
+    fn list<F>(&mut self, delims: (&str, &str), span: Span, items: &[Span], table: bool, item: F)
+    where
+        F: Fn(&mut Self, usize),
+    {
+        let (open, close) = delims;
+        let comment_between = self.comments[self.state.next_comment..].iter().any(|c| {
+            c.start > span.start
+                && c.start < span.end
+                && !items.iter().any(|s| c.start >= s.start && c.start < s.end)
+        });
+        if comment_between && self.flat {
+            self.flat_failed = true;
+        }
+        let flat_list = |f: &mut Self| {
+            f.append(open);
+            let padding = table && !items.is_empty();
+            if padding {
+                f.append(" ");
+            }
+            for n in 0..items.len() {
+                if n > 0 {
+                    f.append(", ");
+                }
+                item(f, n);
+            }
+            if padding {
+                f.append(" ");
+            }
+            f.append(close);
+        };
+        if items.is_empty() && !comment_between {
+            flat_list(self);
+            return;
+        }
+        if (!comment_between || self.flat) && self.try_flat(flat_list) {
+            return;
+        }
+
+        self.append(open);
+        self.append("\n");
+        self.depth += 1;
+        self.state.block_start = true;
+        for (n, item_span) in items.iter().enumerate() {
+            self.line_item(item_span.start);
+            item(self, n);
+            if n + 1 < items.len() || (table && self.config.trailing_separator) {
+                self.append(",");
+            }
+            self.append("\n");
+            self.item_end(item_span.end);
+        }
+        self.flush_comments(span.end - 1);
+        self.depth -= 1;
+        self.indent();
+        self.append(close);
+    }
+
+    // keep what `render` writes if it stays within the width without splitting lists or
+    // expressions, otherwise restore the output and return false.
+    fn try_flat<F>(&mut self, render: F) -> bool
+    where
+        F: FnOnce(&mut Self),
+    {
+        if self.flat {
+            render(self);
+            return true;
+        }
+        let mark = self.output.len();
+        let state = self.state;
+        self.flat = true;
+        self.flat_failed = false;
+        render(self);
+        self.flat = false;
+        if !self.flat_failed && self.fits(mark) {
+            return true;
+        }
+        self.output.truncate(mark);
+        self.state = state;
+        false
+    }
+
+    // if the line holding `mark` is not wider than allowed
+    fn fits(&self, mark: usize) -> bool {
+        let begin = self.output[..mark].rfind('\n').map_or(0, |i| i + 1);
+        let end = self.output[mark..]
+            .find('\n')
+            .map_or(self.output.len(), |i| mark + i);
+        let width: usize = self.output[begin..end]
+            .chars()
+            .map(|c| {
+                if c == '\t' {
+                    self.config.indent_width
+                } else {
+                    1
+                }
+            })
+            .sum();
+        width <= self.config.max_width
+    }
+
+    // strings are written as in the source, only their quotes may change
+    fn string(&mut self, s: &str, span: Span) {
+        let raw = &self.source[span.start..span.end];
+        let old = raw.chars().next().unwrap_or('"');
+        if old == '[' {
+            self.append(raw);
+            return;
+        }
+        let doubles = s.matches('"').count();
+        let singles = s.matches('\'').count();
+        let new = match self.config.quote_style {
+            QuoteStyle::Keep => old,
+            QuoteStyle::Double if doubles > singles => '\'',
+            QuoteStyle::Double => '"',
+            QuoteStyle::Single if singles > doubles => '"',
+            QuoteStyle::Single => '\'',
+        };
+        if new == old {
+            self.append(raw);
+            return;
+        }
+        self.output.push(new);
+        let mut chars = raw[1..raw.len() - 1].chars();
+        while let Some(c) = chars.next() {
+            if c == '\\' {
+                match chars.next() {
+                    Some(next) if next == old => self.output.push(next),
+                    Some(next) => {
+                        self.output.push(c);
+                        self.output.push(next);
+                    }
+                    None => self.output.push(c),
+                }
+            } else {
+                if c == new {
+                    self.output.push('\\');
+                }
+                self.output.push(c);
+            }
+        }
+        self.output.push(new);
+    }
+}
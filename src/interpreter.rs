@@ -0,0 +1,1272 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+use std::rc::Rc;
+
+use crate::ast::*;
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use crate::resolver::{Binding, FunctionId, LocalId, LocalKind, Resolver, SharedChunk};
+use crate::stdlib;
+use crate::token::Span;
+use crate::types::{FloatType, IntType, LuaVersion, Number};
+use crate::validator::Validator;
+use crate::value::{self, int_float_cmp, Function, TableRef, Value};
+
+// nested calls before a `stack overflow` error
+const MAX_CALL_DEPTH: usize = 200;
+// bytes of native stack the nested calls may use, well below the 2MB of a spawned thread
+const MAX_STACK_SIZE: usize = 1 << 20;
+// `__index` and `__newindex` tables followed before giving up
+const MAX_META_CHAIN: usize = 2000;
+
+// a value raised by `error` or by a failing operation, messages carry their position
+#[derive(Clone, Debug)]
+pub struct LuaError {
+    pub value: Value,
+}
+
+impl LuaError {
+    pub fn new(value: Value) -> Self {
+        LuaError { value }
+    }
+}
+
+impl fmt::Display for LuaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.value {
+            Value::Str(_) | Value::Int(_) | Value::Float(_) => write!(f, "{}", self.value),
+            value => write!(f, "(error object is a {} value)", value.type_name()),
+        }
+    }
+}
+
+impl std::error::Error for LuaError {}
+
+type Cell = Rc<RefCell<Value>>;
+
+// a loaded chunk, shared by the closures created from it
+struct Chunk {
+    name: String,
+    tree: SharedChunk,
+    env: RefCell<Value>,
+}
+
+// a Lua function with the variables it captured
+pub struct Closure {
+    chunk: Rc<Chunk>,
+    body: Rc<FuncBody>,
+    function: FunctionId,
+    upvalues: HashMap<LocalId, Cell>,
+}
+
+// a running Lua function
+struct Frame {
+    chunk: Rc<Chunk>,
+    closure: Rc<Closure>,
+    // a new cell for every execution of a declaration
+    locals: HashMap<LocalId, Cell>,
+    varargs: Vec<Value>,
+    // line the function was called from
+    call_line: usize,
+}
+
+// how a statement ends
+enum Flow {
+    Normal,
+    Break,
+    Return(Vec<Value>),
+    Goto(String),
+}
+
+type Exec = Result<Flow, LuaError>;
+
+// evaluates parsed chunks. the standard library is sandboxed by default, without
+// any access to files, the environment or the process.
+pub struct Interpreter {
+    pub(crate) version: LuaVersion,
+    pub(crate) sandbox: bool,
+    pub(crate) globals: TableRef,
+    // metatable shared by all strings
+    pub(crate) string_meta: TableRef,
+    // `package.loaded`
+    pub(crate) loaded: TableRef,
+    // sources `require` finds by module name
+    pub(crate) modules: HashMap<String, String>,
+    pub(crate) output: Box<dyn Write>,
+    pub(crate) seed: u64,
+    frames: Vec<Frame>,
+    depth: usize,
+    // address on the native stack where the outermost call started
+    stack_base: usize,
+    // line of the latest call, where errors raised by builtins are
+    line: usize,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Interpreter::new()
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        let table = || match Value::new_table() {
+            Value::Table(t) => t,
+            _ => unreachable!(),
+        };
+        let mut interpreter = Interpreter {
+            version: LuaVersion::default(),
+            sandbox: true,
+            globals: table(),
+            string_meta: table(),
+            loaded: table(),
+            modules: HashMap::new(),
+            output: Box::new(std::io::stdout()),
+            seed: 0x2545_f491_4f6c_dd1d,
+            frames: Vec::new(),
+            depth: 0,
+            stack_base: 0,
+            line: 0,
+        };
+        stdlib::open(&mut interpreter);
+        interpreter
+    }
+
+    // dialect of the chunks, the standard library follows it
+    pub fn set_version(&mut self, version: LuaVersion) {
+        self.version = version;
+        stdlib::open(self);
+    }
+
+    pub fn version(&self) -> LuaVersion {
+        self.version
+    }
+
+    // without the sandbox `io`, `os`, `dofile`, `loadfile` and `require` of files are available
+    pub fn set_sandbox(&mut self, sandbox: bool) {
+        self.sandbox = sandbox;
+        stdlib::open(self);
+    }
+
+    // where `print` and `io.write` go, stdout by default
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.output = output;
+    }
+
+    // a module for `require`, loaded from `source` on first use
+    pub fn add_module(&mut self, name: &str, source: &str) {
+        self.modules.insert(name.to_string(), source.to_string());
+    }
+
+    pub fn globals(&self) -> TableRef {
+        self.globals.clone()
+    }
+
+    pub fn get_global(&self, name: &str) -> Value {
+        self.globals.borrow().get_str(name)
+    }
+
+    pub fn set_global(&mut self, name: &str, value: Value) {
+        self.globals.borrow_mut().set_str(name, value);
+    }
+
+    // a global function implemented in Rust
+    pub fn register<F>(&mut self, name: &str, func: F)
+    where
+        F: Fn(&mut Interpreter, Vec<Value>) -> Result<Vec<Value>, LuaError> + 'static,
+    {
+        self.set_global(name, Value::builtin(name, func));
+    }
+
+    // runs a tree parsed with the version of the interpreter
+    pub fn run(&mut self, block: &Block) -> Result<Vec<Value>, LuaError> {
+        let main = self.chunk(block.clone(), "?", None);
+        self.call(&main, Vec::new())
+    }
+
+    // compiles `source` into a function, `name` is the chunk name used in error messages
+    pub fn load(&mut self, source: &str, name: &str) -> Result<Value, LuaError> {
+        self.load_with_env(source, name, None)
+    }
+
+    pub fn exec(&mut self, source: &str, name: &str) -> Result<Vec<Value>, LuaError> {
+        let main = self.load(source, name)?;
+        self.call(&main, Vec::new())
+    }
+
+    pub(crate) fn load_with_env(
+        &mut self,
+        source: &str,
+        name: &str,
+        env: Option<Value>,
+    ) -> Result<Value, LuaError> {
+        let error = |e: &dyn fmt::Display| LuaError::new(format!("{}: {}", name, e).into());
+        let mut lexer = Lexer::new();
+        lexer.set_version(self.version);
+        let tokens = lexer.run(source).map_err(|e| error(&e))?;
+        let mut parser = Parser::new();
+        parser.set_version(self.version);
+        let block = parser.run(tokens).map_err(|e| error(&e))?;
+        let mut validator = Validator::new();
+        validator.set_version(self.version);
+        if let Some(e) = validator.run(&block).first() {
+            return Err(error(e));
+        }
+        Ok(self.chunk(block, name, env))
+    }
+
+    // the main function of a chunk
+    fn chunk(&mut self, block: Block, name: &str, env: Option<Value>) -> Value {
+        let mut resolver = Resolver::new();
+        resolver.set_version(self.version);
+        let tree = resolver.run_shared(block);
+        let body = tree.body(0).clone();
+        let chunk = Rc::new(Chunk {
+            name: name.to_string(),
+            tree,
+            env: RefCell::new(env.unwrap_or_else(|| Value::Table(self.globals.clone()))),
+        });
+        Value::Function(Function::Lua(Rc::new(Closure {
+            chunk,
+            body,
+            function: 0,
+            upvalues: HashMap::new(),
+        })))
+    }
+
+    pub fn call(&mut self, f: &Value, mut args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+        match f {
+            Value::Function(Function::Lua(closure)) => self.call_closure(closure.clone(), args),
+            Value::Function(Function::Builtin(builtin)) => {
+                let builtin = builtin.clone();
+                self.enter()?;
+                let result = (builtin.func)(self, args);
+                self.depth -= 1;
+                result
+            }
+            _ => {
+                let handler = self.metafield(f, "__call");
+                if let Value::Function(_) = handler {
+                    args.insert(0, f.clone());
+                    self.call(&handler, args)
+                } else {
+                    Err(self.error(format!("attempt to call a {} value", f.type_name())))
+                }
+            }
+        }
+    }
+
+    fn enter(&mut self) -> Result<(), LuaError> {
+        let marker = 0u8;
+        let here = &marker as *const u8 as usize;
+        if self.depth == 0 {
+            self.stack_base = here;
+        }
+        // debug builds use much more stack per call than release ones
+        if self.depth >= MAX_CALL_DEPTH || self.stack_base.saturating_sub(here) > MAX_STACK_SIZE {
+            return Err(self.error("stack overflow"));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn call_closure(
+        &mut self,
+        closure: Rc<Closure>,
+        args: Vec<Value>,
+    ) -> Result<Vec<Value>, LuaError> {
+        self.enter()?;
+        let chunk = closure.chunk.clone();
+        let body = closure.body.clone();
+        let resolution = chunk.tree.resolution();
+        let scope = resolution.functions[closure.function].scope;
+        // `self` and the parameters come first in the scope of the function
+        let params = resolution.scopes[scope].locals.iter().take_while(|id| {
+            matches!(
+                resolution.locals[**id].kind,
+                LocalKind::Param | LocalKind::SelfParam
+            )
+        });
+        let mut args = args.into_iter();
+        let locals = params
+            .map(|id| (*id, Rc::new(RefCell::new(args.next().unwrap_or_default()))))
+            .collect();
+        let varargs = match body.params.last() {
+            Some(Param::VarArg(_)) => args.collect(),
+            _ => Vec::new(),
+        };
+        self.frames.push(Frame {
+            chunk: chunk.clone(),
+            closure,
+            locals,
+            varargs,
+            call_line: self.line,
+        });
+        let result = self.block(&body.block);
+        self.frames.pop();
+        self.depth -= 1;
+        match result? {
+            Flow::Return(values) => Ok(values),
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    fn frame(&self) -> &Frame {
+        self.frames.last().unwrap()
+    }
+
+    // "chunk:line: " of a running function, 1 is the innermost
+    pub(crate) fn location(&self, level: usize) -> String {
+        let count = self.frames.len();
+        if level == 0 || level > count {
+            return String::new();
+        }
+        let line = match level {
+            1 => self.line,
+            _ => self.frames[count - level + 1].call_line,
+        };
+        format!("{}:{}: ", self.frames[count - level].chunk.name, line)
+    }
+
+    // error at the latest call, how builtins fail
+    pub(crate) fn error(&self, message: impl fmt::Display) -> LuaError {
+        LuaError::new(format!("{}{}", self.location(1), message).into())
+    }
+
+    fn error_at(&self, span: Span, message: impl fmt::Display) -> LuaError {
+        let name = match self.frames.last() {
+            Some(frame) => frame.chunk.name.as_str(),
+            None => "?",
+        };
+        LuaError::new(format!("{}:{}: {}", name, span.line, message).into())
+    }
+
+    fn block(&mut self, block: &Block) -> Exec {
+        let stats = &block.stats;
+        let mut index = 0;
+        while index < stats.len() {
+            match self.stat(&stats[index])? {
+                Flow::Normal => index += 1,
+                Flow::Goto(label) => {
+                    match stats
+                        .iter()
+                        .position(|s| matches!(s, Stat::LabelStat(l) if l.label.name == label))
+                    {
+                        Some(target) => index = target + 1,
+                        None => return Ok(Flow::Goto(label)),
+                    }
+                }
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    // body of a loop, `None` to go on with the next iteration
+    fn loop_body(&mut self, block: &Block) -> Result<Option<Flow>, LuaError> {
+        match self.block(block)? {
+            Flow::Normal => Ok(None),
+            Flow::Break => Ok(Some(Flow::Normal)),
+            flow => Ok(Some(flow)),
+        }
+    }
+
+    fn stat(&mut self, stat: &Stat) -> Exec {
+        match stat {
+            Stat::IfStat(stat) => {
+                for cond_block in stat.cond_blocks.iter() {
+                    if self.expr(&cond_block.cond)?.truthy() {
+                        return self.block(&cond_block.block);
+                    }
+                }
+                self.block(&stat.else_block)
+            }
+            Stat::WhileStat(stat) => {
+                while self.expr(&stat.cond)?.truthy() {
+                    if let Some(flow) = self.loop_body(&stat.block)? {
+                        return Ok(flow);
+                    }
+                }
+                Ok(Flow::Normal)
+            }
+            Stat::DoBlock(stat) => self.block(&stat.block),
+            Stat::ForStat(ForStat::ForNum(stat)) => self.fornum(stat),
+            Stat::ForStat(ForStat::ForList(stat)) => self.forlist(stat),
+            // the condition sees the locals of the body
+            Stat::RepeatStat(stat) => loop {
+                if let Some(flow) = self.loop_body(&stat.block)? {
+                    return Ok(flow);
+                }
+                if self.expr(&stat.cond)?.truthy() {
+                    return Ok(Flow::Normal);
+                }
+            },
+            Stat::FuncStat(stat) => self.funcstat(stat),
+            Stat::LocalStat(stat) => {
+                let values = self.explist(&stat.exprs, stat.names.len())?;
+                for (local, value) in stat.names.iter().zip(values) {
+                    self.declare(&local.name, value);
+                }
+                Ok(Flow::Normal)
+            }
+            Stat::RetStat(stat) => Ok(Flow::Return(self.exprs(&stat.exprs)?)),
+            Stat::AssignStat(stat) => self.assign(stat),
+            Stat::CallStat(stat) => {
+                self.suffixedexpr(&stat.call, stat.call.suffixes.len())?;
+                Ok(Flow::Normal)
+            }
+            Stat::BreakStat(_) => Ok(Flow::Break),
+            Stat::GotoStat(stat) => Ok(Flow::Goto(stat.label.name.clone())),
+            Stat::LabelStat(_) | Stat::Empty(_) => Ok(Flow::Normal),
+            Stat::Error(span) => Err(self.error_at(*span, "syntax error")),
+        }
+    }
+
+    fn fornum(&mut self, stat: &ForNum) -> Exec {
+        let init = self.expr(&stat.init)?;
+        let limit = self.expr(&stat.limit)?;
+        let step = match &stat.step {
+            Some(step) => self.expr(step)?,
+            None => Value::Int(1),
+        };
+        let number = |value: &Value, what: &str| match value {
+            Value::Int(_) | Value::Float(_) => Ok(()),
+            _ => Err(self.error_at(stat.span, format!("'for' {} must be a number", what))),
+        };
+        number(&init, "initial value")?;
+        number(&limit, "limit")?;
+        number(&step, "step")?;
+        if let (Value::Int(init), Value::Int(step)) = (&init, &step) {
+            let (init, step) = (*init, *step);
+            if step == 0 {
+                return Err(self.error_at(stat.span, "'for' step is zero"));
+            }
+            let limit = match limit {
+                Value::Int(limit) => limit,
+                Value::Float(f) if f.is_nan() => return Ok(Flow::Normal),
+                Value::Float(f) => {
+                    let f = if step > 0 { f.floor() } else { f.ceil() };
+                    if f >= 2f64.powi(63) {
+                        IntType::MAX
+                    } else if f < -(2f64.powi(63)) {
+                        IntType::MIN
+                    } else {
+                        f as IntType
+                    }
+                }
+                _ => unreachable!(),
+            };
+            if (step > 0 && init > limit) || (step < 0 && init < limit) {
+                return Ok(Flow::Normal);
+            }
+            // iterations are counted up front so the variable never overflows
+            let mut count = if step > 0 {
+                (limit as u64).wrapping_sub(init as u64) / step as u64
+            } else {
+                (init as u64).wrapping_sub(limit as u64) / ((-(step + 1)) as u64 + 1)
+            };
+            let mut i = init;
+            loop {
+                self.declare(&stat.var, Value::Int(i));
+                if let Some(flow) = self.loop_body(&stat.body)? {
+                    return Ok(flow);
+                }
+                if count == 0 {
+                    return Ok(Flow::Normal);
+                }
+                count -= 1;
+                i = i.wrapping_add(step);
+            }
+        }
+        let (mut i, limit, step) = (
+            init.to_float().unwrap(),
+            limit.to_float().unwrap(),
+            step.to_float().unwrap(),
+        );
+        if step == 0.0 {
+            return Err(self.error_at(stat.span, "'for' step is zero"));
+        }
+        while (step > 0.0 && i <= limit) || (step < 0.0 && i >= limit) {
+            self.declare(&stat.var, Value::Float(i));
+            if let Some(flow) = self.loop_body(&stat.body)? {
+                return Ok(flow);
+            }
+            i += step;
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn forlist(&mut self, stat: &ForList) -> Exec {
+        let mut values = self.explist(&stat.exprs, 3)?.into_iter();
+        let (f, state, mut control) = (
+            values.next().unwrap(),
+            values.next().unwrap(),
+            values.next().unwrap(),
+        );
+        loop {
+            self.line = stat.span.line;
+            if !self.callable(&f) {
+                return Err(self.error_at(
+                    stat.span,
+                    format!("attempt to call a {} value", f.type_name()),
+                ));
+            }
+            let results = self.call(&f, vec![state.clone(), control.clone()])?;
+            match results.first() {
+                None | Some(Value::Nil) => return Ok(Flow::Normal),
+                Some(first) => control = first.clone(),
+            }
+            let mut results = results.into_iter();
+            for var in stat.vars.iter() {
+                self.declare(var, results.next().unwrap_or_default());
+            }
+            if let Some(flow) = self.loop_body(&stat.body)? {
+                return Ok(flow);
+            }
+        }
+    }
+
+    fn funcstat(&mut self, stat: &FuncStat) -> Exec {
+        let name = &stat.func_name;
+        if stat.func_type == FuncType::Local {
+            // declared first so the function can call itself
+            let cell = self.declare(&name.fields[0], Value::Nil);
+            *cell.borrow_mut() = self.closure(&stat.body);
+            return Ok(Flow::Normal);
+        }
+        let func = self.closure(&stat.body);
+        let mut keys: Vec<&Name> = name.fields[1..].iter().collect();
+        keys.extend(name.method.iter());
+        let last = match keys.pop() {
+            Some(last) => last,
+            None => {
+                self.set_var(&name.fields[0], func)?;
+                return Ok(Flow::Normal);
+            }
+        };
+        let mut obj = self.get_var(&name.fields[0])?;
+        let mut desc = self.describe_name(&name.fields[0]);
+        for key in keys {
+            obj = self.index_described(&obj, Value::from(key.name.as_str()), &desc, key.span)?;
+            desc = format!(" (field '{}')", key.name);
+        }
+        if !self.set_index(&obj, Value::from(last.name.as_str()), func)? {
+            return Err(self.index_error(&obj, &desc, last.span));
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn assign(&mut self, stat: &AssignStat) -> Exec {
+        // prefixes and keys of all targets are evaluated before the values
+        let mut targets = Vec::with_capacity(stat.left.len());
+        for target in stat.left.iter() {
+            match (&target.primary, target.suffixes.last()) {
+                (PrimaryExpr::Name(name), None) => targets.push(Err(name)),
+                (_, Some(last)) => {
+                    let count = target.suffixes.len() - 1;
+                    let obj = self
+                        .suffixedexpr(target, count)?
+                        .into_iter()
+                        .next()
+                        .unwrap_or_default();
+                    let key = match last {
+                        Suffix::Attr(name) => Value::from(name.name.as_str()),
+                        Suffix::Index(expr) => self.expr(expr)?,
+                        _ => return Err(self.error_at(target.span, "cannot assign to a call")),
+                    };
+                    targets.push(Ok((obj, key, target, count)));
+                }
+                (_, None) => return Err(self.error_at(target.span, "cannot assign")),
+            }
+        }
+        let values = self.explist(&stat.right, targets.len())?;
+        for (target, value) in targets.into_iter().zip(values) {
+            match target {
+                Err(name) => self.set_var(name, value)?,
+                Ok((obj, key, target, count)) => {
+                    if !self.set_index(&obj, key, value)? {
+                        let desc = self.describe(target, count);
+                        return Err(self.index_error(&obj, &desc, target.span));
+                    }
+                }
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    // binds a declaring name of the tree to a new variable
+    fn declare(&mut self, name: &Name, value: Value) -> Cell {
+        let cell = Rc::new(RefCell::new(value));
+        let frame = self.frames.last_mut().unwrap();
+        if let Some(id) = frame.chunk.tree.resolution().declaration(name) {
+            frame.locals.insert(id, cell.clone());
+        }
+        cell
+    }
+
+    fn cell(&self, id: LocalId) -> Option<Cell> {
+        let frame = self.frame();
+        frame
+            .locals
+            .get(&id)
+            .or_else(|| frame.closure.upvalues.get(&id))
+            .cloned()
+    }
+
+    fn binding(&self, name: &Name) -> Binding {
+        self.frame()
+            .chunk
+            .tree
+            .resolution()
+            .binding(name)
+            .unwrap_or(Binding::Global(None))
+    }
+
+    // the table globals are fields of
+    fn env(&self, env: Option<LocalId>) -> Value {
+        match env {
+            Some(id) => self
+                .cell(id)
+                .map(|c| c.borrow().clone())
+                .unwrap_or_default(),
+            None => self.frame().chunk.env.borrow().clone(),
+        }
+    }
+
+    fn get_var(&mut self, name: &Name) -> Result<Value, LuaError> {
+        match self.binding(name) {
+            Binding::Local(id) | Binding::Upvalue(id) => Ok(self
+                .cell(id)
+                .map(|c| c.borrow().clone())
+                .unwrap_or_default()),
+            Binding::Global(env) => {
+                let env = self.env(env);
+                self.index_described(
+                    &env,
+                    Value::from(name.name.as_str()),
+                    " (upvalue '_ENV')",
+                    name.span,
+                )
+            }
+            Binding::Env => Ok(self.env(None)),
+        }
+    }
+
+    fn set_var(&mut self, name: &Name, value: Value) -> Result<(), LuaError> {
+        match self.binding(name) {
+            Binding::Local(id) | Binding::Upvalue(id) => {
+                if let Some(cell) = self.cell(id) {
+                    *cell.borrow_mut() = value;
+                }
+            }
+            Binding::Global(env) => {
+                let env = self.env(env);
+                if !self.set_index(&env, Value::from(name.name.as_str()), value)? {
+                    return Err(self.index_error(&env, " (upvalue '_ENV')", name.span));
+                }
+            }
+            Binding::Env => *self.frame().chunk.env.borrow_mut() = value,
+        }
+        Ok(())
+    }
+
+    fn closure(&mut self, body: &FuncBody) -> Value {
+        let frame = self.frame();
+        let chunk = frame.chunk.clone();
+        let resolution = chunk.tree.resolution();
+        let function = resolution.function(body).unwrap();
+        let upvalues = resolution.functions[function]
+            .upvalues
+            .iter()
+            .filter_map(|id| self.cell(*id).map(|cell| (*id, cell)))
+            .collect();
+        let body = chunk.tree.body(function).clone();
+        Value::Function(Function::Lua(Rc::new(Closure {
+            chunk,
+            body,
+            function,
+            upvalues,
+        })))
+    }
+
+    // values of `exprs`, the last one expanded
+    fn exprs(&mut self, exprs: &[Expr]) -> Result<Vec<Value>, LuaError> {
+        let mut values = Vec::with_capacity(exprs.len());
+        if let Some((last, exprs)) = exprs.split_last() {
+            for expr in exprs.iter() {
+                values.push(self.expr(expr)?);
+            }
+            values.extend(self.multi(last)?);
+        }
+        Ok(values)
+    }
+
+    // values of `exprs` adjusted to `count`
+    fn explist(&mut self, exprs: &[Expr], count: usize) -> Result<Vec<Value>, LuaError> {
+        let mut values = self.exprs(exprs)?;
+        values.resize(count, Value::Nil);
+        Ok(values)
+    }
+
+    // all values of a call or `...`, the value of other expressions
+    fn multi(&mut self, expr: &Expr) -> Result<Vec<Value>, LuaError> {
+        match expr {
+            Expr::VarArg(_) => Ok(self.frame().varargs.clone()),
+            Expr::SuffixedExpr(expr) => self.suffixedexpr(expr, expr.suffixes.len()),
+            _ => Ok(vec![self.expr(expr)?]),
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Result<Value, LuaError> {
+        match expr {
+            Expr::Nil(_) => Ok(Value::Nil),
+            Expr::True(_) => Ok(Value::Boolean(true)),
+            Expr::False(_) => Ok(Value::Boolean(false)),
+            Expr::Float(f, _) => Ok(Value::Float(*f)),
+            Expr::Int(i, _) | Expr::UInt(i, _) => Ok(Value::Int(*i)),
+            Expr::String(s, _) => Ok(Value::from(s.as_str())),
+            Expr::VarArg(_) => Ok(self.frame().varargs.first().cloned().unwrap_or_default()),
+            Expr::FuncBody(body) => Ok(self.closure(body)),
+            Expr::Table(table) => self.table(table),
+            Expr::BinExpr(expr) => self.binexpr(expr),
+            Expr::UnExpr(expr) => self.unexpr(expr),
+            Expr::SuffixedExpr(expr) => Ok(self
+                .suffixedexpr(expr, expr.suffixes.len())?
+                .into_iter()
+                .next()
+                .unwrap_or_default()),
+        }
+    }
+
+    fn table(&mut self, table: &Table) -> Result<Value, LuaError> {
+        let mut t = value::Table::default();
+        // positional items are stored last, like the SETLIST of the reference compiler
+        let mut list = Vec::new();
+        for (i, field) in table.fields.iter().enumerate() {
+            match field {
+                Field::ListField(expr) if i + 1 == table.fields.len() => {
+                    list.extend(self.multi(expr)?)
+                }
+                Field::ListField(expr) => list.push(self.expr(expr)?),
+                Field::RecFileld(field) => {
+                    let key = match &field.key {
+                        FieldKey::Name(name) => Value::from(name.name.as_str()),
+                        FieldKey::Expr(expr) => self.expr(expr)?,
+                    };
+                    let value = self.expr(&field.value)?;
+                    t.set(key, value)
+                        .map_err(|e| self.error_at(field.span, e))?;
+                }
+            }
+        }
+        t.set_list(list);
+        Ok(Value::Table(Rc::new(RefCell::new(t))))
+    }
+
+    // the primary expression and the first `count` suffixes, all values of a final call
+    fn suffixedexpr(&mut self, expr: &SuffixedExpr, count: usize) -> Result<Vec<Value>, LuaError> {
+        let mut value = match &expr.primary {
+            PrimaryExpr::Name(name) => self.get_var(name)?,
+            PrimaryExpr::ParenExpr(expr) => self.expr(expr)?,
+        };
+        // object of a method call
+        let mut this = None;
+        for (i, suffix) in expr.suffixes[..count].iter().enumerate() {
+            match suffix {
+                Suffix::Attr(name) | Suffix::Method(name) => {
+                    let desc = self.describe(expr, i);
+                    let field = self.index_described(
+                        &value,
+                        Value::from(name.name.as_str()),
+                        &desc,
+                        name.span,
+                    )?;
+                    if let Suffix::Method(_) = suffix {
+                        this = Some(value);
+                    }
+                    value = field;
+                }
+                Suffix::Index(key) => {
+                    let key = self.expr(key)?;
+                    let desc = self.describe(expr, i);
+                    value = self.index_described(&value, key, &desc, expr.span)?;
+                }
+                Suffix::FuncArgs(args) => {
+                    let mut values: Vec<Value> = this.take().into_iter().collect();
+                    let span = match args {
+                        FuncArgs::Exprs(exprs, span) => {
+                            values.extend(self.exprs(exprs)?);
+                            *span
+                        }
+                        FuncArgs::Table(table) => {
+                            values.push(self.table(table)?);
+                            table.span
+                        }
+                        FuncArgs::String(s, span) => {
+                            values.push(Value::from(s.as_str()));
+                            *span
+                        }
+                    };
+                    if !self.callable(&value) {
+                        let desc = self.describe(expr, i);
+                        return Err(self.error_at(
+                            span,
+                            format!("attempt to call a {} value{}", value.type_name(), desc),
+                        ));
+                    }
+                    self.line = span.line;
+                    let results = self.call(&value, values)?;
+                    if i + 1 == count {
+                        return Ok(results);
+                    }
+                    value = results.into_iter().next().unwrap_or_default();
+                }
+            }
+        }
+        Ok(vec![value])
+    }
+
+    fn callable(&self, f: &Value) -> bool {
+        matches!(f, Value::Function(_)) || !self.metafield(f, "__call").is_nil()
+    }
+
+    // what the value before suffix `i` is, for error messages
+    fn describe(&self, expr: &SuffixedExpr, i: usize) -> String {
+        match (i, &expr.primary) {
+            (0, PrimaryExpr::Name(name)) => self.describe_name(name),
+            (0, _) => String::new(),
+            _ => match &expr.suffixes[i - 1] {
+                Suffix::Attr(name) => format!(" (field '{}')", name.name),
+                Suffix::Method(name) => format!(" (method '{}')", name.name),
+                Suffix::Index(Expr::String(s, _)) => format!(" (field '{}')", s),
+                _ => String::new(),
+            },
+        }
+    }
+
+    fn describe_name(&self, name: &Name) -> String {
+        match self.binding(name) {
+            Binding::Local(_) => format!(" (local '{}')", name.name),
+            Binding::Upvalue(_) => format!(" (upvalue '{}')", name.name),
+            Binding::Global(_) => format!(" (global '{}')", name.name),
+            Binding::Env => String::new(),
+        }
+    }
+
+    // description of an operand, a variable or field that is not called
+    fn describe_expr(&self, expr: &Expr) -> String {
+        match expr {
+            Expr::SuffixedExpr(expr) => match expr.suffixes.last() {
+                Some(Suffix::FuncArgs(_)) => String::new(),
+                _ => self.describe(expr, expr.suffixes.len()),
+            },
+            _ => String::new(),
+        }
+    }
+
+    fn index_error(&self, obj: &Value, desc: &str, span: Span) -> LuaError {
+        self.error_at(
+            span,
+            format!("attempt to index a {} value{}", obj.type_name(), desc),
+        )
+    }
+
+    fn index_described(
+        &mut self,
+        obj: &Value,
+        key: Value,
+        desc: &str,
+        span: Span,
+    ) -> Result<Value, LuaError> {
+        match self.index(obj, &key)? {
+            Some(value) => Ok(value),
+            None => Err(self.index_error(obj, desc, span)),
+        }
+    }
+
+    pub(crate) fn metatable(&self, value: &Value) -> Option<TableRef> {
+        match value {
+            Value::Table(t) => t.borrow().metatable.clone(),
+            Value::Str(_) => Some(self.string_meta.clone()),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn metafield(&self, value: &Value, event: &str) -> Value {
+        match self.metatable(value) {
+            Some(meta) => meta.borrow().get_str(event),
+            None => Value::Nil,
+        }
+    }
+
+    // `obj[key]` with `__index`, none if `obj` can not be indexed
+    pub(crate) fn index(&mut self, obj: &Value, key: &Value) -> Result<Option<Value>, LuaError> {
+        let mut obj = obj.clone();
+        for _ in 0..MAX_META_CHAIN {
+            let handler = match &obj {
+                Value::Table(t) => {
+                    let t = t.borrow();
+                    let value = t.get(key);
+                    match &t.metatable {
+                        Some(meta) if value.is_nil() => meta.borrow().get_str("__index"),
+                        _ => return Ok(Some(value)),
+                    }
+                }
+                _ => self.metafield(&obj, "__index"),
+            };
+            match handler {
+                Value::Nil if obj.as_table().is_some() => return Ok(Some(Value::Nil)),
+                Value::Nil => return Ok(None),
+                Value::Function(_) => {
+                    let results = self.call(&handler, vec![obj, key.clone()])?;
+                    return Ok(Some(results.into_iter().next().unwrap_or_default()));
+                }
+                handler => obj = handler,
+            }
+        }
+        Err(self.error("'__index' chain too long; possible loop"))
+    }
+
+    // `obj[key] = value` with `__newindex`, false if `obj` can not be indexed
+    pub(crate) fn set_index(
+        &mut self,
+        obj: &Value,
+        key: Value,
+        value: Value,
+    ) -> Result<bool, LuaError> {
+        let mut obj = obj.clone();
+        for _ in 0..MAX_META_CHAIN {
+            let handler = match &obj {
+                Value::Table(t) => {
+                    let handler = {
+                        let t = t.borrow();
+                        match &t.metatable {
+                            Some(meta) if t.get(&key).is_nil() => {
+                                meta.borrow().get_str("__newindex")
+                            }
+                            _ => Value::Nil,
+                        }
+                    };
+                    if handler.is_nil() {
+                        t.borrow_mut().set(key, value).map_err(|e| self.error(e))?;
+                        return Ok(true);
+                    }
+                    handler
+                }
+                _ => match self.metafield(&obj, "__newindex") {
+                    Value::Nil => return Ok(false),
+                    handler => handler,
+                },
+            };
+            if let Value::Function(_) = handler {
+                self.call(&handler, vec![obj, key, value])?;
+                return Ok(true);
+            }
+            obj = handler;
+        }
+        Err(self.error("'__newindex' chain too long; possible loop"))
+    }
+
+    fn binexpr(&mut self, expr: &BinExpr) -> Result<Value, LuaError> {
+        let left = self.expr(&expr.left)?;
+        match expr.op {
+            BinOp::And if !left.truthy() => return Ok(left),
+            BinOp::Or if left.truthy() => return Ok(left),
+            BinOp::And | BinOp::Or => return self.expr(&expr.right),
+            _ => (),
+        }
+        let right = self.expr(&expr.right)?;
+        self.line = expr.span.line;
+        match expr.op {
+            BinOp::Eq => Ok(Value::Boolean(self.equals(&left, &right)?)),
+            BinOp::Ne => Ok(Value::Boolean(!self.equals(&left, &right)?)),
+            BinOp::Lt => Ok(Value::Boolean(self.less_than(&left, &right)?)),
+            BinOp::Le => Ok(Value::Boolean(self.less_equal(&left, &right)?)),
+            BinOp::Gt => Ok(Value::Boolean(self.less_than(&right, &left)?)),
+            BinOp::Ge => Ok(Value::Boolean(self.less_equal(&right, &left)?)),
+            BinOp::Concat => match self.concat(&left, &right)? {
+                Some(value) => Ok(value),
+                None => {
+                    let (bad, operand) = match self.to_str(&left) {
+                        Some(_) => (&right, &expr.right),
+                        None => (&left, &expr.left),
+                    };
+                    Err(self.error_at(
+                        expr.span,
+                        format!(
+                            "attempt to concatenate a {} value{}",
+                            bad.type_name(),
+                            self.describe_expr(operand)
+                        ),
+                    ))
+                }
+            },
+            op => match self.arith(op, &left, &right)? {
+                Some(value) => Ok(value),
+                None => {
+                    let bitwise = matches!(
+                        op,
+                        BinOp::BAnd | BinOp::BOr | BinOp::BXor | BinOp::Shl | BinOp::Shr
+                    );
+                    let (bad, operand) = match self.coerce(&left) {
+                        Number::None => (&left, &expr.left),
+                        _ => (&right, &expr.right),
+                    };
+                    let message = match self.coerce(bad) {
+                        Number::None if bitwise => format!(
+                            "attempt to perform bitwise operation on a {} value{}",
+                            bad.type_name(),
+                            self.describe_expr(operand)
+                        ),
+                        Number::None => format!(
+                            "attempt to perform arithmetic on a {} value{}",
+                            bad.type_name(),
+                            self.describe_expr(operand)
+                        ),
+                        _ => "number has no integer representation".to_string(),
+                    };
+                    Err(self.error_at(expr.span, message))
+                }
+            },
+        }
+    }
+
+    fn unexpr(&mut self, expr: &UnExpr) -> Result<Value, LuaError> {
+        let value = self.expr(&expr.expr)?;
+        self.line = expr.span.line;
+        let (event, what) = match expr.op {
+            UnOp::Not => return Ok(Value::Boolean(!value.truthy())),
+            UnOp::Minus => match self.coerce(&value) {
+                Number::Int(i) => return Ok(Value::Int(i.wrapping_neg())),
+                Number::Float(f) => return Ok(Value::Float(-f)),
+                Number::None => ("__unm", "perform arithmetic on"),
+            },
+            UnOp::BNot => match (self.coerce(&value), value.to_integer()) {
+                (Number::None, _) => ("__bnot", "perform bitwise operation on"),
+                (_, Some(i)) => return Ok(Value::Int(!i)),
+                (_, None) => {
+                    return Err(self.error_at(expr.span, "number has no integer representation"))
+                }
+            },
+            UnOp::TLen => match &value {
+                Value::Str(s) => return Ok(self.integer(s.len() as IntType)),
+                _ => ("__len", "get length of"),
+            },
+            UnOp::None => return Ok(value),
+        };
+        let handler = self.metafield(&value, event);
+        if !handler.is_nil() {
+            let results = self.call(&handler, vec![value.clone(), value])?;
+            return Ok(results.into_iter().next().unwrap_or_default());
+        }
+        if let (UnOp::TLen, Value::Table(t)) = (expr.op, &value) {
+            let len = t.borrow().len();
+            return Ok(self.integer(len as IntType));
+        }
+        Err(self.error_at(
+            expr.span,
+            format!(
+                "attempt to {} a {} value{}",
+                what,
+                value.type_name(),
+                self.describe_expr(&expr.expr)
+            ),
+        ))
+    }
+
+    // an integer result, a float in dialects without integers
+    pub(crate) fn integer(&self, i: IntType) -> Value {
+        if self.version.has_integer() {
+            Value::Int(i)
+        } else {
+            Value::Float(i as FloatType)
+        }
+    }
+
+    // a number operand, strings are converted
+    pub(crate) fn coerce(&self, value: &Value) -> Number {
+        match value.to_number() {
+            Number::Int(i) if !self.version.has_integer() => Number::Float(i as FloatType),
+            number => number,
+        }
+    }
+
+    // a binary operation with its metamethod, none if neither operand supports it
+    pub(crate) fn arith(
+        &mut self,
+        op: BinOp,
+        a: &Value,
+        b: &Value,
+    ) -> Result<Option<Value>, LuaError> {
+        if let (Some(x), Some(y)) = (number(self.coerce(a)), number(self.coerce(b))) {
+            if let Some(value) = self.arith_numbers(op, x, y)? {
+                return Ok(Some(value));
+            }
+        }
+        let event = match op {
+            BinOp::Add => "__add",
+            BinOp::Minus => "__sub",
+            BinOp::Mul => "__mul",
+            BinOp::Mod => "__mod",
+            BinOp::Pow => "__pow",
+            BinOp::Div => "__div",
+            BinOp::IDiv => "__idiv",
+            BinOp::BAnd => "__band",
+            BinOp::BOr => "__bor",
+            BinOp::BXor => "__bxor",
+            BinOp::Shl => "__shl",
+            BinOp::Shr => "__shr",
+            _ => return Ok(None),
+        };
+        let handler = match self.metafield(a, event) {
+            Value::Nil => self.metafield(b, event),
+            handler => handler,
+        };
+        if handler.is_nil() {
+            return Ok(None);
+        }
+        let results = self.call(&handler, vec![a.clone(), b.clone()])?;
+        Ok(Some(results.into_iter().next().unwrap_or_default()))
+    }
+
+    // none for bitwise operations on floats without an integer value
+    fn arith_numbers(&self, op: BinOp, x: Number, y: Number) -> Result<Option<Value>, LuaError> {
+        if let (Number::Int(_), Number::Int(0)) = (x, y) {
+            match op {
+                BinOp::IDiv => return Err(self.error("attempt to perform 'n//0'")),
+                BinOp::Mod => return Err(self.error("attempt to perform 'n%%0'")),
+                _ => (),
+            }
+        }
+        Ok(value::arith(op, x, y))
+    }
+
+    // raw equality, then `__eq` of tables
+    pub(crate) fn equals(&mut self, a: &Value, b: &Value) -> Result<bool, LuaError> {
+        if a.raw_equal(b) {
+            return Ok(true);
+        }
+        if let (Value::Table(_), Value::Table(_)) = (a, b) {
+            let handler = match self.metafield(a, "__eq") {
+                Value::Nil => self.metafield(b, "__eq"),
+                handler => handler,
+            };
+            if !handler.is_nil() {
+                let results = self.call(&handler, vec![a.clone(), b.clone()])?;
+                return Ok(results.first().is_some_and(Value::truthy));
+            }
+        }
+        Ok(false)
+    }
+
+    pub(crate) fn less_than(&mut self, a: &Value, b: &Value) -> Result<bool, LuaError> {
+        match compare(a, b) {
+            Some(ordering) => Ok(ordering == Some(std::cmp::Ordering::Less)),
+            None => self.compare_meta(a, b, "__lt"),
+        }
+    }
+
+    pub(crate) fn less_equal(&mut self, a: &Value, b: &Value) -> Result<bool, LuaError> {
+        match compare(a, b) {
+            Some(ordering) => Ok(matches!(
+                ordering,
+                Some(std::cmp::Ordering::Less) | Some(std::cmp::Ordering::Equal)
+            )),
+            None => {
+                let has_le =
+                    !self.metafield(a, "__le").is_nil() || !self.metafield(b, "__le").is_nil();
+                // before 5.4 `a <= b` falls back to `not (b < a)`
+                if !has_le && self.version != LuaVersion::Lua54 {
+                    return Ok(!self.compare_meta(b, a, "__lt")?);
+                }
+                self.compare_meta(a, b, "__le")
+            }
+        }
+    }
+
+    fn compare_meta(&mut self, a: &Value, b: &Value, event: &str) -> Result<bool, LuaError> {
+        let handler = match self.metafield(a, event) {
+            Value::Nil => self.metafield(b, event),
+            handler => handler,
+        };
+        if handler.is_nil() {
+            let (x, y) = (a.type_name(), b.type_name());
+            return Err(if x == y {
+                self.error(format!("attempt to compare two {} values", x))
+            } else {
+                self.error(format!("attempt to compare {} with {}", x, y))
+            });
+        }
+        let results = self.call(&handler, vec![a.clone(), b.clone()])?;
+        Ok(results.first().is_some_and(Value::truthy))
+    }
+
+    // `a .. b` with `__concat`
+    pub(crate) fn concat(&mut self, a: &Value, b: &Value) -> Result<Option<Value>, LuaError> {
+        if let (Some(mut x), Some(y)) = (self.to_str(a), self.to_str(b)) {
+            x.extend_from_slice(&y);
+            return Ok(Some(Value::from(x)));
+        }
+        let handler = match self.metafield(a, "__concat") {
+            Value::Nil => self.metafield(b, "__concat"),
+            handler => handler,
+        };
+        if handler.is_nil() {
+            return Ok(None);
+        }
+        let results = self.call(&handler, vec![a.clone(), b.clone()])?;
+        Ok(Some(results.into_iter().next().unwrap_or_default()))
+    }
+
+    // bytes of a string or a number
+    pub(crate) fn to_str(&self, value: &Value) -> Option<Vec<u8>> {
+        match value {
+            Value::Str(s) => Some(s.to_vec()),
+            Value::Int(i) => Some(i.to_string().into_bytes()),
+            Value::Float(f) => {
+                Some(value::float_to_string(*f, self.version.has_integer()).into_bytes())
+            }
+            _ => None,
+        }
+    }
+
+    // `tostring` with `__tostring`
+    pub(crate) fn tostring(&mut self, value: &Value) -> Result<Value, LuaError> {
+        let handler = self.metafield(value, "__tostring");
+        if !handler.is_nil() {
+            let result = self.call(&handler, vec![value.clone()])?;
+            return match result.into_iter().next() {
+                Some(result @ Value::Str(_)) => Ok(result),
+                Some(result @ Value::Int(_)) | Some(result @ Value::Float(_)) => {
+                    Ok(Value::from(self.to_str(&result).unwrap()))
+                }
+                _ => Err(self.error("'__tostring' must return a string")),
+            };
+        }
+        Ok(match self.to_str(value) {
+            Some(s) => Value::from(s),
+            None => Value::from(value.to_string()),
+        })
+    }
+}
+
+fn number(n: Number) -> Option<Number> {
+    match n {
+        Number::None => None,
+        n => Some(n),
+    }
+}
+
+// order of numbers or strings, the inner none if a NaN is involved
+fn compare(a: &Value, b: &Value) -> Option<Option<std::cmp::Ordering>> {
+    Some(match (a, b) {
+        (Value::Int(x), Value::Int(y)) => x.partial_cmp(y),
+        (Value::Float(x), Value::Float(y)) => x.partial_cmp(y),
+        (Value::Int(x), Value::Float(y)) => int_float_cmp(*x, *y),
+        (Value::Float(x), Value::Int(y)) => int_float_cmp(*y, *x).map(|o| o.reverse()),
+        (Value::Str(x), Value::Str(y)) => x.partial_cmp(y),
+        _ => return None,
+    })
+}
@@ -1,6 +1,8 @@
-use crate::token::{Source, Token, TokenType, TokenValue};
-use crate::types::{FloatType, IntType, Number};
+use crate::directive::{Command, Directive, DirectiveKind};
+use crate::token::{Source, Token, TokenType, TokenValue, Trivia, TriviaKind};
+use crate::types::{FloatType, IntType, LuaVersion, Number};
 use crate::{debuggable, error, success};
+use std::fmt;
 use std::mem;
 use std::str;
 
@@ -89,94 +91,293 @@ impl<'a> Context<'a> {
 pub struct Lexer {
     debug: bool,
     use_origin_string: bool,
+    keep_trivia: bool,
+    version: LuaVersion,
     tokens: Vec<Token>,
+    // trivia read since the last token
+    trivia: Vec<Trivia>,
+    // if trivia still belongs to the last token (no line break since it)
+    trailing: bool,
+    // errors collected while recovering
+    errors: Option<Vec<LexError>>,
+    directives: Vec<Directive>,
+    // line directives waiting for the next token, and blocks not ended yet
+    pending_directives: Vec<usize>,
+    open_directives: Vec<usize>,
 }
 
-#[derive(Debug)]
-pub struct LexError(String);
+#[derive(Debug, Clone, PartialEq)]
+pub enum LexErrorKind {
+    UnfinishedString,
+    // unfinished long string or comment, with the line it starts at
+    UnfinishedLongString(usize),
+    UnfinishedLongComment(usize),
+    MalformedNumber(String),
+    HexDigitExpected,
+    MissingBrace(char),
+    Utf8ValueTooLarge,
+    InvalidCodepoint,
+    DecimalEscapeTooLarge,
+    InvalidEscape(char),
+    InvalidUtf8String,
+    InvalidLongStringDelimiter,
+    UnknownToken(char),
+    Unavailable(&'static str, LuaVersion),
+}
+
+impl fmt::Display for LexErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LexErrorKind::UnfinishedString => write!(f, "unfinished string"),
+            LexErrorKind::UnfinishedLongString(line) => {
+                write!(f, "unfinished long string (starting at line {})", line)
+            }
+            LexErrorKind::UnfinishedLongComment(line) => {
+                write!(f, "unfinished long comment (starting at line {})", line)
+            }
+            LexErrorKind::MalformedNumber(num) => write!(f, "malformed number near '{}'", num),
+            LexErrorKind::HexDigitExpected => write!(f, "hexadecimal digit expected"),
+            LexErrorKind::MissingBrace(c) => write!(f, "missing '{}'", c),
+            LexErrorKind::Utf8ValueTooLarge => write!(f, "UTF-8 value too large"),
+            LexErrorKind::InvalidCodepoint => write!(f, "invalid utf8 codepoint"),
+            LexErrorKind::DecimalEscapeTooLarge => write!(f, "decimal escape too large"),
+            LexErrorKind::InvalidEscape(c) => write!(f, "invalid escape sequence '\\{}'", c),
+            LexErrorKind::InvalidUtf8String => write!(f, "invalid utf8 string"),
+            LexErrorKind::InvalidLongStringDelimiter => {
+                write!(f, "invalid long string delimiter")
+            }
+            LexErrorKind::UnknownToken(c) => write!(f, "unknown token near '{}'", c),
+            LexErrorKind::Unavailable(what, version) => {
+                write!(f, "{} is not available in {}", what, version)
+            }
+        }
+    }
+}
+
+// error with the position where lexing stopped
+#[derive(Debug, Clone, PartialEq)]
+pub struct LexError {
+    pub kind: LexErrorKind,
+    pub line: usize,
+    pub col: usize,
+    pub offset: usize,
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[lex error] {} at line [{}:{}].",
+            self.kind, self.line, self.col
+        )
+    }
+}
+
+impl std::error::Error for LexError {}
 
 type LexResult = Result<Option<(TokenType, TokenValue)>, LexError>;
 
 macro_rules! lex_error {
-    ($self:ident, $ctx:ident, $msg:expr) => {
+    ($self:ident, $ctx:ident, $kind:expr) => {
         error!(
             $self,
-            LexError,
-            format!("[lex error] {} at line [{}:{}].", $msg, $ctx.line, $ctx.col)
+            LexError {
+                kind: $kind,
+                line: $ctx.line,
+                col: $ctx.col,
+                offset: $ctx.current,
+            }
         )
     };
 }
 
+impl Default for Lexer {
+    fn default() -> Self {
+        Lexer::new()
+    }
+}
+
 impl<'a> Lexer {
     pub fn new() -> Self {
         Lexer {
             debug: false,
             use_origin_string: false,
+            keep_trivia: false,
+            version: LuaVersion::default(),
             tokens: Vec::<Token>::new(),
+            trivia: Vec::<Trivia>::new(),
+            trailing: false,
+            errors: None,
+            directives: Vec::new(),
+            pending_directives: Vec::new(),
+            open_directives: Vec::new(),
         }
     }
 
+    // select the language dialect, default is Lua 5.4.
+    pub fn set_version(&mut self, version: LuaVersion) {
+        self.version = version;
+    }
+
+    pub fn version(&self) -> LuaVersion {
+        self.version
+    }
+
     // if use origin string, lexer won't escape special chars and keep the quotes or string boundaries.
     pub fn set_use_origin_string(&mut self, use_origin_string: bool) {
         self.use_origin_string = use_origin_string;
     }
 
+    // if keep trivia, whitespaces, line breaks and comments are attached to tokens,
+    // trivia on the same line after a token trails it, everything else leads the next token.
+    pub fn set_keep_trivia(&mut self, keep_trivia: bool) {
+        self.keep_trivia = keep_trivia;
+    }
+
+    // `-- rslua:` comments of the last input, see `Directive`
+    pub fn directives(&self) -> &[Directive] {
+        &self.directives
+    }
+
     pub fn run(&mut self, input: &'a str) -> Result<Vec<Token>, LexError> {
+        self.errors = None;
+        self.lex(input)
+    }
+
+    // lex without stopping at the first error, bad input is skipped and emitted as an `Error` token.
+    // returns all tokens and all errors.
+    pub fn run_recovering(&mut self, input: &'a str) -> (Vec<Token>, Vec<LexError>) {
+        self.errors = Some(Vec::new());
+        // errors are recovered inside lex
+        let tokens = self.lex(input).unwrap_or_default();
+        (tokens, self.errors.take().unwrap_or_default())
+    }
+
+    fn lex(&mut self, input: &'a str) -> Result<Vec<Token>, LexError> {
         self.reset();
         let mut ctx = Context::new(input);
         loop {
             ctx.save();
             if let Some(c) = ctx.get() {
-                if let Some((token_type, token_value)) = match c {
-                    _ if Lexer::is_line_break(c) => self.read_line_break(&mut ctx)?,
-                    _ if Lexer::is_space(c) => self.read_space(&mut ctx)?,
-                    _ if Lexer::is_digit(c) => self.read_number(&mut ctx)?,
-                    b'-' if self.check_next(&ctx, '-') => self.read_comment(&mut ctx)?,
-                    b'=' => self.read_eq_assign(&mut ctx)?,
-                    b'<' => self.read_le_shl_lt(&mut ctx)?,
-                    b'>' => self.read_ge_shr_gt(&mut ctx)?,
-                    b'/' if self.check_next(&ctx, '-') => self.read_idiv(&mut ctx)?,
-                    b'~' => self.read_ne_xor(&mut ctx)?,
-                    b':' => self.read_colon(&mut ctx)?,
-                    b'.' => self.read_attr_concat_dots_numbers(&mut ctx)?,
-                    b'"' | b'\'' => self.read_short_string(&mut ctx)?,
-                    b'[' if self.check_next2(&ctx, '[', '=') => self.read_long_string(&mut ctx)?,
-                    _ => self.read_other_tokens(&mut ctx)?,
-                } {
-                    self.add_token(&ctx, token_type, token_value);
+                match self.read(&mut ctx, c) {
+                    Err(error) if self.errors.is_some() => self.recover(&mut ctx, error),
+                    result => result?,
                 }
             } else {
                 // append eos and return tokens
                 self.add_token(&ctx, TokenType::Eos, TokenValue::None);
-                return Ok(mem::replace(&mut self.tokens, Vec::<Token>::new()));
+                for directive in self.directives.iter_mut() {
+                    if directive.last_line == usize::MAX {
+                        directive.last_line = ctx.line;
+                    }
+                }
+                return Ok(mem::take(&mut self.tokens));
+            }
+        }
+    }
+
+    // read trivia or a token at current position
+    fn read(&mut self, ctx: &mut Context, c: u8) -> Result<(), LexError> {
+        if let Some(kind) = self.read_trivia(ctx, c)? {
+            if let TriviaKind::ShortComment | TriviaKind::LongComment(_) = kind {
+                self.add_directive(ctx, kind);
+            }
+            if self.keep_trivia {
+                self.add_trivia(ctx, kind);
+            }
+            return Ok(());
+        }
+        if let Some((token_type, token_value)) = match c {
+            _ if Lexer::is_digit(c) => self.read_number(ctx)?,
+            b'=' => self.read_eq_assign(ctx)?,
+            b'<' => self.read_le_shl_lt(ctx)?,
+            b'>' => self.read_ge_shr_gt(ctx)?,
+            b'/' if self.check_next(ctx, '/') => self.read_idiv(ctx)?,
+            b'~' => self.read_ne_xor(ctx)?,
+            b':' => self.read_colon(ctx)?,
+            b'.' => self.read_attr_concat_dots_numbers(ctx)?,
+            b'"' | b'\'' => self.read_short_string(ctx)?,
+            b'[' if self.check_next2(ctx, '[', '=') => self.read_long_string(ctx)?,
+            _ => self.read_other_tokens(ctx)?,
+        } {
+            self.add_token(ctx, token_type, token_value);
+        }
+        Ok(())
+    }
+
+    // record the error, skip the rest of the bad token and emit an error token for it
+    fn recover(&mut self, ctx: &mut Context, error: LexError) {
+        if let Some(errors) = &mut self.errors {
+            errors.push(error);
+        }
+        let start = ctx.buffer.as_bytes()[ctx.old_pos];
+        if start == b'"' || start == b'\'' {
+            // skip to the closing quote, a string never spans lines
+            while let Some(c) = ctx.get() {
+                if Lexer::is_line_break(c) {
+                    break;
+                }
+                ctx.next();
+                if c == b'\\' {
+                    if self.check_current_if(ctx, |c| !Lexer::is_line_break(c)) {
+                        ctx.next();
+                    }
+                } else if c == start {
+                    break;
+                }
             }
+        } else if ctx.current == ctx.old_pos {
+            // unknown character
+            let len = ctx.buffer[ctx.current..]
+                .chars()
+                .next()
+                .map_or(1, |c| c.len_utf8());
+            ctx.skip(len);
         }
+        self.add_token(ctx, TokenType::Error, TokenValue::None);
     }
 
-    fn read_line_break(&self, ctx: &mut Context) -> LexResult {
+    // read a line break, a run of spaces or a comment
+    fn read_trivia(&mut self, ctx: &mut Context, c: u8) -> Result<Option<TriviaKind>, LexError> {
+        match c {
+            _ if Lexer::is_line_break(c) => {
+                self.read_line_break(ctx);
+                Ok(Some(TriviaKind::LineBreak))
+            }
+            _ if Lexer::is_space(c) => {
+                self.read_space(ctx);
+                Ok(Some(TriviaKind::Whitespace))
+            }
+            b'-' if self.check_next(ctx, '-') => Ok(Some(self.read_comment(ctx)?)),
+            _ => Ok(None),
+        }
+    }
+
+    fn read_line_break(&self, ctx: &mut Context) {
         let old = ctx.get();
         ctx.next();
-        if old != ctx.get() && self.check_current_if(ctx, |c| Lexer::is_line_break(c)) {
+        if old != ctx.get() && self.check_current_if(ctx, Lexer::is_line_break) {
             ctx.next();
         }
         ctx.inc_line();
-        Ok(None)
     }
 
-    fn read_space(&self, ctx: &mut Context) -> LexResult {
-        ctx.next();
-        Ok(None)
+    fn read_space(&self, ctx: &mut Context) {
+        while self.check_current_if(ctx, Lexer::is_space) {
+            ctx.next();
+        }
     }
 
-    fn read_comment(&mut self, ctx: &mut Context) -> LexResult {
+    fn read_comment(&mut self, ctx: &mut Context) -> Result<TriviaKind, LexError> {
         ctx.skip(2);
         let sep_count = self.try_read_long_string_boundary(ctx, b'[');
         if sep_count >= 0 {
-            self.skip_long_string(ctx, sep_count as usize, "comment")?;
+            self.skip_long_string(ctx, sep_count as usize, true)?;
+            Ok(TriviaKind::LongComment(sep_count as usize))
         } else {
             self.read_short_comment(ctx);
+            Ok(TriviaKind::ShortComment)
         }
-        Ok(None)
     }
 
     fn read_short_comment(&mut self, ctx: &mut Context) {
@@ -271,39 +472,79 @@ impl<'a> Lexer {
         success!((TokenType::Attr, TokenValue::None))
     }
 
+    // read a numeral the way Lua does: hex digits, dots and exponents are consumed greedily,
+    // then the whole text must be a valid number.
     fn read_number(&mut self, ctx: &mut Context) -> LexResult {
         let mut expo = ('E', 'e');
         let mut num_str: Vec<u8> = Vec::new();
-        let mut hex = false;
         if self.check_current(ctx, '0') && self.check_next2(ctx, 'x', 'X') {
             expo = ('P', 'p');
             ctx.skip_into(2, &mut num_str);
-            hex = true;
         }
-        let is_digit = |c| {
-            (hex && Lexer::is_hex_digit(c)) || (!hex && Lexer::is_digit(c)) || (c as char) == '.'
-        };
         loop {
-            if self.check_current_if(ctx, is_digit) {
-                ctx.skip_into(1, &mut num_str)
-            } else if self.check_current2(ctx, expo.0, expo.1) {
+            if self.check_current2(ctx, expo.0, expo.1) {
                 ctx.skip_into(1, &mut num_str);
                 if self.check_current2(ctx, '-', '+') {
                     ctx.skip_into(1, &mut num_str)
                 }
+            } else if self.check_current_if(ctx, |c| Lexer::is_hex_digit(c) || c == b'.') {
+                ctx.skip_into(1, &mut num_str)
             } else {
                 break;
             }
         }
-        if let Ok(string) = str::from_utf8(&num_str) {
-            let num = Lexer::str_to_num(string);
-            match num {
-                Number::Int(n) => success!((TokenType::Int, TokenValue::Int(n))),
-                Number::Float(n) => success!((TokenType::Flt, TokenValue::Float(n))),
-                _ => lex_error!(self, ctx, "malformed number"),
+        if self.version.has_int64_suffix() {
+            let suffix_len = Lexer::int64_suffix_len(ctx);
+            if suffix_len > 0 {
+                let string = String::from_utf8_lossy(&num_str).to_string();
+                // `ULL` makes a `uint64_t`, decimals go up to its max
+                let unsigned = suffix_len == 3;
+                ctx.skip(suffix_len);
+                let n = Lexer::str_to_int(&string).or_else(|| {
+                    string
+                        .parse::<u64>()
+                        .ok()
+                        .filter(|_| unsigned)
+                        .map(|n| n as IntType)
+                });
+                return match n {
+                    Some(n) if unsigned => success!((TokenType::UInt, TokenValue::Int(n))),
+                    Some(n) => success!((TokenType::Int, TokenValue::Int(n))),
+                    None => lex_error!(self, ctx, LexErrorKind::MalformedNumber(string)),
+                };
             }
+        }
+        // a numeral touching a letter is malformed
+        if self.check_current_if(ctx, |c| Lexer::is_alpha(c) || c == b'_') {
+            ctx.skip_into(1, &mut num_str);
+        }
+        let string = String::from_utf8_lossy(&num_str).to_string();
+        let num = if self.version.has_integer() {
+            Lexer::str_to_num(&string)
         } else {
-            unreachable!();
+            match Lexer::str_to_float(&string) {
+                Some(f) => Number::Float(f),
+                None => Number::None,
+            }
+        };
+        match num {
+            Number::Int(n) => success!((TokenType::Int, TokenValue::Int(n))),
+            Number::Float(n) => success!((TokenType::Flt, TokenValue::Float(n))),
+            Number::None => lex_error!(self, ctx, LexErrorKind::MalformedNumber(string)),
+        }
+    }
+
+    // length of a LuaJIT `LL` or `ULL` suffix at current position, 0 if there is none
+    fn int64_suffix_len(ctx: &Context) -> usize {
+        let is = |i, c: u8| {
+            ctx.get_ahead(i)
+                .is_some_and(|x| x.to_ascii_lowercase() == c)
+        };
+        let u = if is(0, b'u') { 1 } else { 0 };
+        if is(u, b'l') && is(u + 1, b'l') {
+            u + 2
+        } else {
+            0
         }
     }
 
@@ -320,7 +561,7 @@ impl<'a> Lexer {
                 return Ok((p1 << 4) + p2);
             }
         }
-        lex_error!(self, ctx, "hexadecimal digit expected")
+        lex_error!(self, ctx, LexErrorKind::HexDigitExpected)
     }
 
     fn try_read_utf8_esc(
@@ -330,7 +571,7 @@ impl<'a> Lexer {
     ) -> Result<(), LexError> {
         if let Some(c) = ctx.get() {
             if c as char != '{' {
-                return lex_error!(self, ctx, "missing '{'");
+                return lex_error!(self, ctx, LexErrorKind::MissingBrace('{'));
             }
             ctx.next();
             if let Some(c) = self.try_read_hexa(ctx) {
@@ -338,7 +579,7 @@ impl<'a> Lexer {
                 while let Some(c) = self.try_read_hexa(ctx) {
                     r = (r << 4) + (c as u32);
                     if r > 0x10FFFF {
-                        return lex_error!(self, ctx, "UTF-8 value too large");
+                        return lex_error!(self, ctx, LexErrorKind::Utf8ValueTooLarge);
                     }
                 }
                 if self.check_current(ctx, '}') {
@@ -348,13 +589,13 @@ impl<'a> Lexer {
                         bytes.append(&mut string.into_bytes());
                         ctx.next();
                     } else {
-                        return lex_error!(self, ctx, "invalid utf8 codepoint");
+                        return lex_error!(self, ctx, LexErrorKind::InvalidCodepoint);
                     }
                 } else {
-                    return lex_error!(self, ctx, "missing '}'");
+                    return lex_error!(self, ctx, LexErrorKind::MissingBrace('}'));
                 }
             } else {
-                return lex_error!(self, ctx, "hexadecimal digit expected");
+                return lex_error!(self, ctx, LexErrorKind::HexDigitExpected);
             }
         }
         Ok(())
@@ -378,7 +619,7 @@ impl<'a> Lexer {
             ctx.next();
         }
         if r > 0xFF {
-            lex_error!(self, ctx, "decimal escape too large")
+            lex_error!(self, ctx, LexErrorKind::DecimalEscapeTooLarge)
         } else {
             bytes.push(r as u8);
             Ok(())
@@ -397,16 +638,25 @@ impl<'a> Lexer {
                 b'b' => bytes.push(b'\x08'),
                 b'v' => bytes.push(b'\x0B'),
                 b'f' => bytes.push(b'\x0C'),
+                b'x' if !self.version.has_hex_escape() => {
+                    return lex_error!(self, ctx, self.unavailable("'\\x' escape"));
+                }
                 b'x' => {
                     let v = self.try_read_hex_esc(ctx)?;
                     bytes.push(v);
                 }
+                b'u' if !self.version.has_utf8_escape() => {
+                    return lex_error!(self, ctx, self.unavailable("'\\u' escape"));
+                }
                 b'u' => self.try_read_utf8_esc(ctx, bytes)?,
                 b'\r' | b'\n' => {
                     bytes.push(b'\n');
                     ctx.inc_line();
                 }
                 b'\\' | b'"' | b'\'' => bytes.push(next),
+                b'z' if !self.version.has_hex_escape() => {
+                    return lex_error!(self, ctx, self.unavailable("'\\z' escape"));
+                }
                 b'z' => {
                     // skip the following span of white-space characters, including line breaks
                     while let Some(c) = ctx.get() {
@@ -422,7 +672,7 @@ impl<'a> Lexer {
                 }
                 _ if Lexer::is_digit(next) => self.try_read_dec_esc(ctx, bytes, next)?,
                 _ => {
-                    return lex_error!(self, ctx, "invalid escape sequence");
+                    return lex_error!(self, ctx, LexErrorKind::InvalidEscape(next as char));
                 }
             }
         }
@@ -436,20 +686,19 @@ impl<'a> Lexer {
             bytes.push(start.unwrap());
         }
         ctx.next();
-        let unfinished_error: &'static str = "unfinished string";
         while ctx.get() != start {
             match ctx.get() {
                 Some(b'\\') if self.use_origin_string => ctx.skip_into(2, &mut bytes),
                 Some(b'\\') => self.try_read_esc(ctx, &mut bytes)?,
                 Some(c) => {
                     if Lexer::is_line_break(c) {
-                        return lex_error!(self, ctx, unfinished_error);
+                        return lex_error!(self, ctx, LexErrorKind::UnfinishedString);
                     } else {
                         bytes.push(c);
                         ctx.next();
                     }
                 }
-                None => return lex_error!(self, ctx, unfinished_error),
+                None => return lex_error!(self, ctx, LexErrorKind::UnfinishedString),
             }
         }
         if self.use_origin_string {
@@ -459,7 +708,7 @@ impl<'a> Lexer {
             ctx.next();
             success!((TokenType::String, TokenValue::Str(string)))
         } else {
-            lex_error!(self, ctx, "invalid utf8 string")
+            lex_error!(self, ctx, LexErrorKind::InvalidUtf8String)
         }
     }
 
@@ -467,17 +716,16 @@ impl<'a> Lexer {
     fn try_read_long_string_boundary(&self, ctx: &mut Context, sep: u8) -> i8 {
         if self.check_current(ctx, sep as char) {
             let mut sep_count = 0;
-            loop {
-                if let Some(c) = ctx.get_ahead(sep_count + 1) {
-                    match c {
-                        b'=' => sep_count += 1,
-                        _ if c == sep => {
-                            ctx.skip(sep_count + 2);
-                            return sep_count as i8;
-                        }
-                        _ => break,
-                    };
-                }
+            // the input may end in the middle of the boundary
+            while let Some(c) = ctx.get_ahead(sep_count + 1) {
+                match c {
+                    b'=' => sep_count += 1,
+                    _ if c == sep => {
+                        ctx.skip(sep_count + 2);
+                        return sep_count as i8;
+                    }
+                    _ => break,
+                };
             }
         }
         -1
@@ -488,7 +736,7 @@ impl<'a> Lexer {
         &mut self,
         ctx: &mut Context,
         sep_count: usize,
-        sem: &str,
+        comment: bool,
     ) -> Result<Source, LexError> {
         let line = ctx.line;
         let mut start = 0;
@@ -498,8 +746,8 @@ impl<'a> Lexer {
         }
 
         // skip first line break
-        if self.check_current_if(ctx, |c| Lexer::is_line_break(c)) {
-            self.read_line_break(ctx)?;
+        if self.check_current_if(ctx, Lexer::is_line_break) {
+            self.read_line_break(ctx);
         }
 
         if !self.use_origin_string {
@@ -511,12 +759,11 @@ impl<'a> Lexer {
             match c {
                 b']' => {
                     if self.try_read_long_string_boundary(ctx, b']') == sep_count as i8 {
-                        let length;
-                        if self.use_origin_string {
-                            length = ctx.current - start;
+                        let length = if self.use_origin_string {
+                            ctx.current - start
                         } else {
-                            length = ctx.current - 2 - sep_count - start;
-                        }
+                            ctx.current - 2 - sep_count - start
+                        };
                         return Ok(Source {
                             line,
                             pos: start,
@@ -528,7 +775,7 @@ impl<'a> Lexer {
                     }
                 }
                 _ if Lexer::is_line_break(c) => {
-                    self.read_line_break(ctx)?;
+                    self.read_line_break(ctx);
                 }
                 _ => ctx.next(),
             }
@@ -536,7 +783,11 @@ impl<'a> Lexer {
         lex_error!(
             self,
             ctx,
-            &format!("unfinished long {} (starting at line {})", sem, line)
+            if comment {
+                LexErrorKind::UnfinishedLongComment(line)
+            } else {
+                LexErrorKind::UnfinishedLongString(line)
+            }
         )
     }
 
@@ -548,19 +799,16 @@ impl<'a> Lexer {
                 length,
                 col: _,
                 line: _,
-            } = self.skip_long_string(ctx, sep_count as usize, "string")?;
+            } = self.skip_long_string(ctx, sep_count as usize, false)?;
             if let Some(slice) = ctx.buffer.get(pos..(pos + length)) {
                 let string = String::from(slice);
                 return success!((TokenType::String, TokenValue::Str(string)));
             } else {
-                return lex_error!(
-                    self,
-                    ctx,
-                    &format!("invalid string slice ({}, {})", pos, length)
-                );
+                return lex_error!(self, ctx, LexErrorKind::InvalidUtf8String);
             }
         }
-        unreachable!()
+        // `[=` not followed by a second bracket
+        lex_error!(self, ctx, LexErrorKind::InvalidLongStringDelimiter)
     }
 
     fn read_other_tokens(&mut self, ctx: &mut Context) -> LexResult {
@@ -589,21 +837,23 @@ impl<'a> Lexer {
             if let Some(t) = token_type {
                 ctx.next();
                 return success!((t, TokenValue::None));
-            } else if self.check_current_if(ctx, |c| Lexer::is_valid_name_start(c)) {
+            } else if self.check_current_if(ctx, Lexer::is_valid_name_start) {
                 let mut word: Vec<u8> = Vec::new();
                 ctx.skip_into(1, &mut word);
-                while self.check_current_if(ctx, |c| Lexer::is_valid_name(c)) {
+                while self.check_current_if(ctx, Lexer::is_valid_name) {
                     ctx.skip_into(1, &mut word);
                 }
                 if let Ok(s) = str::from_utf8(&word) {
-                    if let Some(t) = TokenType::from_keyword(s) {
+                    let keyword = TokenType::from_keyword(s)
+                        .filter(|t| *t != TokenType::Goto || self.version.has_goto());
+                    if let Some(t) = keyword {
                         return success!((t, TokenValue::None));
                     } else {
                         return success!((TokenType::Name, TokenValue::Str(s.to_string())));
                     }
                 }
             } else {
-                return lex_error!(self, ctx, &format!("unknown token near {}", c as char));
+                return lex_error!(self, ctx, LexErrorKind::UnknownToken(c as char));
             }
         }
         unreachable!()
@@ -611,27 +861,27 @@ impl<'a> Lexer {
 
     fn reset(&mut self) {
         self.tokens.clear();
+        self.trivia.clear();
+        self.trailing = false;
+        self.directives.clear();
+        self.pending_directives.clear();
+        self.open_directives.clear();
+    }
+
+    fn unavailable(&self, what: &'static str) -> LexErrorKind {
+        LexErrorKind::Unavailable(what, self.version)
     }
 
     fn is_line_break(c: u8) -> bool {
-        match c {
-            b'\r' | b'\n' => true,
-            _ => false,
-        }
+        matches!(c, b'\r' | b'\n')
     }
 
     fn is_space(c: u8) -> bool {
-        match c {
-            b' ' | b'\t' | b'\x0B' | b'\x0C' => true,
-            _ => false,
-        }
+        matches!(c, b' ' | b'\t' | b'\x0B' | b'\x0C')
     }
 
     fn is_digit(c: u8) -> bool {
-        match c {
-            b'0' | b'1' | b'2' | b'3' | b'4' | b'5' | b'6' | b'7' | b'8' | b'9' => true,
-            _ => false,
-        }
+        c.is_ascii_digit()
     }
 
     fn is_hex_digit(c: u8) -> bool {
@@ -661,25 +911,25 @@ impl<'a> Lexer {
     }
 
     fn to_hex_digit(c: u8) -> u8 {
-        if c >= b'0' && c <= b'9' {
-            return c - b'0';
+        if c.is_ascii_digit() {
+            c - b'0'
         } else {
-            return ((c as char).to_ascii_lowercase() as u8) - b'a' + 10;
+            ((c as char).to_ascii_lowercase() as u8) - b'a' + 10
         }
     }
 
     fn skip_spaces(bytes: &[u8], i: usize) -> usize {
         let mut index = i;
-        while index < bytes.len() && bytes[index] == b' ' {
+        while index < bytes.len()
+            && (Lexer::is_space(bytes[index]) || Lexer::is_line_break(bytes[index]))
+        {
             index += 1;
         }
         index
     }
 
     fn starts_with_0x(bytes: &[u8], i: usize) -> bool {
-        bytes.len() > i + 2
-            && bytes[i + 0] == b'0'
-            && (bytes[i + 1] == b'x' || bytes[i + 1] == b'X')
+        bytes.len() > i + 2 && bytes[i] == b'0' && (bytes[i + 1] == b'x' || bytes[i + 1] == b'X')
     }
 
     // get number sign, return (sign, index)
@@ -696,24 +946,33 @@ impl<'a> Lexer {
         (sign, index)
     }
 
+    // string to integer like `l_str2int` of Lua, surrounding spaces are allowed.
+    // a decimal integer that overflows is not an integer, a hexadecimal one wraps around.
     pub fn str_to_int(s: &str) -> Option<IntType> {
         let bytes = s.as_bytes();
         let len = bytes.len();
-        let mut r: u32 = 0;
-        let mut i = 0;
+        let mut r: u64 = 0;
         let mut empty = true;
-        i = Lexer::skip_spaces(bytes, i);
+        let i = Lexer::skip_spaces(bytes, 0);
         let (sign, mut i) = Lexer::get_sign(bytes, i);
         if Lexer::starts_with_0x(bytes, i) {
             i += 2;
             while i < len && Lexer::is_hex_digit(bytes[i]) {
-                r = (r << 4) + (Lexer::to_hex_digit(bytes[i]) as u32);
+                r = (r << 4) + (Lexer::to_hex_digit(bytes[i]) as u64);
                 i += 1;
                 empty = false;
             }
         } else {
+            let max = if sign < 0 {
+                IntType::MIN.unsigned_abs()
+            } else {
+                IntType::MAX as u64
+            };
             while i < len && Lexer::is_digit(bytes[i]) {
-                r = r * 10 + (Lexer::to_digit(bytes[i]) as u32);
+                r = r
+                    .checked_mul(10)?
+                    .checked_add(Lexer::to_digit(bytes[i]) as u64)
+                    .filter(|r| *r <= max)?;
                 i += 1;
                 empty = false;
             }
@@ -721,45 +980,54 @@ impl<'a> Lexer {
         i = Lexer::skip_spaces(bytes, i);
         if empty || i != len {
             None
+        } else if sign < 0 {
+            Some((r as IntType).wrapping_neg())
         } else {
-            Some((r as IntType) * sign)
+            Some(r as IntType)
         }
     }
 
+    // string to float like `l_str2d` of Lua, surrounding spaces are allowed, `inf` and `nan` are not.
     pub fn str_to_float(s: &str) -> Option<FloatType> {
+        if s.contains(['n', 'N']) {
+            return None;
+        }
         let bytes = s.as_bytes();
-        let mut i = 0;
-        i = Lexer::skip_spaces(bytes, i);
-        if Lexer::starts_with_0x(bytes, i) {
-            Lexer::str_to_hex_float(&bytes[2..])
+        let i = Lexer::skip_spaces(bytes, 0);
+        let (sign, j) = Lexer::get_sign(bytes, i);
+        if Lexer::starts_with_0x(bytes, j) {
+            Lexer::str_to_hex_float(&bytes[j + 2..]).map(|f| f * (sign as FloatType))
         } else {
-            match s.parse::<FloatType>() {
-                Ok(f) => Some(f),
-                Err(_e) => None,
-            }
+            s.trim_matches(|c| Lexer::is_space(c as u8) || Lexer::is_line_break(c as u8))
+                .parse::<FloatType>()
+                .ok()
         }
     }
 
+    // hexadecimal float without the `0x` prefix, e.g. `A.8p-1`.
+    // keeps the leading 64 bits of the mantissa, the rest only affects rounding.
     pub fn str_to_hex_float(bytes: &[u8]) -> Option<FloatType> {
-        let (sign, mut i) = Lexer::get_sign(bytes, 0);
+        let mut i = 0;
         let mut has_dot = false;
-        let mut e: IntType = 0;
-        let mut r = 0.0;
+        let mut m: u64 = 0;
+        let mut e: i64 = 0;
+        let mut sticky = false;
         let mut empty = true;
         while i < bytes.len() {
             match bytes[i] {
-                b'.' => {
-                    if has_dot {
-                        // can't have more than one dot.
-                        return None;
+                b'.' if !has_dot => has_dot = true,
+                c if Lexer::is_hex_digit(c) => {
+                    let d = Lexer::to_hex_digit(c) as u64;
+                    if m >> 60 == 0 {
+                        m = (m << 4) | d;
+                        if has_dot {
+                            e -= 4;
+                        }
                     } else {
-                        has_dot = true;
-                    }
-                }
-                _ if Lexer::is_hex_digit(bytes[i]) => {
-                    r = r * 16.0 + Lexer::to_hex_digit(bytes[i]) as FloatType;
-                    if has_dot {
-                        e -= 1;
+                        sticky |= d != 0;
+                        if !has_dot {
+                            e += 4;
+                        }
                     }
                     empty = false;
                 }
@@ -767,19 +1035,17 @@ impl<'a> Lexer {
             }
             i += 1;
         }
-        e *= 4;
+        if empty {
+            return None;
+        }
         if i < bytes.len() && (bytes[i] == b'p' || bytes[i] == b'P') {
-            i += 1;
-            let (esign, mut index) = Lexer::get_sign(bytes, i);
-            let mut exp_value = 0;
+            let (esign, mut index) = Lexer::get_sign(bytes, i + 1);
+            let mut exp_value: i64 = 0;
             let mut exp_empty = true;
-            while index < bytes.len() {
-                if Lexer::is_digit(bytes[index]) {
-                    exp_empty = false;
-                    exp_value = exp_value * 10 + Lexer::to_digit(bytes[index]) as IntType;
-                } else {
-                    break;
-                }
+            while index < bytes.len() && Lexer::is_digit(bytes[index]) {
+                exp_empty = false;
+                // large enough to overflow or underflow any mantissa
+                exp_value = (exp_value * 10 + Lexer::to_digit(bytes[index]) as i64).min(1 << 20);
                 index += 1;
             }
             if exp_empty {
@@ -788,16 +1054,31 @@ impl<'a> Lexer {
             e += exp_value * esign;
             i = index;
         }
-        r = r * (2 as FloatType).powf(e as FloatType);
-        Lexer::skip_spaces(bytes, i);
-        if empty || i != bytes.len() {
-            None
-        } else {
-            Some(r * (sign as FloatType))
+        i = Lexer::skip_spaces(bytes, i);
+        if i != bytes.len() {
+            return None;
+        }
+        if sticky {
+            m |= 1;
+        }
+        Some(Lexer::ldexp(m as FloatType, e))
+    }
+
+    // x * 2^e, scaled in steps so that the power itself never overflows
+    fn ldexp(mut x: FloatType, mut e: i64) -> FloatType {
+        while e > 1000 && x.is_finite() && x != 0.0 {
+            x *= (2 as FloatType).powi(1000);
+            e -= 1000;
+        }
+        while e < -1000 && x.is_finite() && x != 0.0 {
+            x *= (2 as FloatType).powi(-1000);
+            e += 1000;
         }
+        x * (2 as FloatType).powi(e.clamp(-1100, 1100) as i32)
     }
 
-    fn str_to_num(s: &str) -> Number {
+    // string to number like `tonumber` of Lua, an integer if it fits, otherwise a float.
+    pub fn str_to_num(s: &str) -> Number {
         if let Some(i) = Lexer::str_to_int(s) {
             Number::Int(i)
         } else if let Some(f) = Lexer::str_to_float(s) {
@@ -843,7 +1124,75 @@ impl<'a> Lexer {
 
     fn add_token(&mut self, ctx: &Context, t: TokenType, value: TokenValue) {
         let source = ctx.get_saved_source();
-        self.tokens.push(Token { t, value, source });
+        let leading = mem::take(&mut self.trivia);
+        for index in self.pending_directives.drain(..) {
+            self.directives[index].first_line = source.line;
+            self.directives[index].last_line = source.line;
+        }
+        self.tokens.push(Token {
+            t,
+            value,
+            source,
+            leading,
+            trailing: Vec::new(),
+        });
+        self.trailing = true;
+    }
+
+    fn add_trivia(&mut self, ctx: &Context, kind: TriviaKind) {
+        let source = ctx.get_saved_source();
+        let text = String::from(&ctx.buffer[source.pos..source.pos + source.length]);
+        if kind == TriviaKind::LineBreak {
+            self.trailing = false;
+        }
+        let trivia = Trivia { kind, text, source };
+        match self.tokens.last_mut() {
+            Some(token) if self.trailing => token.trailing.push(trivia),
+            _ => self.trivia.push(trivia),
+        }
+    }
+
+    // keep a comment which is a directive
+    fn add_directive(&mut self, ctx: &Context, kind: TriviaKind) {
+        let source = ctx.get_saved_source();
+        let text = &ctx.buffer[source.pos..source.pos + source.length];
+        let (kind, rules) = match Directive::parse(text, kind) {
+            Some(Command::Ignore(kind, rules)) => (kind, rules),
+            Some(Command::End) => {
+                if let Some(index) = self.open_directives.pop() {
+                    self.directives[index].last_line = source.line;
+                }
+                return;
+            }
+            None => return,
+        };
+        let index = self.directives.len();
+        let first_line = match kind {
+            DirectiveKind::Line => {
+                let before = ctx.buffer[..source.pos].rsplit(['\n', '\r']).next();
+                if before.is_some_and(|before| before.trim().is_empty()) {
+                    self.pending_directives.push(index);
+                }
+                source.line
+            }
+            DirectiveKind::Block => {
+                self.open_directives.push(index);
+                source.line
+            }
+            DirectiveKind::File => 1,
+        };
+        // the end of a block or the file is only known at the end
+        let last_line = match kind {
+            DirectiveKind::Line => source.line,
+            _ => usize::MAX,
+        };
+        self.directives.push(Directive {
+            kind,
+            rules,
+            first_line,
+            last_line,
+            source,
+        });
     }
 
     debuggable!();
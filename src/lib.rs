@@ -4,4 +4,21 @@ pub mod lexer;
 pub mod types;
 pub mod parser;
 pub mod ast;
-pub mod ast_walker;
\ No newline at end of file
+pub mod ast_walker;
+pub mod cst;
+pub mod printer;
+pub mod formatter;
+pub mod minifier;
+pub mod resolver;
+pub mod linter;
+pub mod directive;
+pub mod validator;
+pub mod interpreter;
+pub mod pattern;
+mod stdlib;
+pub mod value;
+pub mod bytecode;
+pub mod compiler;
+pub mod undump;
+pub mod disassembler;
+pub mod decompiler;
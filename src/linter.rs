@@ -0,0 +1,618 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::ast::*;
+use crate::directive::Directive;
+use crate::pattern;
+use crate::resolver::{Binding, LocalKind, Resolution, Resolver};
+use crate::token::Span;
+use crate::types::{FloatType, IntType, LuaVersion};
+
+const LUA51_GLOBALS: &[&str] = &[
+    "_G",
+    "_VERSION",
+    "assert",
+    "collectgarbage",
+    "coroutine",
+    "debug",
+    "dofile",
+    "error",
+    "getfenv",
+    "getmetatable",
+    "io",
+    "ipairs",
+    "load",
+    "loadfile",
+    "loadstring",
+    "math",
+    "module",
+    "next",
+    "os",
+    "package",
+    "pairs",
+    "pcall",
+    "print",
+    "rawequal",
+    "rawget",
+    "rawset",
+    "require",
+    "select",
+    "setfenv",
+    "setmetatable",
+    "string",
+    "table",
+    "tonumber",
+    "tostring",
+    "type",
+    "unpack",
+    "xpcall",
+];
+
+// gone since 5.2
+const LUA51_ONLY_GLOBALS: &[&str] = &["getfenv", "loadstring", "module", "setfenv", "unpack"];
+
+const LUA52_GLOBALS: &[&str] = &["bit32", "rawlen"];
+
+const LUA53_GLOBALS: &[&str] = &["utf8"];
+
+const LUA54_GLOBALS: &[&str] = &["warn"];
+
+const LUAJIT_GLOBALS: &[&str] = &["bit", "jit"];
+
+// globals defined by the standard library of `version`
+fn std_globals(version: LuaVersion) -> HashSet<&'static str> {
+    let mut globals: HashSet<&'static str> = LUA51_GLOBALS.iter().copied().collect();
+    if version == LuaVersion::LuaJIT {
+        globals.extend(LUAJIT_GLOBALS);
+    }
+    if version.has_env() {
+        for name in LUA51_ONLY_GLOBALS {
+            globals.remove(name);
+        }
+        globals.extend(LUA52_GLOBALS);
+    }
+    if version.has_integer() {
+        globals.extend(LUA53_GLOBALS);
+    }
+    if version == LuaVersion::Lua54 {
+        globals.extend(LUA54_GLOBALS);
+    }
+    globals
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Rule {
+    UnusedLocal,
+    UnusedParam,
+    ShadowedLocal,
+    GlobalAssign,
+    UndefinedGlobal,
+    UnreachableCode,
+    DuplicateKey,
+    SelfAssign,
+    CompareNewTable,
+    MalformedPattern,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 10] = [
+        Rule::UnusedLocal,
+        Rule::UnusedParam,
+        Rule::ShadowedLocal,
+        Rule::GlobalAssign,
+        Rule::UndefinedGlobal,
+        Rule::UnreachableCode,
+        Rule::DuplicateKey,
+        Rule::SelfAssign,
+        Rule::CompareNewTable,
+        Rule::MalformedPattern,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Rule::UnusedLocal => "unused-local",
+            Rule::UnusedParam => "unused-param",
+            Rule::ShadowedLocal => "shadowed-local",
+            Rule::GlobalAssign => "global-assign",
+            Rule::UndefinedGlobal => "undefined-global",
+            Rule::UnreachableCode => "unreachable-code",
+            Rule::DuplicateKey => "duplicate-key",
+            Rule::SelfAssign => "self-assign",
+            Rule::CompareNewTable => "compare-new-table",
+            Rule::MalformedPattern => "malformed-pattern",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Rule> {
+        Rule::ALL.iter().copied().find(|rule| rule.name() == name)
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lint {
+    pub rule: Rule,
+    pub message: String,
+    pub span: Span,
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[{}] {} at line [{}:{}].",
+            self.rule, self.message, self.span.line, self.span.col
+        )
+    }
+}
+
+// constant table key, integral floats are the same key as the integer
+#[derive(PartialEq)]
+enum Key {
+    Str(String),
+    Int(IntType),
+    Float(FloatType),
+    Bool(bool),
+}
+
+impl Key {
+    fn new(expr: &Expr) -> Option<Key> {
+        match expr {
+            Expr::String(s, _) => Some(Key::Str(s.clone())),
+            Expr::Int(i, _) | Expr::UInt(i, _) => Some(Key::Int(*i)),
+            Expr::Float(f, _) if f.fract() == 0.0 && f.abs() < 2f64.powi(63) => {
+                Some(Key::Int(*f as IntType))
+            }
+            Expr::Float(f, _) => Some(Key::Float(*f)),
+            Expr::True(_) => Some(Key::Bool(true)),
+            Expr::False(_) => Some(Key::Bool(false)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Key::Str(s) => write!(f, "'{}'", s),
+            Key::Int(i) => write!(f, "{}", i),
+            Key::Float(n) => write!(f, "{}", n),
+            Key::Bool(b) => write!(f, "{}", b),
+        }
+    }
+}
+
+// checks a parsed chunk for common mistakes, every rule is enabled by default.
+// names starting with `_` are never reported as unused or shadowing.
+pub struct Linter {
+    version: LuaVersion,
+    disabled: HashSet<Rule>,
+    // globals defined outside the chunk on top of the standard library
+    globals: HashSet<String>,
+    // `-- rslua: ignore` comments of the source
+    directives: Vec<Directive>,
+    lints: Vec<Lint>,
+}
+
+impl Default for Linter {
+    fn default() -> Self {
+        Linter::new()
+    }
+}
+
+impl Linter {
+    pub fn new() -> Self {
+        Linter {
+            version: LuaVersion::default(),
+            disabled: HashSet::new(),
+            globals: HashSet::new(),
+            directives: Vec::new(),
+            lints: Vec::new(),
+        }
+    }
+
+    // dialect of the tree, decides the standard globals and how globals resolve
+    pub fn set_version(&mut self, version: LuaVersion) {
+        self.version = version;
+    }
+
+    pub fn set_enabled(&mut self, rule: Rule, enabled: bool) {
+        if enabled {
+            self.disabled.remove(&rule);
+        } else {
+            self.disabled.insert(rule);
+        }
+    }
+
+    pub fn is_enabled(&self, rule: Rule) -> bool {
+        !self.disabled.contains(&rule)
+    }
+
+    // a global the host defines, neither reading nor assigning it is reported
+    pub fn add_global(&mut self, name: &str) {
+        self.globals.insert(name.to_string());
+    }
+
+    // silence what the directive comments of the source ask for, from `Lexer::directives`
+    pub fn set_directives(&mut self, directives: &[Directive]) {
+        self.directives = directives.to_vec();
+    }
+
+    // lints in source order
+    pub fn run(&mut self, block: &Block) -> Vec<Lint> {
+        self.lints.clear();
+        let mut resolver = Resolver::new();
+        resolver.set_version(self.version);
+        let resolution = resolver.run(block);
+        self.locals(&resolution);
+        self.globals(&resolution);
+        self.block(block, &resolution);
+        let mut lints = std::mem::take(&mut self.lints);
+        lints.sort_by_key(|lint| lint.span.start);
+        lints
+    }
+
+    fn report(&mut self, rule: Rule, message: String, span: Span) {
+        let ignored = self
+            .directives
+            .iter()
+            .any(|directive| directive.covers(rule.name(), span.line));
+        if self.is_enabled(rule) && !ignored {
+            self.lints.push(Lint {
+                rule,
+                message,
+                span,
+            });
+        }
+    }
+
+    fn locals(&mut self, resolution: &Resolution) {
+        for (id, local) in resolution.locals.iter().enumerate() {
+            if local.name.starts_with('_') || local.kind == LocalKind::SelfParam {
+                continue;
+            }
+            let reads = local
+                .uses
+                .iter()
+                .filter(|u| !resolution.uses[**u].write)
+                .count();
+            // a to-be-closed variable is used by going out of scope
+            if reads == 0 && local.attrib != Some(LocalAttrib::Close) {
+                let (rule, message) = match local.kind {
+                    LocalKind::Param => (
+                        Rule::UnusedParam,
+                        format!("unused parameter '{}'", local.name),
+                    ),
+                    _ if !local.uses.is_empty() => (
+                        Rule::UnusedLocal,
+                        format!("local '{}' is assigned but never read", local.name),
+                    ),
+                    _ => (Rule::UnusedLocal, format!("unused local '{}'", local.name)),
+                };
+                self.report(rule, message, local.span);
+            }
+            // a local with a lower id in the same or an enclosing scope is declared earlier
+            let mut scope = Some(local.scope);
+            while let Some(id_scope) = scope {
+                let shadowed = resolution.scopes[id_scope]
+                    .locals
+                    .iter()
+                    .rev()
+                    .find(|other| **other < id && resolution.locals[**other].name == local.name);
+                if let Some(other) = shadowed {
+                    let line = resolution.locals[*other].span.line;
+                    self.report(
+                        Rule::ShadowedLocal,
+                        format!("local '{}' shadows the one at line {}", local.name, line),
+                        local.span,
+                    );
+                    break;
+                }
+                scope = resolution.scopes[id_scope].parent;
+            }
+        }
+    }
+
+    fn globals(&mut self, resolution: &Resolution) {
+        let std = std_globals(self.version);
+        // globals assigned anywhere in the chunk may be read anywhere
+        let defined: HashSet<&str> = resolution
+            .uses
+            .iter()
+            .filter(|u| u.write && u.binding == Binding::Global(None))
+            .map(|u| u.name.as_str())
+            .collect();
+        for u in resolution.uses.iter() {
+            // fields of a local `_ENV` could be anything
+            if u.binding != Binding::Global(None) || self.globals.contains(&u.name) {
+                continue;
+            }
+            if u.write {
+                self.report(
+                    Rule::GlobalAssign,
+                    format!("assignment to global '{}'", u.name),
+                    u.span,
+                );
+            } else if !std.contains(u.name.as_str()) && !defined.contains(u.name.as_str()) {
+                self.report(
+                    Rule::UndefinedGlobal,
+                    format!("undefined global '{}'", u.name),
+                    u.span,
+                );
+            }
+        }
+    }
+
+    fn block(&mut self, block: &Block, resolution: &Resolution) {
+        // after a jump nothing runs until a label it could jump to
+        let mut dead = false;
+        let mut reported = false;
+        for stat in block.stats.iter() {
+            match stat {
+                Stat::LabelStat(_) => {
+                    dead = false;
+                    reported = false;
+                }
+                Stat::Empty(_) => (),
+                _ if dead && !reported => {
+                    reported = true;
+                    self.report(
+                        Rule::UnreachableCode,
+                        "unreachable code".to_string(),
+                        stat.span(),
+                    );
+                }
+                _ => (),
+            }
+            if Linter::is_jump(stat) {
+                dead = true;
+            }
+            self.stat(stat, resolution);
+        }
+    }
+
+    // `return` has to end its block, so code after one is only possible after `do return end`
+    fn is_jump(stat: &Stat) -> bool {
+        match stat {
+            Stat::RetStat(_) | Stat::BreakStat(_) | Stat::GotoStat(_) => true,
+            Stat::DoBlock(stat) => stat
+                .block
+                .stats
+                .iter()
+                .rev()
+                .find(|stat| !matches!(stat, Stat::Empty(_)))
+                .is_some_and(Linter::is_jump),
+            _ => false,
+        }
+    }
+
+    fn stat(&mut self, stat: &Stat, resolution: &Resolution) {
+        match stat {
+            Stat::IfStat(stat) => {
+                for cond_block in stat.cond_blocks.iter() {
+                    self.expr(&cond_block.cond, resolution);
+                    self.block(&cond_block.block, resolution);
+                }
+                self.block(&stat.else_block, resolution);
+            }
+            Stat::WhileStat(stat) => {
+                self.expr(&stat.cond, resolution);
+                self.block(&stat.block, resolution);
+            }
+            Stat::DoBlock(stat) => self.block(&stat.block, resolution),
+            Stat::ForStat(ForStat::ForNum(stat)) => {
+                self.expr(&stat.init, resolution);
+                self.expr(&stat.limit, resolution);
+                if let Some(step) = &stat.step {
+                    self.expr(step, resolution);
+                }
+                self.block(&stat.body, resolution);
+            }
+            Stat::ForStat(ForStat::ForList(stat)) => {
+                self.exprs(&stat.exprs, resolution);
+                self.block(&stat.body, resolution);
+            }
+            Stat::RepeatStat(stat) => {
+                self.block(&stat.block, resolution);
+                self.expr(&stat.cond, resolution);
+            }
+            Stat::FuncStat(stat) => self.block(&stat.body.block, resolution),
+            Stat::LocalStat(stat) => self.exprs(&stat.exprs, resolution),
+            Stat::RetStat(stat) => self.exprs(&stat.exprs, resolution),
+            Stat::AssignStat(stat) => {
+                for (target, value) in stat.left.iter().zip(stat.right.iter()) {
+                    if let Expr::SuffixedExpr(value) = Linter::strip(value) {
+                        if Linter::is_pure_suffixed(target) && same_structure(target, value) {
+                            self.report(
+                                Rule::SelfAssign,
+                                "value assigned to itself".to_string(),
+                                target.span,
+                            );
+                        }
+                    }
+                }
+                for target in stat.left.iter() {
+                    self.suffixedexpr(target, resolution);
+                }
+                self.exprs(&stat.right, resolution);
+            }
+            Stat::CallStat(stat) => self.suffixedexpr(&stat.call, resolution),
+            Stat::LabelStat(_)
+            | Stat::BreakStat(_)
+            | Stat::GotoStat(_)
+            | Stat::Empty(_)
+            | Stat::Error(_) => (),
+        }
+    }
+
+    fn exprs(&mut self, exprs: &[Expr], resolution: &Resolution) {
+        for expr in exprs.iter() {
+            self.expr(expr, resolution);
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr, resolution: &Resolution) {
+        match expr {
+            Expr::FuncBody(body) => self.block(&body.block, resolution),
+            Expr::Table(table) => self.table(table, resolution),
+            Expr::BinExpr(expr) => {
+                if let BinOp::Eq | BinOp::Ne = expr.op {
+                    let new_table = |e: &Expr| matches!(Linter::strip(e), Expr::Table(_));
+                    if new_table(&expr.left) || new_table(&expr.right) {
+                        let result = expr.op == BinOp::Ne;
+                        self.report(
+                            Rule::CompareNewTable,
+                            format!("comparison with a new table is always {}", result),
+                            expr.span,
+                        );
+                    }
+                }
+                self.expr(&expr.left, resolution);
+                self.expr(&expr.right, resolution);
+            }
+            Expr::UnExpr(expr) => self.expr(&expr.expr, resolution),
+            Expr::SuffixedExpr(expr) => self.suffixedexpr(expr, resolution),
+            _ => (),
+        }
+    }
+
+    fn suffixedexpr(&mut self, expr: &SuffixedExpr, resolution: &Resolution) {
+        if let PrimaryExpr::ParenExpr(expr) = &expr.primary {
+            self.expr(expr, resolution);
+        }
+        self.patterns(expr, resolution);
+        for suffix in expr.suffixes.iter() {
+            match suffix {
+                Suffix::Index(expr) => self.expr(expr, resolution),
+                Suffix::FuncArgs(FuncArgs::Exprs(exprs, _)) => self.exprs(exprs, resolution),
+                Suffix::FuncArgs(FuncArgs::Table(table)) => self.table(table, resolution),
+                _ => (),
+            }
+        }
+    }
+
+    // constant patterns given to `string.find`, `s:match` and the like
+    fn patterns(&mut self, expr: &SuffixedExpr, resolution: &Resolution) {
+        for (i, suffix) in expr.suffixes.iter().enumerate() {
+            // the pattern follows the subject, which a method call passes as `self`
+            let (name, index) = match (suffix, &expr.primary) {
+                (Suffix::Method(name), _) => (name, 0),
+                // the standard `string` library, not a local or a field of a local `_ENV`
+                (Suffix::Attr(name), PrimaryExpr::Name(lib))
+                    if i == 0
+                        && lib.name == "string"
+                        && resolution.binding(lib) == Some(Binding::Global(None)) =>
+                {
+                    (name, 1)
+                }
+                _ => continue,
+            };
+            if !matches!(name.name.as_str(), "find" | "match" | "gmatch" | "gsub") {
+                continue;
+            }
+            let (pat, span) = match expr.suffixes.get(i + 1) {
+                Some(Suffix::FuncArgs(FuncArgs::Exprs(args, _))) => {
+                    // `find` with a plain flag does not use patterns
+                    let plain = args.get(index + 2).map(Linter::strip);
+                    if name.name == "find"
+                        && !matches!(plain, None | Some(Expr::Nil(_)) | Some(Expr::False(_)))
+                    {
+                        continue;
+                    }
+                    match args.get(index) {
+                        Some(Expr::String(pat, span)) => (pat, *span),
+                        _ => continue,
+                    }
+                }
+                Some(Suffix::FuncArgs(FuncArgs::String(pat, span))) if index == 0 => (pat, *span),
+                _ => continue,
+            };
+            if let Err(err) = pattern::validate(pat.as_bytes()) {
+                self.report(
+                    Rule::MalformedPattern,
+                    format!("'string.{}': {}", name.name, err),
+                    span,
+                );
+            }
+        }
+    }
+
+    fn table(&mut self, table: &Table, resolution: &Resolution) {
+        let mut keys: Vec<Key> = Vec::new();
+        let mut index = 0;
+        for field in table.fields.iter() {
+            let key = match field {
+                Field::ListField(value) => {
+                    self.expr(value, resolution);
+                    index += 1;
+                    Some(Key::Int(index))
+                }
+                Field::RecFileld(field) => {
+                    if let FieldKey::Expr(key) = &field.key {
+                        self.expr(key, resolution);
+                    }
+                    self.expr(&field.value, resolution);
+                    match &field.key {
+                        FieldKey::Name(name) => Some(Key::Str(name.name.clone())),
+                        FieldKey::Expr(key) => Key::new(key),
+                    }
+                }
+            };
+            if let Some(key) = key {
+                if keys.contains(&key) {
+                    self.report(
+                        Rule::DuplicateKey,
+                        format!("duplicate key {} in table", key),
+                        field.span(),
+                    );
+                } else {
+                    keys.push(key);
+                }
+            }
+        }
+    }
+
+    fn strip(expr: &Expr) -> &Expr {
+        match expr {
+            Expr::SuffixedExpr(SuffixedExpr {
+                primary: PrimaryExpr::ParenExpr(inner),
+                suffixes,
+                ..
+            }) if suffixes.is_empty() => Linter::strip(inner),
+            _ => expr,
+        }
+    }
+
+    // evaluating it twice gives the same value, barring metamethods
+    fn is_pure(expr: &Expr) -> bool {
+        match expr {
+            Expr::Nil(_)
+            | Expr::True(_)
+            | Expr::False(_)
+            | Expr::Float(..)
+            | Expr::Int(..)
+            | Expr::UInt(..)
+            | Expr::String(..) => true,
+            Expr::SuffixedExpr(expr) => Linter::is_pure_suffixed(expr),
+            _ => false,
+        }
+    }
+
+    fn is_pure_suffixed(expr: &SuffixedExpr) -> bool {
+        let primary = match &expr.primary {
+            PrimaryExpr::Name(_) => true,
+            PrimaryExpr::ParenExpr(expr) => Linter::is_pure(expr),
+        };
+        primary
+            && expr.suffixes.iter().all(|suffix| match suffix {
+                Suffix::Attr(_) => true,
+                Suffix::Index(expr) => Linter::is_pure(expr),
+                _ => false,
+            })
+    }
+}
@@ -19,13 +19,13 @@ macro_rules! debuggable {
 
 #[macro_export]
 macro_rules! error {
-    ($self:ident, $error_type:ident, $msg:expr) => {
+    ($self:ident, $error:expr) => {{
+        let error = $error;
         // panic! when at debug mode, otherwise return Error
         if $self.is_debug() {
-            panic!("{}", &$msg);
+            panic!("{}", error);
         } else {
-            println!("{}", &$msg);
-            Err($error_type($msg))
+            Err(error)
         }
-    };
+    }};
 }
@@ -0,0 +1,492 @@
+use rslua::compiler::Compiler;
+use rslua::decompiler::Decompiler;
+use rslua::disassembler::disassemble;
+use rslua::formatter::{self, CallParens, FormatConfig, QuoteStyle};
+use rslua::lexer::Lexer;
+use rslua::linter::{Linter, Rule};
+use rslua::minifier::Minifier;
+use rslua::parser::Parser;
+use rslua::printer;
+use rslua::token::{TokenType, TokenValue};
+use rslua::types::LuaVersion;
+use rslua::undump::undump;
+use rslua::validator::Validator;
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+const USAGE: &str = "usage: rslua <command> [options] [paths...]
+
+commands:
+  tokens    print the tokens of the input
+  ast       print the syntax tree of the input
+  check     report syntax errors, fails if any is found
+  fmt       format files in place, stdin is formatted to stdout
+  minify    print the input minified
+  lint      report suspicious code, fails if any is found
+  compile   compile files to Lua 5.3 binary chunks next to them as .luac, stdin is
+            compiled to stdout
+  disasm    list the instructions of Lua 5.1, 5.3 or 5.4 binary chunks like `luac -l`
+  decompile print Lua 5.1 or 5.3 binary chunks as source
+
+paths are files or directories searched for .lua files (.luac for disasm and decompile),
+stdin is read when no path is given or for `-`.
+
+options:
+  --lua <5.1|5.2|5.3|5.4|jit>       dialect, 5.4 by default
+  -h, --help                        print this message
+
+lint options:
+  --disable <rule,...>              rules not to check, out of unused-local, unused-param,
+                                    shadowed-local, global-assign, undefined-global,
+                                    unreachable-code, duplicate-key, self-assign,
+                                    compare-new-table, malformed-pattern
+  --globals <name,...>              globals defined by the host
+
+compile options:
+  --strip                           leave out debug information
+
+disasm options:
+  --full                            also list constants, locals and upvalues
+
+fmt options:
+  --check                           list unformatted files instead of writing them,
+                                    fails if any is found
+  --indent <n>                      spaces per indent level, 2 by default
+  --tabs                            indent with tabs
+  --width <n>                       max line width, 100 by default
+  --quotes <double|single|keep>     preferred quotes, double by default
+  --no-trailing-separator           no separator after the last field of split tables
+  --call-parens <keep|always|omit>  parentheses around single string or table arguments
+";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Command {
+    Tokens,
+    Ast,
+    Check,
+    Fmt,
+    Minify,
+    Lint,
+    Compile,
+    Disasm,
+    Decompile,
+}
+
+struct Options {
+    command: Command,
+    version: LuaVersion,
+    check: bool,
+    strip: bool,
+    full: bool,
+    format: FormatConfig,
+    disabled: Vec<Rule>,
+    globals: Vec<String>,
+    paths: Vec<String>,
+}
+
+enum Input {
+    Stdin,
+    File(PathBuf),
+}
+
+impl Input {
+    fn name(&self) -> String {
+        match self {
+            Input::Stdin => "<stdin>".to_string(),
+            Input::File(path) => path.display().to_string(),
+        }
+    }
+
+    fn read(&self) -> io::Result<Vec<u8>> {
+        match self {
+            Input::Stdin => {
+                let mut content = Vec::new();
+                io::stdin().read_to_end(&mut content)?;
+                Ok(content)
+            }
+            Input::File(path) => fs::read(path),
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        print!("{}", USAGE);
+        return;
+    }
+    let options = match parse_args(args) {
+        Ok(options) => options,
+        Err(msg) => {
+            eprintln!("rslua: {}\n\n{}", msg, USAGE);
+            process::exit(2);
+        }
+    };
+
+    let mut inputs = Vec::new();
+    if options.paths.is_empty() {
+        inputs.push(Input::Stdin);
+    }
+    let extension = match options.command {
+        Command::Disasm | Command::Decompile => "luac",
+        _ => "lua",
+    };
+    for path in options.paths.iter() {
+        if path == "-" {
+            inputs.push(Input::Stdin);
+        } else if let Err(e) = collect_files(Path::new(path), extension, &mut inputs) {
+            eprintln!("rslua: {}: {}", path, e);
+            process::exit(2);
+        }
+    }
+
+    let mut success = true;
+    let headers = inputs.len() > 1;
+    for input in inputs.iter() {
+        let data = match input.read() {
+            Ok(data) => data,
+            Err(e) => {
+                eprintln!("rslua: {}: {}", input.name(), e);
+                success = false;
+                continue;
+            }
+        };
+        if headers
+            && matches!(
+                options.command,
+                Command::Tokens
+                    | Command::Ast
+                    | Command::Minify
+                    | Command::Disasm
+                    | Command::Decompile
+            )
+        {
+            println!("==> {} <==", input.name());
+        }
+        // binary chunks are the only input that is not text
+        let result = match (options.command, std::str::from_utf8(&data)) {
+            (Command::Disasm, _) => disasm(&options, &data),
+            (Command::Decompile, _) => decompile(&data),
+            (_, Err(e)) => Err(vec![format!("invalid UTF-8: {}", e)]),
+            (Command::Tokens, Ok(content)) => tokens(&options, content),
+            (Command::Ast, Ok(content)) => ast(&options, content),
+            (Command::Check, Ok(content)) => check(&options, content),
+            (Command::Fmt, Ok(content)) => fmt(&options, input, content),
+            (Command::Minify, Ok(content)) => minify(&options, content),
+            (Command::Lint, Ok(content)) => lint(&options, content),
+            (Command::Compile, Ok(content)) => compile(&options, input, content),
+        };
+        if let Err(msgs) = result {
+            for msg in msgs {
+                eprintln!("{}: {}", input.name(), msg);
+            }
+            success = false;
+        }
+    }
+    if !success {
+        process::exit(1);
+    }
+}
+
+fn parse_args(args: Vec<String>) -> Result<Options, String> {
+    let mut args = args.into_iter();
+    let command = match args.next().as_deref() {
+        Some("tokens") => Command::Tokens,
+        Some("ast") => Command::Ast,
+        Some("check") => Command::Check,
+        Some("fmt") => Command::Fmt,
+        Some("minify") => Command::Minify,
+        Some("lint") => Command::Lint,
+        Some("compile") => Command::Compile,
+        Some("disasm") => Command::Disasm,
+        Some("decompile") => Command::Decompile,
+        Some(command) => return Err(format!("unknown command `{}`", command)),
+        None => return Err("missing command".to_string()),
+    };
+    let mut options = Options {
+        command,
+        version: LuaVersion::default(),
+        check: false,
+        strip: false,
+        full: false,
+        format: FormatConfig::default(),
+        disabled: Vec::new(),
+        globals: Vec::new(),
+        paths: Vec::new(),
+    };
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            options.paths.push(arg);
+            continue;
+        }
+        let fmt_only = matches!(
+            arg.as_str(),
+            "--check"
+                | "--indent"
+                | "--tabs"
+                | "--width"
+                | "--quotes"
+                | "--no-trailing-separator"
+                | "--call-parens"
+        );
+        if fmt_only && command != Command::Fmt {
+            return Err(format!("`{}` is only available for fmt", arg));
+        }
+        let lint_only = matches!(arg.as_str(), "--disable" | "--globals");
+        if lint_only && command != Command::Lint {
+            return Err(format!("`{}` is only available for lint", arg));
+        }
+        if arg == "--strip" && command != Command::Compile {
+            return Err(format!("`{}` is only available for compile", arg));
+        }
+        if arg == "--full" && command != Command::Disasm {
+            return Err(format!("`{}` is only available for disasm", arg));
+        }
+        let mut value = || args.next().ok_or(format!("missing value for `{}`", arg));
+        match arg.as_str() {
+            "--lua" => {
+                options.version = match value()?.as_str() {
+                    "5.1" => LuaVersion::Lua51,
+                    "5.2" => LuaVersion::Lua52,
+                    "5.3" => LuaVersion::Lua53,
+                    "5.4" => LuaVersion::Lua54,
+                    "jit" => LuaVersion::LuaJIT,
+                    version => return Err(format!("unknown Lua version `{}`", version)),
+                };
+                options.format.version = options.version;
+            }
+            "--disable" => {
+                for name in value()?.split(',') {
+                    match Rule::from_name(name) {
+                        Some(rule) => options.disabled.push(rule),
+                        None => return Err(format!("unknown rule `{}`", name)),
+                    }
+                }
+            }
+            "--globals" => options
+                .globals
+                .extend(value()?.split(',').map(str::to_string)),
+            "--check" => options.check = true,
+            "--strip" => options.strip = true,
+            "--full" => options.full = true,
+            "--indent" => options.format.indent_width = parse_number(&value()?)?,
+            "--tabs" => options.format.use_tabs = true,
+            "--width" => options.format.max_width = parse_number(&value()?)?,
+            "--quotes" => {
+                options.format.quote_style = match value()?.as_str() {
+                    "double" => QuoteStyle::Double,
+                    "single" => QuoteStyle::Single,
+                    "keep" => QuoteStyle::Keep,
+                    quotes => return Err(format!("unknown quote style `{}`", quotes)),
+                }
+            }
+            "--no-trailing-separator" => options.format.trailing_separator = false,
+            "--call-parens" => {
+                options.format.call_parens = match value()?.as_str() {
+                    "keep" => CallParens::Keep,
+                    "always" => CallParens::Always,
+                    "omit" => CallParens::Omit,
+                    parens => return Err(format!("unknown call parens `{}`", parens)),
+                }
+            }
+            _ => return Err(format!("unknown option `{}`", arg)),
+        }
+    }
+    Ok(options)
+}
+
+fn parse_number(value: &str) -> Result<usize, String> {
+    value
+        .parse()
+        .map_err(|_| format!("`{}` is not a number", value))
+}
+
+// `path` itself if it is a file, otherwise the files with `extension` below it in name order
+fn collect_files(path: &Path, extension: &str, inputs: &mut Vec<Input>) -> io::Result<()> {
+    if !path.is_dir() {
+        fs::metadata(path)?;
+        inputs.push(Input::File(path.to_path_buf()));
+        return Ok(());
+    }
+    let mut entries = fs::read_dir(path)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<PathBuf>>>()?;
+    entries.sort();
+    for entry in entries {
+        if entry.is_dir() {
+            collect_files(&entry, extension, inputs)?;
+        } else if entry.extension().is_some_and(|ext| ext == extension) {
+            inputs.push(Input::File(entry));
+        }
+    }
+    Ok(())
+}
+
+fn lexer(options: &Options) -> Lexer {
+    let mut lexer = Lexer::new();
+    lexer.set_version(options.version);
+    lexer
+}
+
+fn parser(options: &Options) -> Parser {
+    let mut parser = Parser::new();
+    parser.set_version(options.version);
+    parser
+}
+
+fn tokens(options: &Options, content: &str) -> Result<(), Vec<String>> {
+    let tokens = lexer(options)
+        .run(content)
+        .map_err(|e| vec![e.to_string()])?;
+    for token in tokens.iter() {
+        let value = match &token.value {
+            TokenValue::None => String::new(),
+            TokenValue::Int(i) => format!(" {}", i),
+            TokenValue::Float(f) => format!(" {:?}", f),
+            TokenValue::Str(s) => format!(" {:?}", s),
+        };
+        println!(
+            "{}:{} {:?}{}",
+            token.source.line, token.source.col, token.t, value
+        );
+    }
+    Ok(())
+}
+
+fn ast(options: &Options, content: &str) -> Result<(), Vec<String>> {
+    let tokens = lexer(options)
+        .run(content)
+        .map_err(|e| vec![e.to_string()])?;
+    let block = parser(options)
+        .run(tokens)
+        .map_err(|e| vec![e.to_string()])?;
+    println!("{:#?}", block);
+    Ok(())
+}
+
+fn minify(options: &Options, content: &str) -> Result<(), Vec<String>> {
+    let tokens = lexer(options)
+        .run(content)
+        .map_err(|e| vec![e.to_string()])?;
+    let block = parser(options)
+        .run(tokens)
+        .map_err(|e| vec![e.to_string()])?;
+    let mut minifier = Minifier::new();
+    minifier.set_version(options.version);
+    println!("{}", minifier.run(&block));
+    Ok(())
+}
+
+fn lint(options: &Options, content: &str) -> Result<(), Vec<String>> {
+    let mut lexer = lexer(options);
+    let tokens = lexer.run(content).map_err(|e| vec![e.to_string()])?;
+    let block = parser(options)
+        .run(tokens)
+        .map_err(|e| vec![e.to_string()])?;
+    let mut linter = Linter::new();
+    linter.set_version(options.version);
+    linter.set_directives(lexer.directives());
+    for rule in options.disabled.iter() {
+        linter.set_enabled(*rule, false);
+    }
+    for name in options.globals.iter() {
+        linter.add_global(name);
+    }
+    let lints = linter.run(&block);
+    if lints.is_empty() {
+        return Ok(());
+    }
+    Err(lints.iter().map(|lint| lint.to_string()).collect())
+}
+
+// chunk names follow `luac`, `@` and the path for files
+fn compile(options: &Options, input: &Input, content: &str) -> Result<(), Vec<String>> {
+    let tokens = lexer(options)
+        .run(content)
+        .map_err(|e| vec![e.to_string()])?;
+    let block = parser(options)
+        .run(tokens)
+        .map_err(|e| vec![e.to_string()])?;
+    let mut compiler = Compiler::new();
+    match input {
+        Input::Stdin => compiler.set_chunk_name("=stdin"),
+        Input::File(path) => compiler.set_chunk_name(&format!("@{}", path.display())),
+    }
+    let chunk = compiler
+        .run(&block)
+        .map_err(|e| vec![e.to_string()])?
+        .dump(options.strip);
+    match input {
+        Input::Stdin => io::stdout().write_all(&chunk),
+        Input::File(path) => fs::write(path.with_extension("luac"), chunk),
+    }
+    .map_err(|e| vec![e.to_string()])
+}
+
+fn disasm(options: &Options, data: &[u8]) -> Result<(), Vec<String>> {
+    let chunk = undump(data).map_err(|e| vec![e.to_string()])?;
+    print!("{}", disassemble(&chunk, options.full));
+    Ok(())
+}
+
+fn decompile(data: &[u8]) -> Result<(), Vec<String>> {
+    let chunk = undump(data).map_err(|e| vec![e.to_string()])?;
+    let block = Decompiler::new()
+        .run(&chunk)
+        .map_err(|e| vec![e.to_string()])?;
+    print!("{}", printer::print(&block));
+    Ok(())
+}
+
+// every error of the input, syntax errors at bad input the lexer already reported are left out
+fn check(options: &Options, content: &str) -> Result<(), Vec<String>> {
+    let (tokens, lex_errors) = lexer(options).run_recovering(content);
+    let (block, syntax_errors) = parser(options).run_recovering(tokens);
+    let mut errors: Vec<(usize, String)> = lex_errors
+        .iter()
+        .map(|e| (e.offset, e.to_string()))
+        .collect();
+    errors.extend(
+        syntax_errors
+            .iter()
+            .filter(|e| e.found != TokenType::Error)
+            .map(|e| (e.offset, e.to_string())),
+    );
+    // a tree patched up after errors could have loops or labels missing
+    if errors.is_empty() {
+        let mut validator = Validator::new();
+        validator.set_version(options.version);
+        errors.extend(
+            validator
+                .run(&block)
+                .iter()
+                .map(|e| (e.offset, e.to_string())),
+        );
+    }
+    if errors.is_empty() {
+        return Ok(());
+    }
+    errors.sort_by_key(|(offset, _)| *offset);
+    Err(errors.into_iter().map(|(_, msg)| msg).collect())
+}
+
+fn fmt(options: &Options, input: &Input, content: &str) -> Result<(), Vec<String>> {
+    let output = formatter::format(content, &options.format).map_err(|e| vec![e.to_string()])?;
+    if options.check {
+        if output != content {
+            return Err(vec!["not formatted".to_string()]);
+        }
+        return Ok(());
+    }
+    match input {
+        Input::Stdin => print!("{}", output),
+        Input::File(path) if output != content => {
+            fs::write(path, output).map_err(|e| vec![e.to_string()])?
+        }
+        Input::File(_) => (),
+    }
+    Ok(())
+}
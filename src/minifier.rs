@@ -0,0 +1,683 @@
+use std::collections::HashSet;
+
+use crate::ast::*;
+use crate::printer::ends_with_expr;
+use crate::types::{FloatType, IntType, LuaVersion};
+
+const KEYWORDS: [&str; 22] = [
+    "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "goto", "if", "in",
+    "local", "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
+];
+
+// first char of a generated name, the following ones may also be digits
+const NAME_CHARS: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ_0123456789";
+
+struct Local {
+    name: String,
+    short: String,
+}
+
+// turns an AST into the shortest Lua source found: no comments or needless whitespace,
+// local variables and parameters renamed to short names, redundant parentheses and `;` dropped,
+// numerals and strings respelled. globals, `_ENV`, `self` and fields keep their names, so code
+// looking locals up by name (`debug.getlocal`, `load` of a string) is the only thing that changes.
+pub struct Minifier {
+    version: LuaVersion,
+    output: String,
+    // the last token written is a numeral, which would absorb a following `.` or letter
+    numeral: bool,
+    // locals in scope, innermost last
+    locals: Vec<Local>,
+    // names used without a local in scope, never given to a local
+    globals: HashSet<String>,
+}
+
+impl Default for Minifier {
+    fn default() -> Self {
+        Minifier::new()
+    }
+}
+
+impl Minifier {
+    pub fn new() -> Self {
+        Minifier {
+            version: LuaVersion::default(),
+            output: String::new(),
+            numeral: false,
+            locals: Vec::new(),
+            globals: HashSet::new(),
+        }
+    }
+
+    // dialect of the output, numerals are spelled differently without integers
+    pub fn set_version(&mut self, version: LuaVersion) {
+        self.version = version;
+    }
+
+    pub fn run(&mut self, block: &Block) -> String {
+        // the first pass only gathers the globals to keep clear of
+        self.globals.clear();
+        self.write(block);
+        self.write(block)
+    }
+
+    fn write(&mut self, block: &Block) -> String {
+        self.output.clear();
+        self.numeral = false;
+        self.locals.clear();
+        self.block(block);
+        std::mem::take(&mut self.output)
+    }
+
+    // write a token, separated from the previous one if they would merge
+    fn token(&mut self, text: &str) {
+        if let (Some(last), Some(first)) = (self.output.chars().last(), text.chars().next()) {
+            let ident = |c: char| c.is_ascii_alphanumeric() || c == '_';
+            let merge = (ident(last) && ident(first))
+                || (self.numeral && (ident(first) || first == '.'))
+                || (last == '-' && first == '-')
+                || (last == '.' && first == '.')
+                || (last == '[' && first == '[')
+                || (last == '>' && first == '=');
+            if merge {
+                self.output.push(' ');
+            }
+        }
+        self.output.push_str(text);
+        self.numeral = false;
+    }
+
+    fn numeral(&mut self, text: &str) {
+        self.token(text);
+        self.numeral = true;
+    }
+
+    // generated name for the `n`th short name
+    fn nth_name(mut n: usize) -> String {
+        let mut name = vec![NAME_CHARS[n % 53]];
+        n /= 53;
+        while n > 0 {
+            n -= 1;
+            name.push(NAME_CHARS[n % 63]);
+            n /= 63;
+        }
+        String::from_utf8(name).unwrap()
+    }
+
+    // shortest name not hiding a local in scope or a global
+    fn short_name(&self, pending: &[Local]) -> String {
+        (0..)
+            .map(Minifier::nth_name)
+            .find(|name| {
+                !KEYWORDS.contains(&name.as_str())
+                    && name != "_ENV"
+                    && !self.globals.contains(name)
+                    && !self.locals.iter().chain(pending).any(|l| &l.short == name)
+            })
+            .unwrap()
+    }
+
+    // `_ENV` decides where globals are found, it keeps its name
+    fn new_local(&self, name: &str, pending: &[Local]) -> Local {
+        let short = if name == "_ENV" {
+            name.to_string()
+        } else {
+            self.short_name(pending)
+        };
+        Local {
+            name: name.to_string(),
+            short,
+        }
+    }
+
+    fn declare(&mut self, name: &str) -> String {
+        let local = self.new_local(name, &[]);
+        let short = local.short.clone();
+        self.locals.push(local);
+        short
+    }
+
+    fn resolve(&mut self, name: &str) -> String {
+        match self.locals.iter().rev().find(|l| l.name == name) {
+            Some(local) => local.short.clone(),
+            None => {
+                self.globals.insert(name.to_string());
+                name.to_string()
+            }
+        }
+    }
+
+    fn block(&mut self, block: &Block) {
+        let scope = self.locals.len();
+        self.stats(block);
+        self.locals.truncate(scope);
+    }
+
+    fn stats(&mut self, block: &Block) {
+        let mut prev: Option<&Stat> = None;
+        for stat in block.stats.iter() {
+            if let Stat::Empty(_) | Stat::Error(_) = stat {
+                continue;
+            }
+            let start = self.output.len();
+            self.stat(stat);
+            // `a = b (f)()` would be a call of `b`
+            if self.output[start..].starts_with('(') && prev.is_some_and(ends_with_expr) {
+                self.output.insert(start, ';');
+            }
+            prev = Some(stat);
+        }
+    }
+
+    fn stat(&mut self, stat: &Stat) {
+        match stat {
+            Stat::IfStat(stat) => {
+                for (n, cond_block) in stat.cond_blocks.iter().enumerate() {
+                    self.token(if n == 0 { "if" } else { "elseif" });
+                    self.expr(&cond_block.cond, false);
+                    self.token("then");
+                    self.block(&cond_block.block);
+                }
+                if !stat.else_block.stats.is_empty() {
+                    self.token("else");
+                    self.block(&stat.else_block);
+                }
+                self.token("end");
+            }
+            Stat::WhileStat(stat) => {
+                self.token("while");
+                self.expr(&stat.cond, false);
+                self.token("do");
+                self.block(&stat.block);
+                self.token("end");
+            }
+            Stat::DoBlock(stat) => {
+                self.token("do");
+                self.block(&stat.block);
+                self.token("end");
+            }
+            Stat::ForStat(ForStat::ForNum(stat)) => {
+                self.token("for");
+                let scope = self.locals.len();
+                let local = self.new_local(&stat.var.name, &[]);
+                self.token(&local.short);
+                self.token("=");
+                self.expr(&stat.init, false);
+                self.token(",");
+                self.expr(&stat.limit, false);
+                if let Some(step) = &stat.step {
+                    self.token(",");
+                    self.expr(step, false);
+                }
+                self.token("do");
+                self.locals.push(local);
+                self.block(&stat.body);
+                self.locals.truncate(scope);
+                self.token("end");
+            }
+            Stat::ForStat(ForStat::ForList(stat)) => {
+                self.token("for");
+                let scope = self.locals.len();
+                let mut vars: Vec<Local> = Vec::new();
+                for (n, var) in stat.vars.iter().enumerate() {
+                    if n > 0 {
+                        self.token(",");
+                    }
+                    let local = self.new_local(&var.name, &vars);
+                    self.token(&local.short);
+                    vars.push(local);
+                }
+                self.token("in");
+                self.exprlist(&stat.exprs, true);
+                self.token("do");
+                self.locals.append(&mut vars);
+                self.block(&stat.body);
+                self.locals.truncate(scope);
+                self.token("end");
+            }
+            Stat::RepeatStat(stat) => {
+                // `until` sees the locals of the block
+                self.token("repeat");
+                let scope = self.locals.len();
+                self.stats(&stat.block);
+                self.token("until");
+                self.expr(&stat.cond, false);
+                self.locals.truncate(scope);
+            }
+            Stat::FuncStat(stat) => {
+                if stat.func_type == FuncType::Local {
+                    self.token("local");
+                    self.token("function");
+                    let short = self.declare(&stat.func_name.fields[0].name);
+                    self.token(&short);
+                } else {
+                    self.token("function");
+                    let mut fields = stat.func_name.fields.iter();
+                    if let Some(name) = fields.next() {
+                        let short = self.resolve(&name.name);
+                        self.token(&short);
+                    }
+                    for field in fields {
+                        self.token(".");
+                        self.token(&field.name);
+                    }
+                    if let Some(method) = &stat.func_name.method {
+                        self.token(":");
+                        self.token(&method.name);
+                    }
+                }
+                self.funcbody(&stat.body, stat.func_name.method.is_some());
+            }
+            Stat::LocalStat(stat) => {
+                self.token("local");
+                let mut names: Vec<Local> = Vec::new();
+                for (n, name) in stat.names.iter().enumerate() {
+                    if n > 0 {
+                        self.token(",");
+                    }
+                    let local = self.new_local(&name.name.name, &names);
+                    self.token(&local.short);
+                    if let Some(attrib) = name.attrib {
+                        self.token(&format!("<{}>", attrib.name()));
+                    }
+                    names.push(local);
+                }
+                if !stat.exprs.is_empty() {
+                    self.token("=");
+                    self.exprlist(&stat.exprs, names.len() > stat.exprs.len());
+                }
+                self.locals.append(&mut names);
+            }
+            Stat::LabelStat(stat) => self.token(&format!("::{}::", stat.label.name)),
+            Stat::RetStat(stat) => {
+                self.token("return");
+                self.exprlist(&stat.exprs, true);
+            }
+            Stat::BreakStat(_) => self.token("break"),
+            Stat::GotoStat(stat) => {
+                self.token("goto");
+                self.token(&stat.label.name);
+            }
+            Stat::AssignStat(stat) => {
+                for (n, expr) in stat.left.iter().enumerate() {
+                    if n > 0 {
+                        self.token(",");
+                    }
+                    self.suffixedexpr(expr);
+                }
+                self.token("=");
+                self.exprlist(&stat.right, stat.left.len() > stat.right.len());
+            }
+            Stat::CallStat(stat) => self.suffixedexpr(&stat.call),
+            Stat::Empty(_) | Stat::Error(_) => (),
+        }
+    }
+
+    // a method body gets the implicit `self` parameter
+    fn funcbody(&mut self, body: &FuncBody, method: bool) {
+        let scope = self.locals.len();
+        if method {
+            self.locals.push(Local {
+                name: "self".to_string(),
+                short: "self".to_string(),
+            });
+        }
+        self.token("(");
+        for (n, param) in body.params.iter().enumerate() {
+            if n > 0 {
+                self.token(",");
+            }
+            match param {
+                Param::VarArg(_) => self.token("..."),
+                Param::Name(name) => {
+                    let short = self.declare(&name.name);
+                    self.token(&short);
+                }
+            }
+        }
+        self.token(")");
+        self.block(&body.block);
+        self.locals.truncate(scope);
+        self.token("end");
+    }
+
+    // only the last expression of a list may give several values, `multi` if they are used
+    fn exprlist(&mut self, exprs: &[Expr], multi: bool) {
+        for (n, expr) in exprs.iter().enumerate() {
+            if n > 0 {
+                self.token(",");
+            }
+            self.expr(expr, multi && n + 1 == exprs.len());
+        }
+    }
+
+    // innermost expression of nested parentheses
+    fn strip(expr: &Expr) -> &Expr {
+        match expr {
+            Expr::SuffixedExpr(SuffixedExpr {
+                primary: PrimaryExpr::ParenExpr(inner),
+                suffixes,
+                ..
+            }) if suffixes.is_empty() => Minifier::strip(inner),
+            _ => expr,
+        }
+    }
+
+    // calls and `...` give several values unless parenthesized
+    fn is_multi(expr: &Expr) -> bool {
+        match expr {
+            Expr::VarArg(_) => true,
+            Expr::SuffixedExpr(expr) => matches!(expr.suffixes.last(), Some(Suffix::FuncArgs(_))),
+            _ => false,
+        }
+    }
+
+    // `multi` if the expression may expand to several values where it stands
+    fn expr(&mut self, expr: &Expr, multi: bool) {
+        let inner = Minifier::strip(expr);
+        if multi && !std::ptr::eq(inner, expr) && Minifier::is_multi(inner) {
+            self.token("(");
+            self.expr(inner, false);
+            self.token(")");
+            return;
+        }
+        match inner {
+            Expr::Nil(_) => self.token("nil"),
+            Expr::True(_) => self.token("true"),
+            Expr::False(_) => self.token("false"),
+            Expr::VarArg(_) => self.token("..."),
+            Expr::Float(f, _) => self.float(*f),
+            Expr::Int(i, _) => self.int(*i),
+            Expr::UInt(i, _) => self.uint(*i),
+            Expr::String(s, _) => self.string(s),
+            Expr::FuncBody(body) => {
+                self.token("function");
+                self.funcbody(body, false);
+            }
+            Expr::Table(table) => self.table(table),
+            Expr::BinExpr(expr) => self.binexpr(expr),
+            Expr::UnExpr(expr) => self.unexpr(expr),
+            Expr::SuffixedExpr(expr) => self.suffixedexpr(expr),
+        }
+    }
+
+    fn paren_expr(&mut self, paren: bool, expr: &Expr) {
+        if paren {
+            self.token("(");
+        }
+        self.expr(expr, false);
+        if paren {
+            self.token(")");
+        }
+    }
+
+    // same rules as the printer, on operands stripped of their parentheses
+    fn binexpr(&mut self, expr: &BinExpr) {
+        let priority = expr.op.priority();
+        let left = Minifier::strip(&expr.left);
+        let right = Minifier::strip(&expr.right);
+        let left_paren = match left {
+            Expr::BinExpr(left) => priority.left > left.op.priority().right,
+            Expr::UnExpr(left) => priority.left > left.op.priority(),
+            _ => false,
+        };
+        let right_paren = match right {
+            Expr::BinExpr(right) => right.op.priority().left <= priority.right,
+            _ => false,
+        };
+        self.paren_expr(left_paren, left);
+        self.token(expr.op.as_str());
+        self.paren_expr(right_paren, right);
+    }
+
+    fn unexpr(&mut self, expr: &UnExpr) {
+        self.token(expr.op.as_str());
+        let operand = Minifier::strip(&expr.expr);
+        let paren = match operand {
+            Expr::BinExpr(operand) => operand.op.priority().left <= expr.op.priority(),
+            _ => false,
+        };
+        self.paren_expr(paren, operand);
+    }
+
+    // `(a.b).c` is written `a.b.c`, indexing and calling only use the first value anyway
+    fn suffixedexpr(&mut self, expr: &SuffixedExpr) {
+        let mut chain = vec![&expr.suffixes];
+        let mut primary = &expr.primary;
+        while let PrimaryExpr::ParenExpr(inner) = primary {
+            match Minifier::strip(inner) {
+                Expr::SuffixedExpr(inner) => {
+                    chain.push(&inner.suffixes);
+                    primary = &inner.primary;
+                }
+                _ => break,
+            }
+        }
+        match primary {
+            PrimaryExpr::Name(name) => {
+                let short = self.resolve(&name.name);
+                self.token(&short);
+            }
+            PrimaryExpr::ParenExpr(expr) => self.paren_expr(true, expr),
+        }
+        for suffix in chain.into_iter().rev().flatten() {
+            match suffix {
+                Suffix::Attr(name) => {
+                    self.token(".");
+                    self.token(&name.name);
+                }
+                Suffix::Method(name) => {
+                    self.token(":");
+                    self.token(&name.name);
+                }
+                Suffix::Index(index) => match Minifier::as_name(index) {
+                    Some(name) => {
+                        self.token(".");
+                        self.token(name);
+                    }
+                    None => {
+                        self.token("[");
+                        self.expr(index, false);
+                        self.token("]");
+                    }
+                },
+                Suffix::FuncArgs(args) => self.funcargs(args),
+            }
+        }
+    }
+
+    // `f("s")` and `f({})` lose their parentheses
+    fn funcargs(&mut self, args: &FuncArgs) {
+        match args {
+            FuncArgs::Exprs(exprs, _) if exprs.len() == 1 => match Minifier::strip(&exprs[0]) {
+                Expr::String(s, _) => self.string(s),
+                Expr::Table(table) => self.table(table),
+                _ => {
+                    self.token("(");
+                    self.expr(&exprs[0], true);
+                    self.token(")");
+                }
+            },
+            FuncArgs::Exprs(exprs, _) => {
+                self.token("(");
+                self.exprlist(exprs, true);
+                self.token(")");
+            }
+            FuncArgs::Table(table) => self.table(table),
+            FuncArgs::String(s, _) => self.string(s),
+        }
+    }
+
+    // string key usable as a name: `t["a"]` is `t.a`
+    fn as_name(expr: &Expr) -> Option<&str> {
+        match Minifier::strip(expr) {
+            Expr::String(s, _) => {
+                let mut chars = s.chars();
+                let first = chars.next()?;
+                let name = (first.is_ascii_alphabetic() || first == '_')
+                    && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+                    && !KEYWORDS.contains(&s.as_str());
+                if name {
+                    Some(s)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn table(&mut self, table: &Table) {
+        self.token("{");
+        for (n, field) in table.fields.iter().enumerate() {
+            if n > 0 {
+                self.token(",");
+            }
+            match field {
+                Field::ListField(expr) => self.expr(expr, n + 1 == table.fields.len()),
+                Field::RecFileld(field) => {
+                    match &field.key {
+                        FieldKey::Name(name) => self.token(&name.name),
+                        FieldKey::Expr(key) => match Minifier::as_name(key) {
+                            Some(name) => self.token(name),
+                            None => {
+                                self.token("[");
+                                self.expr(key, false);
+                                self.token("]");
+                            }
+                        },
+                    }
+                    self.token("=");
+                    self.expr(&field.value, false);
+                }
+            }
+        }
+        self.token("}");
+    }
+
+    fn shortest(candidates: Vec<String>) -> String {
+        candidates
+            .into_iter()
+            .reduce(|a, b| if b.len() < a.len() { b } else { a })
+            .unwrap()
+    }
+
+    fn int(&mut self, i: IntType) {
+        let hex = format!("0x{:x}", i as u64);
+        let mut text = if i >= 0 && i.to_string().len() <= hex.len() {
+            i.to_string()
+        } else {
+            hex
+        };
+        if self.version.has_int64_suffix() {
+            text.push_str("LL");
+        }
+        self.numeral(&text);
+    }
+
+    // a `ULL` numeral keeps its suffix, without it the value would be an `int64_t`
+    fn uint(&mut self, i: IntType) {
+        let (dec, hex) = ((i as u64).to_string(), format!("0x{:x}", i as u64));
+        let text = Minifier::shortest(vec![dec, hex]);
+        self.numeral(&format!("{}ULL", text));
+    }
+
+    // shortest digits from `{:e}`, written with an exponent or a dot. with integers the
+    // dot or exponent must stay so the numeral remains a float.
+    fn float(&mut self, f: FloatType) {
+        if f.is_nan() {
+            self.token("(");
+            self.numeral("0");
+            self.token("/");
+            self.numeral("0");
+            self.token(")");
+            return;
+        }
+        if f.is_sign_negative() {
+            self.token("(");
+            self.token("-");
+            self.float(-f);
+            self.token(")");
+            return;
+        }
+        if f.is_infinite() {
+            self.numeral("1e999");
+            return;
+        }
+        let sci = format!("{:e}", f);
+        let (mantissa, exp) = sci.split_once('e').unwrap();
+        let exp: i32 = exp.parse().unwrap();
+        let digits = mantissa.replace('.', "");
+        let n = digits.len() as i32;
+        let mut candidates = Vec::new();
+        if exp >= n - 1 {
+            let zeros = "0".repeat((exp - (n - 1)) as usize);
+            if self.version.has_integer() {
+                candidates.push(format!("{}{}.", digits, zeros));
+            } else {
+                candidates.push(format!("{}{}", digits, zeros));
+            }
+        } else if exp >= 0 {
+            let (int, frac) = digits.split_at(exp as usize + 1);
+            candidates.push(format!("{}.{}", int, frac));
+        } else {
+            candidates.push(format!(".{}{}", "0".repeat((-exp - 1) as usize), digits));
+        }
+        candidates.push(format!("{}e{}", digits, exp - (n - 1)));
+        self.numeral(&Minifier::shortest(candidates));
+    }
+
+    fn string(&mut self, s: &str) {
+        let mut candidates = vec![Minifier::quoted(s, '"'), Minifier::quoted(s, '\'')];
+        candidates.extend(Minifier::long_string(s));
+        self.token(&Minifier::shortest(candidates));
+    }
+
+    fn quoted(s: &str, quote: char) -> String {
+        let chars: Vec<char> = s.chars().collect();
+        let mut output = String::new();
+        output.push(quote);
+        for (n, c) in chars.iter().enumerate() {
+            match *c {
+                '\\' => output.push_str("\\\\"),
+                '\n' => output.push_str("\\n"),
+                '\r' => output.push_str("\\r"),
+                '\t' => output.push_str("\\t"),
+                '\x07' => output.push_str("\\a"),
+                '\x08' => output.push_str("\\b"),
+                '\x0B' => output.push_str("\\v"),
+                '\x0C' => output.push_str("\\f"),
+                c if c == quote => {
+                    output.push('\\');
+                    output.push(c);
+                }
+                // a following digit would extend a short decimal escape
+                c if c.is_ascii_control() => {
+                    if chars.get(n + 1).is_some_and(|c| c.is_ascii_digit()) {
+                        output.push_str(&format!("\\{:03}", c as u8));
+                    } else {
+                        output.push_str(&format!("\\{}", c as u8));
+                    }
+                }
+                c => output.push(c),
+            }
+        }
+        output.push(quote);
+        output
+    }
+
+    // long strings turn `\r` into line breaks and the first line break is skipped,
+    // other control chars are kept out of the output.
+    fn long_string(s: &str) -> Option<String> {
+        if s.contains(|c: char| c.is_ascii_control() && c != '\n' && c != '\t') {
+            return None;
+        }
+        let level = (0..)
+            .map(|level| format!("]{}]", "=".repeat(level)))
+            .position(|close| (s.to_string() + &close).find(&close) == Some(s.len()))
+            .unwrap();
+        let sep = "=".repeat(level);
+        let newline = if s.starts_with('\n') { "\n" } else { "" };
+        Some(format!("[{}[{}{}]{}]", sep, newline, s, sep))
+    }
+}
@@ -1,84 +1,262 @@
 use crate::{debuggable, error};
+use std::fmt;
+use std::mem;
 
 use crate::ast::*;
-use crate::token::{Token, TokenType, TokenValue};
+use crate::cst::{self, NodeKind, NodeRange, SyntaxNode};
+use crate::token::{Span, Token, TokenType, TokenValue};
+use crate::types::LuaVersion;
 
 pub struct Parser {
     tokens: Vec<Token>,
     current: usize,
+    version: LuaVersion,
     debug: bool,
+    // node ranges recorded while building a syntax tree
+    nodes: Option<Vec<NodeRange>>,
+    // errors collected while recovering
+    errors: Option<Vec<SyntaxError>>,
 }
 
-#[derive(Debug)]
-pub struct SyntaxError(String);
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyntaxErrorKind {
+    Expected(TokenType),
+    ExpectedOneOf(Vec<TokenType>),
+    // closing token expected for the opening token at the given line
+    Unmatched {
+        expected: TokenType,
+        opening: TokenType,
+        line: usize,
+    },
+    UnexpectedSymbol,
+    FuncArgsExpected,
+    MultipleToBeClosed,
+    UnknownAttribute(String),
+    Unavailable(&'static str, LuaVersion),
+}
+
+impl fmt::Display for SyntaxErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SyntaxErrorKind::Expected(t) => write!(f, "{:?} expected", t),
+            SyntaxErrorKind::ExpectedOneOf(types) => {
+                let names: Vec<String> = types.iter().map(|t| format!("{:?}", t)).collect();
+                write!(f, "{} expected", names.join(" or "))
+            }
+            SyntaxErrorKind::Unmatched {
+                expected,
+                opening,
+                line,
+            } => write!(
+                f,
+                "{:?} expected (to close {:?} at line {})",
+                expected, opening, line
+            ),
+            SyntaxErrorKind::UnexpectedSymbol => write!(f, "unexpected symbol"),
+            SyntaxErrorKind::FuncArgsExpected => write!(f, "function arguments expected"),
+            SyntaxErrorKind::MultipleToBeClosed => {
+                write!(f, "multiple to-be-closed variables in local list")
+            }
+            SyntaxErrorKind::UnknownAttribute(name) => write!(f, "unknown attribute '{}'", name),
+            SyntaxErrorKind::Unavailable(what, version) => {
+                write!(f, "{} is not available in {}", what, version)
+            }
+        }
+    }
+}
+
+// error with the token it was found at
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxError {
+    pub kind: SyntaxErrorKind,
+    pub found: TokenType,
+    pub value: TokenValue,
+    pub line: usize,
+    pub col: usize,
+    pub offset: usize,
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[syntax error] {} at line [{}:{}] near ",
+            self.kind, self.line, self.col
+        )?;
+        match self.value {
+            TokenValue::None => write!(f, "[{:?}]", self.found),
+            _ => write!(f, "[{:?}]", self.value),
+        }
+    }
+}
+
+impl std::error::Error for SyntaxError {}
 
 type ParseResult<T> = Result<T, SyntaxError>;
 
 macro_rules! syntax_error {
-    ($self:ident, $msg:expr) => {{
+    ($self:ident, $kind:expr) => {{
         let token = &$self.tokens[$self.current];
-        let error_msg;
-        match token.value {
-            TokenValue::None => {
-                error_msg = format!(
-                    "[syntax error] {} at line [{}:{}] near [{:?}]",
-                    $msg, token.source.line, token.source.col, token.t
-                )
+        error!(
+            $self,
+            SyntaxError {
+                kind: $kind,
+                found: token.t,
+                value: token.value.clone(),
+                line: token.source.line,
+                col: token.source.col,
+                offset: token.source.pos,
             }
-            _ => {
-                error_msg = format!(
-                    "[syntax error] {} at line [{}:{}] near [{:?}]",
-                    $msg, token.source.line, token.source.col, token.value
-                )
-            }
-        }
-        error!($self, SyntaxError, error_msg)
+        )
     }};
 }
 
 macro_rules! error_expected {
     ($self:ident, $expected:expr) => {
-        syntax_error!($self, &format!("{:?} expected", $expected))?
+        syntax_error!($self, SyntaxErrorKind::Expected($expected))?
     };
 }
 
+impl Default for Parser {
+    fn default() -> Self {
+        Parser::new()
+    }
+}
+
 impl Parser {
     pub fn new() -> Self {
         Parser {
             tokens: Vec::new(),
             current: 0,
+            version: LuaVersion::default(),
             debug: false,
+            nodes: None,
+            errors: None,
         }
     }
 
+    // select the language dialect, default is Lua 5.4.
+    pub fn set_version(&mut self, version: LuaVersion) {
+        self.version = version;
+    }
+
+    pub fn version(&self) -> LuaVersion {
+        self.version
+    }
+
     pub fn run(&mut self, tokens: Vec<Token>) -> ParseResult<Block> {
         self.reset();
         self.tokens = tokens;
-        self.block()
+        self.nodes = None;
+        self.errors = None;
+        let block = self.block()?;
+        self.check(TokenType::Eos)?;
+        Ok(block)
+    }
+
+    // parse and also build a lossless syntax tree, `tokens` must be lexed from `input`
+    // with trivia kept for the tree to reproduce the source exactly.
+    pub fn run_with_cst(
+        &mut self,
+        tokens: Vec<Token>,
+        input: &str,
+    ) -> ParseResult<(Block, SyntaxNode)> {
+        self.reset();
+        self.tokens = tokens;
+        self.nodes = Some(Vec::new());
+        self.errors = None;
+        let result = self.block().and_then(|block| {
+            self.check(TokenType::Eos)?;
+            Ok(block)
+        });
+        let nodes = self.nodes.take().unwrap_or_default();
+        let block = result?;
+        let tokens = mem::take(&mut self.tokens);
+        Ok((block, cst::build(input, tokens, nodes)))
+    }
+
+    // parse without stopping at the first error, a statement that fails to parse is skipped up to
+    // the next statement and kept as `Stat::Error`. returns the partial block and all errors.
+    pub fn run_recovering(&mut self, tokens: Vec<Token>) -> (Block, Vec<SyntaxError>) {
+        self.reset();
+        self.tokens = tokens;
+        self.nodes = None;
+        self.errors = Some(Vec::new());
+        let start = self.current;
+        let mut stats: Vec<Stat> = Vec::new();
+        loop {
+            // statement errors are recovered inside block
+            if let Ok(mut block) = self.block() {
+                stats.append(&mut block.stats);
+            }
+            match self.check(TokenType::Eos) {
+                Ok(()) => break,
+                Err(error) => {
+                    // unmatched block end at top level
+                    let error_start = self.current;
+                    self.add_error(error);
+                    self.next();
+                    stats.push(Stat::Error(self.span_from(error_start)));
+                }
+            }
+        }
+        let errors = self.errors.take().unwrap_or_default();
+        let block = Block {
+            stats,
+            span: self.span_from(start),
+        };
+        (block, errors)
     }
 
     // block -> { stat [';'] }
     fn block(&mut self) -> ParseResult<Block> {
+        let start = self.current;
         let mut stats: Vec<Stat> = Vec::new();
         while !self.is_block_end() {
             match self.current_token_type() {
                 TokenType::Return => {
-                    stats.push(self.stat()?);
+                    stats.push(self.recoverable_stat()?);
                     break;
                 }
-                _ => stats.push(self.stat()?),
+                _ => {
+                    stats.push(self.recoverable_stat()?);
+                    // without empty statements, ';' is an optional terminator of the previous one
+                    if !self.version.has_empty_stat() {
+                        self.test_next(TokenType::Semi);
+                    }
+                }
             }
         }
-        Ok(Block { stats })
+        Ok(Block {
+            stats,
+            span: self.node_span(NodeKind::Block, start),
+        })
+    }
+
+    // stat, or an error stat when recovering
+    fn recoverable_stat(&mut self) -> ParseResult<Stat> {
+        let start = self.current;
+        match self.stat() {
+            Err(error) if self.errors.is_some() => {
+                self.add_error(error);
+                self.synchronize(start);
+                Ok(Stat::Error(self.span_from(start)))
+            }
+            result => result,
+        }
     }
 
     fn stat(&mut self) -> ParseResult<Stat> {
+        let start = self.current;
         let line = self.current_line();
         match self.current_token_type() {
             // stat -> ';' (empty stat)
             TokenType::Semi => {
+                if !self.version.has_empty_stat() {
+                    syntax_error!(self, self.unavailable("empty statement"))?;
+                }
                 self.next();
-                Ok(Stat::Empty)
+                Ok(Stat::Empty(self.node_span(NodeKind::EmptyStat, start)))
             }
             // stat -> if stat
             TokenType::If => Ok(Stat::IfStat(self.ifstat()?)),
@@ -89,7 +267,10 @@ impl Parser {
                 self.next();
                 let block = self.block()?;
                 self.check_match(TokenType::End, TokenType::Do, line)?;
-                Ok(Stat::DoBlock(DoBlock { block }))
+                Ok(Stat::DoBlock(DoBlock {
+                    block,
+                    span: self.node_span(NodeKind::DoBlock, start),
+                }))
             }
             // stat -> forstat
             TokenType::For => Ok(Stat::ForStat(self.forstat()?)),
@@ -99,8 +280,7 @@ impl Parser {
             TokenType::Function => Ok(Stat::FuncStat(self.funcstat()?)),
             // stat -> localstat
             TokenType::Local => {
-                self.next();
-                if self.test(TokenType::Function) {
+                if self.next_token_type() == TokenType::Function {
                     Ok(Stat::FuncStat(self.localfunc()?))
                 } else {
                     Ok(Stat::LocalStat(self.localstat()?))
@@ -108,18 +288,22 @@ impl Parser {
             }
             // stat -> label
             TokenType::DbColon => {
-                self.next();
+                if !self.version.has_goto() {
+                    syntax_error!(self, self.unavailable("label"))?;
+                }
                 Ok(Stat::LabelStat(self.labelstat()?))
             }
             // stat -> retstat
-            TokenType::Return => {
-                self.next();
-                Ok(Stat::RetStat(self.retstat()?))
-            }
+            TokenType::Return => Ok(Stat::RetStat(self.retstat()?)),
             // stat -> breakstat
             TokenType::Break => Ok(Stat::BreakStat(self.breakstat()?)),
             // stat -> gotostat
-            TokenType::Goto => Ok(Stat::GotoStat(self.gotostat()?)),
+            TokenType::Goto => {
+                if !self.version.has_goto() {
+                    syntax_error!(self, self.unavailable("goto"))?;
+                }
+                Ok(Stat::GotoStat(self.gotostat()?))
+            }
             // stat -> func | assignment
             _ => Ok(self.exprstat()?),
         }
@@ -127,41 +311,57 @@ impl Parser {
 
     // ifstat -> IF cond THEN block {ELSEIF cond THEN block} [ELSE block] END
     fn ifstat(&mut self) -> ParseResult<IfStat> {
+        let start = self.current;
         let line = self.current_line();
         let mut cond_blocks: Vec<CondBlock> = Vec::new();
         cond_blocks.push(self.test_then_block()?);
         while self.current_token_type() == TokenType::ElseIf {
             cond_blocks.push(self.test_then_block()?);
         }
-        let mut else_block = Block::empty();
-        if self.test_next(TokenType::Else) {
-            else_block = self.block()?;
-        }
+        let else_block = if self.test_next(TokenType::Else) {
+            self.block()?
+        } else {
+            Block {
+                stats: Vec::new(),
+                span: self.span_from(self.current),
+            }
+        };
         self.check_match(TokenType::End, TokenType::If, line)?;
         Ok(IfStat {
             cond_blocks,
             else_block,
+            span: self.node_span(NodeKind::IfStat, start),
         })
     }
 
     //  [IF | ELSEIF] cond THEN block
     fn test_then_block(&mut self) -> ParseResult<CondBlock> {
+        let start = self.current;
         self.next();
         let cond = self.cond()?;
         self.check_next(TokenType::Then)?;
         let block = self.block()?;
-        Ok(CondBlock { cond, block })
+        Ok(CondBlock {
+            cond,
+            block,
+            span: self.node_span(NodeKind::CondBlock, start),
+        })
     }
 
     // whilestat -> WHILE cond DO block END
     fn whilestat(&mut self) -> ParseResult<WhileStat> {
+        let start = self.current;
         let line = self.current_line();
         self.next();
         let cond = self.cond()?;
         self.check_next(TokenType::Do)?;
         let block = self.block()?;
         self.check_match(TokenType::End, TokenType::While, line)?;
-        Ok(WhileStat { cond, block })
+        Ok(WhileStat {
+            cond,
+            block,
+            span: self.node_span(NodeKind::WhileStat, start),
+        })
     }
 
     fn cond(&mut self) -> ParseResult<Expr> {
@@ -170,25 +370,33 @@ impl Parser {
 
     // forstat -> FOR (fornum | forlist) END
     fn forstat(&mut self) -> ParseResult<ForStat> {
+        let start = self.current;
         let line = self.current_line();
         self.next();
         let var_name = self.check_name()?;
-        let forstat = match self.current_token_type() {
-            TokenType::Assign => self.forenum(&var_name),
-            TokenType::Comma | TokenType::In => self.forlist(&var_name),
-            _ => syntax_error!(self, "'=' or 'in' expected"),
+        let mut forstat = match self.current_token_type() {
+            TokenType::Assign => self.forenum(var_name)?,
+            TokenType::Comma | TokenType::In => self.forlist(var_name)?,
+            _ => syntax_error!(
+                self,
+                SyntaxErrorKind::ExpectedOneOf(vec![TokenType::Assign, TokenType::In])
+            )?,
         };
-        match forstat {
-            Ok(stat) => {
-                self.check_match(TokenType::End, TokenType::For, line)?;
-                Ok(stat)
-            }
-            Err(err) => Err(err),
-        }
+        self.check_match(TokenType::End, TokenType::For, line)?;
+        let kind = match &forstat {
+            ForStat::ForNum(_) => NodeKind::ForNum,
+            ForStat::ForList(_) => NodeKind::ForList,
+        };
+        let span = self.node_span(kind, start);
+        match &mut forstat {
+            ForStat::ForNum(fornum) => fornum.span = span,
+            ForStat::ForList(forlist) => forlist.span = span,
+        };
+        Ok(forstat)
     }
 
     // fornum -> NAME = exp1,exp1[,exp1] forbody
-    fn forenum(&mut self, var_name: &str) -> ParseResult<ForStat> {
+    fn forenum(&mut self, var: Name) -> ParseResult<ForStat> {
         self.next();
         let init = self.expr()?;
         self.check_next(TokenType::Comma)?;
@@ -200,18 +408,18 @@ impl Parser {
         self.check_next(TokenType::Do)?;
         let body = self.block()?;
         Ok(ForStat::ForNum(ForNum {
-            var: String::from(var_name),
+            var,
             init,
             limit,
             step,
             body,
+            span: Span::default(),
         }))
     }
 
     // forlist -> NAME {,NAME} IN explist forbody
-    fn forlist(&mut self, var_name: &str) -> ParseResult<ForStat> {
-        let mut vars: Vec<String> = Vec::new();
-        vars.push(String::from(var_name));
+    fn forlist(&mut self, var: Name) -> ParseResult<ForStat> {
+        let mut vars: Vec<Name> = vec![var];
         while self.test_next(TokenType::Comma) {
             vars.push(self.check_name()?);
         }
@@ -219,34 +427,47 @@ impl Parser {
         let exprs = self.exprlist()?;
         self.check_next(TokenType::Do)?;
         let body = self.block()?;
-        Ok(ForStat::ForList(ForList { vars, exprs, body }))
+        Ok(ForStat::ForList(ForList {
+            vars,
+            exprs,
+            body,
+            span: Span::default(),
+        }))
     }
 
     // repeatstat -> REPEAT block UNTIL cond
     fn repeatstat(&mut self) -> ParseResult<RepeatStat> {
+        let start = self.current;
         let line = self.current_line();
         self.next();
         let block = self.block()?;
         self.check_match(TokenType::Until, TokenType::Repeat, line)?;
         let cond = self.cond()?;
-        Ok(RepeatStat { block, cond })
+        Ok(RepeatStat {
+            block,
+            cond,
+            span: self.node_span(NodeKind::RepeatStat, start),
+        })
     }
 
     // funcstat -> FUNCTION funcname body
     fn funcstat(&mut self) -> ParseResult<FuncStat> {
+        let start = self.current;
         self.next();
         let func_name = self.funcname()?;
-        let body = self.funcbody()?;
+        let body = self.funcbody(start)?;
         Ok(FuncStat {
             func_type: FuncType::Global,
             func_name,
             body,
+            span: self.node_span(NodeKind::FuncStat, start),
         })
     }
 
     // funcname -> NAME {'.' NAME} [':' NAME]
     fn funcname(&mut self) -> ParseResult<FuncName> {
-        let mut fields: Vec<String> = Vec::new();
+        let start = self.current;
+        let mut fields: Vec<Name> = Vec::new();
         fields.push(self.check_name()?);
         while self.test_next(TokenType::Attr) {
             fields.push(self.check_name()?);
@@ -255,11 +476,16 @@ impl Parser {
         if self.test_next(TokenType::Colon) {
             method = Some(self.check_name()?);
         }
-        Ok(FuncName { fields, method })
+        Ok(FuncName {
+            fields,
+            method,
+            span: self.node_span(NodeKind::FuncName, start),
+        })
     }
 
     // body ->  '(' parlist ')' block END
-    fn funcbody(&mut self) -> ParseResult<FuncBody> {
+    // the body span starts at the `function` keyword at `start`
+    fn funcbody(&mut self, start: usize) -> ParseResult<FuncBody> {
         let line = self.current_line();
         self.check_next(TokenType::Lp)?;
         let mut params: Vec<Param> = Vec::new();
@@ -269,11 +495,15 @@ impl Parser {
             }
             match self.current_token_type() {
                 TokenType::Dots => {
-                    params.push(Param::VarArg);
-                    self.next()
+                    let start = self.current;
+                    self.next();
+                    params.push(Param::VarArg(self.span_from(start)))
                 }
                 TokenType::Name => params.push(Param::Name(self.check_name()?)),
-                _ => syntax_error!(self, "<name> or '...' expected")?,
+                _ => syntax_error!(
+                    self,
+                    SyntaxErrorKind::ExpectedOneOf(vec![TokenType::Name, TokenType::Dots])
+                )?,
             };
             if !self.test_next(TokenType::Comma) {
                 break;
@@ -282,26 +512,51 @@ impl Parser {
         self.check_next(TokenType::Rp)?;
         let block = self.block()?;
         self.check_match(TokenType::End, TokenType::Function, line)?;
-        Ok(FuncBody { params, block })
+        Ok(FuncBody {
+            params,
+            block,
+            span: self.node_span(NodeKind::FuncBody, start),
+        })
     }
 
     // funcstat -> local FUNCTION funcname body
     fn localfunc(&mut self) -> ParseResult<FuncStat> {
+        let start = self.current;
+        self.next();
+        let body_start = self.current;
         self.next();
         let func_name = self.funcname()?;
-        let body = self.funcbody()?;
+        let body = self.funcbody(body_start)?;
         Ok(FuncStat {
             func_type: FuncType::Local,
             func_name,
             body,
+            span: self.node_span(NodeKind::FuncStat, start),
         })
     }
 
-    // stat -> LOCAL NAME {',' NAME} ['=' explist]
+    // stat -> LOCAL attnamelist ['=' explist]
+    // attnamelist -> NAME attrib {',' NAME attrib}
     fn localstat(&mut self) -> ParseResult<LocalStat> {
-        let mut names: Vec<String> = Vec::new();
+        let start = self.current;
+        self.next();
+        let mut names: Vec<LocalName> = Vec::new();
+        let mut has_close = false;
         loop {
-            names.push(self.check_name()?);
+            let name_start = self.current;
+            let name = self.check_name()?;
+            let attrib = self.attrib()?;
+            if attrib == Some(LocalAttrib::Close) {
+                if has_close {
+                    syntax_error!(self, SyntaxErrorKind::MultipleToBeClosed)?;
+                }
+                has_close = true;
+            }
+            names.push(LocalName {
+                name,
+                attrib,
+                span: self.node_span(NodeKind::LocalName, name_start),
+            });
             if !self.test_next(TokenType::Comma) {
                 break;
             }
@@ -310,58 +565,114 @@ impl Parser {
         if self.test_next(TokenType::Assign) {
             exprs = self.exprlist()?;
         }
-        Ok(LocalStat { names, exprs })
+        Ok(LocalStat {
+            names,
+            exprs,
+            span: self.node_span(NodeKind::LocalStat, start),
+        })
+    }
+
+    // attrib -> ['<' NAME '>']
+    fn attrib(&mut self) -> ParseResult<Option<LocalAttrib>> {
+        if !self.test(TokenType::Lt) {
+            return Ok(None);
+        }
+        if !self.version.has_attribs() {
+            syntax_error!(self, self.unavailable("attribute"))?;
+        }
+        self.next();
+        self.check(TokenType::Name)?;
+        let name = self.current_token().get_string();
+        let attrib = match LocalAttrib::from_name(&name) {
+            Some(attrib) => attrib,
+            None => syntax_error!(self, SyntaxErrorKind::UnknownAttribute(name))?,
+        };
+        self.next();
+        self.check_next(TokenType::Gt)?;
+        Ok(Some(attrib))
     }
 
     // label -> '::' NAME '::'
     fn labelstat(&mut self) -> ParseResult<LabelStat> {
+        let start = self.current;
+        self.next();
         let label = self.check_name()?;
         self.check_next(TokenType::DbColon)?;
-        Ok(LabelStat { label })
+        Ok(LabelStat {
+            label,
+            span: self.node_span(NodeKind::LabelStat, start),
+        })
     }
 
     // stat -> RETURN [explist] [';']
     fn retstat(&mut self) -> ParseResult<RetStat> {
+        let start = self.current;
+        self.next();
         let mut exprs: Vec<Expr> = Vec::new();
         if !self.is_block_end() && self.current_token_type() != TokenType::Semi {
             exprs = self.exprlist()?;
         }
         self.test_next(TokenType::Semi);
-        Ok(RetStat { exprs })
+        Ok(RetStat {
+            exprs,
+            span: self.node_span(NodeKind::RetStat, start),
+        })
     }
 
     fn breakstat(&mut self) -> ParseResult<BreakStat> {
+        let start = self.current;
         self.next();
-        Ok(BreakStat {})
+        Ok(BreakStat {
+            span: self.node_span(NodeKind::BreakStat, start),
+        })
     }
 
     fn gotostat(&mut self) -> ParseResult<GotoStat> {
+        let start = self.current;
         self.next();
         let label = self.check_name()?;
-        Ok(GotoStat { label })
+        Ok(GotoStat {
+            label,
+            span: self.node_span(NodeKind::GotoStat, start),
+        })
     }
 
     // stat -> func call | assignment
     fn exprstat(&mut self) -> ParseResult<Stat> {
+        let start = self.current;
         let expr = self.suffixedexpr()?;
+        // `goto` is a plain name before Lua 5.2, catch `goto label` for a clearer message
+        if !self.version.has_goto() && self.test(TokenType::Name) && expr.suffixes.is_empty() {
+            if let PrimaryExpr::Name(name) = &expr.primary {
+                if name.name == "goto" {
+                    syntax_error!(self, self.unavailable("goto"))?;
+                }
+            }
+        }
         if self.test(TokenType::Assign) || self.test(TokenType::Comma) {
-            Ok(Stat::AssignStat(self.assignment(expr)?))
+            Ok(Stat::AssignStat(self.assignment(start, expr)?))
         } else {
-            Ok(Stat::CallStat(CallStat { call: expr }))
+            Ok(Stat::CallStat(CallStat {
+                call: expr,
+                span: self.node_span(NodeKind::CallStat, start),
+            }))
         }
     }
 
     // assignment -> ',' suffixedexp assignment
     // assignment -> '=' explist
-    fn assignment(&mut self, expr: SuffixedExpr) -> ParseResult<AssignStat> {
-        let mut left: Vec<SuffixedExpr> = Vec::new();
-        left.push(expr);
+    fn assignment(&mut self, start: usize, expr: SuffixedExpr) -> ParseResult<AssignStat> {
+        let mut left: Vec<SuffixedExpr> = vec![expr];
         while self.test_next(TokenType::Comma) {
             left.push(self.suffixedexpr()?)
         }
         self.check_next(TokenType::Assign)?;
         let right = self.exprlist()?;
-        Ok(AssignStat { left, right })
+        Ok(AssignStat {
+            left,
+            right,
+            span: self.node_span(NodeKind::AssignStat, start),
+        })
     }
 
     // exprlist -> expr { ',' expr }
@@ -389,44 +700,70 @@ impl Parser {
     // subexpr -> (simpleexpr | unop subexpr) { binop subexpr }
     // where 'binop' is any binary operator with a priority higher than 'limit'
     fn subexpr(&mut self, limit: u8) -> ParseResult<Expr> {
+        let start = self.current;
         let mut left;
         let unop = self.get_unop();
+        if unop == UnOp::BNot && !self.version.has_bitwise_ops() {
+            syntax_error!(self, self.unavailable("bitwise operator"))?;
+        }
         if unop != UnOp::None {
             self.next();
             let expr = Box::new(self.subexpr(unop.priority())?);
-            left = Expr::UnExpr(UnExpr { op: unop, expr });
+            left = Expr::UnExpr(UnExpr {
+                op: unop,
+                expr,
+                span: self.node_span(NodeKind::UnExpr, start),
+            });
         } else {
             left = self.simpleexpr()?;
         }
         let mut binop = self.get_binop();
         while binop != BinOp::None && binop.priority().left > limit {
+            self.check_binop(binop)?;
             self.next();
             let right = self.subexpr(binop.priority().right)?;
             left = Expr::BinExpr(BinExpr {
                 left: Box::new(left),
                 right: Box::new(right),
                 op: binop,
+                span: self.node_span(NodeKind::BinExpr, start),
             });
             binop = self.get_binop();
         }
         Ok(left)
     }
 
+    fn check_binop(&self, binop: BinOp) -> ParseResult<()> {
+        if !self.version.has_bitwise_ops() {
+            match binop {
+                BinOp::BAnd | BinOp::BOr | BinOp::BXor | BinOp::Shl | BinOp::Shr => {
+                    syntax_error!(self, self.unavailable("bitwise operator"))?
+                }
+                BinOp::IDiv => syntax_error!(self, self.unavailable("integer division"))?,
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
     // simpleexpr -> FLT | INT | STRING | NIL | TRUE | FALSE | ... | constructor | FUNCTION body | suffixedexp
     fn simpleexpr(&mut self) -> ParseResult<Expr> {
+        let start = self.current;
         let token = self.current_token();
+        let span = Span::from(&token.source);
         let expr = match token.t {
-            TokenType::Flt => Expr::Float(token.get_float()),
-            TokenType::Int => Expr::Int(token.get_int()),
-            TokenType::String => Expr::String(token.get_string()),
-            TokenType::Nil => Expr::Nil,
-            TokenType::True => Expr::True,
-            TokenType::False => Expr::False,
-            TokenType::Dots => Expr::VarArg,
+            TokenType::Flt => Expr::Float(token.get_float(), span),
+            TokenType::Int => Expr::Int(token.get_int(), span),
+            TokenType::UInt => Expr::UInt(token.get_int(), span),
+            TokenType::String => Expr::String(token.get_string(), span),
+            TokenType::Nil => Expr::Nil(span),
+            TokenType::True => Expr::True(span),
+            TokenType::False => Expr::False(span),
+            TokenType::Dots => Expr::VarArg(span),
             TokenType::Lb => return Ok(Expr::Table(self.table()?)),
             TokenType::Function => {
                 self.next();
-                return Ok(Expr::FuncBody(self.funcbody()?));
+                return Ok(Expr::FuncBody(self.funcbody(start)?));
             }
             _ => return Ok(Expr::SuffixedExpr(self.suffixedexpr()?)),
         };
@@ -436,6 +773,7 @@ impl Parser {
 
     // suffixedexpr -> primaryexpr { '.' NAME | '[' exp ']' | ':' NAME funcargs | funcargs }
     fn suffixedexpr(&mut self) -> ParseResult<SuffixedExpr> {
+        let start = self.current;
         let primary = self.primaryexpr()?;
         let mut suffixes: Vec<Suffix> = Vec::new();
         loop {
@@ -445,10 +783,12 @@ impl Parser {
                     suffixes.push(Suffix::Attr(self.check_name()?));
                 }
                 TokenType::Ls => {
+                    let index_start = self.current;
                     let line = self.current_line();
                     self.next();
                     suffixes.push(Suffix::Index(self.expr()?));
                     self.check_match(TokenType::Rs, TokenType::Ls, line)?;
+                    self.mark_node(NodeKind::Index, index_start);
                 }
                 TokenType::Colon => {
                     self.next();
@@ -462,7 +802,11 @@ impl Parser {
             }
         }
 
-        Ok(SuffixedExpr { primary, suffixes })
+        Ok(SuffixedExpr {
+            primary,
+            suffixes,
+            span: self.node_span(NodeKind::SuffixedExpr, start),
+        })
     }
 
     // primaryexp -> NAME | '(' expr ')'
@@ -470,18 +814,15 @@ impl Parser {
         let expr = match self.current_token_type() {
             TokenType::Name => PrimaryExpr::Name(self.check_name()?),
             TokenType::Lp => {
+                let start = self.current;
                 let line = self.current_line();
                 self.next();
                 let expr = self.expr()?;
                 self.check_match(TokenType::Rp, TokenType::Lp, line)?;
+                self.mark_node(NodeKind::ParenExpr, start);
                 PrimaryExpr::ParenExpr(Box::new(expr))
             }
-            _ => {
-                return syntax_error!(
-                    self,
-                    &format!("unexpected symbol '{:?}'", self.current_token_type())
-                )
-            }
+            _ => return syntax_error!(self, SyntaxErrorKind::UnexpectedSymbol),
         };
         Ok(expr)
     }
@@ -489,6 +830,7 @@ impl Parser {
     // table constructor -> '{' [ field { sep field } [sep] ] '}'
     // sep -> ',' | ';'
     fn table(&mut self) -> ParseResult<Table> {
+        let start = self.current;
         let line = self.current_line();
         self.check_next(TokenType::Lb)?;
         let mut fields: Vec<Field> = Vec::new();
@@ -502,7 +844,10 @@ impl Parser {
             }
         }
         self.check_match(TokenType::Rb, TokenType::Lb, line)?;
-        Ok(Table { fields })
+        Ok(Table {
+            fields,
+            span: self.node_span(NodeKind::Table, start),
+        })
     }
 
     // field -> listfield | recfield
@@ -523,6 +868,7 @@ impl Parser {
 
     // recfield -> (NAME | '['exp1']') = exp1
     fn recfield(&mut self) -> ParseResult<Field> {
+        let start = self.current;
         let key;
         match self.current_token_type() {
             TokenType::Name => key = FieldKey::Name(self.check_name()?),
@@ -536,7 +882,11 @@ impl Parser {
         };
         self.check_next(TokenType::Assign)?;
         let value = self.expr()?;
-        Ok(Field::RecFileld(RecField { key, value }))
+        Ok(Field::RecFileld(RecField {
+            key,
+            value,
+            span: self.node_span(NodeKind::RecField, start),
+        }))
     }
 
     // listfield -> expr
@@ -546,6 +896,7 @@ impl Parser {
 
     // funcargs -> '(' [ explist ] ') | table constructor | STRING
     fn funcargs(&mut self) -> ParseResult<FuncArgs> {
+        let start = self.current;
         let func_args = match self.current_token_type() {
             TokenType::Lp => {
                 let line = self.current_line();
@@ -553,20 +904,23 @@ impl Parser {
 
                 // empty arg list
                 if self.test_next(TokenType::Rp) {
-                    return Ok(FuncArgs::Exprs(Vec::<Expr>::new()));
+                    return Ok(FuncArgs::Exprs(
+                        Vec::<Expr>::new(),
+                        self.node_span(NodeKind::FuncArgs, start),
+                    ));
                 }
 
                 let exprs = self.exprlist()?;
                 self.check_match(TokenType::Rp, TokenType::Lp, line)?;
-                FuncArgs::Exprs(exprs)
+                FuncArgs::Exprs(exprs, self.node_span(NodeKind::FuncArgs, start))
             }
             TokenType::Lb => FuncArgs::Table(self.table()?),
             TokenType::String => {
-                let arg = FuncArgs::String(self.current_token().get_string());
+                let string = self.current_token().get_string();
                 self.next();
-                arg
+                FuncArgs::String(string, self.node_span(NodeKind::FuncArgs, start))
             }
-            _ => return syntax_error!(self, "function arguments expected"),
+            _ => return syntax_error!(self, SyntaxErrorKind::FuncArgsExpected),
         };
         Ok(func_args)
     }
@@ -605,13 +959,54 @@ impl Parser {
     // if reach a block end
     fn is_block_end(&self) -> bool {
         let token = self.current_token();
-        match token.t {
+        matches!(
+            token.t,
             TokenType::Else
-            | TokenType::ElseIf
-            | TokenType::End
-            | TokenType::Until
-            | TokenType::Eos => true,
-            _ => false,
+                | TokenType::ElseIf
+                | TokenType::End
+                | TokenType::Until
+                | TokenType::Eos
+        )
+    }
+
+    // if reach a token that starts a statement with a keyword
+    fn is_stat_start(&self) -> bool {
+        matches!(
+            self.current_token_type(),
+            TokenType::Local
+                | TokenType::Function
+                | TokenType::Return
+                | TokenType::If
+                | TokenType::While
+                | TokenType::For
+                | TokenType::Repeat
+                | TokenType::Do
+                | TokenType::Goto
+                | TokenType::Break
+                | TokenType::DbColon
+        )
+    }
+
+    // skip the rest of a broken statement started at `start`,
+    // up to a statement keyword or a name that starts a new line
+    fn synchronize(&mut self, start: usize) {
+        if self.current == start {
+            self.next();
+        }
+        while !self.is_block_end() && !self.is_stat_start() && !self.is_line_start_name() {
+            self.next();
+        }
+    }
+
+    fn is_line_start_name(&self) -> bool {
+        self.test(TokenType::Name)
+            && self.current > 0
+            && self.current_line() > self.tokens[self.current - 1].source.line
+    }
+
+    fn add_error(&mut self, error: SyntaxError) {
+        if let Some(errors) = &mut self.errors {
+            errors.push(error);
         }
     }
 
@@ -622,7 +1017,11 @@ impl Parser {
             } else {
                 syntax_error!(
                     self,
-                    &format!("{:?} expected (to close {:?} at line {})", end, start, line)
+                    SyntaxErrorKind::Unmatched {
+                        expected: end,
+                        opening: start,
+                        line,
+                    }
                 )?;
             }
         }
@@ -657,15 +1056,45 @@ impl Parser {
         Ok(())
     }
 
-    fn check_name(&mut self) -> ParseResult<String> {
+    fn check_name(&mut self) -> ParseResult<Name> {
         self.check(TokenType::Name)?;
         let token = self.current_token();
         let name = match &token.value {
             TokenValue::Str(name) => name.clone(),
             _ => unreachable!(),
         };
+        let span = Span::from(&token.source);
         self.next();
-        Ok(name)
+        Ok(Name { name, span })
+    }
+
+    fn unavailable(&self, what: &'static str) -> SyntaxErrorKind {
+        SyntaxErrorKind::Unavailable(what, self.version)
+    }
+
+    // record a syntax tree node from the token at `start` to the last consumed token
+    fn mark_node(&mut self, kind: NodeKind, start: usize) {
+        let end = self.current;
+        if let Some(nodes) = &mut self.nodes {
+            nodes.push(NodeRange { kind, start, end });
+        }
+    }
+
+    fn node_span(&mut self, kind: NodeKind, start: usize) -> Span {
+        self.mark_node(kind, start);
+        self.span_from(start)
+    }
+
+    // span from the token at `start` to the last consumed token
+    fn span_from(&self, start: usize) -> Span {
+        if self.current > start {
+            Span::new(
+                &self.tokens[start].source,
+                &self.tokens[self.current - 1].source,
+            )
+        } else {
+            Span::at(&self.tokens[start].source)
+        }
     }
 
     debuggable!();
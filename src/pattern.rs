@@ -0,0 +1,469 @@
+use std::fmt;
+
+// pattern matching of Lua's string library, after lstrlib.c
+
+const MAX_CAPTURES: usize = 32;
+// recursion limit of the matcher
+const MAX_DEPTH: usize = 200;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PatternError {
+    EndsWithPercent,
+    MissingBracket,
+    MissingBalanceArgs,
+    MissingFrontierBracket,
+    InvalidCaptureIndex(usize),
+    InvalidPatternCapture,
+    UnfinishedCapture,
+    TooManyCaptures,
+    TooComplex,
+}
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PatternError::EndsWithPercent => write!(f, "malformed pattern (ends with '%')"),
+            PatternError::MissingBracket => write!(f, "malformed pattern (missing ']')"),
+            PatternError::MissingBalanceArgs => write!(f, "missing arguments to '%b'"),
+            PatternError::MissingFrontierBracket => {
+                write!(f, "missing '[' after '%f' in pattern")
+            }
+            PatternError::InvalidCaptureIndex(i) => write!(f, "invalid capture index %{}", i),
+            PatternError::InvalidPatternCapture => write!(f, "invalid pattern capture"),
+            PatternError::UnfinishedCapture => write!(f, "unfinished capture"),
+            PatternError::TooManyCaptures => write!(f, "too many captures"),
+            PatternError::TooComplex => write!(f, "pattern too complex"),
+        }
+    }
+}
+
+impl std::error::Error for PatternError {}
+
+// a captured substring as a byte range, or the position of `()`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Capture {
+    Str(usize, usize),
+    Position(usize),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum CaptureLen {
+    Len(usize),
+    Position,
+    Unclosed,
+}
+
+type MatchResult = Result<Option<usize>, PatternError>;
+
+struct MatchState<'a> {
+    src: &'a [u8],
+    pat: &'a [u8],
+    depth: usize,
+    captures: Vec<(usize, CaptureLen)>,
+}
+
+// match `pat` at `start` of `src` only, giving the end of the match and its captures.
+// a leading `^` is not special here, callers anchor by trying a single position.
+pub fn match_at(
+    src: &[u8],
+    pat: &[u8],
+    start: usize,
+) -> Result<Option<(usize, Vec<Capture>)>, PatternError> {
+    let mut state = MatchState {
+        src,
+        pat,
+        depth: MAX_DEPTH,
+        captures: Vec::new(),
+    };
+    match state.do_match(start, 0)? {
+        Some(end) => {
+            let captures = state
+                .captures
+                .iter()
+                .map(|(start, len)| match len {
+                    CaptureLen::Len(len) => Ok(Capture::Str(*start, start + len)),
+                    CaptureLen::Position => Ok(Capture::Position(*start)),
+                    CaptureLen::Unclosed => Err(PatternError::UnfinishedCapture),
+                })
+                .collect::<Result<Vec<Capture>, PatternError>>()?;
+            Ok(Some((end, captures)))
+        }
+        None => Ok(None),
+    }
+}
+
+// first match of `pat` in `src` at or after `init`: start, end and captures
+pub fn find(
+    src: &[u8],
+    pat: &[u8],
+    init: usize,
+) -> Result<Option<(usize, usize, Vec<Capture>)>, PatternError> {
+    let (anchor, pat) = match pat.first() {
+        Some(b'^') => (true, &pat[1..]),
+        _ => (false, pat),
+    };
+    let mut start = init;
+    while start <= src.len() {
+        if let Some((end, captures)) = match_at(src, pat, start)? {
+            return Ok(Some((start, end, captures)));
+        }
+        if anchor {
+            break;
+        }
+        start += 1;
+    }
+    Ok(None)
+}
+
+// no special characters, `string.find` can search plainly
+pub fn is_plain(pat: &[u8]) -> bool {
+    !pat.iter().any(|c| b"^$*+?.([%-".contains(c))
+}
+
+fn match_class(c: u8, class: u8) -> bool {
+    let matches = match class.to_ascii_lowercase() {
+        b'a' => c.is_ascii_alphabetic(),
+        b'c' => c.is_ascii_control(),
+        b'd' => c.is_ascii_digit(),
+        b'g' => c.is_ascii_graphic(),
+        b'l' => c.is_ascii_lowercase(),
+        b'p' => c.is_ascii_punctuation(),
+        b's' => c.is_ascii_whitespace() || c == 0x0b,
+        b'u' => c.is_ascii_uppercase(),
+        b'w' => c.is_ascii_alphanumeric(),
+        b'x' => c.is_ascii_hexdigit(),
+        b'z' => c == 0,
+        _ => return class == c,
+    };
+    if class.is_ascii_uppercase() {
+        !matches
+    } else {
+        matches
+    }
+}
+
+// index past the single char class at `p`
+fn class_end(pat: &[u8], mut p: usize) -> Result<usize, PatternError> {
+    let c = pat[p];
+    p += 1;
+    match c {
+        b'%' => {
+            if p >= pat.len() {
+                return Err(PatternError::EndsWithPercent);
+            }
+            Ok(p + 1)
+        }
+        b'[' => {
+            if pat.get(p) == Some(&b'^') {
+                p += 1;
+            }
+            // the first char is never the closing bracket, `[]]` is a set of `]`
+            loop {
+                if p >= pat.len() {
+                    return Err(PatternError::MissingBracket);
+                }
+                let c = pat[p];
+                p += 1;
+                if c == b'%' && p < pat.len() {
+                    p += 1;
+                }
+                if pat.get(p) == Some(&b']') {
+                    return Ok(p + 1);
+                }
+            }
+        }
+        _ => Ok(p),
+    }
+}
+
+// the errors matching with `pat` could raise, without a subject to match.
+// Lua only finds most of them when the matcher gets to them.
+pub fn validate(pat: &[u8]) -> Result<(), PatternError> {
+    let mut p = if pat.first() == Some(&b'^') { 1 } else { 0 };
+    // whether each capture is closed, and the open ones
+    let mut captures: Vec<bool> = Vec::new();
+    let mut open: Vec<usize> = Vec::new();
+    while p < pat.len() {
+        match pat[p] {
+            b'(' => {
+                if captures.len() >= MAX_CAPTURES {
+                    return Err(PatternError::TooManyCaptures);
+                }
+                if pat.get(p + 1) == Some(&b')') {
+                    captures.push(true);
+                    p += 2;
+                } else {
+                    open.push(captures.len());
+                    captures.push(false);
+                    p += 1;
+                }
+                continue;
+            }
+            b')' => {
+                let index = open.pop().ok_or(PatternError::InvalidPatternCapture)?;
+                captures[index] = true;
+                p += 1;
+                continue;
+            }
+            b'%' if pat.get(p + 1) == Some(&b'b') => {
+                if p + 3 >= pat.len() {
+                    return Err(PatternError::MissingBalanceArgs);
+                }
+                p += 4;
+                continue;
+            }
+            b'%' if pat.get(p + 1) == Some(&b'f') => {
+                p += 2;
+                if pat.get(p) != Some(&b'[') {
+                    return Err(PatternError::MissingFrontierBracket);
+                }
+                p = class_end(pat, p)?;
+                continue;
+            }
+            b'%' if pat.get(p + 1).is_some_and(u8::is_ascii_digit) => {
+                let index = (pat[p + 1] - b'0') as usize;
+                if captures.get(index.wrapping_sub(1)) != Some(&true) {
+                    return Err(PatternError::InvalidCaptureIndex(index));
+                }
+                p += 2;
+                continue;
+            }
+            _ => (),
+        }
+        p = class_end(pat, p)?;
+        if let Some(b'*') | Some(b'+') | Some(b'-') | Some(b'?') = pat.get(p) {
+            p += 1;
+        }
+    }
+    if open.is_empty() {
+        Ok(())
+    } else {
+        Err(PatternError::UnfinishedCapture)
+    }
+}
+
+impl<'a> MatchState<'a> {
+    // `p` is at `[` and `end` at the closing `]`
+    fn match_bracket_class(&self, c: u8, mut p: usize, end: usize) -> bool {
+        let mut sig = true;
+        if self.pat[p + 1] == b'^' {
+            sig = false;
+            p += 1;
+        }
+        p += 1;
+        while p < end {
+            if self.pat[p] == b'%' {
+                p += 1;
+                if match_class(c, self.pat[p]) {
+                    return sig;
+                }
+            } else if self.pat[p + 1] == b'-' && p + 2 < end {
+                if self.pat[p] <= c && c <= self.pat[p + 2] {
+                    return sig;
+                }
+                p += 2;
+            } else if self.pat[p] == c {
+                return sig;
+            }
+            p += 1;
+        }
+        !sig
+    }
+
+    fn single_match(&self, s: usize, p: usize, ep: usize) -> bool {
+        if s >= self.src.len() {
+            return false;
+        }
+        let c = self.src[s];
+        match self.pat[p] {
+            b'.' => true,
+            b'%' => match_class(c, self.pat[p + 1]),
+            b'[' => self.match_bracket_class(c, p, ep - 1),
+            pc => pc == c,
+        }
+    }
+
+    fn do_match(&mut self, s: usize, p: usize) -> MatchResult {
+        if self.depth == 0 {
+            return Err(PatternError::TooComplex);
+        }
+        self.depth -= 1;
+        let result = self.match_loop(s, p);
+        self.depth += 1;
+        result
+    }
+
+    fn match_loop(&mut self, mut s: usize, mut p: usize) -> MatchResult {
+        loop {
+            if p >= self.pat.len() {
+                return Ok(Some(s));
+            }
+            match self.pat[p] {
+                b'(' => {
+                    return if self.pat.get(p + 1) == Some(&b')') {
+                        self.start_capture(s, p + 2, CaptureLen::Position)
+                    } else {
+                        self.start_capture(s, p + 1, CaptureLen::Unclosed)
+                    };
+                }
+                b')' => return self.end_capture(s, p + 1),
+                b'$' if p + 1 == self.pat.len() => {
+                    return Ok(if s == self.src.len() { Some(s) } else { None });
+                }
+                b'%' if self.pat.get(p + 1) == Some(&b'b') => match self.match_balance(s, p + 2)? {
+                    Some(end) => {
+                        s = end;
+                        p += 4;
+                        continue;
+                    }
+                    None => return Ok(None),
+                },
+                b'%' if self.pat.get(p + 1) == Some(&b'f') => {
+                    p += 2;
+                    if self.pat.get(p) != Some(&b'[') {
+                        return Err(PatternError::MissingFrontierBracket);
+                    }
+                    let ep = class_end(self.pat, p)?;
+                    let prev = if s == 0 { 0 } else { self.src[s - 1] };
+                    let current = self.src.get(s).copied().unwrap_or(0);
+                    if !self.match_bracket_class(prev, p, ep - 1)
+                        && self.match_bracket_class(current, p, ep - 1)
+                    {
+                        p = ep;
+                        continue;
+                    }
+                    return Ok(None);
+                }
+                b'%' if self.pat.get(p + 1).is_some_and(u8::is_ascii_digit) => {
+                    match self.match_capture(s, self.pat[p + 1])? {
+                        Some(end) => {
+                            s = end;
+                            p += 2;
+                            continue;
+                        }
+                        None => return Ok(None),
+                    }
+                }
+                _ => (),
+            }
+            let ep = class_end(self.pat, p)?;
+            let repeat = self.pat.get(ep).copied();
+            if !self.single_match(s, p, ep) {
+                // zero occurrences are fine for these
+                if let Some(b'*') | Some(b'?') | Some(b'-') = repeat {
+                    p = ep + 1;
+                    continue;
+                }
+                return Ok(None);
+            }
+            match repeat {
+                Some(b'?') => {
+                    if let Some(end) = self.do_match(s + 1, ep + 1)? {
+                        return Ok(Some(end));
+                    }
+                    p = ep + 1;
+                }
+                Some(b'+') => return self.max_expand(s + 1, p, ep),
+                Some(b'*') => return self.max_expand(s, p, ep),
+                Some(b'-') => return self.min_expand(s, p, ep),
+                _ => {
+                    s += 1;
+                    p = ep;
+                }
+            }
+        }
+    }
+
+    fn max_expand(&mut self, s: usize, p: usize, ep: usize) -> MatchResult {
+        let mut count = 0;
+        while self.single_match(s + count, p, ep) {
+            count += 1;
+        }
+        loop {
+            if let Some(end) = self.do_match(s + count, ep + 1)? {
+                return Ok(Some(end));
+            }
+            if count == 0 {
+                return Ok(None);
+            }
+            count -= 1;
+        }
+    }
+
+    fn min_expand(&mut self, mut s: usize, p: usize, ep: usize) -> MatchResult {
+        loop {
+            if let Some(end) = self.do_match(s, ep + 1)? {
+                return Ok(Some(end));
+            }
+            if self.single_match(s, p, ep) {
+                s += 1;
+            } else {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn start_capture(&mut self, s: usize, p: usize, len: CaptureLen) -> MatchResult {
+        if self.captures.len() >= MAX_CAPTURES {
+            return Err(PatternError::TooManyCaptures);
+        }
+        self.captures.push((s, len));
+        let result = self.do_match(s, p)?;
+        if result.is_none() {
+            self.captures.pop();
+        }
+        Ok(result)
+    }
+
+    fn end_capture(&mut self, s: usize, p: usize) -> MatchResult {
+        let open = self
+            .captures
+            .iter()
+            .rposition(|(_, len)| *len == CaptureLen::Unclosed)
+            .ok_or(PatternError::InvalidPatternCapture)?;
+        self.captures[open].1 = CaptureLen::Len(s - self.captures[open].0);
+        let result = self.do_match(s, p)?;
+        if result.is_none() {
+            self.captures[open].1 = CaptureLen::Unclosed;
+        }
+        Ok(result)
+    }
+
+    fn match_balance(&self, s: usize, p: usize) -> MatchResult {
+        if p + 1 >= self.pat.len() {
+            return Err(PatternError::MissingBalanceArgs);
+        }
+        let (open, close) = (self.pat[p], self.pat[p + 1]);
+        if self.src.get(s) != Some(&open) {
+            return Ok(None);
+        }
+        let mut depth = 1;
+        for i in s + 1..self.src.len() {
+            if self.src[i] == close {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(Some(i + 1));
+                }
+            } else if self.src[i] == open {
+                depth += 1;
+            }
+        }
+        Ok(None)
+    }
+
+    // `%1` to `%9`, the text of an earlier closed capture
+    fn match_capture(&self, s: usize, digit: u8) -> MatchResult {
+        let index = (digit - b'0') as usize;
+        let (start, len) = match self.captures.get(index.wrapping_sub(1)) {
+            Some((start, CaptureLen::Len(len))) => (*start, *len),
+            // the text of a position capture never matches
+            Some((_, CaptureLen::Position)) => return Ok(None),
+            _ => return Err(PatternError::InvalidCaptureIndex(index)),
+        };
+        if self.src.len() - s >= len && self.src[start..start + len] == self.src[s..s + len] {
+            Ok(Some(s + len))
+        } else {
+            Ok(None)
+        }
+    }
+}
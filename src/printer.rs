@@ -0,0 +1,419 @@
+use crate::ast::*;
+use crate::types::{FloatType, IntType};
+
+// turns an AST back into Lua source. parentheses are only added where the shape of the tree
+// needs them, so parsing the output of a parsed AST gives back the same AST.
+// strings are escaped, so the AST must be parsed without `use_origin_string`.
+pub struct Printer {
+    output: String,
+    indent: usize,
+    depth: usize,
+}
+
+impl Default for Printer {
+    fn default() -> Self {
+        Printer::new()
+    }
+}
+
+// print a block with the default printer
+pub fn print(block: &Block) -> String {
+    Printer::new().run(block)
+}
+
+// a statement after one ending in an expression would continue it when this holds
+pub(crate) fn starts_with_paren(stat: &Stat) -> bool {
+    let expr = match stat {
+        Stat::CallStat(stat) => &stat.call,
+        Stat::AssignStat(stat) => &stat.left[0],
+        _ => return false,
+    };
+    matches!(expr.primary, PrimaryExpr::ParenExpr(_))
+}
+
+pub(crate) fn ends_with_expr(stat: &Stat) -> bool {
+    match stat {
+        Stat::LocalStat(stat) => !stat.exprs.is_empty(),
+        Stat::AssignStat(_) | Stat::CallStat(_) | Stat::RepeatStat(_) => true,
+        _ => false,
+    }
+}
+
+impl Printer {
+    pub fn new() -> Self {
+        Printer {
+            output: String::new(),
+            indent: 2,
+            depth: 0,
+        }
+    }
+
+    // spaces per indent level, default is 2.
+    pub fn set_indent(&mut self, indent: usize) {
+        self.indent = indent;
+    }
+
+    pub fn run(&mut self, block: &Block) -> String {
+        self.output.clear();
+        self.depth = 0;
+        self.block(block);
+        std::mem::take(&mut self.output)
+    }
+
+    pub fn run_expr(&mut self, expr: &Expr) -> String {
+        self.output.clear();
+        self.depth = 0;
+        self.expr(expr);
+        std::mem::take(&mut self.output)
+    }
+
+    fn append(&mut self, content: &str) {
+        self.output.push_str(content);
+    }
+
+    fn indent(&mut self) {
+        self.output.push_str(&" ".repeat(self.depth * self.indent));
+    }
+
+    fn block(&mut self, block: &Block) {
+        let mut prev: Option<&Stat> = None;
+        for stat in block.stats.iter() {
+            // nothing to print for statements that failed to parse
+            if let Stat::Error(_) = stat {
+                continue;
+            }
+            self.indent();
+            // `a = b (f)()` would be a call of `b`
+            if starts_with_paren(stat) && prev.is_some_and(ends_with_expr) {
+                self.append(";");
+            }
+            self.stat(stat);
+            self.append("\n");
+            prev = Some(stat);
+        }
+    }
+
+    // block one level deeper, followed by the indent of the closing keyword
+    fn nested_block(&mut self, block: &Block) {
+        self.append("\n");
+        self.depth += 1;
+        self.block(block);
+        self.depth -= 1;
+        self.indent();
+    }
+
+    fn stat(&mut self, stat: &Stat) {
+        match stat {
+            Stat::Empty(_) => self.append(";"),
+            Stat::IfStat(stat) => self.ifstat(stat),
+            Stat::WhileStat(stat) => {
+                self.append("while ");
+                self.expr(&stat.cond);
+                self.append(" do");
+                self.nested_block(&stat.block);
+                self.append("end");
+            }
+            Stat::DoBlock(stat) => {
+                self.append("do");
+                self.nested_block(&stat.block);
+                self.append("end");
+            }
+            Stat::ForStat(ForStat::ForNum(stat)) => {
+                self.append(&format!("for {} = ", stat.var.name));
+                self.expr(&stat.init);
+                self.append(", ");
+                self.expr(&stat.limit);
+                if let Some(step) = &stat.step {
+                    self.append(", ");
+                    self.expr(step);
+                }
+                self.append(" do");
+                self.nested_block(&stat.body);
+                self.append("end");
+            }
+            Stat::ForStat(ForStat::ForList(stat)) => {
+                let vars: Vec<&str> = stat.vars.iter().map(|v| v.name.as_str()).collect();
+                self.append(&format!("for {} in ", vars.join(", ")));
+                self.exprlist(&stat.exprs);
+                self.append(" do");
+                self.nested_block(&stat.body);
+                self.append("end");
+            }
+            Stat::RepeatStat(stat) => {
+                self.append("repeat");
+                self.nested_block(&stat.block);
+                self.append("until ");
+                self.expr(&stat.cond);
+            }
+            Stat::FuncStat(stat) => self.funcstat(stat),
+            Stat::LocalStat(stat) => self.localstat(stat),
+            Stat::LabelStat(stat) => self.append(&format!("::{}::", stat.label.name)),
+            Stat::RetStat(stat) => {
+                self.append("return");
+                if !stat.exprs.is_empty() {
+                    self.append(" ");
+                    self.exprlist(&stat.exprs);
+                }
+            }
+            Stat::BreakStat(_) => self.append("break"),
+            Stat::GotoStat(stat) => self.append(&format!("goto {}", stat.label.name)),
+            Stat::AssignStat(stat) => {
+                for (n, expr) in stat.left.iter().enumerate() {
+                    if n > 0 {
+                        self.append(", ");
+                    }
+                    self.suffixedexpr(expr);
+                }
+                self.append(" = ");
+                self.exprlist(&stat.right);
+            }
+            Stat::CallStat(stat) => self.suffixedexpr(&stat.call),
+            Stat::Error(_) => (),
+        }
+    }
+
+    fn ifstat(&mut self, stat: &IfStat) {
+        for (n, cond_block) in stat.cond_blocks.iter().enumerate() {
+            self.append(if n == 0 { "if " } else { "elseif " });
+            self.expr(&cond_block.cond);
+            self.append(" then");
+            self.nested_block(&cond_block.block);
+        }
+        if !stat.else_block.stats.is_empty() {
+            self.append("else");
+            self.nested_block(&stat.else_block);
+        }
+        self.append("end");
+    }
+
+    fn funcstat(&mut self, stat: &FuncStat) {
+        if stat.func_type == FuncType::Local {
+            self.append("local ");
+        }
+        self.append("function ");
+        let fields: Vec<&str> = stat
+            .func_name
+            .fields
+            .iter()
+            .map(|f| f.name.as_str())
+            .collect();
+        self.append(&fields.join("."));
+        if let Some(method) = &stat.func_name.method {
+            self.append(":");
+            self.append(&method.name);
+        }
+        self.funcbody(&stat.body);
+    }
+
+    fn funcbody(&mut self, body: &FuncBody) {
+        let params: Vec<&str> = body
+            .params
+            .iter()
+            .map(|param| match param {
+                Param::VarArg(_) => "...",
+                Param::Name(name) => name.name.as_str(),
+            })
+            .collect();
+        self.append(&format!("({})", params.join(", ")));
+        self.nested_block(&body.block);
+        self.append("end");
+    }
+
+    fn localstat(&mut self, stat: &LocalStat) {
+        self.append("local ");
+        for (n, name) in stat.names.iter().enumerate() {
+            if n > 0 {
+                self.append(", ");
+            }
+            self.append(&name.name.name);
+            if let Some(attrib) = name.attrib {
+                self.append(&format!(" <{}>", attrib.name()));
+            }
+        }
+        if !stat.exprs.is_empty() {
+            self.append(" = ");
+            self.exprlist(&stat.exprs);
+        }
+    }
+
+    fn exprlist(&mut self, exprs: &[Expr]) {
+        for (n, expr) in exprs.iter().enumerate() {
+            if n > 0 {
+                self.append(", ");
+            }
+            self.expr(expr);
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Nil(_) => self.append("nil"),
+            Expr::True(_) => self.append("true"),
+            Expr::False(_) => self.append("false"),
+            Expr::VarArg(_) => self.append("..."),
+            Expr::Float(f, _) => self.float(*f),
+            Expr::Int(i, _) => self.int(*i),
+            Expr::UInt(i, _) => self.append(&format!("{}ULL", *i as u64)),
+            Expr::String(s, _) => self.string(s),
+            Expr::FuncBody(body) => {
+                self.append("function");
+                self.funcbody(body);
+            }
+            Expr::Table(table) => self.table(table),
+            Expr::BinExpr(expr) => self.binexpr(expr),
+            Expr::UnExpr(expr) => self.unexpr(expr),
+            Expr::SuffixedExpr(expr) => self.suffixedexpr(expr),
+        }
+    }
+
+    fn paren_expr(&mut self, paren: bool, expr: &Expr) {
+        if paren {
+            self.append("(");
+            self.expr(expr);
+            self.append(")");
+        } else {
+            self.expr(expr);
+        }
+    }
+
+    // an operand is parsed into an operator on its left side if that operator binds tighter
+    // than the operator it belongs to, which is when parentheses are needed.
+    fn binexpr(&mut self, expr: &BinExpr) {
+        let priority = expr.op.priority();
+        let left_paren = match &*expr.left {
+            Expr::BinExpr(left) => priority.left > left.op.priority().right,
+            Expr::UnExpr(left) => priority.left > left.op.priority(),
+            _ => false,
+        };
+        let right_paren = match &*expr.right {
+            Expr::BinExpr(right) => right.op.priority().left <= priority.right,
+            _ => false,
+        };
+        self.paren_expr(left_paren, &expr.left);
+        self.append(&format!(" {} ", expr.op.as_str()));
+        self.paren_expr(right_paren, &expr.right);
+    }
+
+    fn unexpr(&mut self, expr: &UnExpr) {
+        self.append(expr.op.as_str());
+        let paren = match &*expr.expr {
+            Expr::BinExpr(operand) => operand.op.priority().left <= expr.op.priority(),
+            // `- -a` must not become a comment
+            Expr::UnExpr(operand) => {
+                if expr.op == UnOp::Minus && operand.op == UnOp::Minus {
+                    self.append(" ");
+                }
+                false
+            }
+            _ => false,
+        };
+        if expr.op == UnOp::Not {
+            self.append(" ");
+        }
+        self.paren_expr(paren, &expr.expr);
+    }
+
+    fn suffixedexpr(&mut self, expr: &SuffixedExpr) {
+        match &expr.primary {
+            PrimaryExpr::Name(name) => self.append(&name.name),
+            PrimaryExpr::ParenExpr(expr) => self.paren_expr(true, expr),
+        }
+        for suffix in expr.suffixes.iter() {
+            match suffix {
+                Suffix::Attr(name) => {
+                    self.append(".");
+                    self.append(&name.name);
+                }
+                Suffix::Method(name) => {
+                    self.append(":");
+                    self.append(&name.name);
+                }
+                Suffix::Index(index) => {
+                    self.append("[");
+                    self.expr(index);
+                    self.append("]");
+                }
+                Suffix::FuncArgs(FuncArgs::Exprs(exprs, _)) => {
+                    self.append("(");
+                    self.exprlist(exprs);
+                    self.append(")");
+                }
+                Suffix::FuncArgs(FuncArgs::Table(table)) => {
+                    self.append(" ");
+                    self.table(table);
+                }
+                Suffix::FuncArgs(FuncArgs::String(s, _)) => {
+                    self.append(" ");
+                    self.string(s);
+                }
+            }
+        }
+    }
+
+    fn table(&mut self, table: &Table) {
+        self.append("{");
+        for (n, field) in table.fields.iter().enumerate() {
+            if n > 0 {
+                self.append(", ");
+            }
+            match field {
+                Field::ListField(expr) => self.expr(expr),
+                Field::RecFileld(field) => {
+                    match &field.key {
+                        FieldKey::Name(name) => self.append(&name.name),
+                        FieldKey::Expr(key) => {
+                            self.append("[");
+                            self.expr(key);
+                            self.append("]");
+                        }
+                    }
+                    self.append(" = ");
+                    self.expr(&field.value);
+                }
+            }
+        }
+        self.append("}");
+    }
+
+    // negative floats can only come from a rewritten AST, they are wrapped like unary expressions
+    fn float(&mut self, f: FloatType) {
+        let string = if f.is_nan() {
+            "(0/0)".to_string()
+        } else if f.is_infinite() {
+            if f > 0.0 { "1e999" } else { "(-1e999)" }.to_string()
+        } else if f.is_sign_negative() {
+            format!("({:?})", f)
+        } else {
+            format!("{:?}", f)
+        };
+        self.append(&string);
+    }
+
+    // negative integers come from hex numerals that wrap around, like `0xffffffffffffffff`,
+    // and are written back that way
+    fn int(&mut self, i: IntType) {
+        let string = if i < 0 {
+            format!("0x{:x}", i as u64)
+        } else {
+            i.to_string()
+        };
+        self.append(&string);
+    }
+
+    fn string(&mut self, s: &str) {
+        self.output.push('"');
+        for c in s.chars() {
+            match c {
+                '\\' => self.append("\\\\"),
+                '"' => self.append("\\\""),
+                '\n' => self.append("\\n"),
+                '\r' => self.append("\\r"),
+                '\t' => self.append("\\t"),
+                // decimal escapes always take 3 digits so a following digit is not part of it
+                _ if c.is_ascii_control() => self.append(&format!("\\{:03}", c as u8)),
+                _ => self.output.push(c),
+            }
+        }
+        self.output.push('"');
+    }
+}
@@ -0,0 +1,526 @@
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::rc::Rc;
+
+use crate::ast::*;
+use crate::ast_walker::{ast_walker_mut, AstVisitorMut};
+use crate::token::Span;
+use crate::types::LuaVersion;
+
+pub type ScopeId = usize;
+pub type LocalId = usize;
+pub type FunctionId = usize;
+pub type UseId = usize;
+
+// what declared a local variable
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum LocalKind {
+    Local,
+    // `local function f`
+    Function,
+    Param,
+    // implicit parameter of `function t:m()`
+    SelfParam,
+    ForNum,
+    ForList,
+}
+
+// what a name refers to
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Binding {
+    // local declared in the same function
+    Local(LocalId),
+    // local of an enclosing function
+    Upvalue(LocalId),
+    // field of the local `_ENV` given, or of the chunk's environment if none is in scope
+    Global(Option<LocalId>),
+    // `_ENV` itself without a local of that name, the chunk's environment
+    Env,
+}
+
+#[derive(Debug)]
+pub struct Scope {
+    pub parent: Option<ScopeId>,
+    pub function: FunctionId,
+    pub span: Span,
+    // declared in this scope, in order
+    pub locals: Vec<LocalId>,
+}
+
+#[derive(Debug)]
+pub struct LocalVar {
+    pub name: String,
+    // the declaring name, the method name for `self`
+    pub span: Span,
+    pub kind: LocalKind,
+    pub attrib: Option<LocalAttrib>,
+    pub scope: ScopeId,
+    pub function: FunctionId,
+    pub uses: Vec<UseId>,
+    // used by a nested function
+    pub captured: bool,
+}
+
+#[derive(Debug)]
+pub struct Function {
+    // none for the main chunk
+    pub parent: Option<FunctionId>,
+    pub span: Span,
+    // scope of the parameters and the body
+    pub scope: ScopeId,
+    // locals of enclosing functions used here or by a nested function, in first use order
+    pub upvalues: Vec<LocalId>,
+}
+
+#[derive(Debug)]
+pub struct Use {
+    pub name: String,
+    pub span: Span,
+    pub binding: Binding,
+    // assigned rather than read
+    pub write: bool,
+    pub scope: ScopeId,
+    pub function: FunctionId,
+}
+
+// scope tree of a chunk, ids index the vectors. function 0 is the main chunk and
+// scope 0 its body.
+#[derive(Debug, Default)]
+pub struct Resolution<'a> {
+    pub scopes: Vec<Scope>,
+    pub locals: Vec<LocalVar>,
+    pub functions: Vec<Function>,
+    // every variable name in the chunk, in source order
+    pub uses: Vec<Use>,
+    // keyed by the address of the node, so a tree built without spans resolves too. the
+    // borrow keeps the tree from moving or changing while the lookups are made.
+    declarations: HashMap<usize, LocalId>,
+    bindings: HashMap<usize, UseId>,
+    bodies: HashMap<usize, FunctionId>,
+    tree: PhantomData<&'a Block>,
+}
+
+impl Resolution<'_> {
+    // what a variable name of the tree refers to
+    pub fn binding(&self, name: &Name) -> Option<Binding> {
+        self.bindings
+            .get(&node(name))
+            .map(|id| self.uses[*id].binding)
+    }
+
+    // the local a declaring name of the tree introduces
+    pub fn declaration(&self, name: &Name) -> Option<LocalId> {
+        self.declarations.get(&node(name)).copied()
+    }
+
+    // the function a body of the tree defines
+    pub fn function(&self, body: &FuncBody) -> Option<FunctionId> {
+        self.bodies.get(&node(body)).copied()
+    }
+
+    pub fn local(&self, name: &Name) -> Option<&LocalVar> {
+        match self.binding(name) {
+            Some(Binding::Local(id)) | Some(Binding::Upvalue(id)) => Some(&self.locals[id]),
+            _ => self.declaration(name).map(|id| &self.locals[id]),
+        }
+    }
+
+    // assignments to a `<const>` or `<close>` local, which 5.4 rejects
+    pub fn const_writes(&self) -> impl Iterator<Item = &Use> + '_ {
+        self.uses.iter().filter(move |u| match u.binding {
+            Binding::Local(id) | Binding::Upvalue(id) => {
+                u.write && self.locals[id].attrib.is_some()
+            }
+            _ => false,
+        })
+    }
+}
+
+fn node<T>(node: &T) -> usize {
+    node as *const T as usize
+}
+
+// a chunk resolved for running it. every function body is moved out of the tree into an
+// `Rc` of its own before it is resolved, so the nodes are shared and never move or change
+// again while the resolution keyed by their addresses lives.
+pub(crate) struct SharedChunk {
+    // by function, the main function's body first
+    bodies: Vec<Rc<FuncBody>>,
+    // borrows nothing, the bodies above hold the tree
+    resolution: Resolution<'static>,
+}
+
+impl SharedChunk {
+    pub(crate) fn body(&self, function: FunctionId) -> &Rc<FuncBody> {
+        &self.bodies[function]
+    }
+
+    pub(crate) fn resolution(&self) -> &Resolution<'_> {
+        &self.resolution
+    }
+}
+
+// moves the function bodies out of a tree, each one leaves an empty body in its place
+struct MoveBodies(HashMap<usize, Rc<FuncBody>>);
+
+impl AstVisitorMut for MoveBodies {
+    fn begin_func_body(&mut self, body: &mut FuncBody) -> bool {
+        let empty = FuncBody {
+            params: Vec::new(),
+            block: Block::empty(),
+            span: body.span,
+        };
+        // in place before the bodies nested in it are moved out
+        let mut moved = Rc::new(std::mem::replace(body, empty));
+        ast_walker_mut::walk_block_mut(&mut Rc::get_mut(&mut moved).unwrap().block, self);
+        self.0.insert(node(body), moved);
+        true
+    }
+}
+
+// links every variable name to its declaration, see `Resolution`
+pub struct Resolver {
+    version: LuaVersion,
+    result: Resolution<'static>,
+    // locals in scope, innermost last
+    active: Vec<LocalId>,
+    scope: ScopeId,
+    function: FunctionId,
+    // bodies moved out of the tree by the address of the empty body left, see `SharedChunk`
+    moved: HashMap<usize, Rc<FuncBody>>,
+    shared: Vec<Rc<FuncBody>>,
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Resolver::new()
+    }
+}
+
+impl Resolver {
+    pub fn new() -> Self {
+        Resolver {
+            version: LuaVersion::default(),
+            result: Resolution::default(),
+            active: Vec::new(),
+            scope: 0,
+            function: 0,
+            moved: HashMap::new(),
+            shared: Vec::new(),
+        }
+    }
+
+    // dialect of the tree, globals only go through `_ENV` from 5.2 on
+    pub fn set_version(&mut self, version: LuaVersion) {
+        self.version = version;
+    }
+
+    pub fn run<'a>(&mut self, block: &'a Block) -> Resolution<'a> {
+        self.resolve(block)
+    }
+
+    // the lookups of the result are only valid while `block` stays where it is
+    fn resolve(&mut self, block: &Block) -> Resolution<'static> {
+        self.result = Resolution::default();
+        self.active.clear();
+        self.result.functions.push(Function {
+            parent: None,
+            span: block.span,
+            scope: 0,
+            upvalues: Vec::new(),
+        });
+        self.function = 0;
+        self.begin_scope(block.span);
+        self.stats(block);
+        std::mem::take(&mut self.result)
+    }
+
+    // the main function of a chunk to run, with its function bodies moved out of the tree
+    pub(crate) fn run_shared(&mut self, block: Block) -> SharedChunk {
+        let span = block.span;
+        let mut main = Rc::new(FuncBody {
+            params: vec![Param::VarArg(span)],
+            block,
+            span,
+        });
+        let mut moved = MoveBodies(HashMap::new());
+        ast_walker_mut::walk_block_mut(&mut Rc::get_mut(&mut main).unwrap().block, &mut moved);
+        self.moved = moved.0;
+        self.shared = vec![main.clone()];
+        let resolution = self.resolve(&main.block);
+        self.moved.clear();
+        SharedChunk {
+            bodies: std::mem::take(&mut self.shared),
+            resolution,
+        }
+    }
+
+    fn begin_scope(&mut self, span: Span) -> usize {
+        self.result.scopes.push(Scope {
+            parent: self.result.scopes.get(self.scope).map(|_| self.scope),
+            function: self.function,
+            span,
+            locals: Vec::new(),
+        });
+        self.scope = self.result.scopes.len() - 1;
+        self.active.len()
+    }
+
+    fn end_scope(&mut self, active: usize) {
+        self.active.truncate(active);
+        if let Some(parent) = self.result.scopes[self.scope].parent {
+            self.scope = parent;
+        }
+    }
+
+    fn declare_name(&mut self, name: &Name, kind: LocalKind, attrib: Option<LocalAttrib>) {
+        let id = self.declare(&name.name, name.span, kind, attrib);
+        self.result.declarations.insert(node(name), id);
+    }
+
+    fn declare(
+        &mut self,
+        name: &str,
+        span: Span,
+        kind: LocalKind,
+        attrib: Option<LocalAttrib>,
+    ) -> LocalId {
+        let id = self.result.locals.len();
+        self.result.locals.push(LocalVar {
+            name: name.to_string(),
+            span,
+            kind,
+            attrib,
+            scope: self.scope,
+            function: self.function,
+            uses: Vec::new(),
+            captured: false,
+        });
+        self.result.scopes[self.scope].locals.push(id);
+        self.active.push(id);
+        id
+    }
+
+    // innermost local in scope called `name`, captured if it belongs to an enclosing function
+    fn lookup(&mut self, name: &str) -> Option<Binding> {
+        let id = *self
+            .active
+            .iter()
+            .rev()
+            .find(|id| self.result.locals[**id].name == name)?;
+        let owner = self.result.locals[id].function;
+        if owner == self.function {
+            return Some(Binding::Local(id));
+        }
+        self.result.locals[id].captured = true;
+        // every function in between passes the upvalue down
+        let mut function = self.function;
+        while function != owner {
+            let upvalues = &mut self.result.functions[function].upvalues;
+            if !upvalues.contains(&id) {
+                upvalues.push(id);
+            }
+            function = self.result.functions[function].parent.unwrap();
+        }
+        Some(Binding::Upvalue(id))
+    }
+
+    fn use_name(&mut self, name: &Name, write: bool) {
+        let binding = match self.lookup(&name.name) {
+            Some(binding) => binding,
+            None if !self.version.has_env() => Binding::Global(None),
+            None if name.name == "_ENV" => Binding::Env,
+            None => match self.lookup("_ENV") {
+                Some(Binding::Local(env)) | Some(Binding::Upvalue(env)) => {
+                    Binding::Global(Some(env))
+                }
+                _ => Binding::Global(None),
+            },
+        };
+        let id = self.result.uses.len();
+        self.result.uses.push(Use {
+            name: name.name.clone(),
+            span: name.span,
+            binding,
+            write,
+            scope: self.scope,
+            function: self.function,
+        });
+        self.result.bindings.insert(node(name), id);
+        if let Binding::Local(local) | Binding::Upvalue(local) = binding {
+            self.result.locals[local].uses.push(id);
+        }
+    }
+
+    fn block(&mut self, block: &Block) {
+        let active = self.begin_scope(block.span);
+        self.stats(block);
+        self.end_scope(active);
+    }
+
+    fn stats(&mut self, block: &Block) {
+        for stat in block.stats.iter() {
+            self.stat(stat);
+        }
+    }
+
+    fn stat(&mut self, stat: &Stat) {
+        match stat {
+            Stat::IfStat(stat) => {
+                for cond_block in stat.cond_blocks.iter() {
+                    self.expr(&cond_block.cond);
+                    self.block(&cond_block.block);
+                }
+                self.block(&stat.else_block);
+            }
+            Stat::WhileStat(stat) => {
+                self.expr(&stat.cond);
+                self.block(&stat.block);
+            }
+            Stat::DoBlock(stat) => self.block(&stat.block),
+            Stat::ForStat(ForStat::ForNum(stat)) => {
+                self.expr(&stat.init);
+                self.expr(&stat.limit);
+                if let Some(step) = &stat.step {
+                    self.expr(step);
+                }
+                let active = self.begin_scope(stat.span);
+                self.declare_name(&stat.var, LocalKind::ForNum, None);
+                self.stats(&stat.body);
+                self.end_scope(active);
+            }
+            Stat::ForStat(ForStat::ForList(stat)) => {
+                self.exprs(&stat.exprs);
+                let active = self.begin_scope(stat.span);
+                for var in stat.vars.iter() {
+                    self.declare_name(var, LocalKind::ForList, None);
+                }
+                self.stats(&stat.body);
+                self.end_scope(active);
+            }
+            // the condition sees the locals of the body
+            Stat::RepeatStat(stat) => {
+                let active = self.begin_scope(stat.block.span);
+                self.stats(&stat.block);
+                self.expr(&stat.cond);
+                self.end_scope(active);
+            }
+            Stat::FuncStat(stat) => {
+                let name = &stat.func_name;
+                match stat.func_type {
+                    // the function can call itself
+                    FuncType::Local => {
+                        self.declare_name(&name.fields[0], LocalKind::Function, None);
+                    }
+                    FuncType::Global => {
+                        let write = name.fields.len() == 1 && name.method.is_none();
+                        self.use_name(&name.fields[0], write);
+                    }
+                }
+                self.funcbody(&stat.body, name.method.as_ref());
+            }
+            // values are evaluated before the names exist
+            Stat::LocalStat(stat) => {
+                self.exprs(&stat.exprs);
+                for local in stat.names.iter() {
+                    self.declare_name(&local.name, LocalKind::Local, local.attrib);
+                }
+            }
+            Stat::RetStat(stat) => self.exprs(&stat.exprs),
+            Stat::AssignStat(stat) => {
+                for target in stat.left.iter() {
+                    match (&target.primary, target.suffixes.is_empty()) {
+                        (PrimaryExpr::Name(name), true) => self.use_name(name, true),
+                        _ => self.suffixedexpr(target),
+                    }
+                }
+                self.exprs(&stat.right);
+            }
+            Stat::CallStat(stat) => self.suffixedexpr(&stat.call),
+            Stat::LabelStat(_)
+            | Stat::BreakStat(_)
+            | Stat::GotoStat(_)
+            | Stat::Empty(_)
+            | Stat::Error(_) => (),
+        }
+    }
+
+    fn funcbody(&mut self, body: &FuncBody, method: Option<&Name>) {
+        let parent = self.function;
+        self.function = self.result.functions.len();
+        self.result.bodies.insert(node(body), self.function);
+        self.result.functions.push(Function {
+            parent: Some(parent),
+            span: body.span,
+            scope: self.result.scopes.len(),
+            upvalues: Vec::new(),
+        });
+        // a body moved out of the tree is resolved where it is now
+        let moved = self.moved.get(&node(body)).cloned();
+        if let Some(moved) = &moved {
+            self.shared.push(moved.clone());
+        }
+        let body = moved.as_deref().unwrap_or(body);
+        let active = self.begin_scope(body.span);
+        if let Some(method) = method {
+            self.declare("self", method.span, LocalKind::SelfParam, None);
+        }
+        for param in body.params.iter() {
+            if let Param::Name(name) = param {
+                self.declare_name(name, LocalKind::Param, None);
+            }
+        }
+        self.stats(&body.block);
+        self.end_scope(active);
+        self.function = parent;
+    }
+
+    fn exprs(&mut self, exprs: &[Expr]) {
+        for expr in exprs.iter() {
+            self.expr(expr);
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::FuncBody(body) => self.funcbody(body, None),
+            Expr::Table(table) => self.table(table),
+            Expr::BinExpr(expr) => {
+                self.expr(&expr.left);
+                self.expr(&expr.right);
+            }
+            Expr::UnExpr(expr) => self.expr(&expr.expr),
+            Expr::SuffixedExpr(expr) => self.suffixedexpr(expr),
+            _ => (),
+        }
+    }
+
+    fn suffixedexpr(&mut self, expr: &SuffixedExpr) {
+        match &expr.primary {
+            PrimaryExpr::Name(name) => self.use_name(name, false),
+            PrimaryExpr::ParenExpr(expr) => self.expr(expr),
+        }
+        for suffix in expr.suffixes.iter() {
+            match suffix {
+                Suffix::Index(expr) => self.expr(expr),
+                Suffix::FuncArgs(FuncArgs::Exprs(exprs, _)) => self.exprs(exprs),
+                Suffix::FuncArgs(FuncArgs::Table(table)) => self.table(table),
+                _ => (),
+            }
+        }
+    }
+
+    fn table(&mut self, table: &Table) {
+        for field in table.fields.iter() {
+            match field {
+                Field::ListField(expr) => self.expr(expr),
+                Field::RecFileld(field) => {
+                    if let FieldKey::Expr(key) = &field.key {
+                        self.expr(key);
+                    }
+                    self.expr(&field.value);
+                }
+            }
+        }
+    }
+}
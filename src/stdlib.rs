@@ -0,0 +1,1655 @@
+use std::cell::{Cell, RefCell};
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+
+use crate::interpreter::{Interpreter, LuaError};
+use crate::lexer::Lexer;
+use crate::pattern::{self, Capture};
+use crate::types::{FloatType, IntType, LuaVersion, Number};
+use crate::value::{self, float_to_int, TableRef, Value};
+
+type LuaResult = Result<Vec<Value>, LuaError>;
+type Builtin = fn(&mut Interpreter, &Args) -> LuaResult;
+
+// `string.rep` and friends refuse to build anything larger
+const MAX_STRING_SIZE: usize = i32::MAX as usize;
+
+// arguments of a builtin with the name used in `bad argument` errors
+struct Args<'a> {
+    name: &'a str,
+    values: Vec<Value>,
+}
+
+impl<'a> Args<'a> {
+    fn get(&self, i: usize) -> Value {
+        self.values.get(i).cloned().unwrap_or_default()
+    }
+
+    fn rest(&self, i: usize) -> Vec<Value> {
+        self.values
+            .get(i..)
+            .map(<[Value]>::to_vec)
+            .unwrap_or_default()
+    }
+
+    fn error(&self, interp: &Interpreter, i: usize, message: impl std::fmt::Display) -> LuaError {
+        interp.error(format!(
+            "bad argument #{} to '{}' ({})",
+            i + 1,
+            self.name,
+            message
+        ))
+    }
+
+    fn type_error(&self, interp: &Interpreter, i: usize, expected: &str) -> LuaError {
+        let got = match self.values.get(i) {
+            Some(value) => value.type_name(),
+            None => "no value",
+        };
+        self.error(interp, i, format!("{} expected, got {}", expected, got))
+    }
+
+    fn check(&self, interp: &Interpreter, i: usize) -> Result<Value, LuaError> {
+        match self.values.get(i) {
+            Some(value) => Ok(value.clone()),
+            None => Err(self.error(interp, i, "value expected")),
+        }
+    }
+
+    fn number(&self, interp: &Interpreter, i: usize) -> Result<Number, LuaError> {
+        match interp.coerce(&self.get(i)) {
+            Number::None => Err(self.type_error(interp, i, "number")),
+            number => Ok(number),
+        }
+    }
+
+    fn float(&self, interp: &Interpreter, i: usize) -> Result<FloatType, LuaError> {
+        Ok(match self.number(interp, i)? {
+            Number::Int(i) => i as FloatType,
+            Number::Float(f) => f,
+            Number::None => unreachable!(),
+        })
+    }
+
+    fn int(&self, interp: &Interpreter, i: usize) -> Result<IntType, LuaError> {
+        match self.number(interp, i)? {
+            Number::Int(n) => Ok(n),
+            Number::Float(f) => float_to_int(f)
+                .ok_or_else(|| self.error(interp, i, "number has no integer representation")),
+            Number::None => unreachable!(),
+        }
+    }
+
+    fn opt_int(
+        &self,
+        interp: &Interpreter,
+        i: usize,
+        default: IntType,
+    ) -> Result<IntType, LuaError> {
+        match self.get(i) {
+            Value::Nil => Ok(default),
+            _ => self.int(interp, i),
+        }
+    }
+
+    // strings and numbers
+    fn string(&self, interp: &Interpreter, i: usize) -> Result<Rc<[u8]>, LuaError> {
+        match self.get(i) {
+            Value::Str(s) => Ok(s),
+            value => match interp.to_str(&value) {
+                Some(s) => Ok(s.into()),
+                None => Err(self.type_error(interp, i, "string")),
+            },
+        }
+    }
+
+    fn opt_string(
+        &self,
+        interp: &Interpreter,
+        i: usize,
+        default: &str,
+    ) -> Result<Rc<[u8]>, LuaError> {
+        match self.get(i) {
+            Value::Nil => Ok(default.as_bytes().into()),
+            _ => self.string(interp, i),
+        }
+    }
+
+    fn table(&self, interp: &Interpreter, i: usize) -> Result<TableRef, LuaError> {
+        match self.get(i) {
+            Value::Table(t) => Ok(t),
+            _ => Err(self.type_error(interp, i, "table")),
+        }
+    }
+}
+
+fn builtin(name: &'static str, f: Builtin) -> Value {
+    Value::builtin(name, move |interp, values| {
+        f(interp, &Args { name, values })
+    })
+}
+
+fn library(functions: &[(&'static str, Builtin)]) -> TableRef {
+    let table = value::Table::default();
+    let table = Rc::new(RefCell::new(table));
+    for (name, f) in functions.iter() {
+        table.borrow_mut().set_str(name, builtin(name, *f));
+    }
+    table
+}
+
+// fills the globals with the libraries for the version and sandbox of the interpreter
+pub(crate) fn open(interp: &mut Interpreter) {
+    let version = interp.version;
+    let globals = interp.globals.clone();
+    let mut g = globals.borrow_mut();
+    for (name, f) in BASE.iter() {
+        g.set_str(name, builtin(name, *f));
+    }
+    g.set_str("_G", Value::Table(globals.clone()));
+    let version_name = match version {
+        LuaVersion::LuaJIT => LuaVersion::Lua51.to_string(),
+        version => version.to_string(),
+    };
+    g.set_str("_VERSION", Value::from(version_name));
+    let old = matches!(version, LuaVersion::Lua51 | LuaVersion::LuaJIT);
+    let optional = |g: &mut value::Table, name: &'static str, f: Builtin, available: bool| {
+        let value = if available {
+            builtin(name, f)
+        } else {
+            Value::Nil
+        };
+        g.set_str(name, value);
+    };
+    optional(&mut g, "unpack", table_unpack, old);
+    optional(&mut g, "loadstring", base_load, old);
+    optional(&mut g, "dofile", base_dofile, !interp.sandbox);
+    optional(&mut g, "loadfile", base_loadfile, !interp.sandbox);
+
+    let string = library(STRING);
+    interp
+        .string_meta
+        .borrow_mut()
+        .set_str("__index", Value::Table(string.clone()));
+
+    let table = library(TABLE);
+    if !old {
+        table
+            .borrow_mut()
+            .set_str("unpack", builtin("unpack", table_unpack));
+        table
+            .borrow_mut()
+            .set_str("pack", builtin("pack", table_pack));
+    }
+
+    let math = library(MATH);
+    {
+        let mut m = math.borrow_mut();
+        m.set_str("pi", Value::Float(std::f64::consts::PI));
+        m.set_str("huge", Value::Float(FloatType::INFINITY));
+        if version.has_integer() {
+            m.set_str("maxinteger", Value::Int(IntType::MAX));
+            m.set_str("mininteger", Value::Int(IntType::MIN));
+            m.set_str("tointeger", builtin("tointeger", math_tointeger));
+            m.set_str("type", builtin("type", math_type));
+            m.set_str("ult", builtin("ult", math_ult));
+        } else {
+            m.set_str("pow", builtin("pow", math_pow));
+        }
+    }
+
+    let package = value::Table::default();
+    let package = Rc::new(RefCell::new(package));
+    {
+        let mut p = package.borrow_mut();
+        p.set_str("loaded", Value::Table(interp.loaded.clone()));
+        p.set_str("preload", Value::new_table());
+        p.set_str("path", Value::from("./?.lua;./?/init.lua"));
+    }
+
+    let mut libraries = vec![
+        ("string", string),
+        ("table", table),
+        ("math", math),
+        ("package", package),
+    ];
+    if !interp.sandbox {
+        libraries.push(("io", library(IO)));
+        libraries.push(("os", library(OS)));
+    } else {
+        g.set_str("io", Value::Nil);
+        g.set_str("os", Value::Nil);
+    }
+    let mut loaded = interp.loaded.borrow_mut();
+    loaded.set_str("_G", Value::Table(globals.clone()));
+    for (name, library) in libraries {
+        g.set_str(name, Value::Table(library.clone()));
+        loaded.set_str(name, Value::Table(library));
+    }
+}
+
+const BASE: &[(&str, Builtin)] = &[
+    ("assert", base_assert),
+    ("collectgarbage", base_collectgarbage),
+    ("error", base_error),
+    ("getmetatable", base_getmetatable),
+    ("ipairs", base_ipairs),
+    ("load", base_load),
+    ("next", base_next),
+    ("pairs", base_pairs),
+    ("pcall", base_pcall),
+    ("print", base_print),
+    ("rawequal", base_rawequal),
+    ("rawget", base_rawget),
+    ("rawlen", base_rawlen),
+    ("rawset", base_rawset),
+    ("require", base_require),
+    ("select", base_select),
+    ("setmetatable", base_setmetatable),
+    ("tonumber", base_tonumber),
+    ("tostring", base_tostring),
+    ("type", base_type),
+    ("xpcall", base_xpcall),
+];
+
+fn base_assert(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    if args.check(interp, 0)?.truthy() {
+        return Ok(args.values.clone());
+    }
+    match args.get(1) {
+        Value::Nil => Err(interp.error("assertion failed!")),
+        message => Err(LuaError::new(message)),
+    }
+}
+
+// there is nothing to collect by hand, memory is reference counted
+fn base_collectgarbage(_: &mut Interpreter, args: &Args) -> LuaResult {
+    match args.get(0).as_bytes() {
+        Some(b"count") => Ok(vec![Value::Float(0.0), Value::Int(0)]),
+        _ => Ok(vec![Value::Int(0)]),
+    }
+}
+
+fn base_error(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    let level = args.opt_int(interp, 1, 1)?;
+    match args.get(0) {
+        Value::Str(s) if level > 0 => {
+            let mut message = interp.location(level as usize).into_bytes();
+            message.extend_from_slice(&s);
+            Err(LuaError::new(Value::from(message)))
+        }
+        value => Err(LuaError::new(value)),
+    }
+}
+
+fn base_getmetatable(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    let meta = match interp.metatable(&args.check(interp, 0)?) {
+        Some(meta) => meta,
+        None => return Ok(vec![Value::Nil]),
+    };
+    let protected = meta.borrow().get_str("__metatable");
+    match protected {
+        Value::Nil => Ok(vec![Value::Table(meta)]),
+        protected => Ok(vec![protected]),
+    }
+}
+
+fn base_setmetatable(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    let table = args.table(interp, 0)?;
+    let meta = match args.get(1) {
+        Value::Nil => None,
+        Value::Table(meta) => Some(meta),
+        _ => return Err(args.type_error(interp, 1, "nil or table")),
+    };
+    if !interp.metafield(&args.get(0), "__metatable").is_nil() {
+        return Err(interp.error("cannot change a protected metatable"));
+    }
+    table.borrow_mut().metatable = meta;
+    Ok(vec![args.get(0)])
+}
+
+fn ipairs_next(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    let i = args.int(interp, 1)?.wrapping_add(1);
+    let value = match interp.index(&args.get(0), &Value::Int(i))? {
+        Some(value) => value,
+        None => return Err(args.type_error(interp, 0, "table")),
+    };
+    if value.is_nil() {
+        Ok(vec![Value::Nil])
+    } else {
+        Ok(vec![interp.integer(i), value])
+    }
+}
+
+fn base_ipairs(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    let value = args.check(interp, 0)?;
+    Ok(vec![
+        builtin("ipairs_next", ipairs_next),
+        value,
+        interp.integer(0),
+    ])
+}
+
+fn base_next(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    let table = args.table(interp, 0)?;
+    let next = table.borrow().next(&args.get(1));
+    match next {
+        Ok(Some((key, value))) => Ok(vec![key, value]),
+        Ok(None) => Ok(vec![Value::Nil]),
+        Err(_) => Err(interp.error("invalid key to 'next'")),
+    }
+}
+
+fn base_pairs(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    let value = args.check(interp, 0)?;
+    let handler = interp.metafield(&value, "__pairs");
+    if !handler.is_nil() && interp.version != LuaVersion::Lua51 {
+        let mut results = interp.call(&handler, vec![value])?;
+        results.resize(3, Value::Nil);
+        return Ok(results);
+    }
+    args.table(interp, 0)?;
+    Ok(vec![builtin("next", base_next), value, Value::Nil])
+}
+
+fn base_pcall(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    let f = args.check(interp, 0)?;
+    match interp.call(&f, args.rest(1)) {
+        Ok(mut results) => {
+            results.insert(0, Value::Boolean(true));
+            Ok(results)
+        }
+        Err(e) => Ok(vec![Value::Boolean(false), e.value]),
+    }
+}
+
+fn base_xpcall(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    let f = args.check(interp, 0)?;
+    let handler = args.get(1);
+    match interp.call(&f, args.rest(2)) {
+        Ok(mut results) => {
+            results.insert(0, Value::Boolean(true));
+            Ok(results)
+        }
+        Err(e) => {
+            let mut results = interp.call(&handler, vec![e.value])?;
+            results.insert(0, Value::Boolean(false));
+            Ok(results)
+        }
+    }
+}
+
+fn base_print(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    let mut line = Vec::new();
+    for (i, value) in args.values.iter().enumerate() {
+        if i > 0 {
+            line.push(b'\t');
+        }
+        if let Value::Str(s) = interp.tostring(value)? {
+            line.extend_from_slice(&s);
+        }
+    }
+    line.push(b'\n');
+    let _ = interp.output.write_all(&line);
+    let _ = interp.output.flush();
+    Ok(Vec::new())
+}
+
+fn base_rawequal(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    let (a, b) = (args.check(interp, 0)?, args.check(interp, 1)?);
+    Ok(vec![Value::Boolean(a.raw_equal(&b))])
+}
+
+fn base_rawget(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    let table = args.table(interp, 0)?;
+    let value = table.borrow().get(&args.get(1));
+    Ok(vec![value])
+}
+
+fn base_rawlen(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    let len = match args.get(0) {
+        Value::Table(t) => t.borrow().len(),
+        Value::Str(s) => s.len(),
+        _ => return Err(args.error(interp, 0, "table or string expected")),
+    };
+    Ok(vec![interp.integer(len as IntType)])
+}
+
+fn base_rawset(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    let table = args.table(interp, 0)?;
+    let result = table.borrow_mut().set(args.get(1), args.get(2));
+    result.map_err(|e| interp.error(e))?;
+    Ok(vec![args.get(0)])
+}
+
+fn base_select(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    let count = args.values.len() as IntType - 1;
+    if let Some(b"#") = args.get(0).as_bytes() {
+        return Ok(vec![interp.integer(count)]);
+    }
+    let n = args.int(interp, 0)?;
+    let start = if n < 0 { count + n } else { n - 1 };
+    if n == 0 || start < 0 {
+        return Err(args.error(interp, 0, "index out of range"));
+    }
+    Ok(args.rest(start as usize + 1))
+}
+
+fn base_tonumber(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    let value = args.check(interp, 0)?;
+    if args.get(1).is_nil() {
+        let number = match value {
+            Value::Int(_) | Value::Float(_) => value,
+            Value::Str(_) => match interp.coerce(&value) {
+                Number::Int(i) => Value::Int(i),
+                Number::Float(f) => Value::Float(f),
+                Number::None => Value::Nil,
+            },
+            _ => Value::Nil,
+        };
+        return Ok(vec![number]);
+    }
+    let base = args.int(interp, 1)?;
+    if !(2..=36).contains(&base) {
+        return Err(args.error(interp, 1, "base out of range"));
+    }
+    let s = match value {
+        Value::Str(s) => s,
+        _ => return Err(args.type_error(interp, 0, "string")),
+    };
+    let s = String::from_utf8_lossy(&s);
+    let s = s.trim();
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, s),
+    };
+    let mut n: IntType = 0;
+    for c in digits.chars() {
+        match c.to_digit(base as u32) {
+            Some(d) => n = n.wrapping_mul(base).wrapping_add(d as IntType),
+            None => return Ok(vec![Value::Nil]),
+        }
+    }
+    if digits.is_empty() {
+        return Ok(vec![Value::Nil]);
+    }
+    Ok(vec![interp.integer(if negative {
+        n.wrapping_neg()
+    } else {
+        n
+    })])
+}
+
+fn base_tostring(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    let value = args.check(interp, 0)?;
+    Ok(vec![interp.tostring(&value)?])
+}
+
+fn base_type(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    let value = args.check(interp, 0)?;
+    Ok(vec![Value::from(value.type_name())])
+}
+
+// `load(chunk, chunkname, mode, env)`, also `loadstring` of 5.1
+fn base_load(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    let source = match args.get(0) {
+        Value::Str(s) => s.to_vec(),
+        f @ Value::Function(_) => {
+            let mut source = Vec::new();
+            loop {
+                let piece = interp.call(&f, Vec::new())?;
+                match piece.into_iter().next() {
+                    Some(Value::Str(s)) if !s.is_empty() => source.extend_from_slice(&s),
+                    None | Some(Value::Nil) | Some(Value::Str(_)) => break,
+                    Some(_) => {
+                        return Ok(vec![
+                            Value::Nil,
+                            Value::from("reader function must return a string"),
+                        ])
+                    }
+                }
+            }
+            source
+        }
+        _ => return Err(args.type_error(interp, 0, "string")),
+    };
+    let source = String::from_utf8_lossy(&source).into_owned();
+    let name = match args.get(1) {
+        Value::Str(name) => String::from_utf8_lossy(&name).into_owned(),
+        _ => {
+            let line = source.lines().next().unwrap_or("");
+            format!("[string \"{}\"]", line)
+        }
+    };
+    let env = if args.values.len() >= 4 {
+        Some(args.get(3))
+    } else {
+        None
+    };
+    match interp.load_with_env(&source, &name, env) {
+        Ok(f) => Ok(vec![f]),
+        Err(e) => Ok(vec![Value::Nil, e.value]),
+    }
+}
+
+fn read_file(interp: &Interpreter, path: &str) -> Result<String, LuaError> {
+    std::fs::read_to_string(path).map_err(|e| interp.error(format!("cannot open {}: {}", path, e)))
+}
+
+fn base_dofile(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    let path = String::from_utf8_lossy(&args.string(interp, 0)?).into_owned();
+    let source = read_file(interp, &path)?;
+    let f = interp.load_with_env(&source, &path, None)?;
+    interp.call(&f, Vec::new())
+}
+
+fn base_loadfile(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    let path = String::from_utf8_lossy(&args.string(interp, 0)?).into_owned();
+    let env = if args.values.len() >= 3 {
+        Some(args.get(2))
+    } else {
+        None
+    };
+    let loaded =
+        read_file(interp, &path).and_then(|source| interp.load_with_env(&source, &path, env));
+    match loaded {
+        Ok(f) => Ok(vec![f]),
+        Err(e) => Ok(vec![Value::Nil, e.value]),
+    }
+}
+
+// modules added to the interpreter, `package.preload`, then files outside the sandbox
+fn base_require(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    let name = String::from_utf8_lossy(&args.string(interp, 0)?).into_owned();
+    let loaded = interp.loaded.borrow().get_str(&name);
+    if !loaded.is_nil() {
+        return Ok(vec![loaded]);
+    }
+    let package = interp.get_global("package");
+    let field = |field: &str| match &package {
+        Value::Table(t) => t.borrow().get_str(field),
+        _ => Value::Nil,
+    };
+    let mut tried = Vec::new();
+    let (loader, origin) = if let Some(source) = interp.modules.get(&name).cloned() {
+        (interp.load_with_env(&source, &name, None)?, name.clone())
+    } else if let Some(preload) = field("preload")
+        .as_table()
+        .map(|t| t.borrow().get_str(&name))
+        .filter(|f| !f.is_nil())
+    {
+        (preload, ":preload:".to_string())
+    } else {
+        tried.push(format!("no field package.preload['{}']", name));
+        let mut found = None;
+        if !interp.sandbox {
+            let path = field("path");
+            let path = path
+                .as_bytes()
+                .map(String::from_utf8_lossy)
+                .unwrap_or_default();
+            for template in path.split(';').filter(|t| !t.is_empty()) {
+                let file = template.replace('?', &name.replace('.', "/"));
+                match std::fs::read_to_string(&file) {
+                    Ok(source) => {
+                        found = Some((interp.load_with_env(&source, &file, None)?, file));
+                        break;
+                    }
+                    Err(_) => tried.push(format!("no file '{}'", file)),
+                }
+            }
+        }
+        match found {
+            Some(found) => found,
+            None => {
+                return Err(interp.error(format!(
+                    "module '{}' not found:\n\t{}",
+                    name,
+                    tried.join("\n\t")
+                )))
+            }
+        }
+    };
+    let result = interp.call(
+        &loader,
+        vec![Value::from(name.as_str()), Value::from(origin)],
+    )?;
+    let mut value = result.into_iter().next().unwrap_or_default();
+    if value.is_nil() {
+        value = interp.loaded.borrow().get_str(&name);
+    }
+    if value.is_nil() {
+        value = Value::Boolean(true);
+    }
+    interp.loaded.borrow_mut().set_str(&name, value.clone());
+    Ok(vec![value])
+}
+
+const STRING: &[(&str, Builtin)] = &[
+    ("byte", str_byte),
+    ("char", str_char),
+    ("find", str_find),
+    ("format", str_format),
+    ("gmatch", str_gmatch),
+    ("gsub", str_gsub),
+    ("len", str_len),
+    ("lower", str_lower),
+    ("match", str_match),
+    ("rep", str_rep),
+    ("reverse", str_reverse),
+    ("sub", str_sub),
+    ("upper", str_upper),
+];
+
+// start of a range, 1 based, negative from the end
+fn start_pos(pos: IntType, len: usize) -> usize {
+    if pos > 0 {
+        pos as usize
+    } else if pos == 0 || pos < -(len as IntType) {
+        1
+    } else {
+        (len as IntType + pos + 1) as usize
+    }
+}
+
+// inclusive end of a range, 1 based, negative from the end
+fn end_pos(pos: IntType, len: usize) -> usize {
+    if pos > len as IntType {
+        len
+    } else if pos >= 0 {
+        pos as usize
+    } else if pos < -(len as IntType) {
+        0
+    } else {
+        (len as IntType + pos + 1) as usize
+    }
+}
+
+fn str_len(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    let s = args.string(interp, 0)?;
+    Ok(vec![interp.integer(s.len() as IntType)])
+}
+
+fn str_sub(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    let s = args.string(interp, 0)?;
+    let start = start_pos(args.opt_int(interp, 1, 1)?, s.len());
+    let end = end_pos(args.opt_int(interp, 2, -1)?, s.len());
+    if start > end {
+        return Ok(vec![Value::from("")]);
+    }
+    Ok(vec![Value::from(&s[start - 1..end])])
+}
+
+fn str_upper(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    Ok(vec![Value::from(
+        args.string(interp, 0)?.to_ascii_uppercase(),
+    )])
+}
+
+fn str_lower(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    Ok(vec![Value::from(
+        args.string(interp, 0)?.to_ascii_lowercase(),
+    )])
+}
+
+fn str_reverse(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    let mut s = args.string(interp, 0)?.to_vec();
+    s.reverse();
+    Ok(vec![Value::from(s)])
+}
+
+fn str_rep(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    let s = args.string(interp, 0)?;
+    let n = args.int(interp, 1)?;
+    let sep = args.opt_string(interp, 2, "")?;
+    if n <= 0 {
+        return Ok(vec![Value::from("")]);
+    }
+    let size = (s.len() + sep.len()).checked_mul(n as usize);
+    if size.is_none_or(|size| size > MAX_STRING_SIZE) {
+        return Err(interp.error("resulting string too large"));
+    }
+    let mut result = Vec::with_capacity(size.unwrap());
+    for i in 0..n {
+        if i > 0 {
+            result.extend_from_slice(&sep);
+        }
+        result.extend_from_slice(&s);
+    }
+    Ok(vec![Value::from(result)])
+}
+
+fn str_byte(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    let s = args.string(interp, 0)?;
+    let i = args.opt_int(interp, 1, 1)?;
+    let start = start_pos(i, s.len());
+    let end = end_pos(args.opt_int(interp, 2, i)?, s.len());
+    if start > end {
+        return Ok(Vec::new());
+    }
+    Ok(s[start - 1..end]
+        .iter()
+        .map(|c| interp.integer(*c as IntType))
+        .collect())
+}
+
+fn str_char(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    let mut s = Vec::with_capacity(args.values.len());
+    for i in 0..args.values.len() {
+        let c = args.int(interp, i)?;
+        if !(0..=255).contains(&c) {
+            return Err(args.error(interp, i, "value out of range"));
+        }
+        s.push(c as u8);
+    }
+    Ok(vec![Value::from(s)])
+}
+
+// values of the captures, the whole match if there are none
+fn captures(
+    interp: &Interpreter,
+    src: &[u8],
+    start: usize,
+    end: usize,
+    captures: &[Capture],
+) -> Vec<Value> {
+    if captures.is_empty() {
+        return vec![Value::from(&src[start..end])];
+    }
+    captures
+        .iter()
+        .map(|capture| match capture {
+            Capture::Str(start, end) => Value::from(&src[*start..*end]),
+            Capture::Position(pos) => interp.integer(*pos as IntType + 1),
+        })
+        .collect()
+}
+
+// `string.find` and `string.match`
+fn str_find_aux(interp: &mut Interpreter, args: &Args, find: bool) -> LuaResult {
+    let s = args.string(interp, 0)?;
+    let pat = args.string(interp, 1)?;
+    let init = start_pos(args.opt_int(interp, 2, 1)?, s.len());
+    if init > s.len() + 1 {
+        return Ok(vec![Value::Nil]);
+    }
+    if find && (args.get(3).truthy() || pattern::is_plain(&pat)) {
+        let found = if pat.is_empty() {
+            Some(init - 1)
+        } else {
+            s[init - 1..]
+                .windows(pat.len())
+                .position(|w| w == &pat[..])
+                .map(|i| i + init - 1)
+        };
+        return Ok(match found {
+            Some(start) => vec![
+                interp.integer(start as IntType + 1),
+                interp.integer((start + pat.len()) as IntType),
+            ],
+            None => vec![Value::Nil],
+        });
+    }
+    match pattern::find(&s, &pat, init - 1).map_err(|e| interp.error(e))? {
+        Some((start, end, caps)) if find => {
+            let mut results = vec![
+                interp.integer(start as IntType + 1),
+                interp.integer(end as IntType),
+            ];
+            if !caps.is_empty() {
+                results.extend(captures(interp, &s, start, end, &caps));
+            }
+            Ok(results)
+        }
+        Some((start, end, caps)) => Ok(captures(interp, &s, start, end, &caps)),
+        None => Ok(vec![Value::Nil]),
+    }
+}
+
+fn str_find(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    str_find_aux(interp, args, true)
+}
+
+fn str_match(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    str_find_aux(interp, args, false)
+}
+
+fn str_gmatch(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    let s = args.string(interp, 0)?;
+    let pat = args.string(interp, 1)?;
+    let pos = Cell::new(0);
+    let last_match = Cell::new(None);
+    let iterator = Value::builtin("gmatch_next", move |interp, _| {
+        let mut start = pos.get();
+        while start <= s.len() {
+            let found = pattern::match_at(&s, &pat, start).map_err(|e| interp.error(e))?;
+            match found {
+                Some((end, caps)) if Some(end) != last_match.get() => {
+                    pos.set(end);
+                    last_match.set(Some(end));
+                    return Ok(captures(interp, &s, start, end, &caps));
+                }
+                _ => start += 1,
+            }
+        }
+        pos.set(start);
+        Ok(vec![Value::Nil])
+    });
+    Ok(vec![iterator])
+}
+
+fn str_gsub(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    let src = args.string(interp, 0)?;
+    let pat = args.string(interp, 1)?;
+    let repl = args.get(2);
+    if !matches!(
+        repl,
+        Value::Str(_) | Value::Int(_) | Value::Float(_) | Value::Table(_) | Value::Function(_)
+    ) {
+        return Err(args.type_error(interp, 2, "string/function/table"));
+    }
+    let max = match args.get(3) {
+        Value::Nil => IntType::MAX,
+        _ => args.int(interp, 3)?,
+    };
+    let (anchor, pat) = match pat.first() {
+        Some(b'^') => (true, &pat[1..]),
+        _ => (false, &pat[..]),
+    };
+    let mut result = Vec::new();
+    let mut start = 0;
+    let mut last_match = None;
+    let mut count = 0;
+    while count < max {
+        let found = pattern::match_at(&src, pat, start).map_err(|e| interp.error(e))?;
+        match found {
+            Some((end, caps)) if Some(end) != last_match => {
+                count += 1;
+                let whole = &src[start..end];
+                let value = match &repl {
+                    Value::Table(_) => {
+                        let key = captures(interp, &src, start, end, &caps).swap_remove(0);
+                        interp.index(&repl, &key)?.unwrap_or_default()
+                    }
+                    Value::Function(_) => {
+                        let caps = captures(interp, &src, start, end, &caps);
+                        interp
+                            .call(&repl, caps)?
+                            .into_iter()
+                            .next()
+                            .unwrap_or_default()
+                    }
+                    _ => {
+                        let template = interp.to_str(&repl).unwrap();
+                        Value::from(expand(interp, &template, &src, whole, &caps)?)
+                    }
+                };
+                match value {
+                    Value::Nil | Value::Boolean(false) => result.extend_from_slice(whole),
+                    value => match interp.to_str(&value) {
+                        Some(s) => result.extend_from_slice(&s),
+                        None => {
+                            return Err(interp.error(format!(
+                                "invalid replacement value (a {})",
+                                value.type_name()
+                            )))
+                        }
+                    },
+                }
+                start = end;
+                last_match = Some(end);
+            }
+            _ if start < src.len() => {
+                result.push(src[start]);
+                start += 1;
+            }
+            _ => break,
+        }
+        if anchor {
+            break;
+        }
+    }
+    if start < src.len() {
+        result.extend_from_slice(&src[start..]);
+    }
+    Ok(vec![Value::from(result), interp.integer(count)])
+}
+
+// a replacement string with `%0` to `%9` and `%%`
+fn expand(
+    interp: &Interpreter,
+    template: &[u8],
+    src: &[u8],
+    whole: &[u8],
+    caps: &[Capture],
+) -> Result<Vec<u8>, LuaError> {
+    let mut result = Vec::with_capacity(template.len());
+    let mut i = 0;
+    while i < template.len() {
+        let c = template[i];
+        i += 1;
+        if c != b'%' {
+            result.push(c);
+            continue;
+        }
+        match template.get(i) {
+            Some(b'%') => result.push(b'%'),
+            Some(b'0') => result.extend_from_slice(whole),
+            Some(d) if d.is_ascii_digit() => {
+                let index = (d - b'0') as usize;
+                match caps.get(index - 1) {
+                    Some(Capture::Str(start, end)) => result.extend_from_slice(&src[*start..*end]),
+                    Some(Capture::Position(pos)) => {
+                        result.extend_from_slice((pos + 1).to_string().as_bytes())
+                    }
+                    None if index == 1 && caps.is_empty() => result.extend_from_slice(whole),
+                    None => {
+                        return Err(interp.error(format!(
+                            "invalid capture index %{} in replacement string",
+                            index
+                        )))
+                    }
+                }
+            }
+            _ => return Err(interp.error("invalid use of '%' in replacement string")),
+        }
+        i += 1;
+    }
+    Ok(result)
+}
+
+// flags, width and precision of a `string.format` conversion
+#[derive(Default)]
+struct Spec {
+    left: bool,
+    zero: bool,
+    plus: bool,
+    space: bool,
+    alt: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl Spec {
+    // `body` padded to the width, zeros go after the sign and `0x` of numbers
+    fn pad(&self, out: &mut Vec<u8>, body: &[u8], numeric: bool) {
+        if body.len() >= self.width {
+            out.extend_from_slice(body);
+        } else if self.left {
+            out.extend_from_slice(body);
+            out.resize(out.len() + self.width - body.len(), b' ');
+        } else if self.zero && numeric {
+            let mut prefix = match body.first() {
+                Some(b'-') | Some(b'+') | Some(b' ') => 1,
+                _ => 0,
+            };
+            if body[prefix..].starts_with(b"0x") || body[prefix..].starts_with(b"0X") {
+                prefix += 2;
+            }
+            out.extend_from_slice(&body[..prefix]);
+            out.resize(out.len() + self.width - body.len(), b'0');
+            out.extend_from_slice(&body[prefix..]);
+        } else {
+            out.resize(out.len() + self.width - body.len(), b' ');
+            out.extend_from_slice(body);
+        }
+    }
+
+    fn sign(&self, negative: bool) -> &'static str {
+        if negative {
+            "-"
+        } else if self.plus {
+            "+"
+        } else if self.space {
+            " "
+        } else {
+            ""
+        }
+    }
+}
+
+fn str_format(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    let format = args.string(interp, 0)?;
+    let mut out = Vec::with_capacity(format.len());
+    let mut arg = 0;
+    let mut i = 0;
+    while i < format.len() {
+        let c = format[i];
+        i += 1;
+        if c != b'%' {
+            out.push(c);
+            continue;
+        }
+        if format.get(i) == Some(&b'%') {
+            out.push(b'%');
+            i += 1;
+            continue;
+        }
+        let start = i;
+        let mut spec = Spec::default();
+        while let Some(flag) = format.get(i) {
+            match flag {
+                b'-' => spec.left = true,
+                b'0' => spec.zero = true,
+                b'+' => spec.plus = true,
+                b' ' => spec.space = true,
+                b'#' => spec.alt = true,
+                _ => break,
+            }
+            i += 1;
+        }
+        let digits = |i: &mut usize| {
+            let mut n = 0;
+            let mut count = 0;
+            while let Some(d) = format.get(*i).filter(|d| d.is_ascii_digit()) {
+                n = n * 10 + (d - b'0') as usize;
+                count += 1;
+                *i += 1;
+            }
+            (n, count)
+        };
+        let (width, width_digits) = digits(&mut i);
+        spec.width = width;
+        let mut precision_digits = 0;
+        if format.get(i) == Some(&b'.') {
+            i += 1;
+            let (precision, count) = digits(&mut i);
+            spec.precision = Some(precision);
+            precision_digits = count;
+        }
+        let conversion = format.get(i).copied();
+        i += 1;
+        let invalid = || {
+            let end = i.min(format.len());
+            interp.error(format!(
+                "invalid conversion '%{}' to 'format'",
+                String::from_utf8_lossy(&format[start..end])
+            ))
+        };
+        if width_digits > 2 || precision_digits > 2 {
+            return Err(invalid());
+        }
+        arg += 1;
+        match conversion {
+            Some(b'c') => {
+                let c = args.int(interp, arg)?;
+                spec.pad(&mut out, &[c as u8], false);
+            }
+            Some(b'd') | Some(b'i') => {
+                let n = args.int(interp, arg)?;
+                let mut digits = n.unsigned_abs().to_string();
+                if let Some(precision) = spec.precision {
+                    if precision == 0 && n == 0 {
+                        digits.clear();
+                    }
+                    while digits.len() < precision {
+                        digits.insert(0, '0');
+                    }
+                }
+                let body = format!("{}{}", spec.sign(n < 0), digits);
+                spec.pad(&mut out, body.as_bytes(), spec.precision.is_none());
+            }
+            Some(c @ b'x') | Some(c @ b'X') | Some(c @ b'o') => {
+                let n = args.int(interp, arg)? as u64;
+                let (mut digits, prefix) = match c {
+                    b'x' => (format!("{:x}", n), "0x"),
+                    b'X' => (format!("{:X}", n), "0X"),
+                    _ => (format!("{:o}", n), "0"),
+                };
+                if let Some(precision) = spec.precision {
+                    if precision == 0 && n == 0 {
+                        digits.clear();
+                    }
+                    while digits.len() < precision {
+                        digits.insert(0, '0');
+                    }
+                }
+                let body = if spec.alt && n != 0 && !(c == b'o' && digits.starts_with('0')) {
+                    format!("{}{}", prefix, digits)
+                } else {
+                    digits
+                };
+                spec.pad(&mut out, body.as_bytes(), spec.precision.is_none());
+            }
+            Some(c @ b'e') | Some(c @ b'E') | Some(c @ b'f') | Some(c @ b'F') | Some(c @ b'g')
+            | Some(c @ b'G') => {
+                let f = args.float(interp, arg)?;
+                let precision = spec.precision.unwrap_or(6);
+                let body = if !f.is_finite() {
+                    value::format_special(f.abs())
+                } else {
+                    match c.to_ascii_lowercase() {
+                        b'e' => value::format_e(f.abs(), precision),
+                        b'f' => format!("{:.*}", precision, f.abs()),
+                        _ => value::format_g(f.abs(), precision, spec.alt),
+                    }
+                };
+                let body = format!("{}{}", spec.sign(f.is_sign_negative() && !f.is_nan()), body);
+                let body = if c.is_ascii_uppercase() {
+                    body.to_uppercase()
+                } else {
+                    body
+                };
+                spec.pad(&mut out, body.as_bytes(), f.is_finite());
+            }
+            Some(b'q') => quote(interp, args, arg, &mut out)?,
+            Some(b's') => {
+                let value = args.check(interp, arg)?;
+                let s = match interp.tostring(&value)? {
+                    Value::Str(s) => s,
+                    _ => unreachable!(),
+                };
+                let s = match spec.precision {
+                    Some(precision) if precision < s.len() => &s[..precision],
+                    _ => &s[..],
+                };
+                spec.pad(&mut out, s, false);
+            }
+            _ => return Err(invalid()),
+        }
+    }
+    Ok(vec![Value::from(out)])
+}
+
+// `%q`, a literal reading back as the same value
+fn quote(
+    interp: &mut Interpreter,
+    args: &Args,
+    arg: usize,
+    out: &mut Vec<u8>,
+) -> Result<(), LuaError> {
+    match args.check(interp, arg)? {
+        Value::Str(s) => {
+            out.push(b'"');
+            for (i, c) in s.iter().enumerate() {
+                match c {
+                    b'"' | b'\\' | b'\n' => {
+                        out.push(b'\\');
+                        out.push(*c);
+                    }
+                    b'\r' => out.extend_from_slice(b"\\r"),
+                    c if c.is_ascii_control() => {
+                        let next_digit = s.get(i + 1).is_some_and(u8::is_ascii_digit);
+                        let escape = if next_digit {
+                            format!("\\{:03}", c)
+                        } else {
+                            format!("\\{}", c)
+                        };
+                        out.extend_from_slice(escape.as_bytes());
+                    }
+                    c => out.push(*c),
+                }
+            }
+            out.push(b'"');
+        }
+        Value::Int(IntType::MIN) => out.extend_from_slice(b"0x8000000000000000"),
+        Value::Int(i) => out.extend_from_slice(i.to_string().as_bytes()),
+        Value::Float(f) if f == FloatType::INFINITY => out.extend_from_slice(b"1e9999"),
+        Value::Float(f) if f == FloatType::NEG_INFINITY => out.extend_from_slice(b"-1e9999"),
+        Value::Float(f) if f.is_nan() => out.extend_from_slice(b"(0/0)"),
+        Value::Float(f) => out.extend_from_slice(format!("{:?}", f).as_bytes()),
+        value @ Value::Nil | value @ Value::Boolean(_) => {
+            out.extend_from_slice(value.to_string().as_bytes())
+        }
+        _ => return Err(args.error(interp, arg, "value has no literal form")),
+    }
+    Ok(())
+}
+
+const TABLE: &[(&str, Builtin)] = &[
+    ("concat", table_concat),
+    ("insert", table_insert),
+    ("remove", table_remove),
+    ("sort", table_sort),
+];
+
+fn table_concat(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    let table = args.table(interp, 0)?;
+    let sep = args.opt_string(interp, 1, "")?;
+    let first = args.opt_int(interp, 2, 1)?;
+    let len = table.borrow().len() as IntType;
+    let last = args.opt_int(interp, 3, len)?;
+    let mut result = Vec::new();
+    let mut i = first;
+    while i <= last {
+        let value = table.borrow().get_int(i);
+        match interp.to_str(&value) {
+            Some(s) => result.extend_from_slice(&s),
+            None => {
+                return Err(interp.error(format!(
+                    "invalid value (at index {}) in table for 'concat'",
+                    i
+                )))
+            }
+        }
+        if i < last {
+            result.extend_from_slice(&sep);
+        }
+        i += 1;
+    }
+    Ok(vec![Value::from(result)])
+}
+
+fn table_insert(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    let table = args.table(interp, 0)?;
+    let mut t = table.borrow_mut();
+    let end = t.len() as IntType + 1;
+    match args.values.len() {
+        2 => t.set_int(end, args.get(1)),
+        3 => {
+            drop(t);
+            let pos = args.int(interp, 1)?;
+            if pos < 1 || pos > end {
+                return Err(args.error(interp, 1, "position out of bounds"));
+            }
+            let mut t = table.borrow_mut();
+            let mut i = end;
+            while i > pos {
+                let value = t.get_int(i - 1);
+                t.set_int(i, value);
+                i -= 1;
+            }
+            t.set_int(pos, args.get(2));
+        }
+        _ => return Err(interp.error("wrong number of arguments to 'insert'")),
+    }
+    Ok(Vec::new())
+}
+
+fn table_remove(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    let table = args.table(interp, 0)?;
+    let size = table.borrow().len() as IntType;
+    let pos = args.opt_int(interp, 1, size)?;
+    if args.values.len() > 1
+        && size + 1 != pos
+        && (pos < 1 || pos > size + 1)
+        && !(size == 0 && pos == 0)
+    {
+        return Err(args.error(interp, 1, "position out of bounds"));
+    }
+    let mut t = table.borrow_mut();
+    let value = t.get_int(pos);
+    let mut i = pos;
+    while i < size {
+        let next = t.get_int(i + 1);
+        t.set_int(i, next);
+        i += 1;
+    }
+    if i <= size {
+        t.set_int(i, Value::Nil);
+    }
+    Ok(vec![value])
+}
+
+fn table_sort(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    let table = args.table(interp, 0)?;
+    let comp = args.get(1);
+    if !comp.is_nil() && !matches!(comp, Value::Function(_)) {
+        return Err(args.type_error(interp, 1, "function"));
+    }
+    let len = table.borrow().len() as IntType;
+    let mut values: Vec<Value> = (1..=len).map(|i| table.borrow().get_int(i)).collect();
+    let mut less = |interp: &mut Interpreter, a: &Value, b: &Value| match &comp {
+        Value::Nil => interp.less_than(a, b),
+        comp => Ok(interp
+            .call(comp, vec![a.clone(), b.clone()])?
+            .first()
+            .is_some_and(Value::truthy)),
+    };
+    merge_sort(interp, &mut values, &mut less)?;
+    let mut t = table.borrow_mut();
+    for (i, value) in values.into_iter().enumerate() {
+        t.set_int(i as IntType + 1, value);
+    }
+    Ok(Vec::new())
+}
+
+// the comparison may fail, so no `sort_by`
+fn merge_sort<F>(
+    interp: &mut Interpreter,
+    values: &mut Vec<Value>,
+    less: &mut F,
+) -> Result<(), LuaError>
+where
+    F: FnMut(&mut Interpreter, &Value, &Value) -> Result<bool, LuaError>,
+{
+    if values.len() <= 1 {
+        return Ok(());
+    }
+    let mut right = values.split_off(values.len() / 2);
+    merge_sort(interp, values, less)?;
+    merge_sort(interp, &mut right, less)?;
+    let left = std::mem::take(values);
+    let (mut left, mut right) = (left.into_iter().peekable(), right.into_iter().peekable());
+    while let (Some(a), Some(b)) = (left.peek(), right.peek()) {
+        if less(interp, b, a)? {
+            values.push(right.next().unwrap());
+        } else {
+            values.push(left.next().unwrap());
+        }
+    }
+    values.extend(left);
+    values.extend(right);
+    Ok(())
+}
+
+fn table_unpack(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    let table = args.table(interp, 0)?;
+    let first = args.opt_int(interp, 1, 1)?;
+    let len = table.borrow().len() as IntType;
+    let last = args.opt_int(interp, 2, len)?;
+    if first > last {
+        return Ok(Vec::new());
+    }
+    if last.wrapping_sub(first) >= 1_000_000 || last.checked_sub(first).is_none() {
+        return Err(interp.error("too many results to unpack"));
+    }
+    let t = table.borrow();
+    Ok((first..=last).map(|i| t.get_int(i)).collect())
+}
+
+fn table_pack(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    let mut table = value::Table::default();
+    for (i, value) in args.values.iter().enumerate() {
+        table.set_int(i as IntType + 1, value.clone());
+    }
+    table.set_str("n", interp.integer(args.values.len() as IntType));
+    Ok(vec![Value::Table(Rc::new(RefCell::new(table)))])
+}
+
+const MATH: &[(&str, Builtin)] = &[
+    ("abs", math_abs),
+    ("acos", math_acos),
+    ("asin", math_asin),
+    ("atan", math_atan),
+    ("ceil", math_ceil),
+    ("cos", math_cos),
+    ("exp", math_exp),
+    ("floor", math_floor),
+    ("fmod", math_fmod),
+    ("log", math_log),
+    ("max", math_max),
+    ("min", math_min),
+    ("modf", math_modf),
+    ("random", math_random),
+    ("randomseed", math_randomseed),
+    ("sin", math_sin),
+    ("sqrt", math_sqrt),
+    ("tan", math_tan),
+];
+
+fn math_abs(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    Ok(vec![match args.number(interp, 0)? {
+        Number::Int(i) => Value::Int(i.wrapping_abs()),
+        Number::Float(f) => Value::Float(f.abs()),
+        Number::None => unreachable!(),
+    }])
+}
+
+// an integral float as an integer if it fits
+fn rounded(interp: &Interpreter, f: FloatType) -> Value {
+    match float_to_int(f) {
+        Some(i) => interp.integer(i),
+        None => Value::Float(f),
+    }
+}
+
+fn math_ceil(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    Ok(vec![match args.number(interp, 0)? {
+        Number::Float(f) => rounded(interp, f.ceil()),
+        Number::Int(i) => Value::Int(i),
+        Number::None => unreachable!(),
+    }])
+}
+
+fn math_floor(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    Ok(vec![match args.number(interp, 0)? {
+        Number::Float(f) => rounded(interp, f.floor()),
+        Number::Int(i) => Value::Int(i),
+        Number::None => unreachable!(),
+    }])
+}
+
+fn math_fmod(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    match (args.number(interp, 0)?, args.number(interp, 1)?) {
+        (Number::Int(_), Number::Int(0)) => Err(args.error(interp, 1, "zero")),
+        (Number::Int(a), Number::Int(b)) => Ok(vec![Value::Int(a.wrapping_rem(b))]),
+        _ => Ok(vec![Value::Float(
+            args.float(interp, 0)? % args.float(interp, 1)?,
+        )]),
+    }
+}
+
+fn math_modf(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    let f = args.float(interp, 0)?;
+    let int = if f.is_infinite() { f } else { f.trunc() };
+    let frac = if f.is_infinite() { 0.0 } else { f - int };
+    Ok(vec![Value::Float(int), Value::Float(frac)])
+}
+
+fn math_log(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    let x = args.float(interp, 0)?;
+    let result = match args.get(1) {
+        Value::Nil => x.ln(),
+        _ => match args.float(interp, 1)? {
+            2.0 => x.log2(),
+            10.0 => x.log10(),
+            base => x.ln() / base.ln(),
+        },
+    };
+    Ok(vec![Value::Float(result)])
+}
+
+fn math_extreme(interp: &mut Interpreter, args: &Args, max: bool) -> LuaResult {
+    args.number(interp, 0)?;
+    let mut best = args.get(0);
+    for i in 1..args.values.len() {
+        args.number(interp, i)?;
+        let value = args.get(i);
+        let better = if max {
+            interp.less_than(&best, &value)?
+        } else {
+            interp.less_than(&value, &best)?
+        };
+        if better {
+            best = value;
+        }
+    }
+    Ok(vec![best])
+}
+
+fn math_max(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    math_extreme(interp, args, true)
+}
+
+fn math_min(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    math_extreme(interp, args, false)
+}
+
+fn math_float(interp: &mut Interpreter, args: &Args, f: fn(FloatType) -> FloatType) -> LuaResult {
+    Ok(vec![Value::Float(f(args.float(interp, 0)?))])
+}
+
+fn math_acos(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    math_float(interp, args, FloatType::acos)
+}
+
+fn math_asin(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    math_float(interp, args, FloatType::asin)
+}
+
+fn math_cos(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    math_float(interp, args, FloatType::cos)
+}
+
+fn math_exp(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    math_float(interp, args, FloatType::exp)
+}
+
+fn math_sin(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    math_float(interp, args, FloatType::sin)
+}
+
+fn math_sqrt(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    math_float(interp, args, FloatType::sqrt)
+}
+
+fn math_tan(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    math_float(interp, args, FloatType::tan)
+}
+
+fn math_atan(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    let y = args.float(interp, 0)?;
+    let x = match args.get(1) {
+        Value::Nil => 1.0,
+        _ => args.float(interp, 1)?,
+    };
+    Ok(vec![Value::Float(y.atan2(x))])
+}
+
+fn math_pow(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    let (x, y) = (args.float(interp, 0)?, args.float(interp, 1)?);
+    Ok(vec![Value::Float(x.powf(y))])
+}
+
+fn math_tointeger(_: &mut Interpreter, args: &Args) -> LuaResult {
+    Ok(vec![match args.get(0) {
+        Value::Int(i) => Value::Int(i),
+        Value::Float(f) => float_to_int(f).map_or(Value::Nil, Value::Int),
+        _ => Value::Nil,
+    }])
+}
+
+fn math_type(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    Ok(vec![match args.check(interp, 0)? {
+        Value::Int(_) => Value::from("integer"),
+        Value::Float(_) => Value::from("float"),
+        _ => Value::Nil,
+    }])
+}
+
+fn math_ult(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    let (a, b) = (args.int(interp, 0)?, args.int(interp, 1)?);
+    Ok(vec![Value::Boolean((a as u64) < (b as u64))])
+}
+
+// xorshift64*, deterministic unless seeded
+fn next_random(interp: &mut Interpreter) -> u64 {
+    let mut x = interp.seed;
+    x ^= x >> 12;
+    x ^= x << 25;
+    x ^= x >> 27;
+    interp.seed = x;
+    x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+}
+
+fn math_random(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    let r = next_random(interp);
+    let (low, high) = match args.values.len() {
+        0 => {
+            return Ok(vec![Value::Float(
+                (r >> 11) as FloatType / (1u64 << 53) as FloatType,
+            )])
+        }
+        1 => (1, args.int(interp, 0)?),
+        2 => (args.int(interp, 0)?, args.int(interp, 1)?),
+        _ => return Err(interp.error("wrong number of arguments")),
+    };
+    if low > high {
+        return Err(args.error(interp, args.values.len() - 1, "interval is empty"));
+    }
+    let range = (high as u64).wrapping_sub(low as u64).wrapping_add(1);
+    let offset = if range == 0 { r } else { r % range };
+    Ok(vec![
+        interp.integer((low as u64).wrapping_add(offset) as IntType)
+    ])
+}
+
+fn math_randomseed(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    let seed = match args.get(0) {
+        Value::Nil => 0,
+        _ => match args.number(interp, 0)? {
+            Number::Int(i) => i as u64,
+            Number::Float(f) => f.to_bits(),
+            Number::None => unreachable!(),
+        },
+    };
+    // xorshift needs a state other than zero
+    interp.seed = seed ^ 0x9e37_79b9_7f4a_7c15;
+    Ok(Vec::new())
+}
+
+const IO: &[(&str, Builtin)] = &[("read", io_read), ("write", io_write)];
+
+fn io_write(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    for i in 0..args.values.len() {
+        let s = args.string(interp, i)?;
+        let _ = interp.output.write_all(&s);
+    }
+    let _ = interp.output.flush();
+    Ok(Vec::new())
+}
+
+// `io.read` of stdin for the formats "l", "L", "n" and "a"
+fn io_read(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    let format = args.opt_string(interp, 0, "l")?;
+    let format = format.strip_prefix(b"*").unwrap_or(&format);
+    let stdin = std::io::stdin();
+    let mut input = String::new();
+    let read = match format.first() {
+        Some(b'a') => std::io::Read::read_to_string(&mut stdin.lock(), &mut input),
+        Some(b'l') | Some(b'L') | Some(b'n') => stdin.lock().read_line(&mut input),
+        _ => return Err(args.error(interp, 0, "invalid format")),
+    };
+    if read.is_err() || (input.is_empty() && format.first() != Some(&b'a')) {
+        return Ok(vec![Value::Nil]);
+    }
+    Ok(vec![match format.first() {
+        Some(b'l') => Value::from(input.trim_end_matches(['\n', '\r'])),
+        Some(b'n') => match Lexer::str_to_num(input.trim()) {
+            Number::Int(i) => interp.integer(i),
+            Number::Float(f) => Value::Float(f),
+            Number::None => Value::Nil,
+        },
+        _ => Value::from(input),
+    }])
+}
+
+const OS: &[(&str, Builtin)] = &[
+    ("clock", os_clock),
+    ("exit", os_exit),
+    ("getenv", os_getenv),
+    ("time", os_time),
+];
+
+fn os_clock(_: &mut Interpreter, _: &Args) -> LuaResult {
+    static START: std::sync::OnceLock<std::time::Instant> = std::sync::OnceLock::new();
+    let start = START.get_or_init(std::time::Instant::now);
+    Ok(vec![Value::Float(start.elapsed().as_secs_f64())])
+}
+
+fn os_exit(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    let code = match args.get(0) {
+        Value::Nil | Value::Boolean(true) => 0,
+        Value::Boolean(false) => 1,
+        _ => args.int(interp, 0)? as i32,
+    };
+    let _ = interp.output.flush();
+    std::process::exit(code)
+}
+
+fn os_getenv(interp: &mut Interpreter, args: &Args) -> LuaResult {
+    let name = args.string(interp, 0)?;
+    let value = std::env::var_os(String::from_utf8_lossy(&name).as_ref());
+    Ok(vec![match value {
+        Some(value) => Value::from(value.to_string_lossy().into_owned()),
+        None => Value::Nil,
+    }])
+}
+
+fn os_time(interp: &mut Interpreter, _: &Args) -> LuaResult {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    Ok(vec![interp.integer(now as IntType)])
+}
@@ -1,6 +1,6 @@
 use crate::types::{FloatType, IntType};
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Source {
     pub pos: usize,
     pub length: usize,
@@ -8,6 +8,69 @@ pub struct Source {
     pub col: usize,
 }
 
+// source range covered by an ast node, from the first to the last token it consumed.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Span {
+    // byte offset of the first char
+    pub start: usize,
+    // byte offset past the last char
+    pub end: usize,
+    // line and column of the first char
+    pub line: usize,
+    pub col: usize,
+}
+
+impl Span {
+    // span covering everything from `first` to `last`
+    pub fn new(first: &Source, last: &Source) -> Self {
+        Span {
+            start: first.pos,
+            end: last.pos + last.length,
+            line: first.line,
+            col: first.col,
+        }
+    }
+
+    // empty span located at the start of `source`
+    pub fn at(source: &Source) -> Self {
+        Span {
+            start: source.pos,
+            end: source.pos,
+            line: source.line,
+            col: source.col,
+        }
+    }
+
+    // span covering both `self` and `other`
+    pub fn merge(self, other: Span) -> Self {
+        let (first, last) = if self.start <= other.start {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        Span {
+            start: first.start,
+            end: first.end.max(last.end),
+            line: first.line,
+            col: first.col,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+}
+
+impl From<&Source> for Span {
+    fn from(source: &Source) -> Self {
+        Span::new(source, source)
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum TokenType {
     And,
@@ -57,6 +120,8 @@ pub enum TokenType {
     Flt,
     // int number
     Int,
+    // LuaJIT `ULL` int number, the value keeps the bits of the unsigned one
+    UInt,
     // var name
     Name,
     // string literal
@@ -91,6 +156,8 @@ pub enum TokenType {
     Semi,
     // .
     Attr,
+    // bad input skipped by `Lexer::run_recovering`
+    Error,
 }
 
 impl TokenType {
@@ -124,7 +191,7 @@ impl TokenType {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum TokenValue {
     None,
     Float(FloatType),
@@ -132,11 +199,42 @@ pub enum TokenValue {
     Str(String),
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TriviaKind {
+    Whitespace,
+    LineBreak,
+    // -- comment
+    ShortComment,
+    // --[==[ comment ]==], with the count of '='
+    LongComment(usize),
+}
+
+// text between tokens, only kept if the lexer is asked to
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trivia {
+    pub kind: TriviaKind,
+    pub text: String,
+    pub source: Source,
+}
+
+impl Trivia {
+    pub fn is_comment(&self) -> bool {
+        matches!(
+            self.kind,
+            TriviaKind::ShortComment | TriviaKind::LongComment(_)
+        )
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub struct Token {
     pub t: TokenType,
     pub value: TokenValue,
     pub source: Source,
+    // trivia before this token which doesn't trail the previous one
+    pub leading: Vec<Trivia>,
+    // trivia after this token up to the end of its line
+    pub trailing: Vec<Trivia>,
 }
 
 impl Token {
@@ -1,8 +1,87 @@
+use std::fmt;
 
 pub type IntType = i64;
 pub type FloatType = f64;
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Number {
     Int(IntType),
     Float(FloatType),
     None,
 }
+
+// language dialect accepted by lexer and parser
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub enum LuaVersion {
+    Lua51,
+    Lua52,
+    Lua53,
+    #[default]
+    Lua54,
+    LuaJIT,
+}
+
+impl LuaVersion {
+    // `goto` and `::label::`
+    pub fn has_goto(self) -> bool {
+        self != LuaVersion::Lua51
+    }
+
+    // standalone `;` statements
+    pub fn has_empty_stat(self) -> bool {
+        self != LuaVersion::Lua51
+    }
+
+    // `\x` and `\z` escapes in strings
+    pub fn has_hex_escape(self) -> bool {
+        self != LuaVersion::Lua51
+    }
+
+    // `\u{XXX}` escapes in strings
+    pub fn has_utf8_escape(self) -> bool {
+        matches!(
+            self,
+            LuaVersion::Lua53 | LuaVersion::Lua54 | LuaVersion::LuaJIT
+        )
+    }
+
+    // integer subtype, otherwise every numeral is a float
+    pub fn has_integer(self) -> bool {
+        matches!(self, LuaVersion::Lua53 | LuaVersion::Lua54)
+    }
+
+    // `&`, `|`, `~`, `<<`, `>>` and `//`
+    pub fn has_bitwise_ops(self) -> bool {
+        matches!(self, LuaVersion::Lua53 | LuaVersion::Lua54)
+    }
+
+    // globals are fields of `_ENV`, which a local of that name replaces
+    pub fn has_env(self) -> bool {
+        matches!(
+            self,
+            LuaVersion::Lua52 | LuaVersion::Lua53 | LuaVersion::Lua54
+        )
+    }
+
+    // `<const>` and `<close>`
+    pub fn has_attribs(self) -> bool {
+        self == LuaVersion::Lua54
+    }
+
+    // `LL` and `ULL` suffixes on integer numerals
+    pub fn has_int64_suffix(self) -> bool {
+        self == LuaVersion::LuaJIT
+    }
+}
+
+impl fmt::Display for LuaVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            LuaVersion::Lua51 => "Lua 5.1",
+            LuaVersion::Lua52 => "Lua 5.2",
+            LuaVersion::Lua53 => "Lua 5.3",
+            LuaVersion::Lua54 => "Lua 5.4",
+            LuaVersion::LuaJIT => "LuaJIT",
+        };
+        write!(f, "{}", name)
+    }
+}
@@ -0,0 +1,411 @@
+use std::fmt;
+
+use crate::bytecode::{
+    Constant, Instruction, LocalVar, Proto, UpvalueDesc, LUAC_DATA, LUAC_INT, LUAC_NUM, SIGNATURE,
+};
+use crate::types::{FloatType, IntType, LuaVersion};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UndumpError {
+    pub message: String,
+    // position in the chunk
+    pub offset: usize,
+}
+
+impl fmt::Display for UndumpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[undump error] {} at byte {}.",
+            self.message, self.offset
+        )
+    }
+}
+
+impl std::error::Error for UndumpError {}
+
+// a binary chunk, instructions keep the encoding of its version
+#[derive(Clone, Debug, PartialEq)]
+pub struct Chunk {
+    pub version: LuaVersion,
+    pub main: Proto,
+}
+
+// reads a chunk of Lua 5.1, 5.3 or 5.4 as `luac` writes it for any word size and byte order.
+// the differences are smoothed over: 5.4 line info is made absolute and its upvalue kinds
+// dropped, 5.1 has no upvalue descriptors, the instructions after CLOSURE give them
+pub fn undump(data: &[u8]) -> Result<Chunk, UndumpError> {
+    let mut reader = Reader {
+        data,
+        pos: 0,
+        version: LuaVersion::Lua53,
+        big_endian: false,
+        int_size: 4,
+        size_t_size: 8,
+        integer_size: 8,
+        number_size: 8,
+        integral: false,
+    };
+    reader.header()?;
+    if reader.version != LuaVersion::Lua51 {
+        // upvalues of the main function, repeated in it
+        reader.byte()?;
+    }
+    let main = reader.function(None)?;
+    if reader.pos < data.len() {
+        return Err(reader.error("trailing bytes"));
+    }
+    Ok(Chunk {
+        version: reader.version,
+        main,
+    })
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    version: LuaVersion,
+    big_endian: bool,
+    int_size: usize,
+    size_t_size: usize,
+    integer_size: usize,
+    number_size: usize,
+    // 5.1 built with integers for numbers
+    integral: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn error(&self, message: &str) -> UndumpError {
+        UndumpError {
+            message: message.to_string(),
+            offset: self.pos,
+        }
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], UndumpError> {
+        if self.data.len() - self.pos < n {
+            self.pos = self.data.len();
+            return Err(self.error("truncated chunk"));
+        }
+        let bytes = &self.data[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, UndumpError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn unsigned(&mut self, size: usize) -> Result<u64, UndumpError> {
+        let bytes = self.bytes(size)?;
+        let fold = |x: u64, b: &u8| x << 8 | *b as u64;
+        Ok(if self.big_endian {
+            bytes.iter().fold(0, fold)
+        } else {
+            bytes.iter().rev().fold(0, fold)
+        })
+    }
+
+    // 5.4 writes sizes and ints in groups of 7 bits, the last one flagged
+    fn varint(&mut self) -> Result<u64, UndumpError> {
+        let mut x: u64 = 0;
+        loop {
+            let b = self.byte()?;
+            if x >> 57 != 0 {
+                return Err(self.error("integer overflow"));
+            }
+            x = x << 7 | (b & 0x7f) as u64;
+            if b & 0x80 != 0 {
+                return Ok(x);
+            }
+        }
+    }
+
+    fn int(&mut self) -> Result<usize, UndumpError> {
+        let n = match self.version {
+            LuaVersion::Lua54 => self.varint()?,
+            _ => self.unsigned(self.int_size)?,
+        };
+        if n > i32::MAX as u64 {
+            return Err(self.error("integer overflow"));
+        }
+        Ok(n as usize)
+    }
+
+    // a count of items that take at least a byte each
+    fn count(&mut self) -> Result<usize, UndumpError> {
+        let n = self.int()?;
+        if n > self.data.len() - self.pos {
+            return Err(self.error("truncated chunk"));
+        }
+        Ok(n)
+    }
+
+    fn size(&mut self) -> Result<usize, UndumpError> {
+        let n = match self.version {
+            LuaVersion::Lua54 => self.varint()?,
+            _ => self.unsigned(self.size_t_size)?,
+        };
+        if n > (self.data.len() - self.pos) as u64 + 1 {
+            return Err(self.error("truncated chunk"));
+        }
+        Ok(n as usize)
+    }
+
+    fn integer(&mut self) -> Result<IntType, UndumpError> {
+        let size = self.integer_size;
+        let x = self.unsigned(size)?;
+        // sign extended from its size
+        let shift = 64 - 8 * size as u32;
+        Ok((x << shift) as IntType >> shift)
+    }
+
+    fn number(&mut self) -> Result<FloatType, UndumpError> {
+        if self.integral {
+            return Ok(self.integer()? as FloatType);
+        }
+        let x = self.unsigned(self.number_size)?;
+        Ok(match self.number_size {
+            4 => f32::from_bits(x as u32) as FloatType,
+            _ => FloatType::from_bits(x),
+        })
+    }
+
+    // none for the null string of a source left out
+    fn string(&mut self) -> Result<Option<Vec<u8>>, UndumpError> {
+        let size = match self.version {
+            LuaVersion::Lua51 | LuaVersion::Lua54 => self.size()?,
+            _ => match self.byte()? {
+                0xff => self.size()?,
+                size => size as usize,
+            },
+        };
+        if size == 0 {
+            return Ok(None);
+        }
+        let s = self.bytes(size - 1)?.to_vec();
+        // 5.1 counts and writes the terminating zero
+        if self.version == LuaVersion::Lua51 {
+            self.byte()?;
+        }
+        Ok(Some(s))
+    }
+
+    fn name(&mut self) -> Result<String, UndumpError> {
+        let s = self.string()?.unwrap_or_default();
+        Ok(String::from_utf8_lossy(&s).into_owned())
+    }
+
+    fn header(&mut self) -> Result<(), UndumpError> {
+        if self.bytes(SIGNATURE.len()).ok() != Some(SIGNATURE) {
+            self.pos = 0;
+            return Err(self.error("not a binary chunk"));
+        }
+        self.version = match self.byte()? {
+            0x51 => LuaVersion::Lua51,
+            0x53 => LuaVersion::Lua53,
+            0x54 => LuaVersion::Lua54,
+            version => {
+                self.pos -= 1;
+                return Err(self.error(&format!(
+                    "unsupported version {}.{}",
+                    version >> 4,
+                    version & 0xf
+                )));
+            }
+        };
+        if self.byte()? != 0 {
+            self.pos -= 1;
+            return Err(self.error("format mismatch"));
+        }
+        if self.version == LuaVersion::Lua51 {
+            self.big_endian = self.byte()? == 0;
+            self.int_size = self.byte()? as usize;
+            self.size_t_size = self.byte()? as usize;
+            self.instruction_size()?;
+            self.number_size = self.byte()? as usize;
+            self.integral = self.byte()? != 0;
+            self.integer_size = self.number_size;
+            return self.check_sizes();
+        }
+        if self.bytes(LUAC_DATA.len())? != LUAC_DATA {
+            self.pos -= LUAC_DATA.len();
+            return Err(self.error("corrupted chunk"));
+        }
+        if self.version == LuaVersion::Lua53 {
+            self.int_size = self.byte()? as usize;
+            self.size_t_size = self.byte()? as usize;
+        }
+        self.instruction_size()?;
+        self.integer_size = self.byte()? as usize;
+        self.number_size = self.byte()? as usize;
+        self.check_sizes()?;
+        // the byte order is whatever reads the test integer right
+        let start = self.pos;
+        if self.integer()? != LUAC_INT {
+            self.pos = start;
+            self.big_endian = true;
+            if self.integer()? != LUAC_INT {
+                self.pos = start;
+                return Err(self.error("integer format mismatch"));
+            }
+        }
+        if self.number()? != LUAC_NUM {
+            self.pos -= self.number_size;
+            return Err(self.error("float format mismatch"));
+        }
+        Ok(())
+    }
+
+    fn instruction_size(&mut self) -> Result<(), UndumpError> {
+        if self.byte()? != 4 {
+            self.pos -= 1;
+            return Err(self.error("Instruction size mismatch"));
+        }
+        Ok(())
+    }
+
+    fn check_sizes(&self) -> Result<(), UndumpError> {
+        let valid = |size: usize| size == 4 || size == 8;
+        if !valid(self.int_size) || !valid(self.size_t_size) {
+            return Err(self.error("int size mismatch"));
+        }
+        if !valid(self.integer_size) || !valid(self.number_size) {
+            return Err(self.error("number size mismatch"));
+        }
+        Ok(())
+    }
+
+    fn function(&mut self, parent_source: Option<&str>) -> Result<Proto, UndumpError> {
+        let source = match self.string()? {
+            Some(s) => Some(String::from_utf8_lossy(&s).into_owned()),
+            None => parent_source.map(str::to_string),
+        };
+        let mut proto = Proto {
+            source,
+            line_defined: self.int()?,
+            last_line_defined: self.int()?,
+            ..Proto::default()
+        };
+        let upvalues = match self.version {
+            LuaVersion::Lua51 => self.byte()? as usize,
+            _ => 0,
+        };
+        proto.num_params = self.byte()?;
+        proto.is_vararg = self.byte()? != 0;
+        proto.max_stack_size = self.byte()?;
+        let n = self.count()?;
+        for _ in 0..n {
+            proto.code.push(Instruction(self.unsigned(4)? as u32));
+        }
+        let n = self.count()?;
+        for _ in 0..n {
+            let constant = self.constant()?;
+            proto.constants.push(constant);
+        }
+        if self.version == LuaVersion::Lua51 {
+            proto.upvalues = vec![
+                UpvalueDesc {
+                    name: String::new(),
+                    in_stack: false,
+                    index: 0,
+                };
+                upvalues
+            ];
+        } else {
+            let n = self.count()?;
+            for _ in 0..n {
+                let in_stack = self.byte()? != 0;
+                let index = self.byte()?;
+                if self.version == LuaVersion::Lua54 {
+                    self.byte()?;
+                }
+                proto.upvalues.push(UpvalueDesc {
+                    name: String::new(),
+                    in_stack,
+                    index,
+                });
+            }
+        }
+        let n = self.count()?;
+        for _ in 0..n {
+            let child = self.function(proto.source.as_deref())?;
+            proto.protos.push(child);
+        }
+        self.debug(proto)
+    }
+
+    fn constant(&mut self) -> Result<Constant, UndumpError> {
+        let tag = self.byte()?;
+        Ok(match (self.version, tag) {
+            (_, 0) => Constant::Nil,
+            (LuaVersion::Lua54, 1) => Constant::Boolean(false),
+            (LuaVersion::Lua54, 17) => Constant::Boolean(true),
+            (_, 1) => Constant::Boolean(self.byte()? != 0),
+            (LuaVersion::Lua51, 3) | (LuaVersion::Lua53, 3) | (LuaVersion::Lua54, 19) => {
+                Constant::Float(self.number()?)
+            }
+            (LuaVersion::Lua53, 19) | (LuaVersion::Lua54, 3) => Constant::Int(self.integer()?),
+            (_, 4) | (LuaVersion::Lua53, 20) | (LuaVersion::Lua54, 20) => {
+                Constant::Str(self.string()?.unwrap_or_default())
+            }
+            _ => {
+                self.pos -= 1;
+                return Err(self.error(&format!("unknown constant type {}", tag)));
+            }
+        })
+    }
+
+    fn debug(&mut self, mut proto: Proto) -> Result<Proto, UndumpError> {
+        let n = self.count()?;
+        if self.version == LuaVersion::Lua54 {
+            // differences to the previous line, with absolute lines here and there
+            let deltas = self.bytes(n)?.to_vec();
+            let mut absolute = Vec::new();
+            for _ in 0..self.count()? {
+                absolute.push((self.int()?, self.int()?));
+            }
+            proto.line_info = absolute_lines(proto.line_defined, &deltas, &absolute);
+        } else {
+            for _ in 0..n {
+                let line = self.int()?;
+                proto.line_info.push(line);
+            }
+        }
+        let n = self.count()?;
+        for _ in 0..n {
+            let name = self.name()?;
+            proto.local_vars.push(LocalVar {
+                name,
+                start_pc: self.int()?,
+                end_pc: self.int()?,
+            });
+        }
+        let n = self.count()?;
+        if n > proto.upvalues.len() {
+            return Err(self.error("too many upvalue names"));
+        }
+        for i in 0..n {
+            proto.upvalues[i].name = self.name()?;
+        }
+        Ok(proto)
+    }
+}
+
+// lines of 5.4 instructions, `luaG_getfuncline` for every pc
+fn absolute_lines(line_defined: usize, deltas: &[u8], absolute: &[(usize, usize)]) -> Vec<usize> {
+    let mut lines = Vec::with_capacity(deltas.len());
+    let mut line = line_defined as i64;
+    let mut next = absolute.iter().peekable();
+    for (pc, delta) in deltas.iter().enumerate() {
+        match next.peek() {
+            Some((abs_pc, abs_line)) if *abs_pc == pc => {
+                line = *abs_line as i64;
+                next.next();
+            }
+            _ => line += *delta as i8 as i64,
+        }
+        lines.push(line.max(0) as usize);
+    }
+    lines
+}
@@ -0,0 +1,314 @@
+use std::fmt;
+
+use crate::ast::*;
+use crate::resolver::Resolver;
+use crate::token::Span;
+use crate::types::LuaVersion;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationErrorKind {
+    BreakOutsideLoop,
+    UndefinedLabel(String),
+    // label and the line it is first defined at
+    DuplicateLabel(String, usize),
+    // label and the local whose scope the goto enters
+    JumpIntoScope(String, String),
+    // assignment to a `<const>` or `<close>` local
+    ConstAssign(String),
+}
+
+impl fmt::Display for ValidationErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValidationErrorKind::BreakOutsideLoop => write!(f, "break outside a loop"),
+            ValidationErrorKind::UndefinedLabel(label) => {
+                write!(f, "no visible label '{}' for goto", label)
+            }
+            ValidationErrorKind::DuplicateLabel(label, line) => {
+                write!(f, "label '{}' already defined on line {}", label, line)
+            }
+            ValidationErrorKind::JumpIntoScope(label, local) => write!(
+                f,
+                "<goto {}> jumps into the scope of local '{}'",
+                label, local
+            ),
+            ValidationErrorKind::ConstAssign(name) => {
+                write!(f, "attempt to assign to const variable '{}'", name)
+            }
+        }
+    }
+}
+
+// a statement the parser accepts but `luac` rejects
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationError {
+    pub kind: ValidationErrorKind,
+    pub line: usize,
+    pub col: usize,
+    pub offset: usize,
+}
+
+impl ValidationError {
+    fn new(kind: ValidationErrorKind, span: Span) -> Self {
+        ValidationError {
+            kind,
+            line: span.line,
+            col: span.col,
+            offset: span.start,
+        }
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[syntax error] {} at line [{}:{}].",
+            self.kind, self.line, self.col
+        )
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+// a block being walked and the index of the statement being walked in it
+struct Frame<'a> {
+    block: &'a Block,
+    index: usize,
+    // the body of `repeat`, whose condition still sees its locals
+    repeat: bool,
+}
+
+// checks `break`, `goto`, labels and assignments to constant locals of a parsed chunk
+pub struct Validator {
+    version: LuaVersion,
+    errors: Vec<ValidationError>,
+}
+
+impl Default for Validator {
+    fn default() -> Self {
+        Validator::new()
+    }
+}
+
+impl Validator {
+    pub fn new() -> Self {
+        Validator {
+            version: LuaVersion::default(),
+            errors: Vec::new(),
+        }
+    }
+
+    // dialect of the tree, 5.4 also rejects a label already visible from an enclosing block
+    pub fn set_version(&mut self, version: LuaVersion) {
+        self.version = version;
+    }
+
+    // errors in source order
+    pub fn run(&mut self, block: &Block) -> Vec<ValidationError> {
+        self.errors.clear();
+        self.function(block);
+        self.assignments(block);
+        let mut errors = std::mem::take(&mut self.errors);
+        errors.sort_by_key(|e| e.offset);
+        errors
+    }
+
+    // locals with an attribute are read-only, in nested functions too
+    fn assignments(&mut self, block: &Block) {
+        let mut resolver = Resolver::new();
+        resolver.set_version(self.version);
+        let resolution = resolver.run(block);
+        for u in resolution.const_writes() {
+            self.errors.push(ValidationError::new(
+                ValidationErrorKind::ConstAssign(u.name.clone()),
+                u.span,
+            ));
+        }
+    }
+
+    // labels and loops are never visible from a nested function
+    fn function(&mut self, block: &Block) {
+        let mut frames = Vec::new();
+        self.block(block, false, false, &mut frames);
+    }
+
+    fn block<'a>(
+        &mut self,
+        block: &'a Block,
+        in_loop: bool,
+        repeat: bool,
+        frames: &mut Vec<Frame<'a>>,
+    ) {
+        frames.push(Frame {
+            block,
+            index: 0,
+            repeat,
+        });
+        for (index, stat) in block.stats.iter().enumerate() {
+            frames.last_mut().unwrap().index = index;
+            self.stat(stat, in_loop, frames);
+        }
+        frames.pop();
+    }
+
+    fn stat<'a>(&mut self, stat: &'a Stat, in_loop: bool, frames: &mut Vec<Frame<'a>>) {
+        match stat {
+            Stat::IfStat(stat) => {
+                for cond_block in stat.cond_blocks.iter() {
+                    self.expr(&cond_block.cond);
+                    self.block(&cond_block.block, in_loop, false, frames);
+                }
+                self.block(&stat.else_block, in_loop, false, frames);
+            }
+            Stat::WhileStat(stat) => {
+                self.expr(&stat.cond);
+                self.block(&stat.block, true, false, frames);
+            }
+            Stat::DoBlock(stat) => self.block(&stat.block, in_loop, false, frames),
+            Stat::ForStat(ForStat::ForNum(stat)) => {
+                self.expr(&stat.init);
+                self.expr(&stat.limit);
+                if let Some(step) = &stat.step {
+                    self.expr(step);
+                }
+                self.block(&stat.body, true, false, frames);
+            }
+            Stat::ForStat(ForStat::ForList(stat)) => {
+                self.exprs(&stat.exprs);
+                self.block(&stat.body, true, false, frames);
+            }
+            Stat::RepeatStat(stat) => {
+                self.block(&stat.block, true, true, frames);
+                self.expr(&stat.cond);
+            }
+            Stat::FuncStat(stat) => self.function(&stat.body.block),
+            Stat::LocalStat(stat) => self.exprs(&stat.exprs),
+            Stat::RetStat(stat) => self.exprs(&stat.exprs),
+            Stat::AssignStat(stat) => {
+                for target in stat.left.iter() {
+                    self.suffixedexpr(target);
+                }
+                self.exprs(&stat.right);
+            }
+            Stat::CallStat(stat) => self.suffixedexpr(&stat.call),
+            Stat::BreakStat(stat) if !in_loop => self.errors.push(ValidationError::new(
+                ValidationErrorKind::BreakOutsideLoop,
+                stat.span,
+            )),
+            Stat::GotoStat(stat) => self.goto(stat, frames),
+            Stat::LabelStat(stat) => self.label(stat, frames),
+            Stat::BreakStat(_) | Stat::Empty(_) | Stat::Error(_) => (),
+        }
+    }
+
+    // the innermost visible label must not be ahead of a local declared after the goto
+    fn goto(&mut self, stat: &GotoStat, frames: &[Frame]) {
+        let name = &stat.label.name;
+        for frame in frames.iter().rev() {
+            let stats = &frame.block.stats;
+            let found = stats
+                .iter()
+                .position(|s| matches!(s, Stat::LabelStat(l) if &l.label.name == name));
+            let target = match found {
+                Some(target) => target,
+                None => continue,
+            };
+            // a label ending its block is outside the scope of the block's locals
+            let at_end = !frame.repeat
+                && stats[target + 1..]
+                    .iter()
+                    .all(|s| matches!(s, Stat::LabelStat(_) | Stat::Empty(_)));
+            if target > frame.index && !at_end {
+                let local = stats[frame.index + 1..target].iter().find_map(|s| match s {
+                    Stat::LocalStat(local) => Some(&local.names[0].name.name),
+                    Stat::FuncStat(func) if func.func_type == FuncType::Local => {
+                        Some(&func.func_name.fields[0].name)
+                    }
+                    _ => None,
+                });
+                if let Some(local) = local {
+                    self.errors.push(ValidationError::new(
+                        ValidationErrorKind::JumpIntoScope(name.clone(), local.clone()),
+                        stat.span,
+                    ));
+                }
+            }
+            return;
+        }
+        self.errors.push(ValidationError::new(
+            ValidationErrorKind::UndefinedLabel(name.clone()),
+            stat.span,
+        ));
+    }
+
+    fn label(&mut self, stat: &LabelStat, frames: &[Frame]) {
+        // labels before this one in its block, from 5.4 on in the enclosing blocks too
+        let visible = match self.version {
+            LuaVersion::Lua54 => frames.len(),
+            _ => 1,
+        };
+        let defined = frames.iter().rev().take(visible).find_map(|frame| {
+            frame.block.stats[..frame.index]
+                .iter()
+                .find_map(|s| match s {
+                    Stat::LabelStat(l) if l.label.name == stat.label.name => Some(l.span.line),
+                    _ => None,
+                })
+        });
+        if let Some(line) = defined {
+            self.errors.push(ValidationError::new(
+                ValidationErrorKind::DuplicateLabel(stat.label.name.clone(), line),
+                stat.span,
+            ));
+        }
+    }
+
+    fn exprs(&mut self, exprs: &[Expr]) {
+        for expr in exprs.iter() {
+            self.expr(expr);
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::FuncBody(body) => self.function(&body.block),
+            Expr::Table(table) => self.table(table),
+            Expr::BinExpr(expr) => {
+                self.expr(&expr.left);
+                self.expr(&expr.right);
+            }
+            Expr::UnExpr(expr) => self.expr(&expr.expr),
+            Expr::SuffixedExpr(expr) => self.suffixedexpr(expr),
+            _ => (),
+        }
+    }
+
+    fn suffixedexpr(&mut self, expr: &SuffixedExpr) {
+        if let PrimaryExpr::ParenExpr(expr) = &expr.primary {
+            self.expr(expr);
+        }
+        for suffix in expr.suffixes.iter() {
+            match suffix {
+                Suffix::Index(expr) => self.expr(expr),
+                Suffix::FuncArgs(FuncArgs::Exprs(exprs, _)) => self.exprs(exprs),
+                Suffix::FuncArgs(FuncArgs::Table(table)) => self.table(table),
+                _ => (),
+            }
+        }
+    }
+
+    fn table(&mut self, table: &Table) {
+        for field in table.fields.iter() {
+            match field {
+                Field::ListField(expr) => self.expr(expr),
+                Field::RecFileld(field) => {
+                    if let FieldKey::Expr(key) = &field.key {
+                        self.expr(key);
+                    }
+                    self.expr(&field.value);
+                }
+            }
+        }
+    }
+}
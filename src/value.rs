@@ -0,0 +1,640 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+use crate::ast::BinOp;
+use crate::interpreter::{Closure, Interpreter, LuaError};
+use crate::lexer::Lexer;
+use crate::types::{FloatType, IntType, Number};
+
+pub type TableRef = Rc<RefCell<Table>>;
+
+pub type BuiltinFn = dyn Fn(&mut Interpreter, Vec<Value>) -> Result<Vec<Value>, LuaError>;
+
+// function implemented in Rust, `name` is used in argument errors
+pub struct Builtin {
+    pub name: String,
+    pub(crate) func: Box<BuiltinFn>,
+}
+
+#[derive(Clone)]
+pub enum Function {
+    Lua(Rc<Closure>),
+    Builtin(Rc<Builtin>),
+}
+
+impl Function {
+    fn addr(&self) -> usize {
+        match self {
+            Function::Lua(closure) => Rc::as_ptr(closure) as *const u8 as usize,
+            Function::Builtin(builtin) => Rc::as_ptr(builtin) as *const u8 as usize,
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub enum Value {
+    #[default]
+    Nil,
+    Boolean(bool),
+    Int(IntType),
+    Float(FloatType),
+    // strings are byte strings like in Lua, not necessarily utf-8
+    Str(Rc<[u8]>),
+    Table(TableRef),
+    Function(Function),
+}
+
+impl Value {
+    pub fn new_table() -> Value {
+        Value::Table(Rc::new(RefCell::new(Table::default())))
+    }
+
+    pub fn builtin<F>(name: &str, func: F) -> Value
+    where
+        F: Fn(&mut Interpreter, Vec<Value>) -> Result<Vec<Value>, LuaError> + 'static,
+    {
+        Value::Function(Function::Builtin(Rc::new(Builtin {
+            name: name.to_string(),
+            func: Box::new(func),
+        })))
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Boolean(_) => "boolean",
+            Value::Int(_) | Value::Float(_) => "number",
+            Value::Str(_) => "string",
+            Value::Table(_) => "table",
+            Value::Function(_) => "function",
+        }
+    }
+
+    // everything but `nil` and `false` is true
+    pub fn truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Boolean(false))
+    }
+
+    pub fn is_nil(&self) -> bool {
+        matches!(self, Value::Nil)
+    }
+
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Value::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_table(&self) -> Option<&TableRef> {
+        match self {
+            Value::Table(t) => Some(t),
+            _ => None,
+        }
+    }
+
+    // numbers and strings convertible to numbers, as arithmetic coerces them
+    pub fn to_number(&self) -> Number {
+        match self {
+            Value::Int(i) => Number::Int(*i),
+            Value::Float(f) => Number::Float(*f),
+            Value::Str(s) => match std::str::from_utf8(s) {
+                Ok(s) => Lexer::str_to_num(s),
+                Err(_) => Number::None,
+            },
+            _ => Number::None,
+        }
+    }
+
+    // a number with an exact integer representation
+    pub fn to_integer(&self) -> Option<IntType> {
+        match self.to_number() {
+            Number::Int(i) => Some(i),
+            Number::Float(f) => float_to_int(f),
+            Number::None => None,
+        }
+    }
+
+    pub fn to_float(&self) -> Option<FloatType> {
+        match self.to_number() {
+            Number::Int(i) => Some(i as FloatType),
+            Number::Float(f) => Some(f),
+            Number::None => None,
+        }
+    }
+
+    // equality without `__eq`
+    pub fn raw_equal(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Boolean(a), Value::Boolean(b)) => a == b,
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Float(a), Value::Float(b)) => a == b,
+            (Value::Int(i), Value::Float(f)) | (Value::Float(f), Value::Int(i)) => {
+                int_float_cmp(*i, *f) == Some(Ordering::Equal)
+            }
+            (Value::Str(a), Value::Str(b)) => a == b,
+            (Value::Table(a), Value::Table(b)) => Rc::ptr_eq(a, b),
+            (Value::Function(a), Value::Function(b)) => a.addr() == b.addr(),
+            _ => false,
+        }
+    }
+
+    fn addr(&self) -> usize {
+        match self {
+            Value::Table(t) => Rc::as_ptr(t) as *const u8 as usize,
+            Value::Function(f) => f.addr(),
+            _ => 0,
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Boolean(b)
+    }
+}
+
+impl From<IntType> for Value {
+    fn from(i: IntType) -> Self {
+        Value::Int(i)
+    }
+}
+
+impl From<FloatType> for Value {
+    fn from(f: FloatType) -> Self {
+        Value::Float(f)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::Str(s.as_bytes().into())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::Str(s.into_bytes().into())
+    }
+}
+
+impl From<Vec<u8>> for Value {
+    fn from(s: Vec<u8>) -> Self {
+        Value::Str(s.into())
+    }
+}
+
+impl From<&[u8]> for Value {
+    fn from(s: &[u8]) -> Self {
+        Value::Str(s.into())
+    }
+}
+
+// `tostring` of 5.4 without metamethods
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Boolean(b) => write!(f, "{}", b),
+            Value::Int(i) => write!(f, "{}", i),
+            Value::Float(n) => write!(f, "{}", float_to_string(*n, true)),
+            Value::Str(s) => write!(f, "{}", String::from_utf8_lossy(s)),
+            Value::Table(_) | Value::Function(_) => {
+                write!(f, "{}: {:#x}", self.type_name(), self.addr())
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Str(s) => write!(f, "{:?}", String::from_utf8_lossy(s)),
+            _ => write!(f, "{}", self),
+        }
+    }
+}
+
+// a float with an exact integer representation
+pub(crate) fn float_to_int(f: FloatType) -> Option<IntType> {
+    if f.fract() == 0.0 && f >= -(2f64.powi(63)) && f < 2f64.powi(63) {
+        Some(f as IntType)
+    } else {
+        None
+    }
+}
+
+// arithmetic and bitwise operators on numbers, none when an operand has no
+// integer representation for a bitwise operator or an integer is divided by zero
+pub(crate) fn arith(op: BinOp, x: Number, y: Number) -> Option<Value> {
+    if let (Number::Int(a), Number::Int(b)) = (x, y) {
+        match op {
+            BinOp::Add => return Some(Value::Int(a.wrapping_add(b))),
+            BinOp::Minus => return Some(Value::Int(a.wrapping_sub(b))),
+            BinOp::Mul => return Some(Value::Int(a.wrapping_mul(b))),
+            BinOp::IDiv | BinOp::Mod if b == 0 => return None,
+            BinOp::IDiv => {
+                let q = a.wrapping_div(b);
+                let q = if a.wrapping_rem(b) != 0 && (a ^ b) < 0 {
+                    q - 1
+                } else {
+                    q
+                };
+                return Some(Value::Int(q));
+            }
+            BinOp::Mod => {
+                let r = a.wrapping_rem(b);
+                let r = if r != 0 && (r ^ b) < 0 { r + b } else { r };
+                return Some(Value::Int(r));
+            }
+            _ => (),
+        }
+    }
+    let int = |n: Number| match n {
+        Number::Int(i) => Some(i),
+        Number::Float(f) => float_to_int(f),
+        Number::None => None,
+    };
+    let bitwise = |f: fn(IntType, IntType) -> IntType| match (int(x), int(y)) {
+        (Some(a), Some(b)) => Some(Value::Int(f(a, b))),
+        _ => None,
+    };
+    let float = |n: Number| match n {
+        Number::Int(i) => i as FloatType,
+        Number::Float(f) => f,
+        Number::None => FloatType::NAN,
+    };
+    let (a, b) = (float(x), float(y));
+    match op {
+        BinOp::Add => Some(Value::Float(a + b)),
+        BinOp::Minus => Some(Value::Float(a - b)),
+        BinOp::Mul => Some(Value::Float(a * b)),
+        BinOp::Div => Some(Value::Float(a / b)),
+        BinOp::Pow => Some(Value::Float(a.powf(b))),
+        BinOp::IDiv => Some(Value::Float((a / b).floor())),
+        BinOp::Mod => {
+            let m = a % b;
+            let m = if (m > 0.0 && b < 0.0) || (m < 0.0 && b > 0.0) {
+                m + b
+            } else {
+                m
+            };
+            Some(Value::Float(m))
+        }
+        BinOp::BAnd => bitwise(|a, b| a & b),
+        BinOp::BOr => bitwise(|a, b| a | b),
+        BinOp::BXor => bitwise(|a, b| a ^ b),
+        BinOp::Shl => bitwise(shift_left),
+        BinOp::Shr => bitwise(|a, b| shift_left(a, b.wrapping_neg())),
+        _ => None,
+    }
+}
+
+// `<<` of Lua, a negative count shifts right and large ones give 0
+pub(crate) fn shift_left(a: IntType, n: IntType) -> IntType {
+    if n <= -64 || n >= 64 {
+        0
+    } else if n >= 0 {
+        ((a as u64) << n) as IntType
+    } else {
+        ((a as u64) >> -n) as IntType
+    }
+}
+
+// exact comparison of an integer and a float
+pub(crate) fn int_float_cmp(i: IntType, f: FloatType) -> Option<Ordering> {
+    if f.is_nan() {
+        None
+    } else if f >= 2f64.powi(63) {
+        Some(Ordering::Less)
+    } else if f < -(2f64.powi(63)) {
+        Some(Ordering::Greater)
+    } else {
+        let floor = f.floor();
+        match i.cmp(&(floor as IntType)) {
+            Ordering::Equal if f > floor => Some(Ordering::Less),
+            ordering => Some(ordering),
+        }
+    }
+}
+
+// `%.14g`, with `.0` added to integral values when the dialect has integers
+pub fn float_to_string(f: FloatType, integer: bool) -> String {
+    let s = format_g(f, 14, false);
+    if integer && f.is_finite() && s.bytes().all(|c| c == b'-' || c.is_ascii_digit()) {
+        s + ".0"
+    } else {
+        s
+    }
+}
+
+// `%e` of C with the given precision
+pub(crate) fn format_e(f: FloatType, precision: usize) -> String {
+    if !f.is_finite() {
+        return format_special(f);
+    }
+    let s = format!("{:.*e}", precision, f);
+    let (mantissa, exp) = s.split_at(s.find('e').unwrap());
+    let exp: i32 = exp[1..].parse().unwrap();
+    format!(
+        "{}e{}{:02}",
+        mantissa,
+        if exp < 0 { '-' } else { '+' },
+        exp.abs()
+    )
+}
+
+// `%g` of C, `alt` keeps trailing zeros like `%#g`
+pub(crate) fn format_g(f: FloatType, precision: usize, alt: bool) -> String {
+    if !f.is_finite() {
+        return format_special(f);
+    }
+    let precision = precision.max(1);
+    let exp = if f == 0.0 {
+        0
+    } else {
+        let s = format!("{:.*e}", precision - 1, f);
+        s[s.find('e').unwrap() + 1..].parse::<i32>().unwrap()
+    };
+    let s = if exp < -4 || exp >= precision as i32 {
+        format_e(f, precision - 1)
+    } else {
+        format!("{:.*}", (precision as i32 - 1 - exp) as usize, f)
+    };
+    if alt || !s.contains('.') {
+        return s;
+    }
+    let (mantissa, exp) = match s.find('e') {
+        Some(i) => s.split_at(i),
+        None => (s.as_str(), ""),
+    };
+    let mantissa = mantissa.trim_end_matches('0').trim_end_matches('.');
+    format!("{}{}", mantissa, exp)
+}
+
+pub(crate) fn format_special(f: FloatType) -> String {
+    let s = if f.is_nan() { "nan" } else { "inf" };
+    if f.is_sign_negative() {
+        format!("-{}", s)
+    } else {
+        s.to_string()
+    }
+}
+
+// table key: integral floats are integers, tables and functions compare by identity
+#[derive(Clone)]
+pub(crate) enum Key {
+    Boolean(bool),
+    Int(IntType),
+    Float(u64),
+    Str(Rc<[u8]>),
+    Object(Value),
+}
+
+impl Key {
+    // `nil` and NaN are no keys
+    pub(crate) fn new(value: &Value) -> Option<Key> {
+        match value {
+            Value::Nil => None,
+            Value::Boolean(b) => Some(Key::Boolean(*b)),
+            Value::Int(i) => Some(Key::Int(*i)),
+            Value::Float(f) if f.is_nan() => None,
+            Value::Float(f) => Some(match float_to_int(*f) {
+                Some(i) => Key::Int(i),
+                None => Key::Float(f.to_bits()),
+            }),
+            Value::Str(s) => Some(Key::Str(s.clone())),
+            Value::Table(_) | Value::Function(_) => Some(Key::Object(value.clone())),
+        }
+    }
+
+    pub(crate) fn to_value(&self) -> Value {
+        match self {
+            Key::Boolean(b) => Value::Boolean(*b),
+            Key::Int(i) => Value::Int(*i),
+            Key::Float(f) => Value::Float(FloatType::from_bits(*f)),
+            Key::Str(s) => Value::Str(s.clone()),
+            Key::Object(value) => value.clone(),
+        }
+    }
+}
+
+impl PartialEq for Key {
+    fn eq(&self, other: &Key) -> bool {
+        match (self, other) {
+            (Key::Boolean(a), Key::Boolean(b)) => a == b,
+            (Key::Int(a), Key::Int(b)) => a == b,
+            (Key::Float(a), Key::Float(b)) => a == b,
+            (Key::Str(a), Key::Str(b)) => a == b,
+            (Key::Object(a), Key::Object(b)) => a.addr() == b.addr(),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Key {}
+
+impl Hash for Key {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            Key::Boolean(b) => b.hash(state),
+            Key::Int(i) => i.hash(state),
+            Key::Float(f) => f.hash(state),
+            Key::Str(s) => s.hash(state),
+            Key::Object(value) => value.addr().hash(state),
+        }
+    }
+}
+
+// the key given to `next` is not in the table
+#[derive(Debug)]
+pub struct InvalidKey;
+
+// a Lua table: an array part for the keys 1..n and a hash part kept in insertion order
+#[derive(Default)]
+pub struct Table {
+    // never ends with nil
+    array: Vec<Value>,
+    index: HashMap<Key, usize>,
+    // removed entries stay as nil so `next` can continue from them
+    entries: Vec<(Key, Value)>,
+    removed: usize,
+    pub metatable: Option<TableRef>,
+}
+
+impl Table {
+    pub fn get(&self, key: &Value) -> Value {
+        match Key::new(key) {
+            Some(key) => self.get_key(&key),
+            None => Value::Nil,
+        }
+    }
+
+    pub fn get_str(&self, key: &str) -> Value {
+        self.get_key(&Key::Str(key.as_bytes().into()))
+    }
+
+    pub fn get_int(&self, key: IntType) -> Value {
+        self.get_key(&Key::Int(key))
+    }
+
+    fn get_key(&self, key: &Key) -> Value {
+        if let Key::Int(i) = key {
+            if *i >= 1 && (*i as usize) <= self.array.len() {
+                return self.array[*i as usize - 1].clone();
+            }
+        }
+        match self.index.get(key) {
+            Some(index) => self.entries[*index].1.clone(),
+            None => Value::Nil,
+        }
+    }
+
+    // fails for the keys `nil` and NaN with the message of Lua
+    pub fn set(&mut self, key: Value, value: Value) -> Result<(), &'static str> {
+        match Key::new(&key) {
+            Some(key) => {
+                self.set_key(key, value);
+                Ok(())
+            }
+            None if key.is_nil() => Err("index is nil"),
+            None => Err("index is NaN"),
+        }
+    }
+
+    pub fn set_str(&mut self, key: &str, value: Value) {
+        self.set_key(Key::Str(key.as_bytes().into()), value);
+    }
+
+    pub fn set_int(&mut self, key: IntType, value: Value) {
+        self.set_key(Key::Int(key), value);
+    }
+
+    fn set_key(&mut self, key: Key, value: Value) {
+        if let Key::Int(i) = key {
+            let len = self.array.len();
+            if i >= 1 && (i as usize) <= len {
+                self.array[i as usize - 1] = value;
+                while let Some(Value::Nil) = self.array.last() {
+                    self.array.pop();
+                }
+                return;
+            }
+            if i >= 1 && i as usize == len + 1 && !value.is_nil() {
+                self.remove_entry(&key);
+                self.array.push(value);
+                // following keys move over from the hash part
+                loop {
+                    let next = Key::Int(self.array.len() as IntType + 1);
+                    match self.remove_entry(&next) {
+                        Some(value) => self.array.push(value),
+                        None => break,
+                    }
+                }
+                return;
+            }
+        }
+        match self.index.get(&key) {
+            Some(index) => {
+                let entry = &mut self.entries[*index].1;
+                match (entry.is_nil(), value.is_nil()) {
+                    (true, false) => self.removed -= 1,
+                    (false, true) => self.removed += 1,
+                    _ => (),
+                }
+                *entry = value;
+            }
+            None if value.is_nil() => (),
+            None => {
+                if self.removed > 8 && self.removed * 2 > self.entries.len() {
+                    self.compact();
+                }
+                self.index.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+    }
+
+    // the positional items of a constructor as keys 1..n, even with nils among them
+    pub(crate) fn set_list(&mut self, values: Vec<Value>) {
+        let n = values.len();
+        for i in 1..=n {
+            self.remove_entry(&Key::Int(i as IntType));
+        }
+        if self.array.len() > n {
+            self.array.splice(..n, values);
+            return;
+        }
+        self.array = values;
+        while let Some(Value::Nil) = self.array.last() {
+            self.array.pop();
+        }
+        loop {
+            let next = Key::Int(self.array.len() as IntType + 1);
+            match self.remove_entry(&next) {
+                Some(value) => self.array.push(value),
+                None => break,
+            }
+        }
+    }
+
+    // takes the value of a key of the hash part
+    fn remove_entry(&mut self, key: &Key) -> Option<Value> {
+        let index = *self.index.get(key)?;
+        let value = std::mem::take(&mut self.entries[index].1);
+        if value.is_nil() {
+            None
+        } else {
+            self.removed += 1;
+            Some(value)
+        }
+    }
+
+    fn compact(&mut self) {
+        self.entries.retain(|(_, value)| !value.is_nil());
+        self.index = self
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(i, (key, _))| (key.clone(), i))
+            .collect();
+        self.removed = 0;
+    }
+
+    // a border, `#t` without `__len`
+    pub fn len(&self) -> usize {
+        self.array.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.array.is_empty() && self.removed == self.entries.len()
+    }
+
+    // the entry after `key`, the first one for `nil`
+    pub fn next(&self, key: &Value) -> Result<Option<(Value, Value)>, InvalidKey> {
+        let start = match Key::new(key) {
+            None if key.is_nil() => 0,
+            None => return Err(InvalidKey),
+            Some(Key::Int(i)) if i >= 1 && (i as usize) <= self.array.len() => i as usize,
+            Some(key) => match self.index.get(&key) {
+                Some(index) => self.array.len() + index + 1,
+                None => return Err(InvalidKey),
+            },
+        };
+        for i in start..self.array.len() {
+            if !self.array[i].is_nil() {
+                return Ok(Some((Value::Int(i as IntType + 1), self.array[i].clone())));
+            }
+        }
+        let start = start.saturating_sub(self.array.len());
+        Ok(self.entries[start.min(self.entries.len())..]
+            .iter()
+            .find(|(_, value)| !value.is_nil())
+            .map(|(key, value)| (key.to_value(), value.clone())))
+    }
+}
@@ -0,0 +1,152 @@
+mod ast_walker_tests {
+    use rslua::ast::*;
+    use rslua::ast_walker::{ast_walker, ast_walker_mut, AstVisitor, AstVisitorMut};
+    use rslua::lexer::Lexer;
+    use rslua::parser::Parser;
+
+    fn try_parse(input: &str) -> Block {
+        let mut lexer = Lexer::new();
+        lexer.set_debug(true);
+        let tokens = lexer.run(input).ok().unwrap();
+        let mut parser = Parser::new();
+        parser.set_debug(true);
+        parser.run(tokens).ok().unwrap()
+    }
+
+    #[derive(Default)]
+    struct CallCounter {
+        calls: usize,
+        ifs: usize,
+    }
+
+    impl AstVisitor for CallCounter {
+        fn begin_func_args(&mut self, _args: &FuncArgs) -> bool {
+            self.calls += 1;
+            false
+        }
+
+        fn begin_if(&mut self, _cond: &Expr) -> bool {
+            self.ifs += 1;
+            false
+        }
+    }
+
+    #[test]
+    fn default_hooks() {
+        let block = try_parse("if f(1) then elseif t:g{} then end while h 'a' do end");
+        let mut counter = CallCounter::default();
+        ast_walker::walk_block(&block, &mut counter);
+        assert_eq!(counter.calls, 3);
+        assert_eq!(counter.ifs, 1);
+    }
+
+    struct Rewriter;
+
+    impl AstVisitorMut for Rewriter {
+        // while true do ... end => repeat ... until false
+        fn stat(&mut self, stat: &mut Stat) -> bool {
+            if let Stat::WhileStat(WhileStat {
+                cond: Expr::True(_),
+                block,
+                span,
+            }) = stat
+            {
+                *stat = Stat::RepeatStat(RepeatStat {
+                    cond: Expr::False(*span),
+                    block: std::mem::replace(block, Block::empty()),
+                    span: *span,
+                });
+            }
+            false
+        }
+
+        // N => 10
+        fn expr(&mut self, expr: &mut Expr) -> bool {
+            if let Expr::SuffixedExpr(e) = expr {
+                if let PrimaryExpr::Name(n) = &e.primary {
+                    if n.name == "N" && e.suffixes.is_empty() {
+                        *expr = Expr::Int(10, e.span);
+                        return true;
+                    }
+                }
+            }
+            false
+        }
+
+        // a => b
+        fn name(&mut self, name: &mut Name) {
+            if name.name == "a" {
+                name.name = "b".to_string();
+            }
+        }
+    }
+
+    #[test]
+    fn mutable_walk() {
+        let mut block =
+            try_parse("while true do if a[N] < N then while a.a do end end end repeat until a");
+        ast_walker_mut::walk_block_mut(&mut block, &mut Rewriter);
+        assert!(same_structure(
+            &block,
+            &try_parse("repeat if b[10] < 10 then while b.a do end end until false repeat until b")
+        ));
+    }
+
+    #[derive(Default)]
+    struct Collector {
+        strings: Vec<String>,
+        calls: usize,
+        seps: usize,
+        stats: usize,
+    }
+
+    impl AstVisitor for Collector {
+        fn string(&mut self, s: &str) {
+            self.strings.push(s.to_string());
+        }
+
+        fn begin_func_args(&mut self, _args: &FuncArgs) -> bool {
+            self.calls += 1;
+            false
+        }
+
+        fn assign_sep(&mut self) {
+            self.seps += 1;
+        }
+
+        fn end_local_stat(&mut self) {
+            self.stats += 1;
+        }
+
+        fn end_ret_stat(&mut self) {
+            self.stats += 1;
+        }
+
+        fn end_assign_stat(&mut self) {
+            self.stats += 1;
+        }
+
+        fn end_call_stat(&mut self) {
+            self.stats += 1;
+        }
+    }
+
+    #[test]
+    fn statement_children() {
+        let block = try_parse(
+            r#"
+local x, y = f("a"), "b"
+t[g("c")], u = "d"
+print("e")
+local z
+return h "f"
+"#,
+        );
+        let mut collector = Collector::default();
+        ast_walker::walk_block(&block, &mut collector);
+        assert_eq!(collector.strings, vec!["a", "b", "c", "d", "e", "f"]);
+        assert_eq!(collector.calls, 4);
+        assert_eq!(collector.seps, 2);
+        assert_eq!(collector.stats, 5);
+    }
+}
@@ -0,0 +1,158 @@
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::process::{Command, Output, Stdio};
+
+fn rslua(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rslua"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // usage errors exit before reading stdin
+    if let Err(error) = child.stdin.take().unwrap().write_all(stdin.as_bytes()) {
+        assert_eq!(error.kind(), ErrorKind::BrokenPipe);
+    }
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).to_string()
+}
+
+#[test]
+fn tokens() {
+    let output = rslua(&["tokens"], "f 'a'");
+    assert!(output.status.success());
+    assert_eq!(
+        stdout(&output),
+        "1:1 Name \"f\"\n1:3 String \"a\"\n1:6 Eos\n"
+    );
+}
+
+#[test]
+fn ast() {
+    let output = rslua(&["ast", "-"], "x = 1");
+    assert!(output.status.success());
+    assert!(stdout(&output).starts_with("Block {\n    stats: [\n        AssignStat("));
+    let output = rslua(&["ast", "--lua", "5.1"], "goto x");
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn check() {
+    let output = rslua(&["check", "lua"], "");
+    assert!(output.status.success());
+    assert_eq!(stderr(&output), "");
+
+    let output = rslua(&["check"], "local a = '\\q'\nx = = 1\n@\n");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        stderr(&output),
+        "<stdin>: [lex error] invalid escape sequence '\\q' at line [1:14].\n\
+         <stdin>: [syntax error] unexpected symbol at line [2:5] near [Assign]\n\
+         <stdin>: [lex error] unknown token near '@' at line [3:1].\n"
+    );
+    let output = rslua(&["check"], "break\ngoto out");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        stderr(&output),
+        "<stdin>: [syntax error] break outside a loop at line [1:1].\n\
+         <stdin>: [syntax error] no visible label 'out' for goto at line [2:1].\n"
+    );
+}
+
+#[test]
+fn truncated_long_bracket() {
+    let output = rslua(&["check"], "x = [==");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("invalid long string delimiter"));
+    let output = rslua(&["fmt"], "x = 1 --[==");
+    assert_eq!(stdout(&output), "x = 1 --[==\n");
+    let output = rslua(&["minify"], "x = 1 --[==");
+    assert_eq!(stdout(&output), "x=1\n");
+    let output = rslua(&["lint"], "x = 1 --[==");
+    assert_eq!(
+        stderr(&output),
+        "<stdin>: [global-assign] assignment to global 'x' at line [1:1].\n"
+    );
+}
+
+#[test]
+fn fmt() {
+    let output = rslua(
+        &["fmt", "--indent", "4", "--quotes", "single"],
+        "if a then f(\"x\") end",
+    );
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "if a then\n    f('x')\nend\n");
+
+    let output = rslua(&["fmt", "--check", "lua/json.lua"], "");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stderr(&output), "lua/json.lua: not formatted\n");
+
+    let dir = std::env::temp_dir().join(format!("rslua_cli_{}", std::process::id()));
+    fs::create_dir_all(dir.join("sub")).unwrap();
+    let file = dir.join("sub").join("a.lua");
+    fs::write(&file, "x=1").unwrap();
+    fs::write(dir.join("b.txt"), "x=1").unwrap();
+    let output = rslua(&["fmt", dir.to_str().unwrap()], "");
+    assert!(output.status.success());
+    assert_eq!(fs::read_to_string(&file).unwrap(), "x = 1\n");
+    assert_eq!(fs::read_to_string(dir.join("b.txt")).unwrap(), "x=1");
+    let output = rslua(&["fmt", "--check", dir.to_str().unwrap()], "");
+    assert!(output.status.success());
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn minify() {
+    let output = rslua(
+        &["minify", "--lua", "5.1"],
+        "local value = 1.0 -- one\nprint(value)",
+    );
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "local a=1 print(a)\n");
+}
+
+#[test]
+fn lint() {
+    let input = "local x = 1\nprint(y, z)";
+    let output = rslua(&["lint", "--globals", "z"], input);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        stderr(&output),
+        "<stdin>: [unused-local] unused local 'x' at line [1:7].\n\
+         <stdin>: [undefined-global] undefined global 'y' at line [2:7].\n"
+    );
+    let output = rslua(
+        &["lint", "--disable", "unused-local,undefined-global"],
+        input,
+    );
+    assert!(output.status.success());
+    let output = rslua(&["lint", "--disable", "unused"], input);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn usage() {
+    let output = rslua(&["--help"], "");
+    assert!(output.status.success());
+    assert!(stdout(&output).starts_with("usage: rslua"));
+    for args in [
+        &["foo"][..],
+        &[],
+        &["check", "--tabs"],
+        &["fmt", "--width", "x"],
+    ] {
+        let output = rslua(args, "");
+        assert_eq!(output.status.code(), Some(2));
+    }
+    let output = rslua(&["check", "missing.lua"], "");
+    assert_eq!(output.status.code(), Some(2));
+}
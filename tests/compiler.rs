@@ -0,0 +1,382 @@
+use rslua::bytecode::{Constant, Proto};
+use rslua::compiler::Compiler;
+use rslua::lexer::Lexer;
+use rslua::parser::Parser;
+use rslua::types::LuaVersion;
+
+fn try_compile(input: &str) -> Result<Proto, String> {
+    let mut lexer = Lexer::new();
+    lexer.set_version(LuaVersion::Lua54);
+    let tokens = lexer.run(input).unwrap();
+    let mut parser = Parser::new();
+    parser.set_version(LuaVersion::Lua54);
+    let block = parser.run(tokens).unwrap();
+    Compiler::new().run(&block).map_err(|e| e.to_string())
+}
+
+fn compile(input: &str) -> Proto {
+    try_compile(input).unwrap()
+}
+
+// instructions as `luac -l` lists them, single spaced
+fn listing(proto: &Proto) -> Vec<String> {
+    proto
+        .code
+        .iter()
+        .map(|i| {
+            i.to_string()
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .collect()
+}
+
+#[test]
+fn expressions() {
+    let proto = compile("local a = 1 + 2\nlocal b = a * 2 + x.y\nprint(a, b, 'hi')");
+    assert_eq!(
+        listing(&proto),
+        vec![
+            "LOADK 0 -1",
+            "MUL 1 0 -2",
+            "GETTABUP 2 0 -3",
+            "GETTABLE 2 2 -4",
+            "ADD 1 1 2",
+            "GETTABUP 2 0 -5",
+            "MOVE 3 0",
+            "MOVE 4 1",
+            "LOADK 5 -6",
+            "CALL 2 4 1",
+            "RETURN 0 1",
+        ]
+    );
+    assert_eq!(proto.line_info, vec![1, 2, 2, 2, 2, 3, 3, 3, 3, 3, 3]);
+    assert_eq!(proto.constants[0], Constant::Int(3));
+    assert_eq!(proto.max_stack_size, 6);
+    assert!(proto.is_vararg);
+    assert_eq!(proto.upvalues[0].name, "_ENV");
+
+    let proto = compile("local a, b = ... return a .. b .. 'c', not a, #b, -a");
+    assert_eq!(
+        listing(&proto),
+        vec![
+            "VARARG 0 3",
+            "MOVE 2 0",
+            "MOVE 3 1",
+            "LOADK 4 -1",
+            "CONCAT 2 2 4",
+            "NOT 3 0",
+            "LEN 4 1",
+            "UNM 5 0",
+            "RETURN 2 5",
+            "RETURN 0 1",
+        ]
+    );
+}
+
+#[test]
+fn constant_folding() {
+    let proto = compile("local a, b, c, d, e = 2 ^ 10, 7 // 2, 1 // 0, 3 & 1.5, - 0.0");
+    assert_eq!(
+        listing(&proto),
+        vec![
+            "LOADK 0 -1",
+            "LOADK 1 -2",
+            "IDIV 2 -4 -3",
+            "BAND 3 -2 -5",
+            "LOADK 4 -6",
+            "UNM 4 4",
+            "RETURN 0 1",
+        ]
+    );
+    assert_eq!(
+        proto.constants,
+        vec![
+            Constant::Float(1024.0),
+            Constant::Int(3),
+            Constant::Int(0),
+            Constant::Int(1),
+            Constant::Float(1.5),
+            Constant::Float(0.0),
+        ]
+    );
+}
+
+#[test]
+fn control_flow() {
+    let input = r#"
+        local t = {}
+        for i = 1, 10 do
+            if i % 2 == 0 and i > 4 or t[i] then
+                t[#t + 1] = i
+            elseif not t then
+                break
+            else
+                t.x = nil
+            end
+        end
+    "#;
+    assert_eq!(
+        listing(&compile(input)),
+        vec![
+            "NEWTABLE 0 0 0",
+            "LOADK 1 -1",
+            "LOADK 2 -2",
+            "LOADK 3 -1",
+            "FORPREP 1 15",
+            "MOD 5 4 -3",
+            "EQ 0 5 -4",
+            "JMP 0 2",
+            "LT 1 -5 4",
+            "JMP 0 3",
+            "GETTABLE 5 0 4",
+            "TEST 5 0",
+            "JMP 0 4",
+            "LEN 5 0",
+            "ADD 5 5 -1",
+            "SETTABLE 0 5 4",
+            "JMP 0 3",
+            "TEST 0 0",
+            "JMP 0 2",
+            "SETTABLE 0 -6 -7",
+            "FORLOOP 1 -16",
+            "RETURN 0 1",
+        ]
+    );
+
+    let input = r#"
+        for k, v in pairs(t) do print(k, v) end
+        while x do x = x - 1 end
+        repeat local y = f() until y
+    "#;
+    assert_eq!(
+        listing(&compile(input)),
+        vec![
+            "GETTABUP 0 0 -1",
+            "GETTABUP 1 0 -2",
+            "CALL 0 2 4",
+            "JMP 0 4",
+            "GETTABUP 5 0 -3",
+            "MOVE 6 3",
+            "MOVE 7 4",
+            "CALL 5 3 1",
+            "TFORCALL 0 2",
+            "TFORLOOP 2 -6",
+            "GETTABUP 0 0 -4",
+            "TEST 0 0",
+            "JMP 0 4",
+            "GETTABUP 0 0 -4",
+            "SUB 0 0 -5",
+            "SETTABUP 0 -4 0",
+            "JMP 0 -7",
+            "GETTABUP 0 0 -6",
+            "CALL 0 1 2",
+            "TEST 0 0",
+            "JMP 0 -4",
+            "RETURN 0 1",
+        ]
+    );
+}
+
+#[test]
+fn gotos() {
+    // the jump out of the scope of a captured local closes it
+    let input = r#"
+        for i = 1, 3 do
+            local c = i
+            g = function() return c end
+            if c then goto continue end
+            h()
+            ::continue::
+        end
+    "#;
+    assert_eq!(
+        listing(&compile(input)),
+        vec![
+            "LOADK 0 -1",
+            "LOADK 1 -2",
+            "LOADK 2 -1",
+            "FORPREP 0 8",
+            "MOVE 4 3",
+            "CLOSURE 5 0",
+            "SETTABUP 0 -3 5",
+            "TEST 4 1",
+            "JMP 5 3",
+            "GETTABUP 5 0 -4",
+            "CALL 5 1 1",
+            "JMP 5 0",
+            "FORLOOP 0 -9",
+            "RETURN 0 1",
+        ]
+    );
+    assert_eq!(
+        listing(&compile("goto done do local x = 1 end ::done::")),
+        vec!["JMP 0 1", "LOADK 0 -1", "RETURN 0 1"]
+    );
+}
+
+#[test]
+fn functions() {
+    let input = r#"
+        local function counter()
+            local n = 0
+            return function() n = n + 1 return n end
+        end
+        function obj.m:go(a, ...)
+            return select('#', ...), {1, 2, ..., x = a}
+        end
+    "#;
+    let proto = compile(input);
+    assert_eq!(
+        listing(&proto),
+        vec![
+            "CLOSURE 0 0",
+            "GETTABUP 1 0 -1",
+            "GETTABLE 1 1 -2",
+            "CLOSURE 2 1",
+            "SETTABLE 1 -3 2",
+            "RETURN 0 1",
+        ]
+    );
+    let counter = &proto.protos[0];
+    assert_eq!((counter.line_defined, counter.num_params), (2, 0));
+    assert_eq!(
+        listing(&counter.protos[0]),
+        vec![
+            "GETUPVAL 0 0",
+            "ADD 0 0 -1",
+            "SETUPVAL 0 0",
+            "GETUPVAL 0 0",
+            "RETURN 0 2",
+            "RETURN 0 1",
+        ]
+    );
+    let upvalue = &counter.protos[0].upvalues[0];
+    assert_eq!(
+        (upvalue.name.as_str(), upvalue.in_stack, upvalue.index),
+        ("n", true, 0)
+    );
+
+    let method = &proto.protos[1];
+    assert_eq!((method.num_params, method.is_vararg), (2, true));
+    assert!(!method.upvalues[0].in_stack);
+    assert_eq!(
+        listing(method),
+        vec![
+            "GETTABUP 2 0 -1",
+            "LOADK 3 -2",
+            "VARARG 4 0",
+            "CALL 2 0 2",
+            "NEWTABLE 3 3 1",
+            "LOADK 4 -3",
+            "LOADK 5 -4",
+            "VARARG 6 2",
+            "SETTABLE 3 -5 1",
+            "SETLIST 3 3 1",
+            "RETURN 2 3",
+            "RETURN 0 1",
+        ]
+    );
+    let names: Vec<_> = method.local_vars.iter().map(|v| v.name.as_str()).collect();
+    assert_eq!(names, vec!["self", "a"]);
+
+    assert_eq!(
+        listing(&compile(
+            "local a, b = {}, 1 a[b], b = 2, 3 return f(a:m(b))"
+        )),
+        vec![
+            "NEWTABLE 0 0 0",
+            "LOADK 1 -1",
+            "MOVE 2 1",
+            "LOADK 3 -2",
+            "LOADK 1 -3",
+            "SETTABLE 0 2 3",
+            "GETTABUP 2 0 -4",
+            "SELF 3 0 -5",
+            "MOVE 5 1",
+            "CALL 3 3 0",
+            "TAILCALL 2 0 0",
+            "RETURN 2 0",
+            "RETURN 0 1",
+        ]
+    );
+}
+
+#[test]
+fn big_tables() {
+    let items: Vec<String> = (0..300).map(|i| format!("'s{}'", i)).collect();
+    let proto = compile(&format!("local t = {{{}}} t.k = 'v'", items.join(", ")));
+    let code = listing(&proto);
+    assert_eq!(code[0], "NEWTABLE 0 50 0");
+    assert_eq!(code[51], "SETLIST 0 50 1");
+    assert_eq!(code[306], "SETLIST 0 50 6");
+    // constants past 255 are loaded into registers to be stored
+    assert_eq!(
+        code[307..310],
+        ["LOADK 1 -301", "LOADK 2 -302", "SETTABLE 0 1 2"]
+    );
+    assert_eq!(proto.max_stack_size, 51);
+}
+
+#[test]
+fn errors() {
+    assert_eq!(
+        try_compile("goto a local x ::a:: print(x)").unwrap_err(),
+        "[compile error] <goto a> at line 1 jumps into the scope of local 'x' at line [1:16]."
+    );
+    assert_eq!(
+        try_compile("if x then break end").unwrap_err(),
+        "[compile error] <break> at line 1 not inside a loop at line [1:11]."
+    );
+    assert!(try_compile("do goto a end")
+        .unwrap_err()
+        .contains("no visible label 'a' for <goto>"));
+    assert!(try_compile("::a:: ::a::")
+        .unwrap_err()
+        .contains("label 'a' already defined on line 1"));
+    assert!(try_compile("function f() return ... end")
+        .unwrap_err()
+        .contains("cannot use '...' outside a vararg function"));
+    assert!(try_compile("local x <close> = nil")
+        .unwrap_err()
+        .contains("to-be-closed local 'x'"));
+    assert_eq!(
+        try_compile("local x <const> = 1\nfunction f() x = 2 end").unwrap_err(),
+        "[compile error] attempt to assign to const variable 'x' at line [2:14]."
+    );
+    assert!(try_compile("local x <const> = 1 return x").is_ok());
+    let names: Vec<String> = (0..201).map(|i| format!("v{}", i)).collect();
+    assert!(try_compile(&format!("local {}", names.join(", ")))
+        .unwrap_err()
+        .contains("too many local variables (limit is 200) in main function"));
+    assert!(try_compile("repeat local x = 1 ::a:: until x goto a").is_err());
+}
+
+#[test]
+fn dump() {
+    let mut lexer = Lexer::new();
+    let tokens = lexer.run("print('hi')").unwrap();
+    let block = Parser::new().run(tokens).unwrap();
+    let mut compiler = Compiler::new();
+    compiler.set_chunk_name("=stdin");
+    let proto = compiler.run(&block).unwrap();
+    let chunk = proto.dump(false);
+    let header: &[u8] = &[
+        0x1b, b'L', b'u', b'a', 0x53, 0, 0x19, 0x93, b'\r', b'\n', 0x1a, b'\n', 4, 8, 4, 8, 8,
+        0x78, 0x56, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x28, 0x77, 0x40,
+    ];
+    assert_eq!(&chunk[..header.len()], header);
+    let main: &[u8] = &[
+        1, 7, b'=', b's', b't', b'd', b'i', b'n', 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 2, 4, 0, 0, 0,
+        0x06, 0x00, 0x40, 0x00, 0x41, 0x40, 0x00, 0x00, 0x24, 0x40, 0x00, 0x01, 0x26, 0x00, 0x80,
+        0x00, 2, 0, 0, 0, 4, 6, b'p', b'r', b'i', b'n', b't', 4, 3, b'h', b'i', 1, 0, 0, 0, 1, 0,
+        0, 0, 0, 0, 4, 0, 0, 0,
+    ];
+    assert_eq!(&chunk[header.len()..header.len() + main.len()], main);
+    assert!(chunk.ends_with(b"\x01\x00\x00\x00\x05_ENV"));
+    // no source, line info, local or upvalue names
+    let stripped = proto.dump(true);
+    assert_eq!(stripped.len(), chunk.len() - 6 - 16 - 5);
+    assert_eq!(stripped[header.len() + 1], 0);
+}
@@ -0,0 +1,83 @@
+mod cst_tests {
+    use rslua::cst::*;
+    use rslua::lexer::Lexer;
+    use rslua::parser::Parser;
+    use rslua::token::TokenType;
+    use std::fs::File;
+    use std::io::prelude::*;
+
+    fn try_cst(input: &str) -> SyntaxNode {
+        let mut lexer = Lexer::new();
+        lexer.set_debug(true);
+        lexer.set_keep_trivia(true);
+        let tokens = lexer.run(input).ok().unwrap();
+        let mut parser = Parser::new();
+        parser.set_debug(true);
+        let (_, cst) = parser.run_with_cst(tokens, input).ok().unwrap();
+        cst
+    }
+
+    fn round_trip(input: &str) {
+        assert_eq!(try_cst(input).to_string(), input);
+    }
+
+    fn round_trip_file(path: &str) -> std::io::Result<()> {
+        let mut file = File::open(path)?;
+        let mut content = String::new();
+        file.read_to_string(&mut content)?;
+        round_trip(&content);
+        Ok(())
+    }
+
+    #[test]
+    fn cst_practical() -> std::io::Result<()> {
+        round_trip_file(r"lua/json.lua")?;
+        round_trip_file(r"lua/json_test.lua")
+    }
+
+    #[test]
+    fn lossless() {
+        round_trip("");
+        round_trip("  -- only a comment\n");
+        round_trip("local a <const> = 0x1p4;;; b = 'x' .. \"y\" .. [==[z]==]\n");
+        round_trip("print((a)) ; f{1, 2; 3,} t[ 1 ] . x = - -1 --[[ tail ]]");
+        round_trip("\tfunction a.b:c(...) return ... end\r\nif x then elseif y then else end");
+        round_trip("for i = 1, 10 do break end for k, v in pairs(t) do goto l end ::l::");
+        round_trip("repeat local x = #t until x ~= 3.0e-1 and not (y >= 1 // 2)");
+    }
+
+    #[test]
+    fn structure() {
+        let cst = try_cst("local a = f(1) -- call\n");
+        assert_eq!(cst.kind, NodeKind::Chunk);
+        let kinds: Vec<NodeKind> = cst.nodes().map(|n| n.kind).collect();
+        assert_eq!(kinds, vec![NodeKind::Block]);
+        let block = cst.nodes().next().unwrap();
+        let stat = block.nodes().next().unwrap();
+        assert_eq!(stat.kind, NodeKind::LocalStat);
+        let kinds: Vec<NodeKind> = stat.nodes().map(|n| n.kind).collect();
+        assert_eq!(kinds, vec![NodeKind::LocalName, NodeKind::SuffixedExpr]);
+        let types: Vec<TokenType> = stat.tokens().iter().map(|t| t.t).collect();
+        assert_eq!(
+            types,
+            vec![
+                TokenType::Local,
+                TokenType::Name,
+                TokenType::Assign,
+                TokenType::Name,
+                TokenType::Lp,
+                TokenType::Int,
+                TokenType::Rp
+            ]
+        );
+        let tokens = cst.tokens();
+        assert_eq!(tokens.last().unwrap().t, TokenType::Eos);
+        assert_eq!(tokens[6].trailing[1].text, "-- call");
+    }
+
+    #[test]
+    #[should_panic]
+    fn trailing_tokens() {
+        try_cst("a = 1 end");
+    }
+}
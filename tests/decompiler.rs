@@ -0,0 +1,234 @@
+use rslua::bytecode::{Instruction, OpCode};
+use rslua::compiler::Compiler;
+use rslua::decompiler::Decompiler;
+use rslua::interpreter::Interpreter;
+use rslua::lexer::Lexer;
+use rslua::parser::Parser;
+use rslua::printer;
+use rslua::undump::undump;
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::prelude::*;
+use std::rc::Rc;
+
+// `print("hi")` compiled by luac 5.1 and 5.4 on x86-64
+const PRINT_51: &[u8] = &[
+    0x1b, b'L', b'u', b'a', 0x51, 0, 1, 4, 8, 4, 8, 0, 7, 0, 0, 0, 0, 0, 0, 0, b'=', b's', b't',
+    b'd', b'i', b'n', 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 2, 4, 0, 0, 0, 0x05, 0, 0, 0, 0x41, 0x40,
+    0, 0, 0x1c, 0x40, 0, 0x01, 0x1e, 0, 0x80, 0, 2, 0, 0, 0, 4, 6, 0, 0, 0, 0, 0, 0, 0, b'p', b'r',
+    b'i', b'n', b't', 0, 4, 3, 0, 0, 0, 0, 0, 0, 0, b'h', b'i', 0, 0, 0, 0, 0, 4, 0, 0, 0, 1, 0, 0,
+    0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+];
+
+const PRINT_54: &[u8] = &[
+    0x1b, b'L', b'u', b'a', 0x54, 0, 0x19, 0x93, b'\r', b'\n', 0x1a, b'\n', 4, 8, 8, 0x78, 0x56, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x28, 0x77, 0x40, 1, 0x87, b'=', b's', b't', b'd', b'i', b'n',
+    0x80, 0x80, 0, 1, 2, 0x85, 0x51, 0, 0, 0, 0x0b, 0, 0, 0, 0x83, 0x80, 0, 0, 0x44, 0, 0x02, 0x01,
+    0x46, 0, 0x01, 0x01, 0x82, 4, 0x86, b'p', b'r', b'i', b'n', b't', 4, 0x83, b'h', b'i', 0x81, 1,
+    0, 0, 0x80, 0x85, 1, 0, 0, 0, 0, 0x80, 0x80, 0x81, 0x85, b'_', b'E', b'N', b'V',
+];
+
+// output of `print` collected by the tests
+#[derive(Clone, Default)]
+struct Output(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn decompile(input: &str, strip: bool) -> String {
+    let tokens = Lexer::new().run(input).unwrap();
+    let block = Parser::new().run(tokens).unwrap();
+    let chunk = Compiler::new().run(&block).unwrap().dump(strip);
+    let block = Decompiler::new().run(&undump(&chunk).unwrap()).unwrap();
+    printer::print(&block)
+}
+
+fn read_file(path: &str) -> String {
+    let mut content = String::new();
+    File::open(path)
+        .unwrap()
+        .read_to_string(&mut content)
+        .unwrap();
+    content
+}
+
+fn run_output(input: &str) -> String {
+    let output = Output::default();
+    let mut interp = Interpreter::new();
+    interp.set_output(Box::new(output.clone()));
+    interp.exec(input, "test").unwrap();
+    let text = String::from_utf8(output.0.borrow().clone()).unwrap();
+    text
+}
+
+#[test]
+fn locals() {
+    let input = "local a, b = 1, \"x\"\nlocal c = a + -2 * b\nprint(a, b, c)\n";
+    assert_eq!(decompile(input, false), input);
+}
+
+#[test]
+fn conditions() {
+    let input = r#"local x = tonumber(arg)
+if x < 0 then
+  print("negative")
+elseif x == 0 or x > 100 then
+  print("edge")
+else
+  print("positive")
+end
+local y = x > 10 and "big" or "small"
+print(not y)
+"#;
+    assert_eq!(decompile(input, false), input);
+}
+
+#[test]
+fn loops() {
+    let input = r#"local n = 0
+while n < 10 do
+  n = n + 1
+  if n == 5 then
+    break
+  end
+end
+repeat
+  n = n - 1
+until n <= 0
+for i = 10, 1, -1 do
+  print(i)
+end
+for k, v in pairs(_G) do
+  print(k, v)
+end
+"#;
+    assert_eq!(decompile(input, false), input);
+}
+
+#[test]
+fn functions() {
+    let input = r#"local t = {1, 2, x = 3, [4] = 5}
+local function f(a, ...)
+  return a, ...
+end
+function t:g(b)
+  return f(b)
+end
+function t:h()
+  self.x = self:g(1)
+end
+t.x, t[1] = t[1], t.x
+"#;
+    assert_eq!(decompile(input, false), input);
+}
+
+#[test]
+fn gotos() {
+    let input = r#"for i = 1, 3 do
+  for j = 1, 3 do
+    if i * j == 4 then
+      goto done
+    end
+    print(i, j)
+  end
+end
+print("none")
+::done::
+print("end")
+"#;
+    let text = decompile(input, false);
+    assert_eq!(text, input.replace("done", "L21"));
+}
+
+#[test]
+fn stripped() {
+    let input = r#"local function fib(n)
+  if n < 2 then
+    return n
+  end
+  return fib(n - 1) + fib(n - 2)
+end
+local t = {}
+for i = 1, 10 do
+  t[#t + 1] = fib(i)
+end
+print(table.concat(t, " "))
+"#;
+    let text = decompile(input, true);
+    assert_eq!(
+        text,
+        r#"local l0, l1
+function l0(l1_0)
+  if l1_0 < 2 then
+    return l1_0
+  end
+  return l0(l1_0 - 1) + l0(l1_0 - 2)
+end
+l1 = {}
+for l5 = 1, 10 do
+  l1[#l1 + 1] = l0(l5)
+end
+print(table.concat(l1, " "))
+"#
+    );
+    assert_eq!(run_output(&text), run_output(input));
+}
+
+#[test]
+fn lua51() {
+    let block = Decompiler::new().run(&undump(PRINT_51).unwrap()).unwrap();
+    assert_eq!(printer::print(&block), "print(\"hi\")\n");
+}
+
+#[test]
+fn lua54() {
+    let result = Decompiler::new().run(&undump(PRINT_54).unwrap());
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "[decompile error] unsupported version Lua 5.4 at instruction 1."
+    );
+}
+
+#[test]
+fn malformed() {
+    let tokens = Lexer::new().run("local t = {}").unwrap();
+    let block = Parser::new().run(tokens).unwrap();
+    let chunk = undump(&Compiler::new().run(&block).unwrap().dump(false)).unwrap();
+    let error = |code: &[Instruction]| {
+        let mut chunk = chunk.clone();
+        chunk.main.code = code.to_vec();
+        Decompiler::new().run(&chunk).unwrap_err().to_string()
+    };
+    assert_eq!(
+        error(&[Instruction::abc(OpCode::NewTable, 0, 0x1ff, 0)]),
+        "[decompile error] table size out of range at instruction 1."
+    );
+    assert_eq!(
+        error(&[Instruction::abx(OpCode::LoadKX, 0, 0)]),
+        "[decompile error] missing arguments of the instruction at instruction 1."
+    );
+}
+
+#[test]
+fn json() {
+    let json = read_file("lua/json.lua");
+    let test = read_file("lua/json_test.lua");
+    for strip in [false, true].iter() {
+        let output = Output::default();
+        let mut interp = Interpreter::new();
+        interp.set_output(Box::new(output.clone()));
+        interp.add_module("json", &decompile(&json, *strip));
+        interp.exec(&decompile(&test, *strip), "json_test").unwrap();
+        let text = String::from_utf8(output.0.borrow().clone()).unwrap();
+        assert_eq!(text.matches("[pass]").count(), 14);
+        assert!(!text.contains("[fail]"));
+    }
+}
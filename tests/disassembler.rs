@@ -0,0 +1,147 @@
+use rslua::compiler::Compiler;
+use rslua::disassembler::disassemble;
+use rslua::lexer::Lexer;
+use rslua::parser::Parser;
+use rslua::undump::undump;
+
+// `print("hi")` compiled by luac 5.1 and 5.4 on x86-64
+const PRINT_51: &[u8] = &[
+    0x1b, b'L', b'u', b'a', 0x51, 0, 1, 4, 8, 4, 8, 0, 7, 0, 0, 0, 0, 0, 0, 0, b'=', b's', b't',
+    b'd', b'i', b'n', 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 2, 4, 0, 0, 0, 0x05, 0, 0, 0, 0x41, 0x40,
+    0, 0, 0x1c, 0x40, 0, 0x01, 0x1e, 0, 0x80, 0, 2, 0, 0, 0, 4, 6, 0, 0, 0, 0, 0, 0, 0, b'p', b'r',
+    b'i', b'n', b't', 0, 4, 3, 0, 0, 0, 0, 0, 0, 0, b'h', b'i', 0, 0, 0, 0, 0, 4, 0, 0, 0, 1, 0, 0,
+    0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+];
+
+const PRINT_54: &[u8] = &[
+    0x1b, b'L', b'u', b'a', 0x54, 0, 0x19, 0x93, b'\r', b'\n', 0x1a, b'\n', 4, 8, 8, 0x78, 0x56, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x28, 0x77, 0x40, 1, 0x87, b'=', b's', b't', b'd', b'i', b'n',
+    0x80, 0x80, 0, 1, 2, 0x85, 0x51, 0, 0, 0, 0x0b, 0, 0, 0, 0x83, 0x80, 0, 0, 0x44, 0, 0x02, 0x01,
+    0x46, 0, 0x01, 0x01, 0x82, 4, 0x86, b'p', b'r', b'i', b'n', b't', 4, 0x83, b'h', b'i', 0x81, 1,
+    0, 0, 0x80, 0x85, 1, 0, 0, 0, 0, 0x80, 0x80, 0x81, 0x85, b'_', b'E', b'N', b'V',
+];
+
+fn lines(text: &str) -> Vec<&str> {
+    text.lines().collect()
+}
+
+fn listing(input: &str, strip: bool, full: bool) -> String {
+    let tokens = Lexer::new().run(input).unwrap();
+    let block = Parser::new().run(tokens).unwrap();
+    let mut compiler = Compiler::new();
+    compiler.set_chunk_name("@test.lua");
+    let chunk = compiler.run(&block).unwrap().dump(strip);
+    disassemble(&undump(&chunk).unwrap(), full)
+}
+
+#[test]
+fn lua53() {
+    let text = listing(
+        "local t = {x = 'a\\n', 1.0}\nfunction t:m(a)\n  return self[a] == 2\nend",
+        false,
+        true,
+    );
+    assert_eq!(
+        lines(&text),
+        vec![
+            "",
+            "main <test.lua:0,0> (7 instructions)",
+            "0+ params, 2 slots, 1 upvalue, 1 local, 4 constants, 1 function",
+            "\t1\t[1]\tNEWTABLE \t0 1 1",
+            "\t2\t[1]\tSETTABLE \t0 -1 -2\t; \"x\" \"a\\n\"",
+            "\t3\t[1]\tLOADK    \t1 -3\t; 1.0",
+            "\t4\t[1]\tSETLIST  \t0 1 1\t; 1",
+            "\t5\t[3]\tCLOSURE  \t1 0\t; function <test.lua:2,3>",
+            "\t6\t[2]\tSETTABLE \t0 -4 1\t; \"m\" -",
+            "\t7\t[3]\tRETURN   \t0 1",
+            "constants (4):",
+            "\t1\t\"x\"",
+            "\t2\t\"a\\n\"",
+            "\t3\t1.0",
+            "\t4\t\"m\"",
+            "locals (1):",
+            "\t0\tt\t5\t8",
+            "upvalues (1):",
+            "\t0\t_ENV\t1\t0",
+            "",
+            "function <test.lua:2,3> (7 instructions)",
+            "2 params, 3 slots, 0 upvalues, 2 locals, 1 constant, 0 functions",
+            "\t1\t[3]\tGETTABLE \t2 0 1",
+            "\t2\t[3]\tEQ       \t1 2 -1\t; - 2",
+            "\t3\t[3]\tJMP      \t0 1\t; to 5",
+            "\t4\t[3]\tLOADBOOL \t2 0 1",
+            "\t5\t[3]\tLOADBOOL \t2 1 0",
+            "\t6\t[3]\tRETURN   \t2 2",
+            "\t7\t[3]\tRETURN   \t0 1",
+            "constants (1):",
+            "\t1\t2",
+            "locals (2):",
+            "\t0\tself\t1\t8",
+            "\t1\ta\t1\t8",
+            "upvalues (0):",
+        ]
+    );
+}
+
+#[test]
+fn stripped() {
+    let text = listing("x = ...\nlocal y <const> = 'y'", true, false);
+    assert_eq!(
+        lines(&text),
+        vec![
+            "",
+            "main <?:0,0> (4 instructions)",
+            "0+ params, 2 slots, 1 upvalue, 0 locals, 2 constants, 0 functions",
+            "\t1\t[-]\tVARARG   \t0 2",
+            "\t2\t[-]\tSETTABUP \t0 -1 0\t; - \"x\"",
+            "\t3\t[-]\tLOADK    \t0 -2\t; \"y\"",
+            "\t4\t[-]\tRETURN   \t0 1",
+        ]
+    );
+}
+
+#[test]
+fn lua51() {
+    let text = disassemble(&undump(PRINT_51).unwrap(), true);
+    assert_eq!(
+        lines(&text),
+        vec![
+            "",
+            "main <stdin:0,0> (4 instructions, 16 bytes)",
+            "0+ params, 2 slots, 0 upvalues, 0 locals, 2 constants, 0 functions",
+            "\t1\t[1]\tGETGLOBAL\t0 -1\t; print",
+            "\t2\t[1]\tLOADK    \t1 -2\t; \"hi\"",
+            "\t3\t[1]\tCALL     \t0 2 1",
+            "\t4\t[1]\tRETURN   \t0 1",
+            "constants (2):",
+            "\t1\t\"print\"",
+            "\t2\t\"hi\"",
+            "locals (0):",
+            "upvalues (0):",
+        ]
+    );
+}
+
+#[test]
+fn lua54() {
+    let text = disassemble(&undump(PRINT_54).unwrap(), true);
+    assert_eq!(
+        lines(&text),
+        vec![
+            "",
+            "main <stdin:0,0> (5 instructions)",
+            "0+ params, 2 slots, 1 upvalue, 0 locals, 2 constants, 0 functions",
+            "\t1\t[1]\tVARARGPREP\t0",
+            "\t2\t[1]\tGETTABUP \t0 0 0\t; _ENV \"print\"",
+            "\t3\t[1]\tLOADK    \t1 1\t; \"hi\"",
+            "\t4\t[1]\tCALL     \t0 2 1\t; 1 in 0 out",
+            "\t5\t[1]\tRETURN   \t0 1 1\t; 0 out",
+            "constants (2):",
+            "\t0\tS\t\"print\"",
+            "\t1\tS\t\"hi\"",
+            "locals (0):",
+            "upvalues (1):",
+            "\t0\t_ENV\t1\t0",
+        ]
+    );
+}
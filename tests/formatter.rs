@@ -0,0 +1,180 @@
+use rslua::ast::{same_structure, Block};
+use rslua::formatter::{format, CallParens, FormatConfig, FormatError, QuoteStyle};
+use rslua::lexer::Lexer;
+use rslua::parser::Parser;
+use std::fs::{read_dir, File};
+use std::io::prelude::*;
+
+fn try_parse(input: &str) -> Block {
+    let mut lexer = Lexer::new();
+    lexer.set_debug(true);
+    let tokens = lexer.run(input).unwrap();
+    let mut parser = Parser::new();
+    parser.set_debug(true);
+    parser.run(tokens).unwrap()
+}
+
+fn try_format(input: &str, config: &FormatConfig) -> String {
+    let output = format(input, config).unwrap();
+    assert_eq!(output, format(&output, config).unwrap());
+    output
+}
+
+#[test]
+fn format_lua_files() -> std::io::Result<()> {
+    for entry in read_dir("./lua")? {
+        let mut file = File::open(entry?.path())?;
+        let mut content = String::new();
+        file.read_to_string(&mut content)?;
+        let output = try_format(&content, &FormatConfig::default());
+        assert!(same_structure(&try_parse(&content), &try_parse(&output)));
+        assert_eq!(content.matches("--").count(), output.matches("--").count());
+    }
+    Ok(())
+}
+
+#[test]
+fn comments() {
+    let config = FormatConfig::default();
+    assert_eq!(
+        try_format(
+            "-- header\n\n\n\nlocal a=1 -- one\nif a then -- cond\n  -- inside\nend\nlocal t={1, -- x\n2}\n-- tail",
+            &config
+        ),
+        "-- header\n\nlocal a = 1 -- one\nif a then -- cond\n  -- inside\nend\nlocal t = {\n  1, -- x\n  2,\n}\n-- tail\n"
+    );
+    assert_eq!(
+        try_format("f(function() --[[a]] end)\nlocal x = --[[b]] 1", &config),
+        "f(function() --[[a]]\nend)\nlocal x = 1 --[[b]]\n"
+    );
+    // a comment moved to the end of its statement leaves no blank line
+    assert_eq!(
+        try_format("x = a and -- why\n  b\ny = 1\n\nz = 2", &config),
+        "x = a and b -- why\ny = 1\n\nz = 2\n"
+    );
+    // an `else` with only a comment stays
+    assert_eq!(
+        try_format(
+            "if a then x() else -- nothing to do here
+end",
+            &config
+        ),
+        "if a then\n  x()\nelse -- nothing to do here\nend\n"
+    );
+    assert_eq!(
+        try_format(
+            "if a then x() elseif b then\nelse\n  -- later\nend",
+            &config
+        ),
+        "if a then\n  x()\nelseif b then\nelse\n  -- later\nend\n"
+    );
+}
+
+#[test]
+fn layout() {
+    let config = FormatConfig::default();
+    assert_eq!(
+        try_format(
+            "local function f(a,b) return a+b end;;a = b; (f)() f(function() end)",
+            &config
+        ),
+        "local function f(a, b)\n  return a + b\nend\na = b\n;(f)()\nf(function() end)\n"
+    );
+    assert_eq!(
+        try_format("if a then elseif b then else end x = t[ [[s]] ]", &config),
+        "if a then\nelseif b then\nend\nx = t[ [[s]] ]\n"
+    );
+
+    let config = FormatConfig {
+        max_width: 20,
+        ..FormatConfig::default()
+    };
+    assert_eq!(
+        try_format(
+            "call(aaaa, bbbb, cccc) t = {1, 2} t = {aaaa, {bbbb, cccc}}",
+            &config
+        ),
+        "call(\n  aaaa,\n  bbbb,\n  cccc\n)\nt = { 1, 2 }\nt = {\n  aaaa,\n  { bbbb, cccc },\n}\n"
+    );
+    assert_eq!(
+        try_format("x = aaaaa or bbbbb and ccccc or ddddd", &config),
+        "x = aaaaa\n  or bbbbb and ccccc\n  or ddddd\n"
+    );
+    let config = FormatConfig {
+        max_width: 20,
+        trailing_separator: false,
+        ..FormatConfig::default()
+    };
+    assert_eq!(
+        try_format("t = {aaaa, bbbb, cccc}", &config),
+        "t = {\n  aaaa,\n  bbbb,\n  cccc\n}\n"
+    );
+}
+
+#[test]
+fn indent() {
+    let input = "while a do if b then c() end end";
+    let config = FormatConfig {
+        indent_width: 4,
+        ..FormatConfig::default()
+    };
+    assert_eq!(
+        try_format(input, &config),
+        "while a do\n    if b then\n        c()\n    end\nend\n"
+    );
+    let config = FormatConfig {
+        use_tabs: true,
+        ..FormatConfig::default()
+    };
+    assert_eq!(
+        try_format(input, &config),
+        "while a do\n\tif b then\n\t\tc()\n\tend\nend\n"
+    );
+}
+
+#[test]
+fn quotes() {
+    let input = r#"x = 'a', "b", 'it\'s', 'say "hi"', "\"", [[c]], '\u{48}\n'"#;
+    let mut config = FormatConfig::default();
+    assert_eq!(
+        try_format(input, &config),
+        "x = \"a\", \"b\", \"it's\", 'say \"hi\"', '\"', [[c]], \"\\u{48}\\n\"\n"
+    );
+    config.quote_style = QuoteStyle::Single;
+    assert_eq!(
+        try_format(input, &config),
+        "x = 'a', 'b', \"it's\", 'say \"hi\"', '\"', [[c]], '\\u{48}\\n'\n"
+    );
+    config.quote_style = QuoteStyle::Keep;
+    assert_eq!(try_format(input, &config), format!("{}\n", input));
+}
+
+#[test]
+fn call_parens() {
+    let input = "f 'a' g {1} h('b') i({}) j(1) k(('c'))";
+    let mut config = FormatConfig::default();
+    assert_eq!(
+        try_format(input, &config),
+        "f \"a\"\ng { 1 }\nh(\"b\")\ni({})\nj(1)\nk((\"c\"))\n"
+    );
+    config.call_parens = CallParens::Always;
+    assert_eq!(
+        try_format(input, &config),
+        "f(\"a\")\ng({ 1 })\nh(\"b\")\ni({})\nj(1)\nk((\"c\"))\n"
+    );
+    config.call_parens = CallParens::Omit;
+    assert_eq!(
+        try_format(input, &config),
+        "f \"a\"\ng { 1 }\nh \"b\"\ni {}\nj(1)\nk((\"c\"))\n"
+    );
+}
+
+#[test]
+fn error() {
+    let config = FormatConfig::default();
+    assert!(matches!(
+        format("x = ", &config),
+        Err(FormatError::Syntax(_))
+    ));
+    assert!(matches!(format("x = '", &config), Err(FormatError::Lex(_))));
+}
@@ -0,0 +1,234 @@
+use rslua::ast::ClearSpans;
+use rslua::compiler::Compiler;
+use rslua::decompiler::Decompiler;
+use rslua::interpreter::Interpreter;
+use rslua::lexer::Lexer;
+use rslua::parser::Parser;
+use rslua::types::LuaVersion;
+use rslua::undump::undump;
+use rslua::value::Value;
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::prelude::*;
+use std::rc::Rc;
+
+// output of `print` collected by the tests
+#[derive(Clone, Default)]
+struct Output(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn exec_with(interp: &mut Interpreter, input: &str) -> Vec<String> {
+    interp
+        .exec(input, "test")
+        .unwrap()
+        .iter()
+        .map(Value::to_string)
+        .collect()
+}
+
+fn exec(input: &str) -> Vec<String> {
+    exec_with(&mut Interpreter::new(), input)
+}
+
+fn exec_err(input: &str) -> String {
+    Interpreter::new()
+        .exec(input, "test")
+        .unwrap_err()
+        .to_string()
+}
+
+fn read(path: &str) -> String {
+    let mut content = String::new();
+    File::open(path)
+        .unwrap()
+        .read_to_string(&mut content)
+        .unwrap();
+    content
+}
+
+#[test]
+fn json_test() {
+    let output = Output::default();
+    let mut interp = Interpreter::new();
+    interp.set_output(Box::new(output.clone()));
+    interp.add_module("json", &read("lua/json.lua"));
+    interp
+        .exec(&read("lua/json_test.lua"), "json_test")
+        .unwrap();
+    let text = String::from_utf8(output.0.borrow().clone()).unwrap();
+    assert!(!text.contains("[fail]"), "{}", text);
+    assert_eq!(text.matches("[pass]").count(), 14);
+}
+
+#[test]
+fn arithmetic() {
+    assert_eq!(
+        exec("return 7 // 2, 7 / 2, 7 % -3, 2^10, 1 + 2.0, '10' + 1, 3 & 5, 1 << 63, -7 // 2.0"),
+        vec![
+            "3",
+            "3.5",
+            "-2",
+            "1024.0",
+            "3.0",
+            "11",
+            "1",
+            "-9223372036854775808",
+            "-4.0"
+        ]
+    );
+    assert_eq!(
+        exec("return math.maxinteger + 1 == math.mininteger, 1 == 1.0, 1 < 1.5, 'a' < 'b'"),
+        vec!["true", "true", "true", "true"]
+    );
+    assert_eq!(exec("return 1 // 0.0, -1 % math.huge"), vec!["inf", "inf"]);
+    assert!(exec_err("return 1 // 0").contains("attempt to perform 'n//0'"));
+}
+
+#[test]
+fn arithmetic_without_integers() {
+    let mut interp = Interpreter::new();
+    interp.set_version(LuaVersion::Lua51);
+    assert_eq!(
+        exec_with(
+            &mut interp,
+            "return tostring(7 / 2), tostring(3 + 4), tostring(2^2), _VERSION"
+        ),
+        vec!["3.5", "7", "4", "Lua 5.1"]
+    );
+}
+
+#[test]
+fn closures_and_varargs() {
+    let input = r#"
+        local function counter()
+            local n = 0
+            return function() n = n + 1 return n end
+        end
+        local a, b = counter(), counter()
+        a() a()
+        local function pack(...) return select('#', ...), ... end
+        return a(), b(), pack(1, nil, 3)
+    "#;
+    assert_eq!(exec(input), vec!["3", "1", "3", "1", "nil", "3"]);
+    assert_eq!(
+        exec("return #{1, 2, 3, nil, 5}, #{(table.unpack({1, 2, 3}))}"),
+        vec!["5", "1"]
+    );
+}
+
+#[test]
+fn metatables() {
+    let input = r#"
+        local V = {}
+        V.__index = V
+        V.__add = function(a, b) return setmetatable({x = a.x + b.x}, V) end
+        V.__eq = function(a, b) return a.x == b.x end
+        V.__tostring = function(v) return 'V(' .. v.x .. ')' end
+        function V:double() return self + self end
+        local v = setmetatable({x = 2}, V)
+        local d = setmetatable({}, {__index = function(_, k) return k .. '!' end})
+        return tostring(v:double()), v == setmetatable({x = 2}, V), d.hi
+    "#;
+    assert_eq!(exec(input), vec!["V(4)", "true", "hi!"]);
+}
+
+#[test]
+fn control_flow() {
+    let input = r#"
+        local s = 0
+        for i = 10, 1, -3 do s = s + i end
+        for _, v in ipairs({1, 2, 3}) do
+            if v == 2 then goto continue end
+            s = s + v
+            ::continue::
+        end
+        local n = 0
+        repeat local m = n; n = n + 1 until m >= 3
+        while true do break end
+        return s, n
+    "#;
+    assert_eq!(exec(input), vec!["26", "4"]);
+}
+
+#[test]
+fn errors() {
+    assert_eq!(
+        exec_err("local t = nil\nreturn t.x"),
+        "test:2: attempt to index a nil value (local 't')"
+    );
+    assert_eq!(
+        exec_err("return undefined()"),
+        "test:1: attempt to call a nil value (global 'undefined')"
+    );
+    assert_eq!(exec_err("error({})"), "(error object is a table value)");
+    assert_eq!(exec("return pcall(error, {code = 1})")[0], "false");
+    assert_eq!(
+        exec("return select(2, pcall(function() error('oops', 0) end))"),
+        vec!["oops"]
+    );
+    assert_eq!(
+        exec("local function f() return f() + 1 end return pcall(f)")[1],
+        "test:1: stack overflow"
+    );
+    assert!(exec_err("return 1 +").starts_with("test:"));
+}
+
+#[test]
+fn sandbox() {
+    assert_eq!(
+        exec("return io, os, dofile, loadfile"),
+        vec!["nil", "nil", "nil", "nil"]
+    );
+    assert!(exec_err("require 'missing'").contains("module 'missing' not found"));
+    let mut interp = Interpreter::new();
+    interp.set_sandbox(false);
+    assert_eq!(
+        exec_with(&mut interp, "return type(os.time)"),
+        vec!["function"]
+    );
+}
+
+#[test]
+fn host_functions() {
+    let mut interp = Interpreter::new();
+    interp.register("add", |_, args| {
+        let sum = args.iter().filter_map(Value::to_integer).sum::<i64>();
+        Ok(vec![Value::from(sum)])
+    });
+    interp.set_global("base", Value::from(10));
+    assert_eq!(exec_with(&mut interp, "return add(base, 2, 3)"), vec!["15"]);
+    let f = interp.load("return ...", "chunk").unwrap();
+    let results = interp
+        .call(&f, vec![Value::from("a"), Value::from(true)])
+        .unwrap();
+    assert_eq!(results.len(), 2);
+    assert!(interp.get_global("missing").is_nil());
+}
+
+#[test]
+fn trees_without_spans() {
+    let input =
+        "local function a() return 'a' end local function b() return 'b' end return a(), b()";
+    let block = Parser::new().run(Lexer::new().run(input).unwrap()).unwrap();
+    let mut bare = block.clone();
+    bare.clear_spans();
+    let results = Interpreter::new().run(&bare).unwrap();
+    let results: Vec<String> = results.iter().map(Value::to_string).collect();
+    assert_eq!(results, vec!["a", "b"]);
+
+    let chunk = Compiler::new().run(&block).unwrap().dump(true);
+    let decompiled = Decompiler::new().run(&undump(&chunk).unwrap()).unwrap();
+    let results = Interpreter::new().run(&decompiled).unwrap();
+    let results: Vec<String> = results.iter().map(Value::to_string).collect();
+    assert_eq!(results, vec!["a", "b"]);
+}
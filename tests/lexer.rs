@@ -1,13 +1,20 @@
 #[allow(unused_must_use)]
 mod lexer_tests {
-    use rslua::lexer::{LexError, Lexer};
+    use rslua::directive::DirectiveKind;
+    use rslua::lexer::{LexError, LexErrorKind, Lexer};
     use rslua::token::*;
+    use rslua::types::{LuaVersion, Number};
     use std::fs::File;
     use std::io::prelude::*;
 
     fn try_lexer(input: &str) -> Vec<Token> {
+        try_lexer_version(input, LuaVersion::default())
+    }
+
+    fn try_lexer_version(input: &str, version: LuaVersion) -> Vec<Token> {
         let mut lexer = Lexer::new();
         lexer.set_debug(true);
+        lexer.set_version(version);
         let tokens = lexer.run(input);
         println!("{:?}", tokens);
         tokens.ok().unwrap()
@@ -132,6 +139,7 @@ mod lexer_tests {
         Ok(())
     }
     #[test]
+    #[allow(clippy::mixed_case_hex_literals)]
     fn str_to_int() {
         assert_eq!(Some(0x12345678), Lexer::str_to_int("0x12345678"));
         assert_eq!(Some(0x6789aBcD), Lexer::str_to_int("0x6789aBcD"));
@@ -152,6 +160,7 @@ mod lexer_tests {
         assert_eq!(None, Lexer::str_to_int("0x12345s"));
     }
     #[test]
+    #[allow(clippy::approx_constant)]
     fn str_to_float() {
         assert_eq!(Some(0.123456), Lexer::str_to_float("0.123456"));
         assert_eq!(Some(3.0), Lexer::str_to_float("03.00"));
@@ -161,7 +170,10 @@ mod lexer_tests {
         assert_eq!(Some(0.345), Lexer::str_to_float(".345"));
         assert_eq!(Some(0.1171875), Lexer::str_to_float("0x0.1E"));
         assert_eq!(Some(162.1875), Lexer::str_to_float("0xA23p-4"));
-        assert_eq!(Some(3.141592653589793), Lexer::str_to_float("0X1.921FB54442D18P+1"));
+        assert_eq!(
+            Some(3.141592653589793),
+            Lexer::str_to_float("0X1.921FB54442D18P+1")
+        );
         assert_eq!(Some(13e-2), Lexer::str_to_float("13e-2"));
         assert_eq!(None, Lexer::str_to_float("a34E1"));
         assert_eq!(None, Lexer::str_to_float("3.14.1"));
@@ -183,7 +195,9 @@ mod lexer_tests {
                         length: 5,
                         line: 1,
                         col: 1
-                    }
+                    },
+                    leading: vec![],
+                    trailing: vec![],
                 },
                 Token {
                     t: TokenType::Eos,
@@ -193,7 +207,9 @@ mod lexer_tests {
                         length: 0,
                         line: 1,
                         col: 6
-                    }
+                    },
+                    leading: vec![],
+                    trailing: vec![],
                 }
             ]
         )
@@ -213,7 +229,9 @@ mod lexer_tests {
                         length: 17,
                         line: 1,
                         col: 1
-                    }
+                    },
+                    leading: vec![],
+                    trailing: vec![],
                 },
                 Token {
                     t: TokenType::Eos,
@@ -223,9 +241,296 @@ mod lexer_tests {
                         length: 0,
                         line: 1,
                         col: 18
-                    }
+                    },
+                    leading: vec![],
+                    trailing: vec![],
                 }
             ]
         )
     }
+
+    fn token_types(tokens: &[Token]) -> Vec<TokenType> {
+        tokens.iter().map(|t| t.t).collect()
+    }
+
+    #[test]
+    fn version() {
+        use TokenType::*;
+        let input = "goto x; a = 1 // 2";
+        assert_eq!(
+            token_types(&try_lexer_version(input, LuaVersion::Lua51)),
+            vec![Name, Name, Semi, Name, Assign, Flt, IDiv, Flt, Eos]
+        );
+        assert_eq!(
+            token_types(&try_lexer_version(input, LuaVersion::Lua53)),
+            vec![Goto, Name, Semi, Name, Assign, Int, IDiv, Int, Eos]
+        );
+        let tokens =
+            try_lexer_version("0x10ULL 12ll 3 18446744073709551615ULL", LuaVersion::LuaJIT);
+        assert_eq!(token_types(&tokens), vec![UInt, Int, Flt, UInt, Eos]);
+        assert_eq!(tokens[0].value, TokenValue::Int(16));
+        assert_eq!(tokens[1].value, TokenValue::Int(12));
+        assert_eq!(tokens[2].value, TokenValue::Float(3.0));
+        assert_eq!(tokens[3].value, TokenValue::Int(-1));
+    }
+
+    #[test]
+    #[should_panic]
+    fn version_panic1() {
+        try_lexer_version(r#""\x41""#, LuaVersion::Lua51);
+    }
+
+    #[test]
+    #[should_panic]
+    fn version_panic2() {
+        try_lexer_version(r#""\u{41}""#, LuaVersion::Lua52);
+    }
+
+    #[test]
+    fn trivia() {
+        let input = "--[==[ header ]==]\nlocal a = 1 -- one\n\n  return a --[[ end ]]\n";
+        let mut lexer = Lexer::new();
+        lexer.set_keep_trivia(true);
+        let tokens = lexer.run(input).ok().unwrap();
+        let kinds = |trivia: &[Trivia]| trivia.iter().map(|t| t.kind).collect::<Vec<_>>();
+        assert_eq!(
+            kinds(&tokens[0].leading),
+            vec![TriviaKind::LongComment(2), TriviaKind::LineBreak]
+        );
+        assert_eq!(
+            kinds(&tokens[3].trailing),
+            vec![TriviaKind::Whitespace, TriviaKind::ShortComment]
+        );
+        assert_eq!(tokens[3].trailing[1].text, "-- one");
+        assert_eq!(
+            kinds(&tokens[4].leading),
+            vec![
+                TriviaKind::LineBreak,
+                TriviaKind::LineBreak,
+                TriviaKind::Whitespace
+            ]
+        );
+        assert_eq!(tokens[5].trailing[1].text, "--[[ end ]]");
+        assert_eq!(kinds(&tokens[6].leading), vec![TriviaKind::LineBreak]);
+
+        // tokens and their trivia cover the whole input
+        let mut output = String::new();
+        for token in tokens.iter() {
+            for trivia in token.leading.iter() {
+                output.push_str(&trivia.text);
+            }
+            let source = &token.source;
+            output.push_str(&input[source.pos..source.pos + source.length]);
+            for trivia in token.trailing.iter() {
+                output.push_str(&trivia.text);
+            }
+        }
+        assert_eq!(output, input);
+    }
+
+    #[test]
+    fn no_trivia() {
+        let tokens = try_lexer("-- comment\na --[[ comment ]]");
+        assert!(tokens
+            .iter()
+            .all(|t| t.leading.is_empty() && t.trailing.is_empty()));
+    }
+
+    fn lex_error(input: &str) -> LexError {
+        Lexer::new().run(input).err().unwrap()
+    }
+
+    #[test]
+    fn error() {
+        let error = lex_error("a = 'abc\nb = 2");
+        assert_eq!(error.kind, LexErrorKind::UnfinishedString);
+        assert_eq!((error.line, error.col, error.offset), (1, 9, 8));
+        assert_eq!(
+            error.to_string(),
+            "[lex error] unfinished string at line [1:9]."
+        );
+        assert_eq!(
+            lex_error("\n--[==[ comment").kind,
+            LexErrorKind::UnfinishedLongComment(2)
+        );
+        assert_eq!(lex_error("'\\q'").kind, LexErrorKind::InvalidEscape('q'));
+        assert_eq!(
+            lex_error("x = 3..2").kind,
+            LexErrorKind::MalformedNumber("3..2".to_string())
+        );
+        assert_eq!(lex_error("x = $").kind, LexErrorKind::UnknownToken('$'));
+    }
+
+    #[test]
+    fn recovering() {
+        use TokenType::*;
+        let input = "a = '\\q' .. \"x\nb = $ + 1 @ 'ok'\nc = 0x";
+        let (tokens, errors) = Lexer::new().run_recovering(input);
+        assert_eq!(
+            token_types(&tokens),
+            vec![
+                Name, Assign, Error, Concat, Error, Name, Assign, Error, Add, Int, Error, String,
+                Name, Assign, Error, Eos
+            ]
+        );
+        let source = |i: usize| {
+            let source = &tokens[i].source;
+            &input[source.pos..source.pos + source.length]
+        };
+        assert_eq!(source(2), "'\\q'");
+        assert_eq!(source(4), "\"x");
+        assert_eq!(source(7), "$");
+        assert_eq!(tokens[11].value, TokenValue::Str("ok".to_string()));
+        let kinds: Vec<LexErrorKind> = errors.iter().map(|e| e.kind.clone()).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                LexErrorKind::InvalidEscape('q'),
+                LexErrorKind::UnfinishedString,
+                LexErrorKind::UnknownToken('$'),
+                LexErrorKind::UnknownToken('@'),
+                LexErrorKind::MalformedNumber("0x".to_string())
+            ]
+        );
+        assert_eq!(errors[2].line, 2);
+
+        let (tokens, errors) = Lexer::new().run_recovering("x = 1");
+        assert!(errors.is_empty());
+        assert_eq!(token_types(&tokens), vec![Name, Assign, Int, Eos]);
+    }
+
+    #[test]
+    fn truncated_long_bracket() {
+        use TokenType::*;
+        assert_eq!(
+            lex_error("x = [==").kind,
+            LexErrorKind::InvalidLongStringDelimiter
+        );
+        assert_eq!(
+            lex_error("x = [=x").kind,
+            LexErrorKind::InvalidLongStringDelimiter
+        );
+        let tokens = Lexer::new().run("x = 1 --[==").unwrap();
+        assert_eq!(token_types(&tokens), vec![Name, Assign, Int, Eos]);
+        let (tokens, errors) = Lexer::new().run_recovering("x = [==");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(token_types(&tokens), vec![Name, Assign, Error, Eq, Eos]);
+    }
+
+    #[test]
+    fn str_to_num() {
+        assert_eq!(Some(4294967296), Lexer::str_to_int("4294967296"));
+        assert_eq!(Some(i64::MAX), Lexer::str_to_int("9223372036854775807"));
+        assert_eq!(None, Lexer::str_to_int("9223372036854775808"));
+        assert_eq!(Some(i64::MIN), Lexer::str_to_int("-9223372036854775808"));
+        assert_eq!(Some(-1), Lexer::str_to_int("0xffffffffffffffff"));
+        assert_eq!(Some(0), Lexer::str_to_int("0x10000000000000000"));
+        assert_eq!(Some(i64::MIN), Lexer::str_to_int("0x8000000000000000"));
+        assert_eq!(Some(10), Lexer::str_to_int("\t10\n"));
+
+        assert_eq!(
+            Number::Float(9223372036854775808.0),
+            Lexer::str_to_num("9223372036854775808")
+        );
+        assert_eq!(Number::Int(-16), Lexer::str_to_num(" -0x10 "));
+        assert_eq!(Number::Float(-1.5), Lexer::str_to_num("-0x1.8"));
+        assert_eq!(Number::Float(16.0), Lexer::str_to_num("0x1p4"));
+        assert_eq!(Number::Float(5.0), Lexer::str_to_num("5."));
+        assert_eq!(Number::Float(f64::INFINITY), Lexer::str_to_num("1e500"));
+        assert_eq!(Number::Float(f64::INFINITY), Lexer::str_to_num("0x1p1024"));
+        assert_eq!(Number::Float(5e-324), Lexer::str_to_num("0x1p-1074"));
+        assert_eq!(
+            Number::Float(0x123456789abcdef0u64 as f64 * 4096.0),
+            Lexer::str_to_num("0x123456789abcdef0123p0")
+        );
+        for s in &[
+            "", "inf", "nan", "-inf", "1e", "0x", "0x.", "0xp1", "0x1p", "3..2", "1.2.3", "0x-1",
+            "1 2", "- 1",
+        ] {
+            assert_eq!(Number::None, Lexer::str_to_num(s), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn numeral() {
+        let values = |input: &str| {
+            try_lexer(input)
+                .into_iter()
+                .map(|t| t.value)
+                .collect::<Vec<TokenValue>>()
+        };
+        assert_eq!(
+            values("9223372036854775808 0xffffffffffffffff 3 .5 1E2 0xA.8p1"),
+            vec![
+                TokenValue::Float(9223372036854775808.0),
+                TokenValue::Int(-1),
+                TokenValue::Int(3),
+                TokenValue::Float(0.5),
+                TokenValue::Float(100.0),
+                TokenValue::Float(21.0),
+                TokenValue::None
+            ]
+        );
+        for (input, text) in &[
+            ("3..2", "3..2"),
+            ("0x", "0x"),
+            ("3x", "3x"),
+            ("12abc", "12abc"),
+            ("1e", "1e"),
+            ("0x1p", "0x1p"),
+            ("1_", "1_"),
+        ] {
+            assert_eq!(
+                lex_error(input).kind,
+                LexErrorKind::MalformedNumber(text.to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn directives() {
+        let input = "-- rslua: ignore-file undefined-global
+local a = 1 -- rslua: ignore unused-local
+-- rslua: ignore
+
+local b --[==[ rslua: ignore-begin shadowed-local, unused-local ]==]
+do
+  -- rslua:ignore-end
+end
+--[[ rslua: ignore-begin ]] -- rslua: unknown
+x = 1 -- not rslua: ignore
+";
+        let mut lexer = Lexer::new();
+        lexer.run(input).unwrap();
+        let directives: Vec<(DirectiveKind, Vec<&str>, usize, usize)> = lexer
+            .directives()
+            .iter()
+            .map(|d| {
+                let rules = d.rules.iter().map(String::as_str).collect();
+                (d.kind, rules, d.first_line, d.last_line)
+            })
+            .collect();
+        assert_eq!(
+            directives,
+            vec![
+                (DirectiveKind::File, vec!["undefined-global"], 1, 11),
+                (DirectiveKind::Line, vec!["unused-local"], 2, 2),
+                (DirectiveKind::Line, vec![], 5, 5),
+                (
+                    DirectiveKind::Block,
+                    vec!["shadowed-local", "unused-local"],
+                    5,
+                    7
+                ),
+                (DirectiveKind::Block, vec![], 9, 11),
+            ]
+        );
+        assert!(lexer.directives()[1].covers("unused-local", 2));
+        assert!(!lexer.directives()[1].covers("unused-param", 2));
+        assert!(lexer.directives()[2].covers("unused-param", 5));
+        assert_eq!(lexer.directives()[3].source.line, 5);
+
+        lexer.run("").unwrap();
+        assert!(lexer.directives().is_empty());
+    }
 }
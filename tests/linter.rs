@@ -0,0 +1,204 @@
+use rslua::ast::{Block, ClearSpans};
+use rslua::lexer::Lexer;
+use rslua::linter::{Lint, Linter, Rule};
+use rslua::parser::Parser;
+use rslua::types::LuaVersion;
+use std::fs::{read_dir, File};
+use std::io::prelude::*;
+
+fn try_parse(input: &str, version: LuaVersion) -> Block {
+    let mut lexer = Lexer::new();
+    lexer.set_debug(true);
+    lexer.set_version(version);
+    let tokens = lexer.run(input).unwrap();
+    let mut parser = Parser::new();
+    parser.set_debug(true);
+    parser.set_version(version);
+    parser.run(tokens).unwrap()
+}
+
+fn lint_with(input: &str, linter: &mut Linter) -> Vec<String> {
+    let mut lexer = Lexer::new();
+    lexer.run(input).unwrap();
+    linter.set_directives(lexer.directives());
+    linter
+        .run(&try_parse(input, LuaVersion::Lua54))
+        .iter()
+        .map(Lint::to_string)
+        .collect()
+}
+
+fn lint(input: &str) -> Vec<String> {
+    lint_with(input, &mut Linter::new())
+}
+
+#[test]
+fn lint_lua_files() -> std::io::Result<()> {
+    for entry in read_dir("./lua")? {
+        let mut file = File::open(entry?.path())?;
+        let mut content = String::new();
+        file.read_to_string(&mut content)?;
+        let lints = Linter::new().run(&try_parse(&content, LuaVersion::Lua54));
+        assert!(lints.iter().all(|l| l.rule != Rule::DuplicateKey));
+    }
+    Ok(())
+}
+
+#[test]
+fn unused() {
+    assert_eq!(
+        lint("local a, _b = 1, 2\nlocal c = 1 c = 2\nlocal function f(x, _, y) return y end\nreturn f"),
+        vec![
+            "[unused-local] unused local 'a' at line [1:7].",
+            "[unused-local] local 'c' is assigned but never read at line [2:7].",
+            "[unused-param] unused parameter 'x' at line [3:18].",
+        ]
+    );
+    assert!(lint("local x <close> = nil for i = 1, 2 do end").len() == 1);
+    assert!(lint("function t:m() return 1 end local function g() return g() end").len() == 1);
+}
+
+#[test]
+fn shadowed() {
+    assert_eq!(
+        lint("local x = 1\nlocal function f(x)\n  for x = x, 2 do print(x) end\nend\nlocal x = f(x)\nreturn x"),
+        vec![
+            "[shadowed-local] local 'x' shadows the one at line 1 at line [2:18].",
+            "[shadowed-local] local 'x' shadows the one at line 2 at line [3:7].",
+            "[shadowed-local] local 'x' shadows the one at line 1 at line [5:7].",
+        ]
+    );
+    assert!(lint("do local y = 1 print(y) end local y = 2 print(y)").is_empty());
+}
+
+#[test]
+fn globals() {
+    assert_eq!(
+        lint("count = 0\nfunction inc() count = count + 1 end\nprint(cuont, string.len(''), t.x)"),
+        vec![
+            "[global-assign] assignment to global 'count' at line [1:1].",
+            "[global-assign] assignment to global 'inc' at line [2:10].",
+            "[global-assign] assignment to global 'count' at line [2:16].",
+            "[undefined-global] undefined global 'cuont' at line [3:7].",
+            "[undefined-global] undefined global 't' at line [3:30].",
+        ]
+    );
+    let mut linter = Linter::new();
+    linter.add_global("t");
+    linter.add_global("count");
+    assert_eq!(
+        lint_with("count = t.x + unpack(t)", &mut linter),
+        vec!["[undefined-global] undefined global 'unpack' at line [1:15]."]
+    );
+    let mut linter = Linter::new();
+    linter.set_version(LuaVersion::Lua51);
+    assert!(linter
+        .run(&try_parse("print(unpack(t))", LuaVersion::Lua51))
+        .iter()
+        .all(|l| l.message.contains("'t'")));
+    // nothing is known about the fields of a local `_ENV`
+    assert!(lint("local _ENV = {} x = y").is_empty());
+}
+
+#[test]
+fn statements() {
+    assert_eq!(
+        lint("local function f(t)\n  while t do\n    break\n    t = t.next\n    t = t\n  end\n  do return end\n  goto skip\n  print(t)\n  print(t)\n  ::skip::\n  t.a[1], t.b = t.a[1], (t.b)\n  t[f()] = t[f()]\nend\nreturn f"),
+        vec![
+            "[unreachable-code] unreachable code at line [4:5].",
+            "[self-assign] value assigned to itself at line [5:5].",
+            "[unreachable-code] unreachable code at line [8:3].",
+            "[self-assign] value assigned to itself at line [12:3].",
+            "[self-assign] value assigned to itself at line [12:11].",
+        ]
+    );
+}
+
+#[test]
+fn tables() {
+    assert_eq!(
+        lint("local t = {1, 2, [2] = 3, a = 1, ['a'] = 2, [1.0] = 4, [true] = 1, [true] = 2}\nprint(t == {}, ({}) ~= t)"),
+        vec![
+            "[duplicate-key] duplicate key 2 in table at line [1:18].",
+            "[duplicate-key] duplicate key 'a' in table at line [1:34].",
+            "[duplicate-key] duplicate key 1 in table at line [1:45].",
+            "[duplicate-key] duplicate key true in table at line [1:68].",
+            "[compare-new-table] comparison with a new table is always false at line [2:7].",
+            "[compare-new-table] comparison with a new table is always true at line [2:16].",
+        ]
+    );
+}
+
+#[test]
+fn patterns() {
+    assert_eq!(
+        lint("local s = ''\nprint(string.find(s, '%'), s:match('[a-'), s:gsub('%b(', ''))\nprint(s:match'(%d+', string.match(s, '%1'), s:find('%', 1, true), s:find('(', 1, false))\nlocal string = {}\nprint(string.find(s, '%'), s:match('(%w+)=%1'))"),
+        vec![
+            "[malformed-pattern] 'string.find': malformed pattern (ends with '%') at line [2:22].",
+            "[malformed-pattern] 'string.match': malformed pattern (missing ']') at line [2:36].",
+            "[malformed-pattern] 'string.gsub': missing arguments to '%b' at line [2:51].",
+            "[malformed-pattern] 'string.match': unfinished capture at line [3:14].",
+            "[malformed-pattern] 'string.match': invalid capture index %1 at line [3:38].",
+            "[malformed-pattern] 'string.find': unfinished capture at line [3:74].",
+        ]
+    );
+}
+
+#[test]
+fn patterns_without_spans() {
+    let mut block = try_parse(
+        "local x = {} x.find('a', '[') string.find('a', '[')",
+        LuaVersion::Lua54,
+    );
+    block.clear_spans();
+    let lints: Vec<String> = Linter::new()
+        .run(&block)
+        .into_iter()
+        .filter(|lint| lint.rule == Rule::MalformedPattern)
+        .map(|lint| lint.message)
+        .collect();
+    assert_eq!(
+        lints,
+        vec!["'string.find': malformed pattern (missing ']')"]
+    );
+}
+
+#[test]
+fn toggle() {
+    let mut linter = Linter::new();
+    for rule in Rule::ALL.iter() {
+        assert_eq!(Rule::from_name(rule.name()), Some(*rule));
+        linter.set_enabled(*rule, false);
+    }
+    assert!(lint_with("local a = {x = 1, x = 2} b = c", &mut linter).is_empty());
+    linter.set_enabled(Rule::UndefinedGlobal, true);
+    assert!(linter.is_enabled(Rule::UndefinedGlobal));
+    assert_eq!(
+        lint_with("local a = {x = 1, x = 2} b = c", &mut linter),
+        vec!["[undefined-global] undefined global 'c' at line [1:30]."]
+    );
+    assert_eq!(Rule::from_name("unknown"), None);
+}
+
+#[test]
+fn directives() {
+    assert_eq!(
+        lint(
+            "-- rslua: ignore-file global-assign
+local a = 1 -- rslua: ignore unused-local
+local b = 1 -- rslua: ignore unused-param
+-- rslua: ignore
+local c = y
+-- rslua: ignore-begin unused-local
+local d = z
+-- rslua: ignore-end
+local e = {}
+g = e == {}"
+        ),
+        vec![
+            "[unused-local] unused local 'b' at line [3:7].",
+            "[undefined-global] undefined global 'z' at line [7:11].",
+            "[compare-new-table] comparison with a new table is always false at line [10:5].",
+        ]
+    );
+}
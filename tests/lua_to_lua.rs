@@ -35,12 +35,12 @@ impl LuaWritter {
     }
 
     fn incline(&mut self) {
-        self.output.push_str("\n");
+        self.output.push('\n');
         self.output.push_str(&" ".repeat(self.depth * self.indent));
     }
 
     fn space(&mut self) {
-        self.output.push_str(" ");
+        self.output.push(' ');
     }
 
     fn append_space(&mut self, content: &str) {
@@ -145,14 +145,14 @@ impl AstVisitor for LuaWritter {
 
     fn for_num(&mut self, fornum: &ForNum) -> bool {
         self.append_space("for");
-        self.append(&format!("{} = ", fornum.var));
+        self.append(&format!("{} = ", fornum.var.name));
         false
     }
 
     fn for_list(&mut self, forlist: &ForList) -> bool {
         self.append_space("for");
         for (n, var) in forlist.vars.iter().enumerate() {
-            self.append(var);
+            self.append(&var.name);
             if n < forlist.vars.len() - 1 {
                 self.append(", ");
             }
@@ -196,40 +196,34 @@ impl AstVisitor for LuaWritter {
         let func_name = &funcstat.func_name;
         let mut fields = func_name.fields.iter();
         if let Some(name) = fields.next() {
-            self.append(name);
-            while let Some(name) = fields.next() {
+            self.append(&name.name);
+            for name in fields {
                 self.append(".");
-                self.append(name);
+                self.append(&name.name);
             }
             if let Some(method) = &func_name.method {
                 self.append(":");
-                self.append(method);
+                self.append(&method.name);
             }
         }
     }
 
-    fn local_stat(&mut self, stat: &LocalStat) {
+    fn begin_local_stat(&mut self, _stat: &LocalStat) -> bool {
         self.append_space("local");
-        for (n, name) in stat.names.iter().enumerate() {
-            self.append(name);
-            if n < stat.names.len() - 1 {
-                self.append(", ");
-            }
-        }
-        self.space();
-        if stat.exprs.len() > 0 {
-            self.append_space("=");
-            ast_walker::walk_exprlist(&stat.exprs, self);
-        }
+        false
+    }
+
+    fn attrib(&mut self, attrib: LocalAttrib) {
+        self.append(&format!(" <{}>", attrib.name()));
     }
 
     fn label_stat(&mut self, stat: &LabelStat) {
-        self.append(&format!("::{}::", stat.label));
+        self.append(&format!("::{}::", stat.label.name));
     }
 
-    fn ret_stat(&mut self, stat: &RetStat) {
+    fn begin_ret_stat(&mut self, _stat: &RetStat) -> bool {
         self.append_space("return");
-        ast_walker::walk_exprlist(&stat.exprs, self);
+        false
     }
 
     fn break_stat(&mut self, _stat: &BreakStat) {
@@ -237,22 +231,11 @@ impl AstVisitor for LuaWritter {
     }
 
     fn goto_stat(&mut self, stat: &GotoStat) {
-        self.append(&format!("goto {}", stat.label));
+        self.append(&format!("goto {}", stat.label.name));
     }
 
-    fn assign_stat(&mut self, stat: &AssignStat) {
-        for (n, suffix) in stat.left.iter().enumerate() {
-            ast_walker::walk_suffixedexpr(suffix, self);
-            if n < stat.left.len() - 1 {
-                self.append_space(",");
-            }
-        }
+    fn assign_sep(&mut self) {
         self.space_append_space("=");
-        ast_walker::walk_exprlist(&stat.right, self);
-    }
-
-    fn call_stat(&mut self, stat: &CallStat) {
-        ast_walker::walk_suffixedexpr(&stat.call, self);
     }
 
     fn expr(&mut self, _stat: &Expr) -> bool {
@@ -299,8 +282,8 @@ impl AstVisitor for LuaWritter {
         self.append("(");
         for (n, param) in body.params.iter().enumerate() {
             match param {
-                Param::VarArg => self.append("..."),
-                Param::Name(s) => self.append(s),
+                Param::VarArg(_) => self.append("..."),
+                Param::Name(s) => self.append(&s.name),
             }
             if n < body.params.len() - 1 {
                 self.append(", ");
@@ -316,7 +299,7 @@ impl AstVisitor for LuaWritter {
     }
 
     fn begin_table(&mut self, t: &Table) -> bool {
-        if t.fields.len() > 0 {
+        if !t.fields.is_empty() {
             self.enter_scope();
             self.append_inc("{");
         } else {
@@ -326,7 +309,7 @@ impl AstVisitor for LuaWritter {
         false
     }
     fn end_table(&mut self, t: &Table) {
-        if t.fields.len() > 0 {
+        if !t.fields.is_empty() {
             self.leave_scope();
             self.append("}");
         }
@@ -345,17 +328,15 @@ impl AstVisitor for LuaWritter {
     }
 
     fn begin_field_key(&mut self, key: &FieldKey) -> bool {
-        match key {
-            FieldKey::Expr(_) => self.append_space("["),
-            _ => (),
+        if let FieldKey::Expr(_) = key {
+            self.append_space("[");
         }
         false
     }
 
     fn end_field_key(&mut self, key: &FieldKey) {
-        match key {
-            FieldKey::Expr(_) => self.space_append("]"),
-            _ => (),
+        if let FieldKey::Expr(_) = key {
+            self.space_append("]");
         }
     }
 
@@ -473,7 +454,7 @@ fn try_convert(input: &str) -> String {
     let mut lexer = Lexer::new();
     lexer.set_debug(true);
     lexer.set_use_origin_string(true);
-    if let Ok(tokens) = lexer.run(&input) {
+    if let Ok(tokens) = lexer.run(input) {
         let mut parser = Parser::new();
         parser.set_debug(true);
         if let Ok(ast) = parser.run(tokens) {
@@ -500,6 +481,14 @@ fn write_method_call() {
     assert_eq!("str:sub(i, i)\n".to_string(), try_convert("str:sub(i,i)"));
 }
 
+#[test]
+fn write_local_attrib() {
+    assert_eq!(
+        "local a <const>, b, c <close> = 1, 2, f()\n".to_string(),
+        try_convert("local a<const>,b,c < close > =1,2,f()")
+    );
+}
+
 #[test]
 fn lua_to_lua() -> std::io::Result<()> {
     let lua_dir: &'static str = "./lua";
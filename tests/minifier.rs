@@ -0,0 +1,134 @@
+use rslua::ast::Block;
+use rslua::lexer::Lexer;
+use rslua::minifier::Minifier;
+use rslua::parser::Parser;
+use rslua::types::LuaVersion;
+use std::fs::{read_dir, File};
+use std::io::prelude::*;
+
+fn try_parse(input: &str, version: LuaVersion) -> Block {
+    let mut lexer = Lexer::new();
+    lexer.set_debug(true);
+    lexer.set_version(version);
+    let tokens = lexer.run(input).unwrap();
+    let mut parser = Parser::new();
+    parser.set_debug(true);
+    parser.set_version(version);
+    parser.run(tokens).unwrap()
+}
+
+fn minify_version(input: &str, version: LuaVersion) -> String {
+    let mut minifier = Minifier::new();
+    minifier.set_version(version);
+    let output = minifier.run(&try_parse(input, version));
+    // minified code is already as short as it gets
+    assert_eq!(output, minifier.run(&try_parse(&output, version)));
+    output
+}
+
+fn minify(input: &str) -> String {
+    minify_version(input, LuaVersion::Lua54)
+}
+
+#[test]
+fn minify_lua_files() -> std::io::Result<()> {
+    for entry in read_dir("./lua")? {
+        let mut file = File::open(entry?.path())?;
+        let mut content = String::new();
+        file.read_to_string(&mut content)?;
+        let output = minify(&content);
+        assert!(output.len() * 3 < content.len() * 2);
+    }
+    Ok(())
+}
+
+#[test]
+fn rename() {
+    assert_eq!(
+        minify("local x = 1 local function f(p, q) return x + p + q + y end"),
+        "local a=1 local function b(c,d)return a+c+d+y end"
+    );
+    // globals are never hidden, even if used before the local is declared
+    assert_eq!(
+        minify("local x = a(b) do local y = x end c = x"),
+        "local d=a(b)do local e=d end c=d"
+    );
+    // a local shadowing another one still gets its own name
+    assert_eq!(
+        minify("local x = 1 local x = x + 1 for i = x, 2 do local x = i end print(x)"),
+        "local a=1 local b=a+1 for c=b,2 do local d=c end print(b)"
+    );
+    assert_eq!(
+        minify("local t = {} function t:m(self2) return self.v, self2 end"),
+        "local a={}function a:m(b)return self.v,b end"
+    );
+    assert_eq!(
+        minify("local self = 1 function t:m() return self end return self"),
+        "local a=1 function t:m()return self end return a"
+    );
+    assert_eq!(
+        minify("local _ENV = {print = print} local y = 1 print(y)"),
+        "local _ENV={print=print}local a=1 print(a)"
+    );
+    assert_eq!(
+        minify("repeat local done = f() until done ::top:: goto top"),
+        "repeat local a=f()until a::top::goto top"
+    );
+    // `>=` would lex as a comparison
+    assert_eq!(
+        minify("local x <const> = 1 local f <close> = nil return x"),
+        "local a<const> =1 local b<close> =nil return a"
+    );
+}
+
+#[test]
+fn parens() {
+    assert_eq!(
+        minify("x = ((a + b)) * (c) - (d * e) x = a + (b + c) x = (a .. b) .. (c .. d)"),
+        "x=(a+b)*c-d*e x=a+(b+c)x=(a..b)..c..d"
+    );
+    assert_eq!(
+        minify("x = (f()) return (f()), (...), g((f()))"),
+        "x=f()return f(),...,g((f()))"
+    );
+    assert_eq!(
+        minify("y = (a).b; (f)(); ((g)):m(); (\"s\"):len() x = -(-a), -(a ^ b), (-a) ^ b"),
+        "y=a.b f()g:m();(\"s\"):len()x=- -a,-a^b,(-a)^b"
+    );
+    assert_eq!(
+        minify(
+            "f(('a')) f({1}) t = {(f())} t = {(f()), 1} x = t['k'] t = {['k'] = 1, ['end'] = 2}"
+        ),
+        "f\"a\"f{1}t={(f())}t={f(),1}x=t.k t={k=1,[\"end\"]=2}"
+    );
+}
+
+#[test]
+fn numerals() {
+    assert_eq!(
+        minify("x = 1000.0, 0.5, 123.456, 1e300, 0.0001, 255, 0xff, 1e999, 0xffffffffffffffff, 1.5e-10"),
+        "x=1e3,.5,123.456,1e300,1e-4,255,255,1e999,0xffffffffffffffff,15e-11"
+    );
+    assert_eq!(minify("x = 1.0 .. 2 .. a"), "x=1. ..2 ..a");
+    assert_eq!(
+        minify_version("x = 100, 0.5, 0x10", LuaVersion::Lua51),
+        "x=100,.5,16"
+    );
+    assert_eq!(minify_version("x = 1LL", LuaVersion::LuaJIT), "x=1LL");
+    assert_eq!(
+        minify_version("x = 0x10ULL + 18446744073709551615ULL", LuaVersion::LuaJIT),
+        "x=16ULL+0xffffffffffffffffULL"
+    );
+}
+
+#[test]
+fn strings() {
+    assert_eq!(
+        minify(r#"x = 'a', "it's", 'say "hi"', '\\', "\0001", "\0", "é\n""#),
+        r#"x="a","it's",'say "hi"',"\\","\0001","\0","é\n""#
+    );
+    assert_eq!(
+        minify(r#"x = '\\"\'\\"\'', "]]\\\\\\\\\\\\", "\n\\\\\\\\", "\r\\\\\\\\""#),
+        "x=[[\\\"'\\\"']],[=[]]\\\\\\\\\\\\]=],[[\n\n\\\\\\\\]],\"\\r\\\\\\\\\\\\\\\\\""
+    );
+}
@@ -2,16 +2,50 @@
 mod parser_tests {
     use rslua::ast::*;
     use rslua::lexer::Lexer;
-    use rslua::parser::Parser;
+    use rslua::parser::{Parser, SyntaxError, SyntaxErrorKind};
+    use rslua::token::{Span, TokenType};
+    use rslua::types::LuaVersion;
     use std::fs::File;
     use std::io::prelude::*;
 
+    fn span() -> Span {
+        Span::default()
+    }
+
+    fn name(s: &str) -> Name {
+        Name {
+            name: s.to_string(),
+            span: span(),
+        }
+    }
+
+    fn local(s: &str, attrib: Option<LocalAttrib>) -> LocalName {
+        LocalName {
+            name: name(s),
+            attrib,
+            span: span(),
+        }
+    }
+
     fn try_parse(input: &str) -> Block {
+        try_parse_version(input, LuaVersion::default())
+    }
+
+    // spans are cleared, so the tree compares equal to the hand-built ones
+    fn try_parse_version(input: &str, version: LuaVersion) -> Block {
+        let mut ast = parse_with_spans(input, version);
+        ast.clear_spans();
+        ast
+    }
+
+    fn parse_with_spans(input: &str, version: LuaVersion) -> Block {
         let mut lexer = Lexer::new();
         lexer.set_debug(true);
+        lexer.set_version(version);
         if let Ok(tokens) = lexer.run(input) {
             let mut parser = Parser::new();
             parser.set_debug(true);
+            parser.set_version(version);
             if let Ok(ast) = parser.run(tokens) {
                 println!("{:#?}", ast);
                 return ast;
@@ -34,7 +68,13 @@ mod parser_tests {
         assert_eq!(
             try_parse(";;;;"),
             Block {
-                stats: vec![Stat::Empty, Stat::Empty, Stat::Empty, Stat::Empty]
+                stats: vec![
+                    Stat::Empty(span()),
+                    Stat::Empty(span()),
+                    Stat::Empty(span()),
+                    Stat::Empty(span())
+                ],
+                span: span(),
             }
         );
     }
@@ -53,16 +93,29 @@ mod parser_tests {
                 stats: vec![Stat::IfStat(IfStat {
                     cond_blocks: vec![
                         CondBlock {
-                            cond: Expr::True,
-                            block: Block { stats: vec![] },
+                            cond: Expr::True(span()),
+                            block: Block {
+                                stats: vec![],
+                                span: span()
+                            },
+                            span: span(),
                         },
                         CondBlock {
-                            cond: Expr::True,
-                            block: Block { stats: vec![] },
+                            cond: Expr::True(span()),
+                            block: Block {
+                                stats: vec![],
+                                span: span()
+                            },
+                            span: span(),
                         },
                     ],
-                    else_block: Block { stats: vec![] },
+                    else_block: Block {
+                        stats: vec![],
+                        span: span()
+                    },
+                    span: span(),
                 })],
+                span: span(),
             }
         )
     }
@@ -74,9 +127,14 @@ mod parser_tests {
             ast,
             Block {
                 stats: vec![Stat::WhileStat(WhileStat {
-                    cond: Expr::True,
-                    block: Block { stats: vec![] },
+                    cond: Expr::True(span()),
+                    block: Block {
+                        stats: vec![],
+                        span: span()
+                    },
+                    span: span(),
                 })],
+                span: span(),
             }
         )
     }
@@ -89,31 +147,43 @@ mod parser_tests {
             forenum,
             Block {
                 stats: vec![Stat::ForStat(ForStat::ForNum(ForNum {
-                    var: String::from("i"),
-                    init: Expr::Int(1),
-                    limit: Expr::Int(10),
-                    step: Some(Expr::Int(1)),
-                    body: Block { stats: vec![] },
+                    var: name("i"),
+                    init: Expr::Int(1, span()),
+                    limit: Expr::Int(10, span()),
+                    step: Some(Expr::Int(1, span())),
+                    body: Block {
+                        stats: vec![],
+                        span: span()
+                    },
+                    span: span(),
                 }))],
+                span: span(),
             }
         );
         assert_eq!(
             forlist,
             Block {
                 stats: vec![Stat::ForStat(ForStat::ForList(ForList {
-                    vars: vec![String::from("a"), String::from("b"),],
+                    vars: vec![name("a"), name("b"),],
                     exprs: vec![
                         Expr::SuffixedExpr(SuffixedExpr {
-                            primary: PrimaryExpr::Name("c".to_string()),
-                            suffixes: vec![]
+                            primary: PrimaryExpr::Name(name("c")),
+                            suffixes: vec![],
+                            span: span(),
                         }),
                         Expr::SuffixedExpr(SuffixedExpr {
-                            primary: PrimaryExpr::Name("d".to_string()),
-                            suffixes: vec![]
+                            primary: PrimaryExpr::Name(name("d")),
+                            suffixes: vec![],
+                            span: span(),
                         })
                     ],
-                    body: Block { stats: vec![] },
+                    body: Block {
+                        stats: vec![],
+                        span: span()
+                    },
+                    span: span(),
                 },),),],
+                span: span(),
             }
         )
     }
@@ -125,8 +195,13 @@ mod parser_tests {
             ast,
             Block {
                 stats: vec![Stat::DoBlock(DoBlock {
-                    block: Block { stats: vec![] },
+                    block: Block {
+                        stats: vec![],
+                        span: span()
+                    },
+                    span: span(),
                 },),],
+                span: span(),
             }
         )
     }
@@ -140,14 +215,21 @@ mod parser_tests {
                 stats: vec![Stat::RepeatStat(RepeatStat {
                     cond: Expr::BinExpr(BinExpr {
                         left: Box::new(Expr::SuffixedExpr(SuffixedExpr {
-                            primary: PrimaryExpr::Name(String::from("a")),
+                            primary: PrimaryExpr::Name(name("a")),
                             suffixes: vec![],
+                            span: span(),
                         })),
                         op: BinOp::Gt,
-                        right: Box::new(Expr::Int(0))
+                        right: Box::new(Expr::Int(0, span())),
+                        span: span(),
                     }),
-                    block: Block { stats: vec![] },
+                    block: Block {
+                        stats: vec![],
+                        span: span()
+                    },
+                    span: span(),
                 })],
+                span: span(),
             }
         )
     }
@@ -161,18 +243,25 @@ mod parser_tests {
                 stats: vec![Stat::FuncStat(FuncStat {
                     func_type: FuncType::Global,
                     func_name: FuncName {
-                        fields: vec!["foo".to_string()],
+                        fields: vec![name("foo")],
                         method: None,
+                        span: span(),
                     },
                     body: FuncBody {
                         params: vec![
-                            Param::Name(String::from("a")),
-                            Param::Name(String::from("b")),
-                            Param::Name(String::from("c"))
+                            Param::Name(name("a")),
+                            Param::Name(name("b")),
+                            Param::Name(name("c"))
                         ],
-                        block: Block { stats: vec![] },
+                        block: Block {
+                            stats: vec![],
+                            span: span()
+                        },
+                        span: span(),
                     },
-                })]
+                    span: span(),
+                })],
+                span: span(),
             }
         )
     }
@@ -186,18 +275,25 @@ mod parser_tests {
                 stats: vec![Stat::FuncStat(FuncStat {
                     func_type: FuncType::Local,
                     func_name: FuncName {
-                        fields: vec!["foo".to_string()],
+                        fields: vec![name("foo")],
                         method: None,
+                        span: span(),
                     },
                     body: FuncBody {
                         params: vec![
-                            Param::Name(String::from("a")),
-                            Param::Name(String::from("b")),
-                            Param::Name(String::from("c"))
+                            Param::Name(name("a")),
+                            Param::Name(name("b")),
+                            Param::Name(name("c"))
                         ],
-                        block: Block { stats: vec![] },
+                        block: Block {
+                            stats: vec![],
+                            span: span()
+                        },
+                        span: span(),
                     },
-                })]
+                    span: span(),
+                })],
+                span: span(),
             }
         )
     }
@@ -209,13 +305,51 @@ mod parser_tests {
             ast,
             Block {
                 stats: vec![Stat::LocalStat(LocalStat {
-                    names: vec!["a".to_string(), "b".to_string(), "c".to_string()],
-                    exprs: vec![Expr::Int(1), Expr::Int(2), Expr::Int(3),],
+                    names: vec![local("a", None), local("b", None), local("c", None)],
+                    exprs: vec![
+                        Expr::Int(1, span()),
+                        Expr::Int(2, span()),
+                        Expr::Int(3, span()),
+                    ],
+                    span: span(),
                 })],
+                span: span(),
             }
         )
     }
 
+    #[test]
+    fn localattrib() {
+        let ast = try_parse("local a <const>, b, c <close> = 1");
+        assert_eq!(
+            ast,
+            Block {
+                stats: vec![Stat::LocalStat(LocalStat {
+                    names: vec![
+                        local("a", Some(LocalAttrib::Const)),
+                        local("b", None),
+                        local("c", Some(LocalAttrib::Close)),
+                    ],
+                    exprs: vec![Expr::Int(1, span())],
+                    span: span(),
+                })],
+                span: span(),
+            }
+        )
+    }
+
+    #[test]
+    #[should_panic]
+    fn localattrib_panic1() {
+        try_parse("local a <static> = 1");
+    }
+
+    #[test]
+    #[should_panic]
+    fn localattrib_panic2() {
+        try_parse("local a <close>, b <close> = f(), g()");
+    }
+
     #[test]
     fn labelstat() {
         let ast = try_parse("::LABEL::");
@@ -223,8 +357,10 @@ mod parser_tests {
             ast,
             Block {
                 stats: vec![Stat::LabelStat(LabelStat {
-                    label: "LABEL".to_string()
-                })]
+                    label: name("LABEL"),
+                    span: span(),
+                })],
+                span: span(),
             }
         )
     }
@@ -238,23 +374,29 @@ mod parser_tests {
                 stats: vec![Stat::RetStat(RetStat {
                     exprs: vec![
                         Expr::BinExpr(BinExpr {
-                            left: Box::new(Expr::Int(1)),
+                            left: Box::new(Expr::Int(1, span())),
                             op: BinOp::Add,
                             right: Box::new(Expr::SuffixedExpr(SuffixedExpr {
-                                primary: PrimaryExpr::Name("a".to_string()),
-                                suffixes: vec![]
-                            }))
+                                primary: PrimaryExpr::Name(name("a")),
+                                suffixes: vec![],
+                                span: span(),
+                            })),
+                            span: span(),
                         }),
                         Expr::SuffixedExpr(SuffixedExpr {
-                            primary: PrimaryExpr::Name("b".to_string()),
-                            suffixes: vec![]
+                            primary: PrimaryExpr::Name(name("b")),
+                            suffixes: vec![],
+                            span: span(),
                         }),
                         Expr::SuffixedExpr(SuffixedExpr {
-                            primary: PrimaryExpr::Name("c".to_string()),
-                            suffixes: vec![]
+                            primary: PrimaryExpr::Name(name("c")),
+                            suffixes: vec![],
+                            span: span(),
                         })
                     ],
+                    span: span(),
                 })],
+                span: span(),
             }
         )
     }
@@ -266,8 +408,10 @@ mod parser_tests {
             ast,
             Block {
                 stats: vec![Stat::GotoStat(GotoStat {
-                    label: "LABEL".to_string()
+                    label: name("LABEL"),
+                    span: span(),
                 })],
+                span: span(),
             }
         )
     }
@@ -281,20 +425,29 @@ mod parser_tests {
                 stats: vec![Stat::AssignStat(AssignStat {
                     left: vec![
                         SuffixedExpr {
-                            primary: PrimaryExpr::Name("a".to_string()),
+                            primary: PrimaryExpr::Name(name("a")),
                             suffixes: vec![],
+                            span: span(),
                         },
                         SuffixedExpr {
-                            primary: PrimaryExpr::Name("b".to_string()),
+                            primary: PrimaryExpr::Name(name("b")),
                             suffixes: vec![],
+                            span: span(),
                         },
                         SuffixedExpr {
-                            primary: PrimaryExpr::Name("c".to_string()),
+                            primary: PrimaryExpr::Name(name("c")),
                             suffixes: vec![],
+                            span: span(),
                         },
                     ],
-                    right: vec![Expr::Int(1), Expr::Int(2), Expr::Int(3),],
+                    right: vec![
+                        Expr::Int(1, span()),
+                        Expr::Int(2, span()),
+                        Expr::Int(3, span()),
+                    ],
+                    span: span(),
                 })],
+                span: span(),
             }
         )
     }
@@ -307,14 +460,20 @@ mod parser_tests {
             Block {
                 stats: vec![Stat::CallStat(CallStat {
                     call: SuffixedExpr {
-                        primary: PrimaryExpr::Name("foo".to_string()),
-                        suffixes: vec![Suffix::FuncArgs(FuncArgs::Exprs(vec![
-                            Expr::Int(1),
-                            Expr::Int(2),
-                            Expr::Int(3),
-                        ]))],
+                        primary: PrimaryExpr::Name(name("foo")),
+                        suffixes: vec![Suffix::FuncArgs(FuncArgs::Exprs(
+                            vec![
+                                Expr::Int(1, span()),
+                                Expr::Int(2, span()),
+                                Expr::Int(3, span()),
+                            ],
+                            span()
+                        ))],
+                        span: span(),
                     },
+                    span: span(),
                 })],
+                span: span(),
             }
         )
     }
@@ -327,23 +486,32 @@ mod parser_tests {
             Block {
                 stats: vec![Stat::CallStat(CallStat {
                     call: SuffixedExpr {
-                        primary: PrimaryExpr::Name("a".to_string()),
-                        suffixes: vec![Suffix::FuncArgs(FuncArgs::Exprs(vec![
-                            Expr::SuffixedExpr(SuffixedExpr {
-                                primary: PrimaryExpr::Name("a".to_string()),
-                                suffixes: vec![],
-                            }),
-                            Expr::SuffixedExpr(SuffixedExpr {
-                                primary: PrimaryExpr::Name("b".to_string()),
-                                suffixes: vec![],
-                            }),
-                            Expr::SuffixedExpr(SuffixedExpr {
-                                primary: PrimaryExpr::Name("c".to_string()),
-                                suffixes: vec![],
-                            }),
-                        ]))],
+                        primary: PrimaryExpr::Name(name("a")),
+                        suffixes: vec![Suffix::FuncArgs(FuncArgs::Exprs(
+                            vec![
+                                Expr::SuffixedExpr(SuffixedExpr {
+                                    primary: PrimaryExpr::Name(name("a")),
+                                    suffixes: vec![],
+                                    span: span(),
+                                }),
+                                Expr::SuffixedExpr(SuffixedExpr {
+                                    primary: PrimaryExpr::Name(name("b")),
+                                    suffixes: vec![],
+                                    span: span(),
+                                }),
+                                Expr::SuffixedExpr(SuffixedExpr {
+                                    primary: PrimaryExpr::Name(name("c")),
+                                    suffixes: vec![],
+                                    span: span(),
+                                }),
+                            ],
+                            span()
+                        ))],
+                        span: span(),
                     },
+                    span: span(),
                 })],
+                span: span(),
             }
         )
     }
@@ -356,46 +524,57 @@ mod parser_tests {
             ast1,
             Block {
                 stats: vec![Stat::LocalStat(LocalStat {
-                    names: vec!["t".to_string()],
+                    names: vec![local("t", None)],
                     exprs: vec![Expr::Table(Table {
                         fields: vec![
-                            Field::ListField(Expr::Int(1)),
-                            Field::ListField(Expr::Float(1.5)),
-                            Field::ListField(Expr::String("2".to_string()))
-                        ]
-                    })]
+                            Field::ListField(Expr::Int(1, span())),
+                            Field::ListField(Expr::Float(1.5, span())),
+                            Field::ListField(Expr::String("2".to_string(), span()))
+                        ],
+                        span: span(),
+                    })],
+                    span: span(),
                 })],
+                span: span(),
             }
         );
         assert_eq!(
             ast2,
             Block {
                 stats: vec![Stat::LocalStat(LocalStat {
-                    names: vec!["t".to_string()],
+                    names: vec![local("t", None)],
                     exprs: vec![Expr::Table(Table {
                         fields: vec![
                             Field::RecFileld(RecField {
-                                key: FieldKey::Name("a".to_string()),
-                                value: Expr::String("1".to_string()),
+                                key: FieldKey::Name(name("a")),
+                                value: Expr::String("1".to_string(), span()),
+                                span: span(),
                             }),
                             Field::RecFileld(RecField {
-                                key: FieldKey::Expr(Expr::String("b".to_string())),
-                                value: Expr::Int(2),
+                                key: FieldKey::Expr(Expr::String("b".to_string(), span())),
+                                value: Expr::Int(2, span()),
+                                span: span(),
                             }),
                             Field::RecFileld(RecField {
                                 key: FieldKey::Expr(Expr::BinExpr(BinExpr {
                                     op: BinOp::Minus,
                                     left: Box::new(Expr::SuffixedExpr(SuffixedExpr {
-                                        primary: PrimaryExpr::Name("a".to_string()),
+                                        primary: PrimaryExpr::Name(name("a")),
                                         suffixes: vec![],
+                                        span: span(),
                                     })),
-                                    right: Box::new(Expr::Int(1)),
+                                    right: Box::new(Expr::Int(1, span())),
+                                    span: span(),
                                 })),
-                                value: Expr::Int(3),
+                                value: Expr::Int(3, span()),
+                                span: span(),
                             }),
                         ],
+                        span: span(),
                     })],
+                    span: span(),
                 })],
+                span: span(),
             }
         );
     }
@@ -408,15 +587,21 @@ mod parser_tests {
             Block {
                 stats: vec![Stat::CallStat(CallStat {
                     call: SuffixedExpr {
-                        primary: PrimaryExpr::Name("a".to_string()),
+                        primary: PrimaryExpr::Name(name("a")),
                         suffixes: vec![
-                            Suffix::Method("b".to_string()),
-                            Suffix::FuncArgs(FuncArgs::Table(Table { fields: vec![] })),
-                            Suffix::FuncArgs(FuncArgs::String("literal".to_string())),
-                            Suffix::FuncArgs(FuncArgs::Exprs(vec![])),
+                            Suffix::Method(name("b")),
+                            Suffix::FuncArgs(FuncArgs::Table(Table {
+                                fields: vec![],
+                                span: span()
+                            })),
+                            Suffix::FuncArgs(FuncArgs::String("literal".to_string(), span())),
+                            Suffix::FuncArgs(FuncArgs::Exprs(vec![], span())),
                         ],
+                        span: span(),
                     },
+                    span: span(),
                 })],
+                span: span(),
             },
         );
     }
@@ -431,14 +616,18 @@ mod parser_tests {
                 stats: vec![Stat::RetStat(RetStat {
                     exprs: vec![Expr::BinExpr(BinExpr {
                         op: BinOp::Concat,
-                        left: Box::new(Expr::Int(1)),
+                        left: Box::new(Expr::Int(1, span())),
                         right: Box::new(Expr::BinExpr(BinExpr {
                             op: BinOp::Concat,
-                            left: Box::new(Expr::Int(2)),
-                            right: Box::new(Expr::Int(3)),
+                            left: Box::new(Expr::Int(2, span())),
+                            right: Box::new(Expr::Int(3, span())),
+                            span: span(),
                         })),
+                        span: span(),
                     })],
+                    span: span(),
                 })],
+                span: span(),
             }
         );
         assert_eq!(
@@ -449,12 +638,16 @@ mod parser_tests {
                         op: BinOp::Add,
                         left: Box::new(Expr::BinExpr(BinExpr {
                             op: BinOp::Add,
-                            left: Box::new(Expr::Int(1)),
-                            right: Box::new(Expr::Int(2)),
+                            left: Box::new(Expr::Int(1, span())),
+                            right: Box::new(Expr::Int(2, span())),
+                            span: span(),
                         })),
-                        right: Box::new(Expr::Int(3)),
+                        right: Box::new(Expr::Int(3, span())),
+                        span: span(),
                     })],
+                    span: span(),
                 })],
+                span: span(),
             }
         );
     }
@@ -467,22 +660,30 @@ mod parser_tests {
             Block {
                 stats: vec![Stat::CallStat(CallStat {
                     call: SuffixedExpr {
-                        primary: PrimaryExpr::Name("str".to_string()),
+                        primary: PrimaryExpr::Name(name("str")),
                         suffixes: vec![
-                            Suffix::Method("sub".to_string()),
-                            Suffix::FuncArgs(FuncArgs::Exprs(vec![
-                                Expr::SuffixedExpr(SuffixedExpr {
-                                    primary: PrimaryExpr::Name("i".to_string()),
-                                    suffixes: vec![],
-                                }),
-                                Expr::SuffixedExpr(SuffixedExpr {
-                                    primary: PrimaryExpr::Name("i".to_string()),
-                                    suffixes: vec![],
-                                }),
-                            ])),
-                        ]
+                            Suffix::Method(name("sub")),
+                            Suffix::FuncArgs(FuncArgs::Exprs(
+                                vec![
+                                    Expr::SuffixedExpr(SuffixedExpr {
+                                        primary: PrimaryExpr::Name(name("i")),
+                                        suffixes: vec![],
+                                        span: span(),
+                                    }),
+                                    Expr::SuffixedExpr(SuffixedExpr {
+                                        primary: PrimaryExpr::Name(name("i")),
+                                        suffixes: vec![],
+                                        span: span(),
+                                    }),
+                                ],
+                                span()
+                            )),
+                        ],
+                        span: span(),
                     },
+                    span: span(),
                 })],
+                span: span(),
             }
         )
     }
@@ -499,22 +700,272 @@ mod parser_tests {
                         left: Box::new(Expr::BinExpr(BinExpr {
                             op: BinOp::Eq,
                             left: Box::new(Expr::SuffixedExpr(SuffixedExpr {
-                                primary: PrimaryExpr::Name("a".to_string()),
+                                primary: PrimaryExpr::Name(name("a")),
                                 suffixes: vec![],
+                                span: span(),
                             })),
-                            right: Box::new(Expr::Int(1)),
+                            right: Box::new(Expr::Int(1, span())),
+                            span: span(),
                         },)),
                         right: Box::new(Expr::BinExpr(BinExpr {
                             op: BinOp::Eq,
                             left: Box::new(Expr::SuffixedExpr(SuffixedExpr {
-                                primary: PrimaryExpr::Name("b".to_string()),
+                                primary: PrimaryExpr::Name(name("b")),
                                 suffixes: vec![],
+                                span: span(),
                             })),
-                            right: Box::new(Expr::Int(2)),
+                            right: Box::new(Expr::Int(2, span())),
+                            span: span(),
                         })),
+                        span: span(),
                     })],
+                    span: span(),
                 })],
+                span: span(),
             }
         );
     }
+
+    #[test]
+    fn spans() {
+        let ast = parse_with_spans("local a = 1\nreturn foo(a) + 2", LuaVersion::default());
+        assert_eq!((ast.span.start, ast.span.end), (0, 29));
+        if let Stat::LocalStat(local) = &ast.stats[0] {
+            assert_eq!((local.span.start, local.span.end), (0, 11));
+            assert_eq!((local.names[0].span.line, local.names[0].span.col), (1, 7));
+            assert_eq!(
+                (local.exprs[0].span().start, local.exprs[0].span().end),
+                (10, 11)
+            );
+        } else {
+            unreachable!()
+        }
+        if let Stat::RetStat(ret) = &ast.stats[1] {
+            assert_eq!((ret.span.line, ret.span.col), (2, 1));
+            let expr = &ret.exprs[0];
+            assert_eq!((expr.span().start, expr.span().end), (19, 29));
+            if let Expr::BinExpr(bin) = expr {
+                assert_eq!((bin.left.span().start, bin.left.span().end), (19, 25));
+                assert_eq!((bin.right.span().line, bin.right.span().col), (2, 17));
+            } else {
+                unreachable!()
+            }
+        } else {
+            unreachable!()
+        }
+        // spans take part in equality, `same_structure` leaves them out
+        let other = parse_with_spans("local a  =  1 return foo(a)+2", LuaVersion::default());
+        assert_ne!(ast, other);
+        assert!(same_structure(&ast, &other));
+    }
+
+    #[test]
+    fn version_lua51() {
+        let ast = try_parse_version("local goto = 1; goto = 2", LuaVersion::Lua51);
+        assert_eq!(
+            ast,
+            Block {
+                stats: vec![
+                    Stat::LocalStat(LocalStat {
+                        names: vec![local("goto", None)],
+                        exprs: vec![Expr::Float(1.0, span())],
+                        span: span(),
+                    }),
+                    Stat::AssignStat(AssignStat {
+                        left: vec![SuffixedExpr {
+                            primary: PrimaryExpr::Name(name("goto")),
+                            suffixes: vec![],
+                            span: span(),
+                        }],
+                        right: vec![Expr::Float(2.0, span())],
+                        span: span(),
+                    }),
+                ],
+                span: span(),
+            }
+        );
+    }
+
+    #[test]
+    fn version_luajit() {
+        try_parse_version("goto continue; ::continue::", LuaVersion::LuaJIT);
+        let ast = try_parse_version("return 1, 2LL, 3ULL", LuaVersion::LuaJIT);
+        assert_eq!(
+            ast,
+            Block {
+                stats: vec![Stat::RetStat(RetStat {
+                    exprs: vec![
+                        Expr::Float(1.0, span()),
+                        Expr::Int(2, span()),
+                        Expr::UInt(3, span())
+                    ],
+                    span: span(),
+                })],
+                span: span(),
+            }
+        );
+    }
+
+    #[test]
+    fn idiv() {
+        let ast = try_parse("return 7 // 2");
+        assert_eq!(
+            ast,
+            Block {
+                stats: vec![Stat::RetStat(RetStat {
+                    exprs: vec![Expr::BinExpr(BinExpr {
+                        op: BinOp::IDiv,
+                        left: Box::new(Expr::Int(7, span())),
+                        right: Box::new(Expr::Int(2, span())),
+                        span: span(),
+                    })],
+                    span: span(),
+                })],
+                span: span(),
+            }
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn version_panic1() {
+        try_parse_version("goto continue", LuaVersion::Lua51);
+    }
+
+    #[test]
+    #[should_panic]
+    fn version_panic2() {
+        try_parse_version("::continue::", LuaVersion::Lua51);
+    }
+
+    #[test]
+    #[should_panic]
+    fn version_panic3() {
+        try_parse_version(";", LuaVersion::Lua51);
+    }
+
+    #[test]
+    #[should_panic]
+    fn version_panic4() {
+        try_parse_version("return a & b", LuaVersion::Lua52);
+    }
+
+    #[test]
+    #[should_panic]
+    fn version_panic5() {
+        try_parse_version("return ~a", LuaVersion::LuaJIT);
+    }
+
+    #[test]
+    #[should_panic]
+    fn version_panic6() {
+        try_parse_version("return a // b", LuaVersion::LuaJIT);
+    }
+
+    #[test]
+    #[should_panic]
+    fn version_panic7() {
+        try_parse_version("local a <const> = 1", LuaVersion::Lua53);
+    }
+
+    fn parse_error(input: &str) -> SyntaxError {
+        let tokens = Lexer::new().run(input).ok().unwrap();
+        Parser::new().run(tokens).err().unwrap()
+    }
+
+    #[test]
+    fn error() {
+        let error = parse_error("if a then\n  b = 1\nx = 2");
+        assert_eq!(
+            error.kind,
+            SyntaxErrorKind::Unmatched {
+                expected: TokenType::End,
+                opening: TokenType::If,
+                line: 1
+            }
+        );
+        assert_eq!(error.found, TokenType::Eos);
+        assert_eq!((error.line, error.col, error.offset), (3, 6, 23));
+
+        let error = parse_error("local function (x) end");
+        assert_eq!(error.kind, SyntaxErrorKind::Expected(TokenType::Name));
+        assert_eq!((error.found, error.col), (TokenType::Lp, 16));
+        assert_eq!(
+            error.to_string(),
+            "[syntax error] Name expected at line [1:16] near [Lp]"
+        );
+
+        let error = parse_error("a = 1 end");
+        assert_eq!(error.kind, SyntaxErrorKind::Expected(TokenType::Eos));
+        assert_eq!(error.found, TokenType::End);
+
+        let error = parse_error("for a.b = 1, 2 do end");
+        assert_eq!(
+            error.kind,
+            SyntaxErrorKind::ExpectedOneOf(vec![TokenType::Assign, TokenType::In])
+        );
+    }
+
+    fn parse_recovering(input: &str) -> (Block, Vec<SyntaxError>) {
+        let tokens = Lexer::new().run(input).ok().unwrap();
+        Parser::new().run_recovering(tokens)
+    }
+
+    #[test]
+    fn recovering() {
+        let input = r#"
+local a = = 1
+function f()
+    b = )
+    return a
+end
+if a then end end
+local c = 2
+"#;
+        let (block, errors) = parse_recovering(input);
+        let kinds: Vec<SyntaxErrorKind> = errors.iter().map(|e| e.kind.clone()).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                SyntaxErrorKind::UnexpectedSymbol,
+                SyntaxErrorKind::UnexpectedSymbol,
+                SyntaxErrorKind::Expected(TokenType::Eos)
+            ]
+        );
+        assert_eq!(
+            errors.iter().map(|e| e.line).collect::<Vec<usize>>(),
+            vec![2, 4, 7]
+        );
+        assert_eq!(block.stats.len(), 5);
+        assert!(matches!(block.stats[0], Stat::Error(_)));
+        match &block.stats[1] {
+            Stat::FuncStat(stat) => {
+                let stats = &stat.body.block.stats;
+                assert!(matches!(stats[0], Stat::Error(_)));
+                assert!(matches!(stats[1], Stat::RetStat(_)));
+            }
+            _ => unreachable!(),
+        }
+        assert!(matches!(block.stats[2], Stat::IfStat(_)));
+        assert!(matches!(block.stats[3], Stat::Error(_)));
+        assert!(matches!(block.stats[4], Stat::LocalStat(_)));
+        assert_eq!(
+            (block.stats[3].span().line, block.stats[3].span().col),
+            (7, 15)
+        );
+
+        let (block, errors) = parse_recovering("local t = {1, 2");
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(block.stats[0], Stat::Error(_)));
+
+        // error tokens from a recovering lexer
+        let (tokens, _) = Lexer::new().run_recovering("a = $\nb = 2");
+        let (block, errors) = Parser::new().run_recovering(tokens);
+        assert_eq!(errors[0].found, TokenType::Error);
+        assert!(matches!(block.stats[1], Stat::AssignStat(_)));
+
+        let (block, errors) = parse_recovering("a = 1 b = 2");
+        assert!(errors.is_empty());
+        assert_eq!(block.stats.len(), 2);
+    }
 }
@@ -0,0 +1,87 @@
+use rslua::pattern::{find, match_at, validate, Capture, PatternError};
+
+fn find_str(src: &str, pat: &str) -> Option<(usize, usize, Vec<String>)> {
+    let (start, end, captures) = find(src.as_bytes(), pat.as_bytes(), 0).unwrap()?;
+    let captures = captures
+        .iter()
+        .map(|capture| match capture {
+            Capture::Str(start, end) => src[*start..*end].to_string(),
+            Capture::Position(p) => (p + 1).to_string(),
+        })
+        .collect();
+    Some((start, end, captures))
+}
+
+#[test]
+fn matching() {
+    assert_eq!(find_str("hello world", "o w"), Some((4, 7, vec![])));
+    assert_eq!(
+        find_str("key = value", "(%w+)%s*=%s*(%w+)"),
+        Some((0, 11, vec!["key".to_string(), "value".to_string()]))
+    );
+    assert_eq!(
+        find_str("  trim  ", "^%s*(.-)%s*$").unwrap().2,
+        vec!["trim"]
+    );
+    assert_eq!(find_str("abc", "()b()").unwrap().2, vec!["2", "3"]);
+    assert_eq!(find_str("a[b]c", "[%]]"), Some((3, 4, vec![])));
+    assert_eq!(find_str("x = f(a, (b)) + 1", "%b()"), Some((5, 13, vec![])));
+    assert_eq!(
+        find_str("THE (quick) fox", "%f[%a]%a+%f[%A]"),
+        Some((0, 3, vec![]))
+    );
+    assert_eq!(
+        find_str("say 'hi' now", "(['\"])(.-)%1").unwrap().2,
+        vec!["'", "hi"]
+    );
+    assert_eq!(find_str("abc", "^b"), None);
+    assert_eq!(find_str("aaa", "a-b"), None);
+    assert_eq!(match_at(b"abc", b"b", 0), Ok(None));
+    assert_eq!(match_at(b"abc", b"b?a", 0), Ok(Some((1, vec![]))));
+}
+
+#[test]
+fn errors() {
+    assert_eq!(find(b"abc", b"a%", 0), Err(PatternError::EndsWithPercent));
+    assert_eq!(
+        find(b"a", b"(a", 0).unwrap_err().to_string(),
+        "unfinished capture"
+    );
+    assert_eq!(
+        find(&[b'a'; 1000], &[b'a', b'?'].repeat(300), 0),
+        Err(PatternError::TooComplex)
+    );
+}
+
+#[test]
+fn validation() {
+    for pat in [
+        "",
+        "^(%d+)%.(%d*)$",
+        "%b()",
+        "%f[%w]",
+        "(a)%1",
+        "[%]]",
+        "()%1",
+        "a$b",
+    ]
+    .iter()
+    {
+        assert_eq!(validate(pat.as_bytes()), Ok(()), "{}", pat);
+    }
+    assert_eq!(validate(b"abc%"), Err(PatternError::EndsWithPercent));
+    assert_eq!(validate(b"[a-"), Err(PatternError::MissingBracket));
+    assert_eq!(validate(b"%b("), Err(PatternError::MissingBalanceArgs));
+    assert_eq!(validate(b"%fa"), Err(PatternError::MissingFrontierBracket));
+    assert_eq!(
+        validate(b"(a%1)"),
+        Err(PatternError::InvalidCaptureIndex(1))
+    );
+    assert_eq!(validate(b"%0"), Err(PatternError::InvalidCaptureIndex(0)));
+    assert_eq!(validate(b"a)"), Err(PatternError::InvalidPatternCapture));
+    assert_eq!(validate(b"((a)"), Err(PatternError::UnfinishedCapture));
+    assert_eq!(
+        validate(&b"()".repeat(33)),
+        Err(PatternError::TooManyCaptures)
+    );
+}
@@ -0,0 +1,154 @@
+use rslua::ast::*;
+use rslua::lexer::Lexer;
+use rslua::parser::Parser;
+use rslua::printer::{print, Printer};
+use rslua::token::Span;
+use rslua::types::LuaVersion;
+use std::fs::{read_dir, File};
+use std::io::prelude::*;
+
+fn try_parse(input: &str, version: LuaVersion) -> Block {
+    let mut lexer = Lexer::new();
+    lexer.set_debug(true);
+    lexer.set_version(version);
+    let tokens = lexer.run(input).unwrap();
+    let mut parser = Parser::new();
+    parser.set_debug(true);
+    parser.set_version(version);
+    parser.run(tokens).unwrap()
+}
+
+// print the AST and make sure parsing the output gives back the same AST
+fn round_trip_version(input: &str, version: LuaVersion) -> String {
+    let ast = try_parse(input, version);
+    let output = print(&ast);
+    assert!(
+        same_structure(&ast, &try_parse(&output, version)),
+        "{}",
+        output
+    );
+    output
+}
+
+fn round_trip(input: &str) -> String {
+    round_trip_version(input, LuaVersion::Lua54)
+}
+
+fn name(name: &str) -> Expr {
+    Expr::SuffixedExpr(SuffixedExpr {
+        primary: PrimaryExpr::Name(Name {
+            name: name.to_string(),
+            span: Span::default(),
+        }),
+        suffixes: vec![],
+        span: Span::default(),
+    })
+}
+
+fn bin(op: BinOp, left: Expr, right: Expr) -> Expr {
+    Expr::BinExpr(BinExpr {
+        op,
+        left: Box::new(left),
+        right: Box::new(right),
+        span: Span::default(),
+    })
+}
+
+fn un(op: UnOp, expr: Expr) -> Expr {
+    Expr::UnExpr(UnExpr {
+        op,
+        expr: Box::new(expr),
+        span: Span::default(),
+    })
+}
+
+#[test]
+fn print_lua_files() -> std::io::Result<()> {
+    for entry in read_dir("./lua")? {
+        let mut file = File::open(entry?.path())?;
+        let mut content = String::new();
+        file.read_to_string(&mut content)?;
+        round_trip(&content);
+    }
+    Ok(())
+}
+
+#[test]
+fn statements() {
+    assert_eq!(
+        round_trip("local a <const>, b = 1, f() if a then elseif b then else end"),
+        "local a <const>, b = 1, f()\nif a then\nelseif b then\nend\n"
+    );
+    assert_eq!(
+        round_trip("function a.b:c(x, ...) return x end"),
+        "function a.b:c(x, ...)\n  return x\nend\n"
+    );
+    assert_eq!(
+        round_trip("t = {1, x = 2, [3] = {}} s:m 'x' f {}"),
+        "t = {1, x = 2, [3] = {}}\ns:m \"x\"\nf {}\n"
+    );
+    round_trip("for i = 1, 10, 2 do goto c end ::c:: for k, v in pairs(t) do break end");
+    round_trip("repeat local x = 1 until x; while true do do end end return");
+    // `;` keeps the parenthesized call from becoming a call of `b`
+    let mut ast = try_parse("a = b; (f)() repeat until c; (g).x = 1", LuaVersion::Lua51);
+    ast.stats.retain(|stat| !matches!(stat, Stat::Empty(_)));
+    let output = print(&ast);
+    assert_eq!(output, "a = b\n;(f)()\nrepeat\nuntil c\n;(g).x = 1.0\n");
+    assert!(same_structure(&ast, &try_parse(&output, LuaVersion::Lua51)));
+}
+
+#[test]
+fn precedence() {
+    assert_eq!(round_trip("x = (a + b) * c"), "x = (a + b) * c\n");
+    round_trip("x = a - (b - c) - d");
+    round_trip("x = a .. b .. c, (a .. b) .. c");
+    round_trip("x = a ^ b ^ c, (a ^ b) ^ c, -a ^ b, (-a) ^ b, 2 ^ -a");
+    round_trip("x = not (a == b), not a == b, - -a, -(-a), #t + 1");
+    round_trip("x = a or b and c or (d or e) and f < g");
+    round_trip("x = a << b >> c | d & e ~ f ~ ~g, a // b % c");
+
+    // trees that do not come from parsing
+    let mut printer = Printer::new();
+    let expr = bin(BinOp::Mul, bin(BinOp::Add, name("a"), name("b")), name("c"));
+    assert_eq!(printer.run_expr(&expr), "(a + b) * c");
+    let expr = bin(
+        BinOp::Minus,
+        name("a"),
+        bin(BinOp::Minus, name("b"), name("c")),
+    );
+    assert_eq!(printer.run_expr(&expr), "a - (b - c)");
+    let expr = bin(BinOp::Pow, un(UnOp::Minus, name("a")), name("b"));
+    assert_eq!(printer.run_expr(&expr), "(-a) ^ b");
+    let expr = un(UnOp::Not, bin(BinOp::Eq, name("a"), name("b")));
+    assert_eq!(printer.run_expr(&expr), "not (a == b)");
+    let expr = un(UnOp::Minus, un(UnOp::Minus, name("a")));
+    assert_eq!(printer.run_expr(&expr), "- -a");
+    let expr = un(UnOp::Minus, Expr::Int(-1, Span::default()));
+    assert_eq!(printer.run_expr(&expr), "-0xffffffffffffffff");
+}
+
+#[test]
+fn literals() {
+    assert_eq!(
+        round_trip(r#"s = "a\"b\\c\n\r\t\0\1271\127" .. 'x' .. [[y"]]"#),
+        "s = \"a\\\"b\\\\c\\n\\r\\t\\000\\1271\\127\" .. \"x\" .. \"y\\\"\"\n"
+    );
+    round_trip("s = '\\u{10FFFF}é'");
+    assert_eq!(
+        round_trip("x = 1.0, 0.5, 1e300, 1e-7, 3, 0xff, 1e999"),
+        "x = 1.0, 0.5, 1e300, 1e-7, 3, 255, 1e999\n"
+    );
+    round_trip_version("x = 1, 2.5, 1e300", LuaVersion::Lua51);
+    assert_eq!(
+        round_trip("x = 0xffffffffffffffff, 0x8000000000000000"),
+        "x = 0xffffffffffffffff, 0x8000000000000000\n"
+    );
+
+    let mut printer = Printer::new();
+    let expr = Expr::Int(i64::MIN, Span::default());
+    assert_eq!(printer.run_expr(&expr), "0x8000000000000000");
+    let expr = Expr::Float(f64::NAN, Span::default());
+    assert_eq!(printer.run_expr(&expr), "(0/0)");
+    let expr = Expr::Float(-0.5, Span::default());
+    assert_eq!(printer.run_expr(&expr), "(-0.5)");
+}
@@ -0,0 +1,220 @@
+use rslua::ast::*;
+use rslua::lexer::Lexer;
+use rslua::parser::Parser;
+use rslua::resolver::{Binding, LocalKind, Resolution, Resolver};
+use rslua::types::LuaVersion;
+
+fn try_parse(input: &str, version: LuaVersion) -> Block {
+    let mut lexer = Lexer::new();
+    lexer.set_debug(true);
+    lexer.set_version(version);
+    let tokens = lexer.run(input).unwrap();
+    let mut parser = Parser::new();
+    parser.set_debug(true);
+    parser.set_version(version);
+    parser.run(tokens).unwrap()
+}
+
+fn parse(input: &str) -> Block {
+    try_parse(input, LuaVersion::Lua54)
+}
+
+fn resolve_version(block: &Block, version: LuaVersion) -> Resolution<'_> {
+    let mut resolver = Resolver::new();
+    resolver.set_version(version);
+    resolver.run(block)
+}
+
+fn resolve(block: &Block) -> Resolution<'_> {
+    resolve_version(block, LuaVersion::Lua54)
+}
+
+// every use as `name binding`, locals shown by their declaring line and column
+fn uses(resolution: &Resolution) -> Vec<String> {
+    let local = |id: usize| {
+        let local = &resolution.locals[id];
+        format!("{}@{}:{}", local.name, local.span.line, local.span.col)
+    };
+    resolution
+        .uses
+        .iter()
+        .map(|u| {
+            let binding = match u.binding {
+                Binding::Local(id) => local(id),
+                Binding::Upvalue(id) => format!("^{}", local(id)),
+                Binding::Global(Some(id)) => format!("global in {}", local(id)),
+                Binding::Global(None) => "global".to_string(),
+                Binding::Env => "env".to_string(),
+            };
+            format!("{}{} {}", u.name, if u.write { "=" } else { "" }, binding)
+        })
+        .collect()
+}
+
+#[test]
+fn locals() {
+    let block = parse("local x = x\nlocal function f(a, ...) return f(x, a) end\nx = y");
+    let resolution = resolve(&block);
+    assert_eq!(
+        uses(&resolution),
+        vec![
+            "x global",
+            "f ^f@2:16",
+            "x ^x@1:7",
+            "a a@2:18",
+            "x= x@1:7",
+            "y global",
+        ]
+    );
+    let kinds: Vec<LocalKind> = resolution.locals.iter().map(|l| l.kind).collect();
+    assert_eq!(
+        kinds,
+        vec![LocalKind::Local, LocalKind::Function, LocalKind::Param]
+    );
+    assert_eq!(resolution.locals[0].uses.len(), 2);
+}
+
+#[test]
+fn scopes() {
+    let block = parse(
+        "local a = 1
+do local a = a end
+for i = i, 2 do print(i) end
+for k, v in pairs(k) do end
+repeat local done = a until done
+print(a, done)",
+    );
+    let resolution = resolve(&block);
+    assert_eq!(
+        uses(&resolution),
+        vec![
+            "a a@1:7",
+            "i global",
+            "print global",
+            "i i@3:5",
+            "pairs global",
+            "k global",
+            "a a@1:7",
+            "done done@5:14",
+            "print global",
+            "a a@1:7",
+            "done global",
+        ]
+    );
+    // the chunk, do, for, for and repeat
+    assert_eq!(resolution.scopes.len(), 5);
+    assert!(resolution.scopes[1..].iter().all(|s| s.parent == Some(0)));
+    assert_eq!(resolution.scopes[3].locals.len(), 2);
+}
+
+#[test]
+fn upvalues() {
+    let block = parse(
+        "local a, b, c = 1, 2, 3
+local function f()
+  local function g() return a + b end
+  return c
+end
+function t.m:n() return function() return self end end",
+    );
+    let resolution = resolve(&block);
+    // f passes `a` and `b` down to g
+    assert_eq!(resolution.functions.len(), 5);
+    assert_eq!(resolution.functions[1].upvalues, vec![0, 1, 2]);
+    assert_eq!(resolution.functions[2].upvalues, vec![0, 1]);
+    assert_eq!(resolution.functions[2].parent, Some(1));
+    assert_eq!(resolution.functions[4].upvalues, vec![5]);
+    assert!(resolution.locals[..3].iter().all(|l| l.captured));
+    assert!(!resolution.locals[3].captured);
+    assert_eq!(resolution.locals[5].kind, LocalKind::SelfParam);
+    assert_eq!(uses(&resolution)[3..], ["t global", "self ^self@6:14"]);
+}
+
+#[test]
+fn env() {
+    let input = "print(_ENV)\nlocal function f(_ENV) x = 1 return function() return y end end";
+    assert_eq!(
+        uses(&resolve(&parse(input))),
+        vec![
+            "print global",
+            "_ENV env",
+            "x= global in _ENV@2:18",
+            "y global in _ENV@2:18",
+        ]
+    );
+    let block = parse(input);
+    let resolution = resolve(&block);
+    assert_eq!(resolution.functions[2].upvalues, vec![1]);
+    // `_ENV` is an ordinary name before 5.2
+    assert_eq!(
+        uses(&resolve_version(
+            &try_parse(input, LuaVersion::Lua51),
+            LuaVersion::Lua51
+        )),
+        vec!["print global", "_ENV global", "x= global", "y global"]
+    );
+}
+
+#[test]
+fn lookup() {
+    let block = try_parse("local x = 1 x = x + 1", LuaVersion::Lua54);
+    let resolution = Resolver::new().run(&block);
+    let (decl, target, value) = match &block.stats[..] {
+        [Stat::LocalStat(local), Stat::AssignStat(assign)] => {
+            let value = match &assign.right[0] {
+                Expr::BinExpr(BinExpr { left, .. }) => match left.as_ref() {
+                    Expr::SuffixedExpr(SuffixedExpr {
+                        primary: PrimaryExpr::Name(name),
+                        ..
+                    }) => name,
+                    _ => unreachable!(),
+                },
+                _ => unreachable!(),
+            };
+            let target = match &assign.left[0].primary {
+                PrimaryExpr::Name(name) => name,
+                _ => unreachable!(),
+            };
+            (&local.names[0].name, target, value)
+        }
+        _ => unreachable!(),
+    };
+    assert_eq!(resolution.declaration(decl), Some(0));
+    assert_eq!(resolution.binding(decl), None);
+    assert_eq!(resolution.binding(target), Some(Binding::Local(0)));
+    assert_eq!(resolution.binding(value), Some(Binding::Local(0)));
+    assert_eq!(resolution.local(value).unwrap().name, "x");
+}
+
+// the name passed to `print(name)`
+fn print_arg(stat: &Stat) -> &Name {
+    if let Stat::CallStat(CallStat { call, .. }) = stat {
+        if let Suffix::FuncArgs(FuncArgs::Exprs(exprs, _)) = &call.suffixes[0] {
+            if let Expr::SuffixedExpr(SuffixedExpr {
+                primary: PrimaryExpr::Name(name),
+                ..
+            }) = &exprs[0]
+            {
+                return name;
+            }
+        }
+    }
+    unreachable!()
+}
+
+#[test]
+fn lookup_without_spans() {
+    // a tree that does not come from the parser, every span is the same
+    let mut block = try_parse(
+        "local x = 1 do local x = 2 print(x) end print(x)",
+        LuaVersion::Lua54,
+    );
+    block.clear_spans();
+    let resolution = Resolver::new().run(&block);
+    let (inner, outer) = match &block.stats[..] {
+        [_, Stat::DoBlock(stat), last] => (print_arg(&stat.block.stats[1]), print_arg(last)),
+        _ => unreachable!(),
+    };
+    assert_eq!(resolution.binding(inner), Some(Binding::Local(1)));
+    assert_eq!(resolution.binding(outer), Some(Binding::Local(0)));
+}
@@ -0,0 +1,141 @@
+use rslua::bytecode::{Constant, Instruction, Proto, UpvalueDesc};
+use rslua::compiler::Compiler;
+use rslua::lexer::Lexer;
+use rslua::parser::Parser;
+use rslua::types::LuaVersion;
+use rslua::undump::undump;
+
+fn compile(input: &str) -> Proto {
+    let tokens = Lexer::new().run(input).unwrap();
+    let block = Parser::new().run(tokens).unwrap();
+    let mut compiler = Compiler::new();
+    compiler.set_chunk_name("@test.lua");
+    compiler.run(&block).unwrap()
+}
+
+fn strip(proto: &mut Proto) {
+    proto.source = None;
+    proto.line_info.clear();
+    proto.local_vars.clear();
+    for upvalue in proto.upvalues.iter_mut() {
+        upvalue.name.clear();
+    }
+    proto.protos.iter_mut().for_each(strip);
+}
+
+// `print("hi")` compiled by luac 5.1 and 5.4 on x86-64
+const PRINT_51: &[u8] = &[
+    0x1b, b'L', b'u', b'a', 0x51, 0, 1, 4, 8, 4, 8, 0, // header
+    7, 0, 0, 0, 0, 0, 0, 0, b'=', b's', b't', b'd', b'i', b'n', 0, // source
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 2, // lines, upvalues, params, vararg, slots
+    4, 0, 0, 0, 0x05, 0, 0, 0, 0x41, 0x40, 0, 0, 0x1c, 0x40, 0, 0x01, 0x1e, 0, 0x80, 0, 2, 0, 0, 0,
+    4, 6, 0, 0, 0, 0, 0, 0, 0, b'p', b'r', b'i', b'n', b't', 0, 4, 3, 0, 0, 0, 0, 0, 0, 0, b'h',
+    b'i', 0, // constants
+    0, 0, 0, 0, // functions
+    4, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+];
+
+const PRINT_54: &[u8] = &[
+    0x1b, b'L', b'u', b'a', 0x54, 0, 0x19, 0x93, b'\r', b'\n', 0x1a, b'\n', 4, 8, 8, 0x78, 0x56, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x28, 0x77, 0x40, 1, // header
+    0x87, b'=', b's', b't', b'd', b'i', b'n', 0x80, 0x80, 0, 1, 2, // function
+    0x85, 0x51, 0, 0, 0, 0x0b, 0, 0, 0, 0x83, 0x80, 0, 0, 0x44, 0, 0x02, 0x01, 0x46, 0, 0x01,
+    0x01, // code
+    0x82, 4, 0x86, b'p', b'r', b'i', b'n', b't', 4, 0x83, b'h', b'i', // constants
+    0x81, 1, 0, 0, 0x80, // upvalues, functions
+    0x85, 1, 0, 0, 0, 0, 0x80, 0x80, 0x81, 0x85, b'_', b'E', b'N', b'V', // debug info
+];
+
+#[test]
+fn round_trip() {
+    let proto = compile(
+        "local t = {1, 2.5, 'x', true, nil}\nlocal function f(a, ...)\n  return a + t[1], ...\nend\nprint(f(1 << 40))",
+    );
+    let chunk = undump(&proto.dump(false)).unwrap();
+    assert_eq!(chunk.version, LuaVersion::Lua53);
+    assert_eq!(chunk.main, proto);
+    assert_eq!(chunk.main.protos[0].source.as_deref(), Some("@test.lua"));
+
+    let chunk = undump(&proto.dump(true)).unwrap();
+    let mut stripped = proto;
+    strip(&mut stripped);
+    assert_eq!(chunk.main, stripped);
+}
+
+#[test]
+fn lua51() {
+    let chunk = undump(PRINT_51).unwrap();
+    assert_eq!(chunk.version, LuaVersion::Lua51);
+    let main = chunk.main;
+    assert_eq!(main.source.as_deref(), Some("=stdin"));
+    assert!(main.is_vararg);
+    assert_eq!(main.max_stack_size, 2);
+    assert_eq!(
+        main.code,
+        vec![
+            Instruction(0x05),
+            Instruction(0x4041),
+            Instruction(0x0100401c),
+            Instruction(0x0080001e)
+        ]
+    );
+    assert_eq!(
+        main.constants,
+        vec![
+            Constant::Str(b"print".to_vec()),
+            Constant::Str(b"hi".to_vec())
+        ]
+    );
+    assert!(main.upvalues.is_empty());
+    assert_eq!(main.line_info, vec![1, 1, 1, 1]);
+}
+
+#[test]
+fn lua54() {
+    let chunk = undump(PRINT_54).unwrap();
+    assert_eq!(chunk.version, LuaVersion::Lua54);
+    let main = chunk.main;
+    assert_eq!(main.source.as_deref(), Some("=stdin"));
+    assert_eq!(main.code.len(), 5);
+    assert_eq!(main.code[2], Instruction(0x8083));
+    assert_eq!(main.constants.len(), 2);
+    assert_eq!(
+        main.upvalues,
+        vec![UpvalueDesc {
+            name: "_ENV".to_string(),
+            in_stack: true,
+            index: 0
+        }]
+    );
+    // differences to the previous line
+    assert_eq!(main.line_info, vec![1, 1, 1, 1, 1]);
+}
+
+#[test]
+fn errors() {
+    let error = |data: &[u8]| undump(data).unwrap_err().to_string();
+    assert_eq!(
+        error(b"print('hi')"),
+        "[undump error] not a binary chunk at byte 0."
+    );
+    assert_eq!(
+        error(b"\x1bLuaR\x00"),
+        "[undump error] unsupported version 5.2 at byte 4."
+    );
+    assert_eq!(
+        error(&PRINT_54[..PRINT_54.len() - 2]),
+        "[undump error] truncated chunk at byte 92."
+    );
+    let mut data = PRINT_51.to_vec();
+    data.push(0);
+    assert_eq!(
+        error(&data),
+        format!("[undump error] trailing bytes at byte {}.", PRINT_51.len())
+    );
+    let mut data = PRINT_54.to_vec();
+    data[12] = 8;
+    assert_eq!(
+        error(&data),
+        "[undump error] Instruction size mismatch at byte 12."
+    );
+}
@@ -0,0 +1,133 @@
+use rslua::ast::Block;
+use rslua::lexer::Lexer;
+use rslua::parser::Parser;
+use rslua::types::LuaVersion;
+use rslua::validator::{ValidationErrorKind, Validator};
+use std::fs::{read_dir, File};
+use std::io::prelude::*;
+
+fn try_parse(input: &str, version: LuaVersion) -> Block {
+    let mut lexer = Lexer::new();
+    lexer.set_debug(true);
+    lexer.set_version(version);
+    let tokens = lexer.run(input).unwrap();
+    let mut parser = Parser::new();
+    parser.set_debug(true);
+    parser.set_version(version);
+    parser.run(tokens).unwrap()
+}
+
+fn validate_version(input: &str, version: LuaVersion) -> Vec<String> {
+    let mut validator = Validator::new();
+    validator.set_version(version);
+    validator
+        .run(&try_parse(input, version))
+        .iter()
+        .map(|e| e.to_string())
+        .collect()
+}
+
+fn validate(input: &str) -> Vec<String> {
+    validate_version(input, LuaVersion::Lua54)
+}
+
+#[test]
+fn validate_lua_files() -> std::io::Result<()> {
+    for entry in read_dir("./lua")? {
+        let mut file = File::open(entry?.path())?;
+        let mut content = String::new();
+        file.read_to_string(&mut content)?;
+        assert_eq!(validate(&content), Vec::<String>::new());
+    }
+    Ok(())
+}
+
+#[test]
+fn breaks() {
+    assert!(validate(
+        "while a do break end repeat if b then break end until c for i = 1, 2 do do break end end"
+    )
+    .is_empty());
+    assert_eq!(
+        validate("if a then break end\nwhile a do local f = function() break end end"),
+        vec![
+            "[syntax error] break outside a loop at line [1:11].",
+            "[syntax error] break outside a loop at line [2:33].",
+        ]
+    );
+    assert_eq!(
+        validate_version("break", LuaVersion::Lua51),
+        vec!["[syntax error] break outside a loop at line [1:1]."]
+    );
+}
+
+#[test]
+fn gotos() {
+    assert!(validate(
+        "::top:: goto top
+do goto continue local x = 1 ::continue:: end
+while a do if b then goto next end local y = 1 ::next:: ; end
+do goto out end ::out::"
+    )
+    .is_empty());
+    assert_eq!(
+        validate(
+            "do ::inner:: end goto inner
+goto skip local x = 1 ::skip:: print(x)
+goto fn local function f() end ::fn:: f()
+repeat goto cont local z = 1 ::cont:: until z
+local g = function() goto top end ::top::"
+        ),
+        vec![
+            "[syntax error] no visible label 'inner' for goto at line [1:18].",
+            "[syntax error] <goto skip> jumps into the scope of local 'x' at line [2:1].",
+            "[syntax error] <goto fn> jumps into the scope of local 'f' at line [3:1].",
+            "[syntax error] <goto cont> jumps into the scope of local 'z' at line [4:8].",
+            "[syntax error] no visible label 'top' for goto at line [5:22].",
+        ]
+    );
+}
+
+#[test]
+fn labels() {
+    let input = "::a:: do ::a:: end ::b:: ::b::";
+    assert_eq!(
+        validate(input),
+        vec![
+            "[syntax error] label 'a' already defined on line 1 at line [1:10].",
+            "[syntax error] label 'b' already defined on line 1 at line [1:26].",
+        ]
+    );
+    // only labels in the same block clash before 5.4
+    assert_eq!(
+        validate_version(input, LuaVersion::Lua53),
+        vec!["[syntax error] label 'b' already defined on line 1 at line [1:26]."]
+    );
+    let mut validator = Validator::new();
+    let errors = validator.run(&try_parse("do ::a:: end ::a:: ::a::", LuaVersion::Lua54));
+    assert_eq!(
+        errors[0].kind,
+        ValidationErrorKind::DuplicateLabel("a".to_string(), 1)
+    );
+    assert_eq!(errors[0].offset, 19);
+}
+
+#[test]
+fn constants() {
+    assert_eq!(
+        validate("local x <const> = 1; x = 2"),
+        vec!["[syntax error] attempt to assign to const variable 'x' at line [1:22]."]
+    );
+    assert_eq!(
+        validate("local f <close> = nil function g() f = nil end function f() end"),
+        vec![
+            "[syntax error] attempt to assign to const variable 'f' at line [1:36].",
+            "[syntax error] attempt to assign to const variable 'f' at line [1:57].",
+        ]
+    );
+    // fields stay writable and a shadowing local is a new variable
+    assert_eq!(
+        validate("local t <const> = {} t.x = 1 local t = t t = nil"),
+        Vec::<String>::new()
+    );
+}